@@ -0,0 +1,227 @@
+use log::warn;
+
+use crate::{
+    asset::{
+        AssetService,
+        sprite_animation::{LoopMode, SpriteAnimationClip},
+    },
+    ecs::{
+        component::{sprite::SpriteImage, sprite_animation::SpriteAnimationComponent},
+        entity::scene::Scene,
+    },
+};
+
+// Frames shorter than this are treated as lasting this long so a clip can't stall the update
+const MIN_FRAME_DURATION: f32 = 0.001;
+
+#[derive(Default)]
+pub struct AnimationService {}
+
+impl AnimationService {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    // Advances every sprite animation and points the sprite of the entity at the current frame,
+    // animated tiles only need their clock advanced since the renderer picks their frame
+    pub fn handle_animation(
+        &self,
+        scene: &mut Scene,
+        asset_service: &AssetService,
+        delta_time: f32,
+    ) {
+        for (current_entity, animation_component) in scene.sprite_animation_components.iter_mut() {
+            animation_component.events.clear();
+
+            let Some(clip) = asset_service
+                .sprite_animations
+                .get(&animation_component.clip)
+            else {
+                warn!(
+                    "Entity {} refers to missing sprite animation {:?}",
+                    current_entity, animation_component.clip
+                );
+                continue;
+            };
+            if clip.frames.is_empty() {
+                continue;
+            }
+
+            Self::advance(animation_component, clip, delta_time);
+
+            // Only the atlas region changes, everything else about the sprite stays as set up
+            if let Some(sprite_component) = scene.sprite_components.get_mut(current_entity) {
+                sprite_component.image = SpriteImage::Atlas {
+                    atlas: clip.atlas,
+                    region: clip.frames[animation_component.frame].region,
+                };
+            }
+        }
+
+        for tilemap_component in scene.tilemap_components.values_mut() {
+            tilemap_component.animation_time += delta_time;
+        }
+    }
+
+    fn advance(
+        animation_component: &mut SpriteAnimationComponent,
+        clip: &SpriteAnimationClip,
+        delta_time: f32,
+    ) {
+        let last_frame = clip.frames.len() - 1;
+        animation_component.frame = animation_component.frame.min(last_frame);
+
+        if !animation_component.started {
+            animation_component.started = true;
+            animation_component
+                .events
+                .extend_from_slice(&clip.frames[animation_component.frame].events);
+        }
+
+        if !animation_component.playing {
+            return;
+        }
+
+        animation_component.frame_time += delta_time * animation_component.speed.max(0.0);
+
+        // A long update can pass through several frames, each of them fires its events
+        loop {
+            let frame_duration = clip.frames[animation_component.frame]
+                .duration
+                .max(MIN_FRAME_DURATION);
+            if animation_component.frame_time < frame_duration {
+                break;
+            }
+            animation_component.frame_time -= frame_duration;
+
+            let frame = animation_component.frame;
+            animation_component.frame = match clip.loop_mode {
+                LoopMode::Once if frame == last_frame => {
+                    animation_component.playing = false;
+                    animation_component.finished = true;
+                    animation_component.frame_time = 0.0;
+                    return;
+                }
+                LoopMode::Once => frame + 1,
+                LoopMode::Loop => (frame + 1) % clip.frames.len(),
+                LoopMode::PingPong if last_frame == 0 => 0,
+                LoopMode::PingPong if animation_component.reversing => {
+                    if frame == 0 {
+                        animation_component.reversing = false;
+                        1
+                    } else {
+                        frame - 1
+                    }
+                }
+                LoopMode::PingPong => {
+                    if frame == last_frame {
+                        animation_component.reversing = true;
+                        frame - 1
+                    } else {
+                        frame + 1
+                    }
+                }
+            };
+
+            animation_component
+                .events
+                .extend_from_slice(&clip.frames[animation_component.frame].events);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::{AtlasHandle, SpriteAnimationHandle};
+
+    // Frames last a quarter of a second so the steps below add up exactly
+    fn clip(frame_count: usize, loop_mode: LoopMode) -> SpriteAnimationClip {
+        (0..frame_count).fold(
+            SpriteAnimationClip::from_regions(AtlasHandle(0), 0..frame_count, 0.25, loop_mode),
+            |clip, frame| clip.with_event(frame, frame.to_string()),
+        )
+    }
+
+    fn advance(
+        animation_component: &mut SpriteAnimationComponent,
+        clip: &SpriteAnimationClip,
+        delta_time: f32,
+    ) -> Vec<String> {
+        animation_component.events.clear();
+        AnimationService::advance(animation_component, clip, delta_time);
+        animation_component.events.clone()
+    }
+
+    #[test]
+    fn single_frame_ping_pong_clip_repeats_its_frame() {
+        let clip = clip(1, LoopMode::PingPong);
+        let mut animation_component = SpriteAnimationComponent::new(SpriteAnimationHandle(0));
+
+        assert_eq!(advance(&mut animation_component, &clip, 1.0), ["0"; 5]);
+        assert_eq!(animation_component.frame, 0);
+        assert!(!animation_component.reversing);
+        assert!(animation_component.playing && !animation_component.finished);
+    }
+
+    #[test]
+    fn long_updates_fire_the_events_of_every_frame_passed() {
+        let clip = clip(4, LoopMode::Loop);
+        let mut animation_component = SpriteAnimationComponent::new(SpriteAnimationHandle(0));
+
+        assert_eq!(advance(&mut animation_component, &clip, 0.0), ["0"]);
+        assert_eq!(
+            advance(&mut animation_component, &clip, 0.875),
+            ["1", "2", "3"]
+        );
+        assert_eq!(animation_component.frame, 3);
+        assert_eq!(animation_component.frame_time, 0.125);
+
+        assert_eq!(advance(&mut animation_component, &clip, 0.375), ["0", "1"]);
+        assert_eq!(animation_component.frame, 1);
+    }
+
+    #[test]
+    fn ping_pong_clips_turn_around_at_both_ends() {
+        let clip = clip(3, LoopMode::PingPong);
+        let mut animation_component = SpriteAnimationComponent::new(SpriteAnimationHandle(0));
+
+        assert_eq!(
+            advance(&mut animation_component, &clip, 1.0),
+            ["0", "1", "2", "1", "0"]
+        );
+        assert_eq!(animation_component.frame, 0);
+        assert!(animation_component.reversing);
+
+        assert_eq!(advance(&mut animation_component, &clip, 0.25), ["1"]);
+        assert!(!animation_component.reversing);
+    }
+
+    #[test]
+    fn once_clips_stop_on_their_last_frame() {
+        let clip = clip(2, LoopMode::Once);
+        let mut animation_component = SpriteAnimationComponent::new(SpriteAnimationHandle(0));
+
+        assert_eq!(advance(&mut animation_component, &clip, 0.625), ["0", "1"]);
+        assert_eq!(animation_component.frame, 1);
+        assert!(!animation_component.playing);
+        assert!(animation_component.finished);
+        assert_eq!(animation_component.frame_time, 0.0);
+
+        assert!(advance(&mut animation_component, &clip, 1.0).is_empty());
+        assert_eq!(animation_component.frame, 1);
+    }
+
+    #[test]
+    fn zero_length_frames_still_take_a_step_each() {
+        let mut clip = clip(3, LoopMode::Loop);
+        for frame in clip.frames.iter_mut() {
+            frame.duration = 0.0;
+        }
+        let mut animation_component = SpriteAnimationComponent::new(SpriteAnimationHandle(0));
+
+        let events = advance(&mut animation_component, &clip, MIN_FRAME_DURATION * 2.5);
+        assert_eq!(events, ["0", "1", "2"]);
+        assert_eq!(animation_component.frame, 2);
+    }
+}
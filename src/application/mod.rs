@@ -1,10 +1,10 @@
-use glam::Vec3;
-use log::{info, trace, warn};
+use glam::{UVec2, Vec2, Vec3};
+use log::{debug, error, info, trace, warn};
 use std::{sync::Arc, time::Instant};
 use wgpu::SurfaceError;
 use winit::{
     application::ApplicationHandler,
-    dpi::LogicalSize,
+    dpi::{LogicalSize, PhysicalSize},
     event::{KeyEvent, WindowEvent},
     event_loop::ActiveEventLoop,
     keyboard::PhysicalKey,
@@ -12,28 +12,71 @@ use winit::{
 };
 
 use crate::{
+    animation::AnimationService,
+    asset::{
+        AssetService, font::Font, gltf_importer::import_gltf, mesh::Mesh, obj_importer::import_obj,
+        texture::Texture, tiled_importer::import_tiled,
+    },
     ecs::{
         component::{
-            camera::CameraComponent, input::InputComponent, physics::PhysicsComponent,
+            camera::{
+                CameraComponent, EnvironmentMap, EnvironmentSettings, PostProcessSettings,
+                Projection,
+            },
+            input::InputComponent,
+            light::{LightComponent, ShadowSettings},
+            mesh::MeshComponent,
+            physics::PhysicsComponent,
+            sprite::{SpriteComponent, SpriteImage},
+            text::TextComponent,
             transform::TransformComponent,
         },
         entity::scene::Scene,
     },
     input::InputService,
     physics::PhysicsService,
-    rendering::RenderingService,
+    rendering::{RenderingService, settings::RendererSettings, vertex::Vertex},
 };
 
+// Hardcoded vertices for a triangle
+// arramged om counter-clockwise order from top to bottom left to bottom right
+// since our render pipeline is configured to use counter-clockwise winding order
+const TRIANGLE_VERTICES: &[Vertex] = &[
+    Vertex {
+        position: [0.0, 0.5, 0.0],
+        color: [1.0, 0.0, 0.0],
+        normal: [0.0, 0.0, 1.0],
+        tex_coords: [0.5, 0.0],
+    },
+    Vertex {
+        position: [-0.5, -0.5, 0.0],
+        color: [0.0, 1.0, 0.0],
+        normal: [0.0, 0.0, 1.0],
+        tex_coords: [0.0, 1.0],
+    },
+    Vertex {
+        position: [0.5, -0.5, 0.0],
+        color: [0.0, 0.0, 1.0],
+        normal: [0.0, 0.0, 1.0],
+        tex_coords: [1.0, 1.0],
+    },
+];
+
 #[derive(Default)]
 pub struct Application {
     width: i32,
     height: i32,
     window: Option<Arc<Window>>,
     scene: Option<Scene>,
+    asset_service: Option<AssetService>,
     rendering_service: Option<RenderingService>,
     input_service: Option<InputService>,
     physics_service: Option<PhysicsService>,
+    animation_service: Option<AnimationService>,
     last_frame: Option<Instant>,
+    active_camera: u32,           // Camera entity the frames are rendered through
+    fps_text_entity: Option<u32>, // Screen text showing the frame rate, only created when a debug font is set
+    average_frame_time: f32,      // Seconds, smoothed so the frame rate text stays readable
 }
 
 impl Application {
@@ -43,16 +86,23 @@ impl Application {
             height,
             window: None,
             scene: None,
+            asset_service: None,
             rendering_service: None,
             input_service: None,
             physics_service: None,
+            animation_service: None,
             last_frame: Some(Instant::now()),
+            active_camera: 0,
+            fps_text_entity: None,
+            average_frame_time: 0.0,
         }
     }
 
     fn setup_scene(&mut self) {
         self.scene = Some(Scene::new());
+        self.asset_service = Some(AssetService::new());
         let scene = self.scene.as_mut().unwrap();
+        let asset_service = self.asset_service.as_mut().unwrap();
 
         // TODO: this is a test entity, remove later
         let test_entity = scene.create_entity();
@@ -65,11 +115,17 @@ impl Application {
                 look_at: Vec3::new(0.0, 0.0, 0.0), // Looking at the origin
                 up_orientation: Vec3::Y,           // Up is the positive Y direction
                 aspect_ratio: self.width as f32 / self.height as f32,
-                field_of_view: 45.0,
+                viewport_size: UVec2::new(self.width as u32, self.height as u32),
+                projection: Projection::Perspective {
+                    field_of_view: 45.0,
+                },
                 z_near_field: 0.1,
                 z_far_field: 100.0,
+                post_process: PostProcessSettings::default(),
+                environment: None,
             },
         );
+        self.active_camera = test_entity;
         scene.physics_components.insert(
             test_entity,
             PhysicsComponent {
@@ -88,26 +144,197 @@ impl Application {
                 translation: Vec3::new(0.0, 0.0, 0.0), // No translation
             },
         );
+
+        // TODO: this is a test entity, remove later
+        let triangle_entity = scene.create_entity();
+        let triangle_mesh = asset_service.add_mesh(Mesh {
+            name: Some("Test Triangle".to_string()),
+            vertices: TRIANGLE_VERTICES.to_vec(),
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        });
+        scene.mesh_components.insert(
+            triangle_entity,
+            MeshComponent {
+                mesh: triangle_mesh,
+            },
+        );
+        scene
+            .transform_components
+            .insert(triangle_entity, TransformComponent::default());
+
+        // TODO: this is a test entity, remove later
+        let sun_entity = scene.create_entity();
+        scene.light_components.insert(
+            sun_entity,
+            LightComponent::directional(Vec3::ONE, 3.0).with_shadow(ShadowSettings::default()),
+        );
+        scene.transform_components.insert(
+            sun_entity,
+            TransformComponent {
+                // Shines down and away from the camera at an angle
+                rotation: Vec3::new(-60.0_f32.to_radians(), 20.0_f32.to_radians(), 0.0),
+                ..Default::default()
+            },
+        );
+
+        // Optionally load a model to preview, e.g. GLTF_MODEL_PATH=assets/model.glb in .env
+        // A model with cameras is viewed through the first of them
+        if let Ok(gltf_model_path) = std::env::var("GLTF_MODEL_PATH") {
+            match import_gltf(&gltf_model_path, scene, asset_service) {
+                Ok(import) => {
+                    if let Some(camera) = import.cameras.first() {
+                        self.active_camera = *camera;
+                    }
+                }
+                Err(e) => error!("Failed to load glTF model: {:?}", e),
+            }
+        }
+
+        if let Ok(obj_model_path) = std::env::var("OBJ_MODEL_PATH")
+            && let Err(e) = import_obj(&obj_model_path, scene, asset_service)
+        {
+            error!("Failed to load OBJ model: {}", e);
+        }
+
+        // Optionally show a sprite in front of the camera, e.g. SPRITE_TEXTURE_PATH=assets/sprite.png in .env
+        if let Ok(sprite_texture_path) = std::env::var("SPRITE_TEXTURE_PATH") {
+            match Texture::load(&sprite_texture_path) {
+                Ok(texture) => {
+                    let sprite_entity = scene.create_entity();
+                    let aspect_ratio = texture.width as f32 / texture.height.max(1) as f32;
+                    let texture = asset_service.add_texture(texture);
+                    scene.sprite_components.insert(
+                        sprite_entity,
+                        SpriteComponent::new(
+                            SpriteImage::Texture(texture),
+                            Vec2::new(aspect_ratio, 1.0),
+                        ),
+                    );
+                    scene
+                        .transform_components
+                        .insert(sprite_entity, TransformComponent::default());
+                }
+                Err(e) => error!("Failed to load sprite texture: {:?}", e),
+            }
+        }
+
+        // Optionally light the scene with a sky, e.g. ENVIRONMENT_MAP_PATH=assets/sky.hdr in .env
+        if let Ok(environment_map_path) = std::env::var("ENVIRONMENT_MAP_PATH") {
+            match Texture::load(&environment_map_path) {
+                Ok(texture) => {
+                    let texture = asset_service.add_texture(texture);
+                    if let Some(camera_component) =
+                        scene.camera_components.get_mut(&self.active_camera)
+                    {
+                        camera_component.environment = Some(EnvironmentSettings::new(
+                            EnvironmentMap::Equirectangular(texture),
+                        ));
+                    }
+                }
+                Err(e) => error!("Failed to load environment map: {:?}", e),
+            }
+        }
+
+        // Optionally load a Tiled map, e.g. TILED_MAP_PATH=assets/level.tmx in .env
+        if let Ok(tiled_map_path) = std::env::var("TILED_MAP_PATH")
+            && let Err(e) = import_tiled(&tiled_map_path, scene, asset_service, 16.0)
+        {
+            error!("Failed to import Tiled map: {:?}", e);
+        }
+
+        // Optionally show the frame rate in the top left corner, e.g. DEBUG_FONT_PATH=assets/font.ttf in .env
+        if let Ok(debug_font_path) = std::env::var("DEBUG_FONT_PATH") {
+            match Font::load(&debug_font_path) {
+                Ok(font) => {
+                    let font = asset_service.add_font(font);
+                    let fps_text_entity = scene.create_entity();
+                    scene.text_components.insert(
+                        fps_text_entity,
+                        TextComponent::screen("", font, 16.0, Vec2::splat(8.0)),
+                    );
+                    self.fps_text_entity = Some(fps_text_entity);
+                }
+                Err(e) => error!("Failed to load debug font: {:?}", e),
+            }
+        }
     }
 
     fn update_services(&mut self, delta_time: f32) {
+        self.scene.as_mut().unwrap().debug_draw.advance(delta_time);
+
+        self.asset_service
+            .as_mut()
+            .unwrap()
+            .reload_changed_shaders();
+
         self.physics_service
             .as_mut()
             .unwrap()
             .handle_physics(self.scene.as_mut().unwrap(), delta_time);
 
+        self.animation_service.as_ref().unwrap().handle_animation(
+            self.scene.as_mut().unwrap(),
+            self.asset_service.as_ref().unwrap(),
+            delta_time,
+        );
+
         self.rendering_service
             .as_mut()
             .unwrap()
-            .update_camera_uniform(self.scene.as_ref().unwrap());
+            .update_camera_uniform(self.scene.as_ref().unwrap(), self.active_camera);
+
+        self.average_frame_time += (delta_time - self.average_frame_time) * 0.05;
+        if let Some(fps_text_entity) = self.fps_text_entity
+            && let Some(text_component) = self
+                .scene
+                .as_mut()
+                .unwrap()
+                .text_components
+                .get_mut(&fps_text_entity)
+        {
+            text_component.text =
+                format!("{:.0} FPS", 1.0 / self.average_frame_time.max(f32::EPSILON));
+        }
+    }
+
+    // Resizes the surface and makes every camera follow the new size of the window
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        // A minimized window has no size, everything keeps its size until it is restored
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+
+        debug!("Resizing to {}x{}", size.width, size.height);
+        self.rendering_service
+            .as_mut()
+            .unwrap()
+            .resize_surface(size.width, size.height);
+        for camera_component in self.scene.as_mut().unwrap().camera_components.values_mut() {
+            camera_component.set_viewport_size(UVec2::new(size.width, size.height));
+        }
     }
 
     fn present(&mut self) {
         trace!("Presenting frame...");
         let rendering_service = self.rendering_service.as_mut().unwrap();
-        match rendering_service.render() {
+        if rendering_service.is_device_lost()
+            && let Err(e) = rendering_service.recreate_device(self.asset_service.as_ref().unwrap())
+        {
+            error!(
+                "Failed to recreate the GPU device, trying again next frame: {:?}",
+                e
+            );
+            return;
+        }
+
+        match rendering_service.render(
+            self.scene.as_ref().unwrap(),
+            self.asset_service.as_ref().unwrap(),
+            self.active_camera,
+        ) {
             Ok(_) => {
-                trace!("Presented frame!");
+                trace!("Presented frame! {:?}", rendering_service.stats());
             }
             Err(SurfaceError::Lost | SurfaceError::Outdated) => {
                 let size = self.window.as_ref().unwrap().inner_size();
@@ -115,12 +342,21 @@ impl Application {
                 self.rendering_service
                     .as_mut()
                     .unwrap()
-                    .resize_surface(size.width, size.height);
+                    .reconfigure_surface(size.width, size.height);
+            }
+            Err(SurfaceError::Timeout) => {
+                warn!("Timed out waiting for a surface texture, skipping the frame");
+            }
+            Err(SurfaceError::OutOfMemory) => {
+                error!("Out of memory for a surface texture, the GPU device will be recreated");
             }
             Err(e) => {
                 warn!("Rendering error: {:?}", e);
             }
         }
+
+        // Shapes queued for a single frame are gone whether or not the frame made it to the screen
+        self.scene.as_mut().unwrap().debug_draw.end_frame();
     }
 }
 
@@ -144,37 +380,59 @@ impl ApplicationHandler for Application {
             .as_ref()
             .unwrap()
             .camera_components
-            .get(&1)
+            .get(&self.active_camera)
             .unwrap();
 
         let main_transform_component = self
             .scene
             .as_ref()
             .unwrap()
-            .transform_components
-            .get(&1)
+            .calculate_camera_transform(self.active_camera)
             .unwrap();
 
-        self.rendering_service = Some(
-            pollster::block_on(RenderingService::new(
-                self.window.as_ref().unwrap().clone(),
-                main_camera_component,
-                main_transform_component,
-            ))
-            .unwrap(),
-        );
+        // Backend, vsync and where shaders and compiled pipelines live come from the environment or .env
+        let renderer_settings = RendererSettings::from_env();
+        let asset_service = self.asset_service.as_mut().unwrap();
+
+        match pollster::block_on(RenderingService::new(
+            self.window.as_ref().unwrap().clone(),
+            asset_service,
+            &renderer_settings,
+            main_camera_component,
+            &main_transform_component,
+        )) {
+            Ok(rendering_service) => self.rendering_service = Some(rendering_service),
+            Err(e) => {
+                error!("Failed to create the renderer, exiting: {:?}", e);
+                event_loop.exit();
+                return;
+            }
+        }
+
+        if renderer_settings.shader_directory.is_some()
+            && let Err(e) = asset_service.watch_shaders()
+        {
+            error!("Failed to watch shaders for changes: {:?}", e);
+        }
 
         self.input_service = Some(InputService::new());
 
-        self.physics_service = Some(PhysicsService::new());
+        let mut physics_service = PhysicsService::new();
+        physics_service.draw_velocities = cfg!(debug_assertions);
+        self.physics_service = Some(physics_service);
 
-        self.rendering_service.as_mut().unwrap().resize_surface(
-            self.window.as_ref().unwrap().inner_size().width,
-            self.window.as_ref().unwrap().inner_size().height,
-        );
+        self.animation_service = Some(AnimationService::new());
+
+        // The window may not have the requested size, e.g. with display scaling
+        self.resize(self.window.as_ref().unwrap().inner_size());
     }
 
     fn about_to_wait(&mut self, _: &ActiveEventLoop) {
+        // Nothing runs without a renderer, e.g. while exiting after it failed to start
+        if self.rendering_service.is_none() {
+            return;
+        }
+
         let now = Instant::now();
         let delta_time = (now - *self.last_frame.as_ref().unwrap()).as_secs_f32();
         self.last_frame = Some(now);
@@ -189,11 +447,21 @@ impl ApplicationHandler for Application {
         window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
+        // Events may still arrive while exiting after the renderer failed to start
+        if self.rendering_service.is_none() {
+            return;
+        }
+
         match event {
             WindowEvent::CloseRequested => {
                 info!("Close window requested");
                 event_loop.exit();
             }
+            // Moving the window to a display with another scale changes its size in pixels,
+            // winit follows ScaleFactorChanged with a Resized event that has the new size
+            WindowEvent::Resized(size) => {
+                self.resize(size);
+            }
             WindowEvent::RedrawRequested => {
                 trace!("Redraw requested for window: {:?}", window_id);
                 self.present();
@@ -217,4 +485,13 @@ impl ApplicationHandler for Application {
             _ => {}
         }
     }
+
+    // Runs once for every way of exiting, closing the window and the quit key alike
+    fn exiting(&mut self, _: &ActiveEventLoop) {
+        if let Some(rendering_service) = &self.rendering_service
+            && let Err(e) = rendering_service.save_pipeline_cache()
+        {
+            error!("Failed to save the pipeline cache: {:?}", e);
+        }
+    }
 }
@@ -0,0 +1,31 @@
+use glam::{Quat, Vec3};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    CubicSpline, // Values are stored as (in tangent, value, out tangent) triplets
+}
+
+#[derive(Debug, Clone)]
+pub enum KeyframeValues {
+    Translations(Vec<Vec3>),
+    Rotations(Vec<Quat>),
+    Scales(Vec<Vec3>),
+    MorphTargetWeights(Vec<f32>),
+}
+
+#[derive(Debug, Clone)]
+pub struct AnimationChannel {
+    pub target_entity: u32,
+    pub interpolation: Interpolation,
+    pub keyframe_times: Vec<f32>, // Seconds
+    pub keyframe_values: KeyframeValues,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    pub duration: f32, // Seconds, the time of the last keyframe across all channels
+    pub channels: Vec<AnimationChannel>,
+}
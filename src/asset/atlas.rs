@@ -0,0 +1,346 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context, anyhow};
+use glam::{UVec2, Vec2};
+use serde::Deserialize;
+use wgpu::{AddressMode, TextureFormat};
+
+use crate::asset::{
+    AssetService, AtlasHandle, TextureHandle,
+    texture::{SamplerSettings, Texture},
+};
+
+// A rectangle of pixels inside an atlas texture
+#[derive(Debug, Clone, PartialEq)]
+pub struct AtlasRegion {
+    pub name: Option<String>,
+    pub position: UVec2, // Top left corner in pixels
+    pub size: UVec2,     // Width and height in pixels
+}
+
+// Many images sharing one texture so sprites using any of them can be drawn together
+#[derive(Debug, Clone)]
+pub struct TextureAtlas {
+    pub name: Option<String>,
+    pub texture: TextureHandle,
+    pub size: UVec2, // Size of the whole texture in pixels, turns regions into texture coordinates
+    pub regions: Vec<AtlasRegion>,
+}
+
+impl TextureAtlas {
+    // Sprite sheet where every frame is a cell of the same size, numbered row by row from the top left
+    pub fn from_grid(
+        texture: TextureHandle,
+        texture_size: UVec2,
+        cell_size: UVec2,
+        columns: u32,
+        rows: u32,
+        padding: UVec2, // Space between neighbouring cells
+        offset: UVec2,  // Space before the first cell
+    ) -> Self {
+        let regions = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| UVec2::new(column, row)))
+            .map(|cell| AtlasRegion {
+                name: None,
+                position: offset + cell * (cell_size + padding),
+                size: cell_size,
+            })
+            .collect();
+
+        Self {
+            name: None,
+            texture,
+            size: texture_size,
+            regions,
+        }
+    }
+
+    pub fn find_region(&self, name: &str) -> Option<usize> {
+        self.regions
+            .iter()
+            .position(|region| region.name.as_deref() == Some(name))
+    }
+
+    // Top left and bottom right texture coordinates of a region
+    pub fn region_tex_coords(&self, index: usize) -> Option<(Vec2, Vec2)> {
+        let region = self.regions.get(index)?;
+        let size = self.size.max(UVec2::ONE).as_vec2();
+
+        Some((
+            region.position.as_vec2() / size,
+            (region.position + region.size).as_vec2() / size,
+        ))
+    }
+
+    // Loads a sprite sheet described by a JSON file in the hash or array layout exported by TexturePacker
+    // and most other packing tools, the image is looked up relative to the JSON file
+    pub fn load(
+        path: impl AsRef<Path>,
+        asset_service: &mut AssetService,
+    ) -> anyhow::Result<AtlasHandle> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read atlas {:?}", path))?;
+        let sheet: SpriteSheet = serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse atlas {:?}", path))?;
+
+        let frames: Vec<(String, SpriteSheetFrame)> = match sheet.frames {
+            SpriteSheetFrames::Hash(frames) => frames.into_iter().collect(),
+            SpriteSheetFrames::Array(frames) => frames
+                .into_iter()
+                .map(|frame| (frame.filename.clone().unwrap_or_default(), frame))
+                .collect(),
+        };
+
+        let mut regions = Vec::with_capacity(frames.len());
+        for (name, frame) in frames {
+            // Rotated frames would need their texture coordinates turned as well
+            if frame.rotated {
+                return Err(anyhow!(
+                    "Atlas {:?} frame {} is rotated, which is not supported",
+                    path,
+                    name
+                ));
+            }
+
+            regions.push(AtlasRegion {
+                name: Some(name),
+                position: UVec2::new(frame.frame.x, frame.frame.y),
+                size: UVec2::new(frame.frame.w, frame.frame.h),
+            });
+        }
+
+        let image_path = path.with_file_name(&sheet.meta.image);
+        let mut texture = Texture::load(&image_path)?;
+        texture.mips.clear();
+        texture.generate_mips = false;
+        texture.sampler = atlas_sampler_settings();
+
+        let size = UVec2::new(texture.width, texture.height);
+        let texture = asset_service.add_texture(texture);
+
+        Ok(asset_service.add_atlas(Self {
+            name: path.file_name().map(|n| n.to_string_lossy().into_owned()),
+            texture,
+            size,
+            regions,
+        }))
+    }
+}
+
+// Neighbouring regions would bleed into each other if the edges of the texture wrapped around,
+// or in smaller mip levels where the padding between them is averaged away, so atlases have none
+fn atlas_sampler_settings() -> SamplerSettings {
+    SamplerSettings {
+        address_mode_u: AddressMode::ClampToEdge,
+        address_mode_v: AddressMode::ClampToEdge,
+        ..Default::default()
+    }
+}
+
+#[derive(Deserialize)]
+struct SpriteSheet {
+    frames: SpriteSheetFrames,
+    meta: SpriteSheetMeta,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SpriteSheetFrames {
+    Hash(BTreeMap<String, SpriteSheetFrame>), // Sorted by name since JSON objects have no order
+    Array(Vec<SpriteSheetFrame>),
+}
+
+#[derive(Deserialize)]
+struct SpriteSheetFrame {
+    filename: Option<String>, // Only present in the array layout
+    frame: SpriteSheetRect,
+    #[serde(default)]
+    rotated: bool,
+}
+
+#[derive(Deserialize)]
+struct SpriteSheetRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize)]
+struct SpriteSheetMeta {
+    image: String,
+}
+
+// Packs loose images into a single atlas texture
+pub struct AtlasPacker {
+    max_size: u32, // Largest width or height the atlas texture may have
+    padding: u32, // Pixels around every image filled with its edge pixels so filtering doesn't bleed
+    images: Vec<(String, Texture)>,
+}
+
+impl AtlasPacker {
+    pub fn new(max_size: u32, padding: u32) -> Self {
+        Self {
+            max_size,
+            padding,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn add_image(&mut self, name: impl Into<String>, texture: Texture) -> anyhow::Result<()> {
+        let name = name.into();
+        if !matches!(
+            texture.format,
+            TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm
+        ) {
+            return Err(anyhow!(
+                "Image {} uses {:?}, only uncompressed RGBA images can be packed",
+                name,
+                texture.format
+            ));
+        }
+        if texture.width == 0
+            || texture.height == 0
+            || texture.pixels.len() < (texture.width * texture.height * 4) as usize
+        {
+            return Err(anyhow!(
+                "Image {} is empty or has fewer pixels than its size",
+                name
+            ));
+        }
+
+        self.images.push((name, texture));
+        Ok(())
+    }
+
+    // The region is named after the file without its extension
+    pub fn add_file(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        self.add_image(name, Texture::load(path)?)
+    }
+
+    // Regions keep the order the images were added in
+    pub fn pack(self, asset_service: &mut AssetService) -> anyhow::Result<AtlasHandle> {
+        let format = self
+            .images
+            .first()
+            .map(|(_, texture)| texture.format)
+            .ok_or_else(|| anyhow!("Cannot pack an atlas without images"))?;
+
+        let padded_sizes: Vec<UVec2> = self
+            .images
+            .iter()
+            .map(|(_, texture)| UVec2::new(texture.width, texture.height) + 2 * self.padding)
+            .collect();
+        let (size, positions) =
+            Self::find_layout(&padded_sizes, self.max_size).ok_or_else(|| {
+                anyhow!(
+                    "{} images do not fit in a {}x{} atlas",
+                    padded_sizes.len(),
+                    self.max_size,
+                    self.max_size
+                )
+            })?;
+
+        let mut pixels = vec![0; (size.x * size.y * 4) as usize];
+        let mut regions = Vec::with_capacity(self.images.len());
+        for ((name, texture), position) in self.images.into_iter().zip(positions) {
+            let image_position = position + self.padding;
+            Self::blit(&mut pixels, size.x, &texture, image_position, self.padding);
+
+            regions.push(AtlasRegion {
+                name: Some(name),
+                position: image_position,
+                size: UVec2::new(texture.width, texture.height),
+            });
+        }
+
+        let texture = asset_service.add_texture(Texture {
+            name: Some("Packed Atlas".to_string()),
+            width: size.x,
+            height: size.y,
+            format,
+            pixels,
+            generate_mips: false,
+            sampler: atlas_sampler_settings(),
+            ..Default::default()
+        });
+
+        Ok(asset_service.add_atlas(TextureAtlas {
+            name: Some("Packed Atlas".to_string()),
+            texture,
+            size,
+            regions,
+        }))
+    }
+
+    // Tries power of two widths and keeps the layout with the smallest power of two area
+    fn find_layout(sizes: &[UVec2], max_size: u32) -> Option<(UVec2, Vec<UVec2>)> {
+        let widest = sizes.iter().map(|size| size.x).max()?;
+        let mut best: Option<(UVec2, Vec<UVec2>)> = None;
+
+        let mut width = widest.next_power_of_two();
+        while width <= max_size {
+            let (height, positions) = Self::shelf_pack(sizes, width);
+            let size = UVec2::new(width, height.next_power_of_two());
+
+            let is_better = best
+                .as_ref()
+                .is_none_or(|(best_size, _)| size.element_product() < best_size.element_product());
+            if size.y <= max_size && is_better {
+                best = Some((size, positions));
+            }
+
+            width *= 2;
+        }
+
+        best
+    }
+
+    // Places the tallest images first on rows that are filled left to right,
+    // returns the height used and the top left corner of every image in the order given
+    fn shelf_pack(sizes: &[UVec2], width: u32) -> (u32, Vec<UVec2>) {
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].y));
+
+        let mut positions = vec![UVec2::ZERO; sizes.len()];
+        let mut cursor = UVec2::ZERO;
+        let mut row_height = 0;
+        for i in order {
+            if cursor.x + sizes[i].x > width {
+                cursor = UVec2::new(0, cursor.y + row_height);
+                row_height = 0;
+            }
+
+            positions[i] = cursor;
+            cursor.x += sizes[i].x;
+            row_height = row_height.max(sizes[i].y);
+        }
+
+        (cursor.y + row_height, positions)
+    }
+
+    // Copies an image into the atlas and repeats its edge pixels into the padding around it
+    fn blit(pixels: &mut [u8], atlas_width: u32, texture: &Texture, position: UVec2, padding: u32) {
+        let padding = padding as i64;
+        for y in -padding..texture.height as i64 + padding {
+            for x in -padding..texture.width as i64 + padding {
+                let source_x = x.clamp(0, texture.width as i64 - 1) as usize;
+                let source_y = y.clamp(0, texture.height as i64 - 1) as usize;
+                let source = (source_y * texture.width as usize + source_x) * 4;
+
+                let target_x = (position.x as i64 + x) as usize;
+                let target_y = (position.y as i64 + y) as usize;
+                let target = (target_y * atlas_width as usize + target_x) * 4;
+
+                pixels[target..target + 4].copy_from_slice(&texture.pixels[source..source + 4]);
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use glam::{Mat4, Vec3};
+
+// Axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    // None when there are no points to enclose
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+
+        Some(points.fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |aabb, point| Self {
+                min: aabb.min.min(point),
+                max: aabb.max.max(point),
+            },
+        ))
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    // The box that encloses this box after it is transformed, which is usually a little larger
+    pub fn transform(&self, matrix: Mat4) -> Self {
+        let center = matrix.transform_point3(self.center());
+        let half_extents = self.half_extents();
+        let world_half_extents = matrix.x_axis.truncate().abs() * half_extents.x
+            + matrix.y_axis.truncate().abs() * half_extents.y
+            + matrix.z_axis.truncate().abs() * half_extents.z;
+
+        Self {
+            min: center - world_half_extents,
+            max: center + world_half_extents,
+        }
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere {
+            center: self.center(),
+            radius: self.half_extents().length(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    // Non-uniform scaling grows the sphere by the largest scale so it still encloses the mesh
+    pub fn transform(&self, matrix: Mat4) -> Self {
+        let max_scale = matrix
+            .x_axis
+            .truncate()
+            .length()
+            .max(matrix.y_axis.truncate().length())
+            .max(matrix.z_axis.truncate().length());
+
+        Self {
+            center: matrix.transform_point3(self.center),
+            radius: self.radius * max_scale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Quat;
+
+    use super::*;
+
+    #[test]
+    fn points_are_enclosed_by_their_box() {
+        let aabb = Aabb::from_points([
+            Vec3::new(1.0, -2.0, 0.0),
+            Vec3::new(-1.0, 3.0, 0.5),
+            Vec3::new(0.0, 0.0, -4.0),
+        ])
+        .unwrap();
+        assert_eq!(aabb.min, Vec3::new(-1.0, -2.0, -4.0));
+        assert_eq!(aabb.max, Vec3::new(1.0, 3.0, 0.5));
+        assert_eq!(Aabb::from_points([]), None);
+    }
+
+    #[test]
+    fn transformed_boxes_enclose_the_transformed_corners() {
+        let aabb = Aabb {
+            min: Vec3::ZERO,
+            max: Vec3::new(2.0, 1.0, 1.0),
+        };
+
+        // A quarter turn around Z swaps the x and y extents
+        let quarter_turn = Mat4::from_rotation_translation(
+            Quat::from_rotation_z(90.0_f32.to_radians()),
+            Vec3::new(10.0, 0.0, 0.0),
+        );
+        let transformed = aabb.transform(quarter_turn);
+        assert!(transformed.min.abs_diff_eq(Vec3::new(9.0, 0.0, 0.0), 1e-5));
+        assert!(transformed.max.abs_diff_eq(Vec3::new(10.0, 2.0, 1.0), 1e-5));
+
+        // Half a quarter turn leaves the box larger than the rotated box
+        let eighth_turn = Mat4::from_rotation_z(45.0_f32.to_radians());
+        let transformed = aabb.transform(eighth_turn);
+        let corners = [
+            Vec3::ZERO,
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(2.0, 1.0, 1.0),
+        ]
+        .map(|corner| eighth_turn.transform_point3(corner));
+        for corner in corners {
+            assert!(corner.cmpge(transformed.min - 1e-5).all(), "{:?}", corner);
+            assert!(corner.cmple(transformed.max + 1e-5).all(), "{:?}", corner);
+        }
+    }
+
+    #[test]
+    fn spheres_grow_by_the_largest_scale() {
+        let sphere = BoundingSphere {
+            center: Vec3::X,
+            radius: 1.0,
+        };
+        let transformed = sphere.transform(Mat4::from_scale(Vec3::new(1.0, 3.0, 2.0)));
+        assert_eq!(transformed.center, Vec3::X);
+        assert_eq!(transformed.radius, 3.0);
+    }
+}
@@ -0,0 +1,220 @@
+use std::{fmt, path::Path};
+
+use anyhow::{Context, anyhow};
+use glam::Vec2;
+use log::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextAlignment {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+// How a block of text is broken into lines and where the lines sit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextLayoutSettings {
+    pub max_width: Option<f32>, // Lines are wrapped at word boundaries to stay within this width
+    pub alignment: TextAlignment,
+    pub line_spacing: f32, // Multiplies the line height of the font, 1 keeps the spacing the font was designed with
+}
+
+impl Default for TextLayoutSettings {
+    fn default() -> Self {
+        Self {
+            max_width: None,
+            alignment: TextAlignment::Left,
+            line_spacing: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionedGlyph {
+    pub glyph: u16,     // Index of the glyph inside the font
+    pub position: Vec2, // Top left corner of the glyph bitmap
+    pub size: Vec2,     // Size of the glyph bitmap
+}
+
+// Glyphs placed by Font::layout, measured in pixels of the size the text was laid out at
+// from the top left corner of the text with y growing downwards
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextLayout {
+    pub glyphs: Vec<PositionedGlyph>, // Whitespace and other empty glyphs are left out
+    pub size: Vec2, // Width of the widest line, or the max width, by the height of every line
+    pub line_count: usize,
+}
+
+// A glyph on a line that is still being filled
+struct LineGlyph {
+    glyph: u16,
+    x: f32, // Pen position the glyph is drawn at, kerning included
+    advance: f32,
+    is_whitespace: bool,
+}
+
+// TrueType or OpenType font, glyphs are rasterized on demand by the renderer
+pub struct Font {
+    pub name: Option<String>,
+    font: fontdue::Font,
+}
+
+// fontdue's font holds every outline of the file and is too large to print
+impl fmt::Debug for Font {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Font")
+            .field("name", &self.name)
+            .field("glyph_count", &self.font.glyph_count())
+            .finish()
+    }
+}
+
+impl Font {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        info!("Loading font: {:?}", path);
+
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Failed to read font {:?}", path))?;
+        let mut font =
+            Self::from_bytes(&bytes).with_context(|| format!("Failed to load font {:?}", path))?;
+        font.name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+
+        Ok(font)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
+            .map_err(|e| anyhow!("Invalid font: {}", e))?;
+
+        Ok(Self {
+            name: font.name().map(str::to_string),
+            font,
+        })
+    }
+
+    // Coverage of every pixel of the glyph bitmap, one byte per pixel row by row
+    pub fn rasterize(&self, glyph: u16, size: f32) -> (fontdue::Metrics, Vec<u8>) {
+        self.font.rasterize_indexed(glyph, size)
+    }
+
+    // Distance from one baseline to the next and from the top of a line to its baseline
+    fn line_metrics(&self, size: f32) -> (f32, f32) {
+        self.font
+            .horizontal_line_metrics(size)
+            .map_or((size * 1.2, size), |metrics| {
+                (metrics.new_line_size, metrics.ascent)
+            })
+    }
+
+    pub fn layout(&self, text: &str, size: f32, settings: &TextLayoutSettings) -> TextLayout {
+        let (line_height, ascent) = self.line_metrics(size);
+        let line_height = line_height * settings.line_spacing;
+
+        let mut lines: Vec<Vec<LineGlyph>> = Vec::new();
+        for paragraph in text.split('\n') {
+            let mut line: Vec<LineGlyph> = Vec::new();
+            let mut pen_x = 0.0;
+
+            for character in paragraph.chars().filter(|c| *c != '\r') {
+                let glyph = self.font.lookup_glyph_index(character);
+                let metrics = self.font.metrics_indexed(glyph, size);
+                let kerning = line
+                    .last()
+                    .and_then(|previous| {
+                        self.font
+                            .horizontal_kern_indexed(previous.glyph, glyph, size)
+                    })
+                    .unwrap_or(0.0);
+                let is_whitespace = character.is_whitespace();
+
+                // Whitespace may hang past the edge, anything else starts a new line
+                if let Some(max_width) = settings.max_width
+                    && !is_whitespace
+                    && !line.is_empty()
+                    && pen_x + kerning + metrics.advance_width > max_width
+                {
+                    // Break after the last whitespace so the word moves down as a whole,
+                    // words wider than a line are broken wherever they overflow
+                    let split = line
+                        .iter()
+                        .rposition(|line_glyph| line_glyph.is_whitespace)
+                        .map_or(line.len(), |whitespace| whitespace + 1);
+                    let carried = line.split_off(split);
+                    lines.push(line);
+
+                    let carried_x = carried.first().map_or(0.0, |line_glyph| line_glyph.x);
+                    line = carried
+                        .into_iter()
+                        .map(|line_glyph| LineGlyph {
+                            x: line_glyph.x - carried_x,
+                            ..line_glyph
+                        })
+                        .collect();
+                    pen_x = line
+                        .last()
+                        .map_or(0.0, |line_glyph| line_glyph.x + line_glyph.advance);
+                }
+
+                let kerning = if line.is_empty() { 0.0 } else { kerning };
+                line.push(LineGlyph {
+                    glyph,
+                    x: pen_x + kerning,
+                    advance: metrics.advance_width,
+                    is_whitespace,
+                });
+                pen_x += kerning + metrics.advance_width;
+            }
+
+            lines.push(line);
+        }
+
+        // Trailing whitespace doesn't count towards the width a line is aligned by
+        let line_widths: Vec<f32> = lines
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .rev()
+                    .find(|line_glyph| !line_glyph.is_whitespace)
+                    .map_or(0.0, |line_glyph| line_glyph.x + line_glyph.advance)
+            })
+            .collect();
+        let width = settings
+            .max_width
+            .unwrap_or_else(|| line_widths.iter().copied().fold(0.0, f32::max));
+
+        let mut glyphs = Vec::new();
+        for (index, (line, line_width)) in lines.iter().zip(line_widths).enumerate() {
+            let offset = match settings.alignment {
+                TextAlignment::Left => 0.0,
+                TextAlignment::Center => (width - line_width) * 0.5,
+                TextAlignment::Right => width - line_width,
+            };
+            let baseline = index as f32 * line_height + ascent;
+
+            for line_glyph in line.iter().filter(|line_glyph| !line_glyph.is_whitespace) {
+                let metrics = self.font.metrics_indexed(line_glyph.glyph, size);
+                if metrics.width == 0 || metrics.height == 0 {
+                    continue;
+                }
+
+                // Bitmap offsets are measured upwards from the baseline
+                glyphs.push(PositionedGlyph {
+                    glyph: line_glyph.glyph,
+                    position: Vec2::new(
+                        offset + line_glyph.x + metrics.xmin as f32,
+                        baseline - (metrics.ymin + metrics.height as i32) as f32,
+                    ),
+                    size: Vec2::new(metrics.width as f32, metrics.height as f32),
+                });
+            }
+        }
+
+        TextLayout {
+            glyphs,
+            size: Vec2::new(width, lines.len() as f32 * line_height),
+            line_count: lines.len(),
+        }
+    }
+}
@@ -0,0 +1,572 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, anyhow};
+use glam::{EulerRot, Mat4, Quat, UVec2, Vec3, Vec4};
+use gltf::{
+    animation::util::ReadOutputs,
+    camera::Projection,
+    image::{Data as ImageData, Format},
+    texture::{MagFilter, MinFilter, WrappingMode},
+};
+use log::{debug, info, warn};
+use wgpu::{AddressMode, FilterMode, TextureFormat};
+
+use crate::{
+    asset::{
+        AnimationHandle, AssetService, MaterialHandle, MeshHandle, TextureHandle,
+        animation::{AnimationChannel, AnimationClip, Interpolation, KeyframeValues},
+        material::Material,
+        mesh::Mesh,
+        texture::{SamplerSettings, Texture},
+    },
+    ecs::{
+        component::{
+            camera::{
+                CameraComponent, PostProcessSettings, Projection as CameraProjection, ScalingMode,
+            },
+            material::MaterialComponent,
+            mesh::MeshComponent,
+            skin::SkinComponent,
+            transform::TransformComponent,
+        },
+        entity::scene::Scene,
+    },
+    rendering::vertex::Vertex,
+};
+
+// Used when a glTF camera does not specify an aspect ratio, the window resize handling overrides it
+const DEFAULT_ASPECT_RATIO: f32 = 800.0 / 600.0;
+
+// Used when a glTF camera is infinite, matches the far plane of the default camera
+const DEFAULT_Z_FAR_FIELD: f32 = 100.0;
+
+#[derive(Debug, Default, Clone)]
+pub struct GltfImport {
+    pub root_entities: Vec<u32>,
+    pub node_entities: HashMap<usize, u32>, // glTF node index to spawned entity
+    pub cameras: Vec<u32>, // Entities of the nodes with a camera, in glTF node order
+    pub animations: Vec<AnimationHandle>,
+}
+
+// Loads a .gltf or .glb file, registers its assets and spawns its default scene into the Scene.
+pub fn import_gltf(
+    path: impl AsRef<Path>,
+    scene: &mut Scene,
+    asset_service: &mut AssetService,
+) -> anyhow::Result<GltfImport> {
+    let path = path.as_ref();
+    info!("Importing glTF file: {:?}", path);
+
+    let (document, buffers, images) =
+        gltf::import(path).with_context(|| format!("Failed to import glTF file {:?}", path))?;
+
+    let get_buffer_data = |buffer: gltf::Buffer| buffers.get(buffer.index()).map(|b| &b.0[..]);
+
+    // A glTF texture pairs an image with a sampler, indexed by their glTF texture index
+    let mut texture_handles: Vec<TextureHandle> = Vec::new();
+    for gltf_texture in document.textures() {
+        let image_index = gltf_texture.source().index();
+        let name = gltf_texture
+            .name()
+            .or_else(|| gltf_texture.source().name())
+            .map(str::to_owned);
+        let mut texture = convert_image(name, &images[image_index])
+            .with_context(|| format!("Failed to convert glTF image {}", image_index))?;
+        texture.sampler = convert_sampler(&gltf_texture.sampler());
+        texture_handles.push(asset_service.add_texture(texture));
+    }
+
+    let texture_handle_for = |texture: gltf::Texture| texture_handles[texture.index()];
+
+    // Materials are indexed by their glTF material index
+    let mut material_handles: Vec<MaterialHandle> = Vec::new();
+    for gltf_material in document.materials() {
+        let pbr = gltf_material.pbr_metallic_roughness();
+        let material = Material {
+            name: gltf_material.name().map(str::to_owned),
+            base_color_factor: Vec4::from_array(pbr.base_color_factor()),
+            base_color_texture: pbr
+                .base_color_texture()
+                .map(|info| texture_handle_for(info.texture())),
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+            metallic_roughness_texture: pbr
+                .metallic_roughness_texture()
+                .map(|info| texture_handle_for(info.texture())),
+            normal_texture: gltf_material
+                .normal_texture()
+                .map(|normal| texture_handle_for(normal.texture())),
+            normal_scale: gltf_material
+                .normal_texture()
+                .map_or(1.0, |normal| normal.scale()),
+            occlusion_texture: gltf_material
+                .occlusion_texture()
+                .map(|occlusion| texture_handle_for(occlusion.texture())),
+            occlusion_strength: gltf_material
+                .occlusion_texture()
+                .map_or(1.0, |occlusion| occlusion.strength()),
+            emissive_factor: Vec3::from_array(gltf_material.emissive_factor()),
+            emissive_texture: gltf_material
+                .emissive_texture()
+                .map(|info| texture_handle_for(info.texture())),
+            double_sided: gltf_material.double_sided(),
+        };
+        material_handles.push(asset_service.add_material(material));
+    }
+
+    // Textures are stored as linear data unless a material uses them as a color
+    let color_textures: Vec<TextureHandle> = material_handles
+        .iter()
+        .filter_map(|handle| asset_service.materials.get(handle))
+        .flat_map(|material| [material.base_color_texture, material.emissive_texture])
+        .flatten()
+        .collect();
+    for handle in color_textures {
+        if let Some(texture) = asset_service.textures.get_mut(&handle) {
+            texture.format = texture.format.add_srgb_suffix();
+        }
+    }
+
+    // Primitives without a material use the glTF default material
+    let mut default_material: Option<MaterialHandle> = None;
+
+    // Every primitive becomes its own mesh, grouped by the glTF mesh index
+    let mut mesh_primitives: Vec<Vec<(MeshHandle, MaterialHandle)>> = Vec::new();
+    for gltf_mesh in document.meshes() {
+        let mut primitives = Vec::new();
+        for primitive in gltf_mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                warn!(
+                    "Skipping primitive {} of mesh {:?}, only triangle lists are supported",
+                    primitive.index(),
+                    gltf_mesh.name()
+                );
+                continue;
+            }
+
+            let reader = primitive.reader(get_buffer_data);
+            let positions: Vec<[f32; 3]> = reader
+                .read_positions()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Primitive {} of mesh {:?} has no positions",
+                        primitive.index(),
+                        gltf_mesh.name()
+                    )
+                })?
+                .collect();
+            let normals: Vec<[f32; 3]> = reader
+                .read_normals()
+                .map(|n| n.collect())
+                .unwrap_or_default();
+            let tex_coords: Vec<[f32; 2]> = reader
+                .read_tex_coords(0)
+                .map(|t| t.into_f32().collect())
+                .unwrap_or_default();
+            let colors: Vec<[f32; 3]> = reader
+                .read_colors(0)
+                .map(|c| c.into_rgb_f32().collect())
+                .unwrap_or_default();
+
+            let vertices: Vec<Vertex> = positions
+                .iter()
+                .enumerate()
+                .map(|(i, position)| Vertex {
+                    position: *position,
+                    color: colors.get(i).copied().unwrap_or([1.0, 1.0, 1.0]),
+                    normal: normals.get(i).copied().unwrap_or([0.0, 0.0, 1.0]),
+                    tex_coords: tex_coords.get(i).copied().unwrap_or([0.0, 0.0]),
+                })
+                .collect();
+
+            // Non-indexed primitives draw their vertices in order
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..vertices.len() as u32).collect(),
+            };
+
+            let mesh = Mesh {
+                name: gltf_mesh.name().map(str::to_owned),
+                vertices,
+                indices,
+                joint_indices: reader
+                    .read_joints(0)
+                    .map(|j| j.into_u16().collect())
+                    .unwrap_or_default(),
+                joint_weights: reader
+                    .read_weights(0)
+                    .map(|w| w.into_f32().collect())
+                    .unwrap_or_default(),
+            };
+
+            let material_handle = match primitive.material().index() {
+                Some(index) => material_handles[index],
+                None => *default_material
+                    .get_or_insert_with(|| asset_service.add_material(Material::default())),
+            };
+
+            primitives.push((asset_service.add_mesh(mesh), material_handle));
+        }
+        mesh_primitives.push(primitives);
+    }
+
+    let gltf_scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| anyhow!("glTF file {:?} does not contain a scene", path))?;
+
+    let mut import = GltfImport::default();
+    for node in gltf_scene.nodes() {
+        let entity = spawn_node(
+            &node,
+            None,
+            Mat4::IDENTITY,
+            scene,
+            &mesh_primitives,
+            &mut import.node_entities,
+        );
+        import.root_entities.push(entity);
+    }
+
+    // Cameras are listed in node order, not in the order the scene tree is walked
+    for node in document.nodes().filter(|node| node.camera().is_some()) {
+        if let Some(&entity) = import.node_entities.get(&node.index()) {
+            import.cameras.push(entity);
+        }
+    }
+
+    // Skins refer to joints by node, so they are attached after every node has an entity
+    for node in document.nodes() {
+        let (Some(skin), Some(&entity)) = (node.skin(), import.node_entities.get(&node.index()))
+        else {
+            continue;
+        };
+
+        // Vertices refer to joints by their index in the skin, so every joint needs an entity
+        let joints = skin
+            .joints()
+            .map(|joint| {
+                import
+                    .node_entities
+                    .get(&joint.index())
+                    .copied()
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Joint node {} of skin {} in {:?} is not part of the scene",
+                            joint.index(),
+                            skin.index(),
+                            path
+                        )
+                    })
+            })
+            .collect::<anyhow::Result<Vec<u32>>>()?;
+        let inverse_bind_matrices: Vec<Mat4> = skin
+            .reader(get_buffer_data)
+            .read_inverse_bind_matrices()
+            .map(|matrices| matrices.map(|m| Mat4::from_cols_array_2d(&m)).collect())
+            .unwrap_or_else(|| vec![Mat4::IDENTITY; joints.len()]);
+
+        let skin_component = SkinComponent {
+            joints,
+            inverse_bind_matrices,
+            skeleton_root: skin
+                .skeleton()
+                .and_then(|root| import.node_entities.get(&root.index()).copied()),
+        };
+
+        // Skinned meshes are spawned as children of the node, they all share the skin
+        scene.skin_components.insert(entity, skin_component.clone());
+        for child in child_mesh_entities(scene, entity) {
+            scene.skin_components.insert(child, skin_component.clone());
+        }
+    }
+
+    for gltf_animation in document.animations() {
+        let mut clip = AnimationClip {
+            name: gltf_animation.name().map(str::to_owned),
+            duration: 0.0,
+            channels: Vec::new(),
+        };
+
+        for channel in gltf_animation.channels() {
+            let Some(&target_entity) = import.node_entities.get(&channel.target().node().index())
+            else {
+                // The target node is not part of the imported scene
+                continue;
+            };
+
+            let reader = channel.reader(get_buffer_data);
+            let (Some(inputs), Some(outputs)) = (reader.read_inputs(), reader.read_outputs())
+            else {
+                warn!(
+                    "Skipping animation channel {} of {:?} without keyframes",
+                    channel.index(),
+                    gltf_animation.name()
+                );
+                continue;
+            };
+
+            let keyframe_times: Vec<f32> = inputs.collect();
+            let keyframe_values = match outputs {
+                ReadOutputs::Translations(t) => {
+                    KeyframeValues::Translations(t.map(Vec3::from_array).collect())
+                }
+                ReadOutputs::Rotations(r) => {
+                    KeyframeValues::Rotations(r.into_f32().map(Quat::from_array).collect())
+                }
+                ReadOutputs::Scales(s) => KeyframeValues::Scales(s.map(Vec3::from_array).collect()),
+                ReadOutputs::MorphTargetWeights(w) => {
+                    KeyframeValues::MorphTargetWeights(w.into_f32().collect())
+                }
+            };
+
+            clip.duration = keyframe_times
+                .last()
+                .copied()
+                .unwrap_or(0.0)
+                .max(clip.duration);
+            clip.channels.push(AnimationChannel {
+                target_entity,
+                interpolation: match channel.sampler().interpolation() {
+                    gltf::animation::Interpolation::Step => Interpolation::Step,
+                    gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                    gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+                },
+                keyframe_times,
+                keyframe_values,
+            });
+        }
+
+        import.animations.push(asset_service.add_animation(clip));
+    }
+
+    debug!(
+        "Imported glTF file {:?} with {} entities and {} animations",
+        path,
+        import.node_entities.len(),
+        import.animations.len()
+    );
+
+    Ok(import)
+}
+
+fn spawn_node(
+    node: &gltf::Node,
+    parent: Option<u32>,
+    parent_world_matrix: Mat4,
+    scene: &mut Scene,
+    mesh_primitives: &[Vec<(MeshHandle, MaterialHandle)>],
+    node_entities: &mut HashMap<usize, u32>,
+) -> u32 {
+    let entity = scene.create_entity();
+    node_entities.insert(node.index(), entity);
+
+    let (translation, rotation, scale) = node.transform().decomposed();
+    let rotation = Quat::from_array(rotation);
+    let (x, y, z) = rotation.to_euler(EulerRot::XYZ);
+    let transform_component = TransformComponent {
+        position: Vec3::from_array(translation),
+        scale: Vec3::from_array(scale),
+        rotation: Vec3::new(x, y, z),
+        translation: Vec3::ZERO,
+    };
+    let world_matrix = parent_world_matrix * transform_component.calculate_model_matrix();
+    scene
+        .transform_components
+        .insert(entity, transform_component);
+
+    if let Some(parent) = parent {
+        scene.set_parent(entity, parent);
+    }
+
+    if let Some(gltf_mesh) = node.mesh() {
+        let primitives = &mesh_primitives[gltf_mesh.index()];
+        if let [(mesh, material)] = primitives.as_slice() {
+            scene
+                .mesh_components
+                .insert(entity, MeshComponent { mesh: *mesh });
+            scene.material_components.insert(
+                entity,
+                MaterialComponent {
+                    material: *material,
+                },
+            );
+        } else {
+            // A mesh component holds a single mesh, so each primitive gets its own child entity
+            for (mesh, material) in primitives {
+                let primitive_entity = scene.create_entity();
+                scene
+                    .transform_components
+                    .insert(primitive_entity, TransformComponent::default());
+                scene
+                    .mesh_components
+                    .insert(primitive_entity, MeshComponent { mesh: *mesh });
+                scene.material_components.insert(
+                    primitive_entity,
+                    MaterialComponent {
+                        material: *material,
+                    },
+                );
+                scene.set_parent(primitive_entity, entity);
+            }
+        }
+    }
+
+    if let Some(gltf_camera) = node.camera() {
+        // glTF cameras look down their local -Z axis
+        let (_, world_rotation, world_position) = world_matrix.to_scale_rotation_translation();
+        let forward = world_rotation * Vec3::NEG_Z;
+
+        // The camera component tracks the camera position in the XY plane, so the look at point is stored
+        // relative to that. The renderer places cameras at their world position, see calculate_camera_transform.
+        let look_at = world_position + forward - Vec3::new(world_position.x, world_position.y, 0.0);
+
+        let camera_component = match gltf_camera.projection() {
+            Projection::Perspective(perspective) => CameraComponent {
+                look_at,
+                up_orientation: world_rotation * Vec3::Y,
+                aspect_ratio: perspective.aspect_ratio().unwrap_or(DEFAULT_ASPECT_RATIO),
+                viewport_size: UVec2::ZERO,
+                projection: CameraProjection::Perspective {
+                    field_of_view: perspective.yfov().to_degrees(),
+                },
+                z_near_field: perspective.znear(),
+                z_far_field: perspective.zfar().unwrap_or(DEFAULT_Z_FAR_FIELD),
+                post_process: PostProcessSettings::default(),
+                environment: None,
+            },
+            // xmag and ymag are half of the visible width and height
+            Projection::Orthographic(orthographic) => CameraComponent {
+                look_at,
+                up_orientation: world_rotation * Vec3::Y,
+                aspect_ratio: orthographic.xmag() / orthographic.ymag(),
+                viewport_size: UVec2::ZERO,
+                projection: CameraProjection::Orthographic {
+                    size: orthographic.ymag() * 2.0,
+                    scaling_mode: ScalingMode::FixedVertical,
+                },
+                z_near_field: orthographic.znear(),
+                z_far_field: orthographic.zfar(),
+                post_process: PostProcessSettings::default(),
+                environment: None,
+            },
+        };
+        scene.camera_components.insert(entity, camera_component);
+    }
+
+    for child in node.children() {
+        spawn_node(
+            &child,
+            Some(entity),
+            world_matrix,
+            scene,
+            mesh_primitives,
+            node_entities,
+        );
+    }
+
+    entity
+}
+
+fn child_mesh_entities(scene: &Scene, entity: u32) -> Vec<u32> {
+    scene
+        .hierarchy_components
+        .get(&entity)
+        .map(|hierarchy_component| {
+            hierarchy_component
+                .children
+                .iter()
+                .copied()
+                .filter(|child| scene.mesh_components.contains_key(child))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Expands the decoded glTF image into tightly packed RGBA8
+fn convert_image(name: Option<String>, image: &ImageData) -> anyhow::Result<Texture> {
+    let pixel_count = (image.width * image.height) as usize;
+    let mut pixels = Vec::with_capacity(pixel_count * 4);
+
+    match image.format {
+        Format::R8 => {
+            for &r in &image.pixels {
+                pixels.extend_from_slice(&[r, r, r, 255]);
+            }
+        }
+        Format::R8G8 => {
+            for rg in image.pixels.chunks_exact(2) {
+                pixels.extend_from_slice(&[rg[0], rg[1], 0, 255]);
+            }
+        }
+        Format::R8G8B8 => {
+            for rgb in image.pixels.chunks_exact(3) {
+                pixels.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+            }
+        }
+        Format::R8G8B8A8 => pixels.extend_from_slice(&image.pixels),
+        Format::R16G16B16 => {
+            for rgb in image.pixels.chunks_exact(6) {
+                // Keep the most significant byte of each little endian channel
+                pixels.extend_from_slice(&[rgb[1], rgb[3], rgb[5], 255]);
+            }
+        }
+        Format::R16G16B16A16 => {
+            for rgba in image.pixels.chunks_exact(8) {
+                pixels.extend_from_slice(&[rgba[1], rgba[3], rgba[5], rgba[7]]);
+            }
+        }
+        other => {
+            return Err(anyhow!("Unsupported glTF image format {:?}", other));
+        }
+    }
+
+    if pixels.len() != pixel_count * 4 {
+        return Err(anyhow!(
+            "glTF image has {} bytes of pixel data but expected {}",
+            pixels.len(),
+            pixel_count * 4
+        ));
+    }
+
+    Ok(Texture {
+        name,
+        width: image.width,
+        height: image.height,
+        format: TextureFormat::Rgba8Unorm,
+        pixels,
+        ..Default::default()
+    })
+}
+
+fn convert_sampler(sampler: &gltf::texture::Sampler) -> SamplerSettings {
+    let convert_wrapping_mode = |wrapping_mode: WrappingMode| match wrapping_mode {
+        WrappingMode::ClampToEdge => AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => AddressMode::Repeat,
+    };
+
+    let defaults = SamplerSettings::default();
+    let (min_filter, mipmap_filter) = match sampler.min_filter() {
+        Some(MinFilter::Nearest) | Some(MinFilter::NearestMipmapNearest) => {
+            (FilterMode::Nearest, FilterMode::Nearest)
+        }
+        Some(MinFilter::Linear) | Some(MinFilter::LinearMipmapNearest) => {
+            (FilterMode::Linear, FilterMode::Nearest)
+        }
+        Some(MinFilter::NearestMipmapLinear) => (FilterMode::Nearest, FilterMode::Linear),
+        Some(MinFilter::LinearMipmapLinear) | None => (defaults.min_filter, defaults.mipmap_filter),
+    };
+
+    SamplerSettings {
+        address_mode_u: convert_wrapping_mode(sampler.wrap_s()),
+        address_mode_v: convert_wrapping_mode(sampler.wrap_t()),
+        mag_filter: match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => FilterMode::Nearest,
+            Some(MagFilter::Linear) | None => defaults.mag_filter,
+        },
+        min_filter,
+        mipmap_filter,
+        anisotropy_clamp: defaults.anisotropy_clamp,
+    }
+}
@@ -0,0 +1,41 @@
+use glam::{Vec3, Vec4};
+
+use crate::asset::TextureHandle;
+
+// Metallic-roughness material parameters, matching the glTF 2.0 material model
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: Option<String>,
+    pub base_color_factor: Vec4,
+    pub base_color_texture: Option<TextureHandle>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub metallic_roughness_texture: Option<TextureHandle>, // Blue channel is metallic, green is roughness
+    pub normal_texture: Option<TextureHandle>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<TextureHandle>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: Vec3,
+    pub emissive_texture: Option<TextureHandle>,
+    pub double_sided: bool,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            name: None,
+            base_color_factor: Vec4::ONE,
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_factor: Vec3::ZERO,
+            emissive_texture: None,
+            double_sided: false,
+        }
+    }
+}
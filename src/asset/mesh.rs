@@ -0,0 +1,23 @@
+use glam::Vec3;
+
+use crate::{asset::bounds::Aabb, rendering::vertex::Vertex};
+
+#[derive(Debug, Default, Clone)]
+pub struct Mesh {
+    pub name: Option<String>,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>, // Triangle list, three indices per triangle
+    pub joint_indices: Vec<[u16; 4]>, // Per vertex skinning data, empty when the mesh is not skinned
+    pub joint_weights: Vec<[f32; 4]>,
+}
+
+impl Mesh {
+    // Bounds of the vertex positions in the mesh's local space, None for a mesh without vertices
+    pub fn calculate_bounds(&self) -> Option<Aabb> {
+        Aabb::from_points(
+            self.vertices
+                .iter()
+                .map(|vertex| Vec3::from_array(vertex.position)),
+        )
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use log::{error, warn};
+
+use crate::asset::{
+    animation::AnimationClip, atlas::TextureAtlas, font::Font, material::Material, mesh::Mesh,
+    shader::Shader, sprite_animation::SpriteAnimationClip, texture::Texture, tileset::Tileset,
+    watcher::FileWatcher,
+};
+
+pub mod animation;
+pub mod atlas;
+pub mod bounds;
+pub mod font;
+pub mod gltf_importer;
+pub mod material;
+pub mod mesh;
+pub mod obj_importer;
+pub mod shader;
+pub mod sprite_animation;
+pub mod texture;
+pub mod tiled_importer;
+pub mod tileset;
+pub mod watcher;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MeshHandle(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MaterialHandle(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureHandle(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AnimationHandle(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AtlasHandle(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpriteAnimationHandle(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TilesetHandle(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontHandle(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderHandle(pub u32);
+
+// Owns the CPU side copy of every loaded asset.
+// Components refer to assets by handle so that many entities can share one asset.
+#[derive(Debug, Default)]
+pub struct AssetService {
+    next_asset_id: u32,
+    pub meshes: HashMap<MeshHandle, Mesh>,
+    pub materials: HashMap<MaterialHandle, Material>,
+    pub textures: HashMap<TextureHandle, Texture>,
+    pub animations: HashMap<AnimationHandle, AnimationClip>,
+    pub atlases: HashMap<AtlasHandle, TextureAtlas>,
+    pub sprite_animations: HashMap<SpriteAnimationHandle, SpriteAnimationClip>,
+    pub tilesets: HashMap<TilesetHandle, Tileset>,
+    pub fonts: HashMap<FontHandle, Font>,
+    pub shaders: HashMap<ShaderHandle, Shader>,
+    shader_watcher: Option<FileWatcher>, // Only set once shader hot reloading is enabled
+}
+
+impl AssetService {
+    pub fn new() -> Self {
+        Self {
+            next_asset_id: 0,
+            meshes: HashMap::new(),
+            materials: HashMap::new(),
+            textures: HashMap::new(),
+            animations: HashMap::new(),
+            atlases: HashMap::new(),
+            sprite_animations: HashMap::new(),
+            tilesets: HashMap::new(),
+            fonts: HashMap::new(),
+            shaders: HashMap::new(),
+            shader_watcher: None,
+        }
+    }
+
+    // Handles are unique across every asset type which makes them easier to tell apart in logs
+    fn next_id(&mut self) -> u32 {
+        self.next_asset_id += 1;
+
+        self.next_asset_id
+    }
+
+    pub fn add_mesh(&mut self, mesh: Mesh) -> MeshHandle {
+        let handle = MeshHandle(self.next_id());
+        self.meshes.insert(handle, mesh);
+
+        handle
+    }
+
+    pub fn add_material(&mut self, material: Material) -> MaterialHandle {
+        let handle = MaterialHandle(self.next_id());
+        self.materials.insert(handle, material);
+
+        handle
+    }
+
+    pub fn add_texture(&mut self, texture: Texture) -> TextureHandle {
+        let handle = TextureHandle(self.next_id());
+        self.textures.insert(handle, texture);
+
+        handle
+    }
+
+    pub fn add_animation(&mut self, animation: AnimationClip) -> AnimationHandle {
+        let handle = AnimationHandle(self.next_id());
+        self.animations.insert(handle, animation);
+
+        handle
+    }
+
+    pub fn add_atlas(&mut self, atlas: TextureAtlas) -> AtlasHandle {
+        let handle = AtlasHandle(self.next_id());
+        self.atlases.insert(handle, atlas);
+
+        handle
+    }
+
+    pub fn add_sprite_animation(
+        &mut self,
+        sprite_animation: SpriteAnimationClip,
+    ) -> SpriteAnimationHandle {
+        let handle = SpriteAnimationHandle(self.next_id());
+        self.sprite_animations.insert(handle, sprite_animation);
+
+        handle
+    }
+
+    pub fn add_tileset(&mut self, tileset: Tileset) -> TilesetHandle {
+        let handle = TilesetHandle(self.next_id());
+        self.tilesets.insert(handle, tileset);
+
+        handle
+    }
+
+    pub fn add_font(&mut self, font: Font) -> FontHandle {
+        let handle = FontHandle(self.next_id());
+        self.fonts.insert(handle, font);
+
+        handle
+    }
+
+    pub fn add_shader(&mut self, shader: Shader) -> ShaderHandle {
+        if let Some(shader_watcher) = &mut self.shader_watcher
+            && let Some(path) = &shader.path
+            && let Err(e) = shader_watcher.watch(path)
+        {
+            warn!("Shader {:?} will not be hot reloaded: {:?}", path, e);
+        }
+
+        let handle = ShaderHandle(self.next_id());
+        self.shaders.insert(handle, shader);
+
+        handle
+    }
+
+    // Watches the files of every shader loaded from disk, including ones added later,
+    // so reload_changed_shaders picks up edits while the engine is running
+    pub fn watch_shaders(&mut self) -> anyhow::Result<()> {
+        let mut shader_watcher = FileWatcher::new()?;
+        for path in self
+            .shaders
+            .values()
+            .filter_map(|shader| shader.path.as_ref())
+        {
+            if let Err(e) = shader_watcher.watch(path) {
+                warn!("Shader {:?} will not be hot reloaded: {:?}", path, e);
+            }
+        }
+        self.shader_watcher = Some(shader_watcher);
+
+        Ok(())
+    }
+
+    // Reads the shaders whose files changed since the last call again, returning the ones whose source changed.
+    // A shader that fails to read keeps its previous source.
+    pub fn reload_changed_shaders(&mut self) -> Vec<ShaderHandle> {
+        let Some(shader_watcher) = &self.shader_watcher else {
+            return Vec::new();
+        };
+        let changed_files = shader_watcher.changed_files();
+        if changed_files.is_empty() {
+            return Vec::new();
+        }
+
+        let mut reloaded = Vec::new();
+        for (handle, shader) in &mut self.shaders {
+            let is_changed = shader
+                .path
+                .as_ref()
+                .and_then(|path| path.canonicalize().ok())
+                .is_some_and(|path| changed_files.contains(&path));
+            if !is_changed {
+                continue;
+            }
+
+            match shader.reload() {
+                Ok(true) => reloaded.push(*handle),
+                Ok(false) => {}
+                Err(e) => error!("Failed to reload shader: {:?}", e),
+            }
+        }
+
+        reloaded
+    }
+}
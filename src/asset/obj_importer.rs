@@ -0,0 +1,683 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    str::SplitWhitespace,
+};
+
+use glam::{Vec3, Vec4};
+use log::{debug, info, warn};
+
+use crate::{
+    asset::{
+        AssetService, MaterialHandle, MeshHandle, TextureHandle, material::Material, mesh::Mesh,
+        texture::Texture,
+    },
+    ecs::{
+        component::{
+            material::MaterialComponent, mesh::MeshComponent, transform::TransformComponent,
+        },
+        entity::scene::Scene,
+    },
+    rendering::vertex::Vertex,
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "Failed to read {:?}: {}", path, source),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{:?} line {}: {}", path, line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ObjImport {
+    pub root_entity: u32,
+    pub meshes: Vec<MeshHandle>, // One per group and material combination
+}
+
+// Position, texture coordinate and normal indices of a face corner, already zero based
+type VertexKey = (usize, Option<usize>, Option<usize>);
+
+// Collects the triangles of one group and material combination into an indexed mesh
+#[derive(Default)]
+struct MeshBuilder {
+    name: String,
+    material: Option<String>,
+    vertices: Vec<Vertex>,
+    has_normal: Vec<bool>,
+    indices: Vec<u32>,
+    vertex_lookup: HashMap<VertexKey, u32>,
+}
+
+impl MeshBuilder {
+    fn build(mut self) -> Mesh {
+        // Vertices without normals get the average normal of the triangles that use them
+        if self.has_normal.iter().any(|has_normal| !has_normal) {
+            let mut accumulated = vec![Vec3::ZERO; self.vertices.len()];
+            for triangle in self.indices.chunks_exact(3) {
+                let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| i as usize);
+                let face_normal = (Vec3::from(self.vertices[b].position)
+                    - Vec3::from(self.vertices[a].position))
+                .cross(
+                    Vec3::from(self.vertices[c].position) - Vec3::from(self.vertices[a].position),
+                );
+                for i in [a, b, c] {
+                    accumulated[i] += face_normal;
+                }
+            }
+
+            for (i, vertex) in self.vertices.iter_mut().enumerate() {
+                if !self.has_normal[i] {
+                    vertex.normal = accumulated[i].normalize_or(Vec3::Z).to_array();
+                }
+            }
+        }
+
+        Mesh {
+            name: Some(self.name),
+            vertices: self.vertices,
+            indices: self.indices,
+            ..Default::default()
+        }
+    }
+}
+
+// Parsed contents of a .mtl file, texture maps are kept as paths until they are loaded
+#[derive(Default)]
+struct ObjMaterial {
+    material: Material,
+    base_color_map: Option<PathBuf>,
+    normal_map: Option<PathBuf>,
+    emissive_map: Option<PathBuf>,
+}
+
+// Loads a Wavefront .obj file and the .mtl files it references,
+// spawning a root entity with one child per group and material combination.
+pub fn import_obj(
+    path: impl AsRef<Path>,
+    scene: &mut Scene,
+    asset_service: &mut AssetService,
+) -> Result<ObjImport, ObjError> {
+    let path = path.as_ref();
+    info!("Importing OBJ file: {:?}", path);
+
+    let source = std::fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut colors: Vec<[f32; 3]> = Vec::new();
+    let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut obj_materials: HashMap<String, ObjMaterial> = HashMap::new();
+
+    let mut builders: Vec<MeshBuilder> = Vec::new();
+    let mut builder_lookup: HashMap<(String, Option<String>), usize> = HashMap::new();
+    let mut current_group = String::from("default");
+    let mut current_material: Option<String> = None;
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let parse_error = |message: String| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: line_number,
+            message,
+        };
+
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+
+        match keyword {
+            "v" => {
+                let values = parse_floats(&mut tokens).map_err(&parse_error)?;
+                match values.as_slice() {
+                    // Some exporters append a vertex color after the position
+                    [x, y, z, r, g, b, ..] => {
+                        positions.push([*x, *y, *z]);
+                        colors.push([*r, *g, *b]);
+                    }
+                    [x, y, z, ..] => {
+                        positions.push([*x, *y, *z]);
+                        colors.push([1.0, 1.0, 1.0]);
+                    }
+                    _ => return Err(parse_error("Vertex needs 3 coordinates".to_string())),
+                }
+            }
+            "vt" => {
+                let values = parse_floats(&mut tokens).map_err(&parse_error)?;
+                match values.as_slice() {
+                    // OBJ puts v = 0 at the bottom of the image, wgpu puts it at the top
+                    [u, v, ..] => tex_coords.push([*u, 1.0 - *v]),
+                    [u] => tex_coords.push([*u, 1.0]),
+                    _ => {
+                        return Err(parse_error(
+                            "Texture coordinate needs at least 1 value".to_string(),
+                        ));
+                    }
+                }
+            }
+            "vn" => {
+                let values = parse_floats(&mut tokens).map_err(&parse_error)?;
+                match values.as_slice() {
+                    [x, y, z, ..] => normals.push([*x, *y, *z]),
+                    _ => return Err(parse_error("Normal needs 3 coordinates".to_string())),
+                }
+            }
+            "f" => {
+                let builder_key = (current_group.clone(), current_material.clone());
+                let builder_index = *builder_lookup.entry(builder_key).or_insert_with(|| {
+                    builders.push(MeshBuilder {
+                        name: match &current_material {
+                            Some(material) => format!("{} ({})", current_group, material),
+                            None => current_group.clone(),
+                        },
+                        material: current_material.clone(),
+                        ..Default::default()
+                    });
+                    builders.len() - 1
+                });
+                let builder = &mut builders[builder_index];
+
+                let mut face_indices: Vec<u32> = Vec::new();
+                for corner in tokens {
+                    let key =
+                        parse_face_corner(corner, positions.len(), tex_coords.len(), normals.len())
+                            .map_err(&parse_error)?;
+
+                    let index = *builder.vertex_lookup.entry(key).or_insert_with(|| {
+                        let (position_index, tex_coord_index, normal_index) = key;
+                        builder.vertices.push(Vertex {
+                            position: positions[position_index],
+                            color: colors[position_index],
+                            normal: normal_index.map_or([0.0, 0.0, 1.0], |i| normals[i]),
+                            tex_coords: tex_coord_index.map_or([0.0, 0.0], |i| tex_coords[i]),
+                        });
+                        builder.has_normal.push(normal_index.is_some());
+                        builder.vertices.len() as u32 - 1
+                    });
+                    face_indices.push(index);
+                }
+
+                if face_indices.len() < 3 {
+                    return Err(parse_error(format!(
+                        "Face needs at least 3 vertices but has {}",
+                        face_indices.len()
+                    )));
+                }
+
+                // Fan triangulation, which is correct for the convex polygons OBJ exporters write
+                for i in 1..face_indices.len() - 1 {
+                    builder.indices.extend_from_slice(&[
+                        face_indices[0],
+                        face_indices[i],
+                        face_indices[i + 1],
+                    ]);
+                }
+            }
+            "g" | "o" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                current_group = if name.is_empty() {
+                    String::from("default")
+                } else {
+                    name
+                };
+            }
+            "usemtl" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                if !obj_materials.contains_key(&name) {
+                    warn!(
+                        "{:?} line {}: unknown material {:?}",
+                        path, line_number, name
+                    );
+                }
+                current_material = Some(name);
+            }
+            "mtllib" => {
+                for mtl_file in tokens {
+                    let mtl_path = directory.join(mtl_file);
+                    match std::fs::read_to_string(&mtl_path) {
+                        Ok(mtl_source) => {
+                            obj_materials.extend(parse_mtl(&mtl_path, &mtl_source)?);
+                        }
+                        // A missing material library still leaves usable geometry
+                        Err(e) => warn!("Failed to read material library {:?}: {}", mtl_path, e),
+                    }
+                }
+            }
+            // Smoothing groups, lines and points do not affect triangle meshes
+            "s" | "l" | "p" => {}
+            _ if keyword.starts_with('#') => {}
+            _ => debug!(
+                "{:?} line {}: ignoring unsupported statement {:?}",
+                path, line_number, keyword
+            ),
+        }
+    }
+
+    // Texture files are shared between materials, so each one is only loaded once
+    let mut texture_handles: HashMap<PathBuf, Option<TextureHandle>> = HashMap::new();
+    let mut load_texture = |texture_path: &Option<PathBuf>, asset_service: &mut AssetService| {
+        let texture_path = texture_path.as_ref()?;
+        *texture_handles
+            .entry(texture_path.clone())
+            .or_insert_with(|| match Texture::load(texture_path) {
+                Ok(texture) => Some(asset_service.add_texture(texture)),
+                Err(e) => {
+                    warn!("{:?}", e);
+                    None
+                }
+            })
+    };
+
+    let mut material_handles: HashMap<Option<String>, MaterialHandle> = HashMap::new();
+    let root_entity = scene.create_entity();
+    scene
+        .transform_components
+        .insert(root_entity, TransformComponent::default());
+
+    let mut import = ObjImport {
+        root_entity,
+        meshes: Vec::new(),
+    };
+    for builder in builders {
+        let material_name = builder.material.clone();
+        let material_handle = match material_handles.get(&material_name) {
+            Some(handle) => *handle,
+            None => {
+                let material = match material_name
+                    .as_ref()
+                    .and_then(|name| obj_materials.get(name))
+                {
+                    Some(obj_material) => Material {
+                        base_color_texture: load_texture(
+                            &obj_material.base_color_map,
+                            asset_service,
+                        ),
+                        normal_texture: load_texture(&obj_material.normal_map, asset_service)
+                            .inspect(|handle| {
+                                // Normal maps hold directions rather than colors
+                                if let Some(texture) = asset_service.textures.get_mut(handle) {
+                                    texture.format = texture.format.remove_srgb_suffix();
+                                }
+                            }),
+                        emissive_texture: load_texture(&obj_material.emissive_map, asset_service),
+                        ..obj_material.material.clone()
+                    },
+                    None => Material::default(),
+                };
+                let handle = asset_service.add_material(material);
+                material_handles.insert(material_name, handle);
+                handle
+            }
+        };
+
+        let mesh_handle = asset_service.add_mesh(builder.build());
+        import.meshes.push(mesh_handle);
+
+        let mesh_entity = scene.create_entity();
+        scene
+            .transform_components
+            .insert(mesh_entity, TransformComponent::default());
+        scene
+            .mesh_components
+            .insert(mesh_entity, MeshComponent { mesh: mesh_handle });
+        scene.material_components.insert(
+            mesh_entity,
+            MaterialComponent {
+                material: material_handle,
+            },
+        );
+        scene.set_parent(mesh_entity, root_entity);
+    }
+
+    debug!(
+        "Imported OBJ file {:?} with {} meshes",
+        path,
+        import.meshes.len()
+    );
+
+    Ok(import)
+}
+
+fn parse_mtl(path: &Path, source: &str) -> Result<HashMap<String, ObjMaterial>, ObjError> {
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut obj_materials: HashMap<String, ObjMaterial> = HashMap::new();
+    let mut current: Option<(String, ObjMaterial)> = None;
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let parse_error = |message: String| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: line_number,
+            message,
+        };
+
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+
+        if keyword == "newmtl" {
+            if let Some((name, obj_material)) = current.take() {
+                obj_materials.insert(name, obj_material);
+            }
+
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            let obj_material = ObjMaterial {
+                material: Material {
+                    name: Some(name.clone()),
+                    // Classic MTL materials are not metallic
+                    metallic_factor: 0.0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            current = Some((name, obj_material));
+            continue;
+        }
+
+        if keyword.starts_with('#') {
+            continue;
+        }
+
+        let Some((_, obj_material)) = current.as_mut() else {
+            return Err(parse_error(format!(
+                "Statement {:?} appears before any newmtl",
+                keyword
+            )));
+        };
+        let material = &mut obj_material.material;
+
+        match keyword {
+            "Kd" => {
+                let [r, g, b] = parse_vec3(&mut tokens).map_err(&parse_error)?;
+                material.base_color_factor = Vec4::new(r, g, b, material.base_color_factor.w);
+            }
+            "d" => material.base_color_factor.w = parse_float(&mut tokens).map_err(&parse_error)?,
+            // Tr is the inverse of d
+            "Tr" => {
+                material.base_color_factor.w =
+                    1.0 - parse_float(&mut tokens).map_err(&parse_error)?
+            }
+            "Ke" => {
+                material.emissive_factor =
+                    Vec3::from_array(parse_vec3(&mut tokens).map_err(&parse_error)?)
+            }
+            // Approximate roughness from the Blinn-Phong specular exponent
+            "Ns" => {
+                let exponent = parse_float(&mut tokens).map_err(&parse_error)?;
+                material.roughness_factor = (2.0 / (exponent.max(0.0) + 2.0)).sqrt();
+            }
+            // PBR extension to the MTL format
+            "Pr" => material.roughness_factor = parse_float(&mut tokens).map_err(&parse_error)?,
+            "Pm" => material.metallic_factor = parse_float(&mut tokens).map_err(&parse_error)?,
+            "map_Kd" => {
+                obj_material.base_color_map =
+                    Some(parse_map_path(directory, tokens).map_err(&parse_error)?)
+            }
+            "map_Bump" | "map_bump" | "bump" | "norm" => {
+                obj_material.normal_map =
+                    Some(parse_map_path(directory, tokens).map_err(&parse_error)?)
+            }
+            "map_Ke" => {
+                obj_material.emissive_map =
+                    Some(parse_map_path(directory, tokens).map_err(&parse_error)?)
+            }
+            _ => debug!(
+                "{:?} line {}: ignoring unsupported statement {:?}",
+                path, line_number, keyword
+            ),
+        }
+    }
+
+    if let Some((name, obj_material)) = current {
+        obj_materials.insert(name, obj_material);
+    }
+
+    Ok(obj_materials)
+}
+
+fn parse_floats(tokens: &mut SplitWhitespace) -> Result<Vec<f32>, String> {
+    tokens
+        .map(|token| {
+            token
+                .parse::<f32>()
+                .map_err(|_| format!("Expected a number but found {:?}", token))
+        })
+        .collect()
+}
+
+fn parse_float(tokens: &mut SplitWhitespace) -> Result<f32, String> {
+    match parse_floats(tokens)?.as_slice() {
+        [value, ..] => Ok(*value),
+        [] => Err("Expected a number".to_string()),
+    }
+}
+
+fn parse_vec3(tokens: &mut SplitWhitespace) -> Result<[f32; 3], String> {
+    match parse_floats(tokens)?.as_slice() {
+        [x, y, z, ..] => Ok([*x, *y, *z]),
+        // A single value applies to every channel
+        [value] => Ok([*value; 3]),
+        _ => Err("Expected 3 numbers".to_string()),
+    }
+}
+
+// Texture maps may be preceded by options such as -bm 1.0, the file name is the last token
+fn parse_map_path(directory: &Path, tokens: SplitWhitespace) -> Result<PathBuf, String> {
+    tokens
+        .last()
+        .map(|file_name| directory.join(file_name))
+        .ok_or_else(|| "Texture map is missing a file name".to_string())
+}
+
+// Parses v, v/vt, v//vn or v/vt/vn where indices start at 1 and negative indices count from the end
+fn parse_face_corner(
+    corner: &str,
+    position_count: usize,
+    tex_coord_count: usize,
+    normal_count: usize,
+) -> Result<VertexKey, String> {
+    let mut parts = corner.split('/');
+    let resolve = |part: Option<&str>, count: usize, what: &str| -> Result<Option<usize>, String> {
+        let Some(part) = part.filter(|p| !p.is_empty()) else {
+            return Ok(None);
+        };
+        let index: i64 = part
+            .parse()
+            .map_err(|_| format!("Invalid {} index {:?}", what, part))?;
+        let resolved = match index {
+            i if i > 0 => i - 1,
+            i if i < 0 => count as i64 + i,
+            _ => return Err(format!("{} index cannot be 0", what)),
+        };
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(format!(
+                "{} index {} is out of range, only {} are defined",
+                what, index, count
+            ));
+        }
+
+        Ok(Some(resolved as usize))
+    };
+
+    let position_index = resolve(parts.next(), position_count, "Position")?
+        .ok_or_else(|| format!("Face vertex {:?} is missing a position index", corner))?;
+    let tex_coord_index = resolve(parts.next(), tex_coord_count, "Texture coordinate")?;
+    let normal_index = resolve(parts.next(), normal_count, "Normal")?;
+
+    Ok((position_index, tex_coord_index, normal_index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Imports OBJ source through a file in the temp directory, like a model on disk
+    fn import_source(name: &str, source: &str) -> (Result<ObjImport, ObjError>, AssetService) {
+        let path =
+            std::env::temp_dir().join(format!("obj_importer_{}_{}.obj", name, std::process::id()));
+        std::fs::write(&path, source).unwrap();
+
+        let mut scene = Scene::new();
+        let mut asset_service = AssetService::new();
+        let result = import_obj(&path, &mut scene, &mut asset_service);
+        std::fs::remove_file(&path).unwrap();
+
+        (result, asset_service)
+    }
+
+    #[test]
+    fn face_corners_resolve_positive_and_negative_indices() {
+        assert_eq!(parse_face_corner("1", 3, 0, 0).unwrap(), (0, None, None));
+        assert_eq!(
+            parse_face_corner("3/2", 3, 2, 0).unwrap(),
+            (2, Some(1), None)
+        );
+        assert_eq!(
+            parse_face_corner("2//1", 3, 0, 1).unwrap(),
+            (1, None, Some(0))
+        );
+        assert_eq!(
+            parse_face_corner("1/1/1", 3, 2, 1).unwrap(),
+            (0, Some(0), Some(0))
+        );
+
+        // Negative indices count back from the last element defined so far
+        assert_eq!(parse_face_corner("-1", 3, 0, 0).unwrap(), (2, None, None));
+        assert_eq!(
+            parse_face_corner("-3/-1/-2", 3, 2, 2).unwrap(),
+            (0, Some(1), Some(0))
+        );
+    }
+
+    #[test]
+    fn invalid_face_corners_are_errors() {
+        assert!(parse_face_corner("0", 3, 0, 0).is_err());
+        assert!(parse_face_corner("4", 3, 0, 0).is_err());
+        assert!(parse_face_corner("-4", 3, 0, 0).is_err());
+        assert!(parse_face_corner("1/2", 3, 1, 0).is_err());
+        assert!(parse_face_corner("1//1", 3, 0, 0).is_err());
+        assert!(parse_face_corner("a", 3, 0, 0).is_err());
+        assert!(parse_face_corner("/1/1", 3, 1, 1).is_err());
+    }
+
+    #[test]
+    fn out_of_range_face_index_is_a_parse_error_on_its_line() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1 2 4\n";
+        let (result, _) = import_source("out_of_range", source);
+
+        match result {
+            Err(ObjError::Parse { line, message, .. }) => {
+                assert_eq!(line, 5);
+                assert!(message.contains("out of range"), "{}", message);
+            }
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn polygons_are_fan_triangulated() {
+        let source = "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0.5 1.5 0
+vn 0 0 1
+f 1//1 2//1 3//1 4//1
+f -5//-1 -4//-1 -3//-1 -2//-1 -1//-1
+";
+        let (result, asset_service) = import_source("fan", source);
+        let import = result.unwrap();
+
+        // A quad becomes two triangles and a pentagon three, sharing the first corner
+        assert_eq!(import.meshes.len(), 1);
+        let mesh = &asset_service.meshes[&import.meshes[0]];
+        assert_eq!(mesh.vertices.len(), 5);
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3, 0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn mtl_statements_fill_in_the_material() {
+        let path = Path::new("models/crate.mtl");
+        let source = "\
+# Exported materials
+newmtl Wood
+Kd 0.5 0.25 0.125
+d 0.5
+Ns 0
+map_Kd -bm 1.0 wood.png
+
+newmtl Glass Pane
+Tr 0.75
+Pm 1
+Pr 0.25
+map_Bump normal.png
+";
+        let obj_materials = parse_mtl(path, source).unwrap();
+        assert_eq!(obj_materials.len(), 2);
+
+        let wood = &obj_materials["Wood"];
+        assert_eq!(wood.material.name.as_deref(), Some("Wood"));
+        assert_eq!(
+            wood.material.base_color_factor,
+            Vec4::new(0.5, 0.25, 0.125, 0.5)
+        );
+        assert_eq!(wood.material.metallic_factor, 0.0);
+        assert_eq!(wood.material.roughness_factor, 1.0);
+        assert_eq!(wood.base_color_map, Some(PathBuf::from("models/wood.png")));
+
+        let glass = &obj_materials["Glass Pane"];
+        assert_eq!(glass.material.base_color_factor.w, 0.25);
+        assert_eq!(glass.material.metallic_factor, 1.0);
+        assert_eq!(glass.material.roughness_factor, 0.25);
+        assert_eq!(glass.normal_map, Some(PathBuf::from("models/normal.png")));
+    }
+
+    #[test]
+    fn mtl_errors_report_their_line() {
+        let path = Path::new("crate.mtl");
+
+        match parse_mtl(path, "Kd 1 1 1\n") {
+            Err(ObjError::Parse { line: 1, .. }) => {}
+            other => panic!("Expected a parse error on line 1, got {:?}", other.err()),
+        }
+        match parse_mtl(path, "newmtl Wood\n\nKd 1 x 1\n") {
+            Err(ObjError::Parse { line: 3, .. }) => {}
+            other => panic!("Expected a parse error on line 3, got {:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use log::info;
+
+// WGSL source the renderer compiles into pipelines
+#[derive(Debug, Clone)]
+pub struct Shader {
+    pub name: Option<String>,
+    pub path: Option<PathBuf>, // File the source was read from, only shaders loaded from disk are reloaded
+    pub source: String,
+    pub revision: u32, // Increases every time the source changes so the renderer knows to recompile
+}
+
+impl Shader {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        info!("Loading shader: {:?}", path);
+
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read shader {:?}", path))?;
+
+        Ok(Self {
+            name: path.file_name().map(|n| n.to_string_lossy().into_owned()),
+            path: Some(path.to_path_buf()),
+            source,
+            revision: 0,
+        })
+    }
+
+    pub fn from_source(name: impl Into<String>, source: impl Into<String>) -> Self {
+        Self {
+            name: Some(name.into()),
+            path: None,
+            source: source.into(),
+            revision: 0,
+        }
+    }
+
+    // Reads the file again, returns false when the source is unchanged or the shader has no file
+    pub fn reload(&mut self) -> anyhow::Result<bool> {
+        let Some(path) = &self.path else {
+            return Ok(false);
+        };
+
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read shader {:?}", path))?;
+        if source == self.source {
+            return Ok(false);
+        }
+
+        info!("Reloaded shader: {:?}", path);
+        self.source = source;
+        self.revision += 1;
+
+        Ok(true)
+    }
+}
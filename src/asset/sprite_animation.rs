@@ -0,0 +1,62 @@
+use crate::asset::AtlasHandle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopMode {
+    Once,     // Stops on the last frame
+    Loop,     // Starts over from the first frame
+    PingPong, // Plays backwards after reaching the last frame and forwards again after the first
+}
+
+#[derive(Debug, Clone)]
+pub struct SpriteAnimationFrame {
+    pub region: usize,       // Index into the regions of the clip's atlas
+    pub duration: f32,       // Seconds
+    pub events: Vec<String>, // Fired when the frame is entered
+}
+
+// Frame by frame animation that swaps the atlas region a sprite shows
+#[derive(Debug, Clone)]
+pub struct SpriteAnimationClip {
+    pub name: Option<String>,
+    pub atlas: AtlasHandle,
+    pub frames: Vec<SpriteAnimationFrame>,
+    pub loop_mode: LoopMode,
+}
+
+impl SpriteAnimationClip {
+    // Every frame lasts equally long
+    pub fn from_regions(
+        atlas: AtlasHandle,
+        regions: impl IntoIterator<Item = usize>,
+        frame_duration: f32,
+        loop_mode: LoopMode,
+    ) -> Self {
+        Self {
+            name: None,
+            atlas,
+            frames: regions
+                .into_iter()
+                .map(|region| SpriteAnimationFrame {
+                    region,
+                    duration: frame_duration,
+                    events: Vec::new(),
+                })
+                .collect(),
+            loop_mode,
+        }
+    }
+
+    // Adds an event to a frame, frames that don't exist are ignored
+    pub fn with_event(mut self, frame: usize, event: impl Into<String>) -> Self {
+        if let Some(frame) = self.frames.get_mut(frame) {
+            frame.events.push(event.into());
+        }
+
+        self
+    }
+
+    // Seconds to play through every frame once
+    pub fn duration(&self) -> f32 {
+        self.frames.iter().map(|frame| frame.duration).sum()
+    }
+}
@@ -0,0 +1,156 @@
+use std::path::Path;
+
+use anyhow::{Context, anyhow};
+use bytemuck::cast_slice;
+use half::f16;
+use wgpu::{AddressMode, FilterMode, TextureFormat};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplerSettings {
+    pub address_mode_u: AddressMode,
+    pub address_mode_v: AddressMode,
+    pub mag_filter: FilterMode, // Used when the texture is magnified on screen
+    pub min_filter: FilterMode, // Used when the texture is minified on screen
+    pub mipmap_filter: FilterMode, // Used to blend between mip levels
+    pub anisotropy_clamp: u16, // 1 disables anisotropic filtering, requires every filter to be linear
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::Repeat,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            anisotropy_clamp: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub name: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub format: TextureFormat, // sRGB formats hold colors, linear formats hold data such as normals
+    pub pixels: Vec<u8>,       // Tightly packed rows, or blocks for compressed formats
+    pub mips: Vec<Vec<u8>>, // Mip levels shipped with the file starting at level 1, generated on upload when empty
+    pub generate_mips: bool, // Without shipped or generated mip levels the texture only has its full size
+    pub sampler: SamplerSettings,
+}
+
+impl Default for Texture {
+    fn default() -> Self {
+        Self {
+            name: None,
+            width: 0,
+            height: 0,
+            format: TextureFormat::Rgba8UnormSrgb,
+            pixels: Vec::new(),
+            mips: Vec::new(),
+            generate_mips: true,
+            sampler: SamplerSettings::default(),
+        }
+    }
+}
+
+impl Texture {
+    // Decodes a PNG, JPEG, HDR or KTX2 image from disk, PNG and JPEG images are assumed to hold colors
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+
+        let is_ktx2 = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("ktx2"));
+        if is_ktx2 {
+            let bytes =
+                std::fs::read(path).with_context(|| format!("Failed to read image {:?}", path))?;
+            let mut texture = Self::from_ktx2(&bytes)
+                .with_context(|| format!("Failed to load KTX2 image {:?}", path))?;
+            texture.name = name;
+
+            return Ok(texture);
+        }
+
+        let image =
+            image::open(path).with_context(|| format!("Failed to load image {:?}", path))?;
+
+        // HDR images keep colors brighter than white as linear half floats, e.g. for environment maps
+        let is_hdr = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("hdr"));
+        if is_hdr {
+            let image = image.to_rgba32f();
+            let pixels: Vec<f16> = image
+                .pixels()
+                .flat_map(|pixel| pixel.0.map(f16::from_f32))
+                .collect();
+
+            return Ok(Self {
+                name,
+                width: image.width(),
+                height: image.height(),
+                format: TextureFormat::Rgba16Float,
+                pixels: cast_slice(&pixels).to_vec(),
+                ..Default::default()
+            });
+        }
+
+        let image = image.to_rgba8();
+
+        Ok(Self {
+            name,
+            width: image.width(),
+            height: image.height(),
+            pixels: image.into_raw(),
+            ..Default::default()
+        })
+    }
+
+    // Only containers without supercompression are supported, every mip level in the file is kept
+    pub fn from_ktx2(bytes: &[u8]) -> anyhow::Result<Self> {
+        let reader = ktx2::Reader::new(bytes)?;
+        let header = reader.header();
+
+        if let Some(scheme) = header.supercompression_scheme {
+            return Err(anyhow!("Unsupported KTX2 supercompression {:?}", scheme));
+        }
+        if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+            return Err(anyhow!(
+                "Only 2D KTX2 textures are supported, found depth {} with {} layers and {} faces",
+                header.pixel_depth,
+                header.layer_count,
+                header.face_count
+            ));
+        }
+
+        let format = match header.format {
+            Some(ktx2::Format::R8G8B8A8_UNORM) => TextureFormat::Rgba8Unorm,
+            Some(ktx2::Format::R8G8B8A8_SRGB) => TextureFormat::Rgba8UnormSrgb,
+            Some(ktx2::Format::BC1_RGBA_UNORM_BLOCK) => TextureFormat::Bc1RgbaUnorm,
+            Some(ktx2::Format::BC1_RGBA_SRGB_BLOCK) => TextureFormat::Bc1RgbaUnormSrgb,
+            Some(ktx2::Format::BC3_UNORM_BLOCK) => TextureFormat::Bc3RgbaUnorm,
+            Some(ktx2::Format::BC3_SRGB_BLOCK) => TextureFormat::Bc3RgbaUnormSrgb,
+            Some(ktx2::Format::BC7_UNORM_BLOCK) => TextureFormat::Bc7RgbaUnorm,
+            Some(ktx2::Format::BC7_SRGB_BLOCK) => TextureFormat::Bc7RgbaUnormSrgb,
+            other => return Err(anyhow!("Unsupported KTX2 format {:?}", other)),
+        };
+
+        let mut levels = reader.levels().map(|level| level.data.to_vec());
+        let pixels = levels
+            .next()
+            .ok_or_else(|| anyhow!("KTX2 image has no mip levels"))?;
+
+        Ok(Self {
+            name: None,
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            format,
+            pixels,
+            mips: levels.collect(),
+            ..Default::default()
+        })
+    }
+}
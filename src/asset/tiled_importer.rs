@@ -0,0 +1,1096 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, anyhow};
+use base64::Engine;
+use glam::{UVec2, Vec4};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use wgpu::FilterMode;
+
+use crate::{
+    asset::{
+        AssetService, TilesetHandle,
+        atlas::{AtlasPacker, AtlasRegion, TextureAtlas},
+        texture::{SamplerSettings, Texture},
+        tileset::{TileAnimationFrame, TileData, Tileset},
+    },
+    ecs::{
+        component::{
+            tilemap::{Tile, TilemapComponent},
+            transform::TransformComponent,
+        },
+        entity::scene::Scene,
+    },
+};
+
+// The top bits of a global tile id store how the tile is flipped
+const FLIPPED_HORIZONTALLY_FLAG: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY_FLAG: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY_FLAG: u32 = 0x2000_0000;
+const TILE_ID_MASK: u32 = 0x0FFF_FFFF; // Also drops the hexagonal rotation flag
+
+// Largest atlas built for tilesets made of separate images
+const MAX_COLLECTION_ATLAS_SIZE: u32 = 4096;
+
+#[derive(Debug, Default, Clone)]
+pub struct TiledImport {
+    pub entity: u32,
+    // In the order the map lists them. Tilesets whose tiles are another size than the map's cells are
+    // skipped with a warning and left out, the cells using them are dropped and stay empty.
+    pub tilesets: Vec<TilesetHandle>,
+}
+
+// Map contents shared by the XML and JSON formats
+struct TiledMap {
+    size: UVec2,
+    tile_size: UVec2,                   // Pixels
+    tilesets: Vec<(u32, TiledTileset)>, // First global tile id of every tileset
+    layers: Vec<TiledLayer>,
+}
+
+#[derive(Default)]
+struct TiledTileset {
+    name: Option<String>,
+    tile_size: UVec2,
+    spacing: u32,
+    margin: u32,
+    columns: u32,
+    tile_count: u32,
+    image: Option<TiledImage>, // None for tilesets made of separate images
+    tiles: Vec<TiledTile>,
+}
+
+struct TiledImage {
+    path: PathBuf,
+    size: Option<UVec2>, // Read from the file when the map leaves it out
+}
+
+#[derive(Default)]
+struct TiledTile {
+    id: u32,
+    image: Option<TiledImage>,
+    collides: bool,
+    animation: Vec<(u32, u32)>, // Tile id and duration in milliseconds
+}
+
+struct TiledLayer {
+    name: Option<String>,
+    gids: Vec<u32>,
+    visible: bool,
+    tint: Vec4,
+    z_order: Option<i32>,
+}
+
+// Loads a Tiled map saved as .tmx or .tmj and spawns it as a tilemap entity.
+// Tiled measures everything in pixels, which is converted to world units with pixels_per_unit.
+pub fn import_tiled(
+    path: impl AsRef<Path>,
+    scene: &mut Scene,
+    asset_service: &mut AssetService,
+    pixels_per_unit: f32,
+) -> anyhow::Result<TiledImport> {
+    let path = path.as_ref();
+    info!("Importing Tiled map: {:?}", path);
+
+    let source =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let map = if is_json(path) {
+        parse_json_map(&source, path)
+    } else {
+        parse_xml_map(&source, path)
+    }
+    .with_context(|| format!("Failed to import Tiled map {:?}", path))?;
+
+    // Slot of every tileset of the map in the component, None for tilesets that were skipped
+    let mut import = TiledImport::default();
+    let mut tileset_slots = Vec::with_capacity(map.tilesets.len());
+    for (_, tileset) in map.tilesets.iter() {
+        let handle = load_tileset(tileset, map.tile_size, asset_service)
+            .with_context(|| format!("Failed to load tileset {:?} of {:?}", tileset.name, path))?;
+        tileset_slots.push(handle.map(|handle| {
+            import.tilesets.push(handle);
+            import.tilesets.len() - 1
+        }));
+    }
+
+    let mut tilemap_component = TilemapComponent::new(
+        map.size,
+        map.tile_size.as_vec2() / pixels_per_unit,
+        import.tilesets.clone(),
+    );
+    let cell_count = (map.size.x * map.size.y) as usize;
+    for (index, layer) in map.layers.into_iter().enumerate() {
+        if layer.gids.len() != cell_count {
+            return Err(anyhow!(
+                "Layer {:?} of {:?} has {} tiles but the map has {} cells",
+                layer.name,
+                path,
+                layer.gids.len(),
+                cell_count
+            ));
+        }
+
+        let layer_index =
+            tilemap_component.add_layer(layer.name, layer.z_order.unwrap_or(index as i32));
+        let tilemap_layer = &mut tilemap_component.layers[layer_index];
+        tilemap_layer.visible = layer.visible;
+        tilemap_layer.tint = layer.tint;
+
+        for (cell, gid) in layer.gids.into_iter().enumerate() {
+            tilemap_layer.tiles[cell] = decode_gid(gid, &map.tilesets).and_then(|tile| {
+                Some(Tile {
+                    tileset: tileset_slots[tile.tileset]?,
+                    ..tile
+                })
+            });
+        }
+    }
+
+    import.entity = scene.create_entity();
+    scene
+        .tilemap_components
+        .insert(import.entity, tilemap_component);
+    scene
+        .transform_components
+        .insert(import.entity, TransformComponent::default());
+
+    info!(
+        "Imported Tiled map {:?} with {} tilesets",
+        path,
+        import.tilesets.len()
+    );
+
+    Ok(import)
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("tmj")
+            || extension.eq_ignore_ascii_case("tsj")
+            || extension.eq_ignore_ascii_case("json")
+    })
+}
+
+// Global tile ids count through every tileset of the map one after another, 0 is an empty cell
+fn decode_gid(gid: u32, tilesets: &[(u32, TiledTileset)]) -> Option<Tile> {
+    let id = gid & TILE_ID_MASK;
+    if id == 0 {
+        return None;
+    }
+
+    let (slot, first_gid) = tilesets
+        .iter()
+        .enumerate()
+        .filter(|(_, (first_gid, _))| *first_gid <= id)
+        .max_by_key(|(_, (first_gid, _))| *first_gid)
+        .map(|(slot, (first_gid, _))| (slot, *first_gid))?;
+
+    Some(Tile {
+        tileset: slot,
+        index: id - first_gid,
+        flip_x: gid & FLIPPED_HORIZONTALLY_FLAG != 0,
+        flip_y: gid & FLIPPED_VERTICALLY_FLAG != 0,
+        flip_diagonal: gid & FLIPPED_DIAGONALLY_FLAG != 0,
+    })
+}
+
+// Pixel art tiles are meant to be shown with hard edges, and filtering would blend in neighbouring tiles
+fn tileset_sampler_settings() -> SamplerSettings {
+    SamplerSettings {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        mag_filter: FilterMode::Nearest,
+        min_filter: FilterMode::Nearest,
+        mipmap_filter: FilterMode::Nearest,
+        anisotropy_clamp: 1,
+    }
+}
+
+// Tiles are drawn at the size of the map's cells, so tilesets with tiles of any other size are skipped
+// rather than stretched, cells using them stay empty
+fn load_tileset(
+    tileset: &TiledTileset,
+    map_tile_size: UVec2,
+    asset_service: &mut AssetService,
+) -> anyhow::Result<Option<TilesetHandle>> {
+    let atlas = match &tileset.image {
+        // A single image cut into a grid of tiles
+        Some(image) => {
+            if tileset.tile_size != map_tile_size {
+                warn!(
+                    "Skipping tileset {:?}, its tiles are {}x{} but the map's tiles are {}x{}",
+                    tileset.name,
+                    tileset.tile_size.x,
+                    tileset.tile_size.y,
+                    map_tile_size.x,
+                    map_tile_size.y
+                );
+                return Ok(None);
+            }
+
+            let mut texture = Texture::load(&image.path)?;
+            texture.generate_mips = false; // Smaller mip levels would blend neighbouring tiles
+            texture.sampler = tileset_sampler_settings();
+            let texture_size = UVec2::new(texture.width, texture.height);
+            if image.size.is_some_and(|size| size != texture_size) {
+                warn!(
+                    "Tileset image {:?} is {:?} but the map expects {:?}",
+                    image.path, texture_size, image.size
+                );
+            }
+
+            let columns = if tileset.columns > 0 {
+                tileset.columns
+            } else {
+                (texture_size.x.saturating_sub(2 * tileset.margin) + tileset.spacing)
+                    / (tileset.tile_size.x + tileset.spacing).max(1)
+            };
+            let rows = tileset.tile_count.div_ceil(columns.max(1));
+
+            let texture = asset_service.add_texture(texture);
+            let mut atlas = TextureAtlas::from_grid(
+                texture,
+                texture_size,
+                tileset.tile_size,
+                columns,
+                rows,
+                UVec2::splat(tileset.spacing),
+                UVec2::splat(tileset.margin),
+            );
+            atlas.regions.truncate(tileset.tile_count as usize);
+            atlas
+        }
+        // A collection without any images yet, tiles using it are drawn transparent
+        None if tileset.tiles.iter().all(|tile| tile.image.is_none()) => {
+            let texture = asset_service.add_texture(Texture {
+                name: Some("Empty Tileset".to_string()),
+                width: 1,
+                height: 1,
+                pixels: vec![0; 4],
+                generate_mips: false,
+                sampler: tileset_sampler_settings(),
+                ..Default::default()
+            });
+
+            TextureAtlas {
+                name: tileset.name.clone(),
+                texture,
+                size: UVec2::ONE,
+                regions: Vec::new(),
+            }
+        }
+        // Every tile has an image of its own, they are packed into an atlas in tile id order
+        None => {
+            let mut images: Vec<&TiledTile> = tileset
+                .tiles
+                .iter()
+                .filter(|tile| tile.image.is_some())
+                .collect();
+            images.sort_by_key(|tile| tile.id);
+
+            let mut packer = AtlasPacker::new(MAX_COLLECTION_ATLAS_SIZE, 1);
+            for tile in images.iter() {
+                if let Some(image) = &tile.image {
+                    let texture = Texture::load(&image.path)?;
+                    let size = UVec2::new(texture.width, texture.height);
+                    if size != map_tile_size {
+                        warn!(
+                            "Skipping tileset {:?}, tile {} uses {:?} which is {}x{} but the map's tiles are {}x{}",
+                            tileset.name,
+                            tile.id,
+                            image.path,
+                            size.x,
+                            size.y,
+                            map_tile_size.x,
+                            map_tile_size.y
+                        );
+                        return Ok(None);
+                    }
+
+                    packer.add_image(tile.id.to_string(), texture)?;
+                }
+            }
+            let packed = packer.pack(asset_service)?;
+            let mut atlas = asset_service.atlases.remove(&packed).unwrap();
+            if let Some(texture) = asset_service.textures.get_mut(&atlas.texture) {
+                texture.sampler = tileset_sampler_settings();
+            }
+
+            // Tile ids can have gaps, regions are laid out so their index is the tile id
+            let tile_count = images.last().map_or(0, |tile| tile.id as usize + 1);
+            let mut regions = vec![
+                AtlasRegion {
+                    name: None,
+                    position: UVec2::ZERO,
+                    size: UVec2::ZERO,
+                };
+                tile_count
+            ];
+            for (tile, region) in images.iter().zip(atlas.regions.drain(..)) {
+                regions[tile.id as usize] = region;
+            }
+            atlas.regions = regions;
+            atlas
+        }
+    };
+
+    let tile_count = atlas.regions.len();
+    let mut asset = Tileset::new(asset_service.add_atlas(atlas), tile_count);
+    asset.name = tileset.name.clone();
+    for tile in tileset.tiles.iter() {
+        let Some(tile_data) = asset.tiles.get_mut(tile.id as usize) else {
+            debug!("Skipping data of tile {} outside the tileset", tile.id);
+            continue;
+        };
+
+        *tile_data = TileData {
+            collides: tile.collides,
+            animation: tile
+                .animation
+                .iter()
+                .map(|(tile, duration)| TileAnimationFrame {
+                    tile: *tile,
+                    duration: *duration as f32 / 1000.0,
+                })
+                .collect(),
+        };
+    }
+
+    Ok(Some(asset_service.add_tileset(asset)))
+}
+
+// Only the features both renderers of the map share are supported, the rest are rejected up front
+fn check_map_layout(orientation: Option<&str>, infinite: bool) -> anyhow::Result<()> {
+    if let Some(orientation) = orientation
+        && orientation != "orthogonal"
+    {
+        return Err(anyhow!(
+            "{} maps are not supported, only orthogonal maps are",
+            orientation
+        ));
+    }
+    if infinite {
+        return Err(anyhow!("Infinite maps are not supported"));
+    }
+
+    Ok(())
+}
+
+// Paths inside a map or tileset are relative to the file they appear in
+fn resolve_path(file: &Path, relative: &str) -> PathBuf {
+    file.parent().unwrap_or(Path::new("")).join(relative)
+}
+
+fn load_external_tileset(path: &Path) -> anyhow::Result<TiledTileset> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read tileset {:?}", path))?;
+
+    if is_json(path) {
+        let tileset: JsonTileset = serde_json::from_str(&source)
+            .with_context(|| format!("Failed to parse tileset {:?}", path))?;
+        Ok(tileset.into_tileset(path))
+    } else {
+        let document = roxmltree::Document::parse(&source)
+            .with_context(|| format!("Failed to parse tileset {:?}", path))?;
+        parse_xml_tileset(document.root_element(), path)
+    }
+}
+
+// Tiled writes global tile ids as CSV, as XML elements or as little endian integers encoded with
+// base64 that can also be compressed
+fn decode_layer_data(
+    data: &str,
+    encoding: Option<&str>,
+    compression: Option<&str>,
+) -> anyhow::Result<Vec<u32>> {
+    match encoding {
+        Some("csv") => data
+            .split(',')
+            .map(str::trim)
+            .filter(|gid| !gid.is_empty())
+            .map(|gid| {
+                gid.parse::<u32>()
+                    .with_context(|| format!("Invalid tile id {}", gid))
+            })
+            .collect(),
+        Some("base64") => {
+            let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(data)
+                .context("Invalid base64 layer data")?;
+
+            let bytes = match compression {
+                None | Some("") => bytes,
+                Some("zlib") => {
+                    let mut decompressed = Vec::new();
+                    flate2::read::ZlibDecoder::new(bytes.as_slice())
+                        .read_to_end(&mut decompressed)
+                        .context("Invalid zlib layer data")?;
+                    decompressed
+                }
+                Some("gzip") => {
+                    let mut decompressed = Vec::new();
+                    flate2::read::GzDecoder::new(bytes.as_slice())
+                        .read_to_end(&mut decompressed)
+                        .context("Invalid gzip layer data")?;
+                    decompressed
+                }
+                Some(compression) => {
+                    return Err(anyhow!(
+                        "{} compressed layer data is not supported",
+                        compression
+                    ));
+                }
+            };
+
+            if bytes.len() % 4 != 0 {
+                return Err(anyhow!(
+                    "Layer data is {} bytes, which is not a whole number of tile ids",
+                    bytes.len()
+                ));
+            }
+
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]]))
+                .collect())
+        }
+        Some(encoding) => Err(anyhow!("Unknown layer encoding {}", encoding)),
+        None => Err(anyhow!("Layer data has no encoding")),
+    }
+}
+
+// Tiled colors are sRGB written as #RRGGBB or #AARRGGBB, tints are applied in linear space
+fn parse_color(color: &str) -> anyhow::Result<Vec4> {
+    let hex = color.trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16).with_context(|| format!("Invalid color {}", color))?;
+    let (alpha, rgb) = match hex.len() {
+        6 => (255, value),
+        8 => (value >> 24, value & 0x00FF_FFFF),
+        _ => return Err(anyhow!("Invalid color {}", color)),
+    };
+
+    let channel = |shift: u32| {
+        let srgb = ((rgb >> shift) & 0xFF) as f32 / 255.0;
+        if srgb <= 0.04045 {
+            srgb / 12.92
+        } else {
+            ((srgb + 0.055) / 1.055).powf(2.4)
+        }
+    };
+
+    Ok(Vec4::new(
+        channel(16),
+        channel(8),
+        channel(0),
+        alpha as f32 / 255.0,
+    ))
+}
+
+// Group layers pass their visibility, opacity and tint on to the layers inside them
+fn layer_tint(opacity: f32, tint_color: Option<&str>, parent_tint: Vec4) -> anyhow::Result<Vec4> {
+    let tint = match tint_color {
+        Some(color) => parse_color(color)?,
+        None => Vec4::ONE,
+    };
+
+    Ok(tint * Vec4::new(1.0, 1.0, 1.0, opacity) * parent_tint)
+}
+
+fn parse_xml_map(source: &str, path: &Path) -> anyhow::Result<TiledMap> {
+    let document = roxmltree::Document::parse(source)?;
+    let map = document.root_element();
+    if !map.has_tag_name("map") {
+        return Err(anyhow!(
+            "Expected a map element, found {:?}",
+            map.tag_name().name()
+        ));
+    }
+    check_map_layout(
+        map.attribute("orientation"),
+        map.attribute("infinite") == Some("1"),
+    )?;
+
+    let mut tilesets = Vec::new();
+    for node in map.children().filter(|node| node.has_tag_name("tileset")) {
+        let first_gid = xml_number(node, "firstgid")?.unwrap_or(1);
+        let tileset = match node.attribute("source") {
+            Some(source) => load_external_tileset(&resolve_path(path, source))?,
+            None => parse_xml_tileset(node, path)?,
+        };
+        tilesets.push((first_gid, tileset));
+    }
+
+    let mut layers = Vec::new();
+    parse_xml_layers(map, true, Vec4::ONE, &mut layers)?;
+
+    Ok(TiledMap {
+        size: UVec2::new(xml_required(map, "width")?, xml_required(map, "height")?),
+        tile_size: UVec2::new(
+            xml_required(map, "tilewidth")?,
+            xml_required(map, "tileheight")?,
+        ),
+        tilesets,
+        layers,
+    })
+}
+
+fn parse_xml_tileset(node: roxmltree::Node, path: &Path) -> anyhow::Result<TiledTileset> {
+    let mut tileset = TiledTileset {
+        name: node.attribute("name").map(str::to_string),
+        tile_size: UVec2::new(
+            xml_required(node, "tilewidth")?,
+            xml_required(node, "tileheight")?,
+        ),
+        spacing: xml_number(node, "spacing")?.unwrap_or(0),
+        margin: xml_number(node, "margin")?.unwrap_or(0),
+        columns: xml_number(node, "columns")?.unwrap_or(0),
+        tile_count: xml_number(node, "tilecount")?.unwrap_or(0),
+        image: None,
+        tiles: Vec::new(),
+    };
+
+    for child in node.children().filter(roxmltree::Node::is_element) {
+        match child.tag_name().name() {
+            "image" => tileset.image = Some(parse_xml_image(child, path)?),
+            "tile" => {
+                let mut tile = TiledTile {
+                    id: xml_required(child, "id")?,
+                    collides: xml_property(child, "collides") == Some("true"),
+                    ..Default::default()
+                };
+
+                for tile_child in child.children().filter(roxmltree::Node::is_element) {
+                    match tile_child.tag_name().name() {
+                        "image" => tile.image = Some(parse_xml_image(tile_child, path)?),
+                        "objectgroup" => tile.collides = true,
+                        "animation" => {
+                            for frame in tile_child.children().filter(|n| n.has_tag_name("frame")) {
+                                tile.animation.push((
+                                    xml_required(frame, "tileid")?,
+                                    xml_required(frame, "duration")?,
+                                ));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+
+                tileset.tiles.push(tile);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(tileset)
+}
+
+fn parse_xml_image(node: roxmltree::Node, path: &Path) -> anyhow::Result<TiledImage> {
+    let source = node
+        .attribute("source")
+        .ok_or_else(|| anyhow!("Image without a source, embedded images are not supported"))?;
+    let size = match (xml_number(node, "width")?, xml_number(node, "height")?) {
+        (Some(width), Some(height)) => Some(UVec2::new(width, height)),
+        _ => None,
+    };
+
+    Ok(TiledImage {
+        path: resolve_path(path, source),
+        size,
+    })
+}
+
+fn parse_xml_layers(
+    node: roxmltree::Node,
+    parent_visible: bool,
+    parent_tint: Vec4,
+    layers: &mut Vec<TiledLayer>,
+) -> anyhow::Result<()> {
+    for child in node.children().filter(roxmltree::Node::is_element) {
+        let kind = child.tag_name().name();
+        if !matches!(kind, "layer" | "group" | "objectgroup" | "imagelayer") {
+            continue;
+        }
+
+        let name = child.attribute("name").map(str::to_string);
+        let visible = parent_visible && child.attribute("visible") != Some("0");
+        let tint = layer_tint(
+            xml_number(child, "opacity")?.unwrap_or(1.0),
+            child.attribute("tintcolor"),
+            parent_tint,
+        )?;
+
+        match kind {
+            "layer" => {
+                let data = child
+                    .children()
+                    .find(|n| n.has_tag_name("data"))
+                    .ok_or_else(|| anyhow!("Layer {:?} has no data", name))?;
+                let gids = match data.attribute("encoding") {
+                    // Every tile is an element of its own when the data isn't encoded
+                    None => data
+                        .children()
+                        .filter(|n| n.has_tag_name("tile"))
+                        .map(|tile| Ok(xml_number(tile, "gid")?.unwrap_or(0)))
+                        .collect::<anyhow::Result<Vec<u32>>>()?,
+                    encoding => decode_layer_data(
+                        data.text().unwrap_or_default(),
+                        encoding,
+                        data.attribute("compression"),
+                    )?,
+                };
+
+                layers.push(TiledLayer {
+                    z_order: xml_property(child, "z_order")
+                        .map(|z_order| z_order.parse())
+                        .transpose()
+                        .with_context(|| format!("Layer {:?} has an invalid z_order", name))?,
+                    name,
+                    gids,
+                    visible,
+                    tint,
+                });
+            }
+            "group" => parse_xml_layers(child, visible, tint, layers)?,
+            _ => debug!(
+                "Skipping {} {:?}, only tile layers are imported",
+                kind, name
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+fn xml_number<T: std::str::FromStr>(
+    node: roxmltree::Node,
+    attribute: &str,
+) -> anyhow::Result<Option<T>> {
+    node.attribute(attribute)
+        .map(|value| {
+            value.trim().parse::<T>().map_err(|_| {
+                anyhow!(
+                    "Invalid {} {:?} on {}",
+                    attribute,
+                    value,
+                    node.tag_name().name()
+                )
+            })
+        })
+        .transpose()
+}
+
+fn xml_required<T: std::str::FromStr>(node: roxmltree::Node, attribute: &str) -> anyhow::Result<T> {
+    xml_number(node, attribute)?
+        .ok_or_else(|| anyhow!("{} is missing {}", node.tag_name().name(), attribute))
+}
+
+// Custom property values are usually an attribute, multi-line strings are stored as text instead
+fn xml_property<'a>(node: roxmltree::Node<'a, 'a>, name: &str) -> Option<&'a str> {
+    node.children()
+        .filter(|n| n.has_tag_name("properties"))
+        .flat_map(|properties| properties.children())
+        .find(|property| {
+            property.has_tag_name("property") && property.attribute("name") == Some(name)
+        })
+        .and_then(|property| property.attribute("value").or_else(|| property.text()))
+}
+
+fn parse_json_map(source: &str, path: &Path) -> anyhow::Result<TiledMap> {
+    let map: JsonMap = serde_json::from_str(source)?;
+    check_map_layout(map.orientation.as_deref(), map.infinite)?;
+
+    let mut tilesets = Vec::with_capacity(map.tilesets.len());
+    for tileset in map.tilesets {
+        let loaded = match &tileset.source {
+            Some(source) => load_external_tileset(&resolve_path(path, source))?,
+            None => tileset.tileset.into_tileset(path),
+        };
+        tilesets.push((tileset.firstgid, loaded));
+    }
+
+    let mut layers = Vec::new();
+    parse_json_layers(map.layers, true, Vec4::ONE, &mut layers)?;
+
+    Ok(TiledMap {
+        size: UVec2::new(map.width, map.height),
+        tile_size: UVec2::new(map.tilewidth, map.tileheight),
+        tilesets,
+        layers,
+    })
+}
+
+fn parse_json_layers(
+    json_layers: Vec<JsonLayer>,
+    parent_visible: bool,
+    parent_tint: Vec4,
+    layers: &mut Vec<TiledLayer>,
+) -> anyhow::Result<()> {
+    for layer in json_layers {
+        let visible = parent_visible && layer.visible;
+        let tint = layer_tint(layer.opacity, layer.tintcolor.as_deref(), parent_tint)?;
+
+        match layer.kind.as_str() {
+            "tilelayer" => {
+                let gids = match layer.data {
+                    Some(JsonLayerData::Gids(gids)) => gids,
+                    Some(JsonLayerData::Encoded(data)) => decode_layer_data(
+                        &data,
+                        layer.encoding.as_deref(),
+                        layer.compression.as_deref(),
+                    )?,
+                    None => return Err(anyhow!("Layer {:?} has no data", layer.name)),
+                };
+
+                let z_order = layer
+                    .properties
+                    .iter()
+                    .find(|property| property.name == "z_order")
+                    .map(|property| {
+                        property
+                            .value
+                            .as_i64()
+                            .map(|z_order| z_order as i32)
+                            .ok_or_else(|| anyhow!("Layer {:?} has an invalid z_order", layer.name))
+                    })
+                    .transpose()?;
+
+                layers.push(TiledLayer {
+                    name: layer.name,
+                    gids,
+                    visible,
+                    tint,
+                    z_order,
+                });
+            }
+            "group" => parse_json_layers(layer.layers, visible, tint, layers)?,
+            kind => debug!(
+                "Skipping {} {:?}, only tile layers are imported",
+                kind, layer.name
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct JsonMap {
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    orientation: Option<String>,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    tilesets: Vec<JsonTilesetReference>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonTilesetReference {
+    firstgid: u32,
+    source: Option<String>, // External tilesets only list their first id and file
+    #[serde(flatten)]
+    tileset: JsonTileset,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct JsonTileset {
+    name: Option<String>,
+    tilewidth: u32,
+    tileheight: u32,
+    spacing: u32,
+    margin: u32,
+    columns: u32,
+    tilecount: u32,
+    image: Option<String>,
+    imagewidth: Option<u32>,
+    imageheight: Option<u32>,
+    tiles: Vec<JsonTile>,
+}
+
+impl JsonTileset {
+    fn into_tileset(self, path: &Path) -> TiledTileset {
+        TiledTileset {
+            name: self.name,
+            tile_size: UVec2::new(self.tilewidth, self.tileheight),
+            spacing: self.spacing,
+            margin: self.margin,
+            columns: self.columns,
+            tile_count: self.tilecount,
+            image: self
+                .image
+                .map(|image| json_image(path, &image, self.imagewidth, self.imageheight)),
+            tiles: self
+                .tiles
+                .into_iter()
+                .map(|tile| TiledTile {
+                    id: tile.id,
+                    image: tile
+                        .image
+                        .map(|image| json_image(path, &image, tile.imagewidth, tile.imageheight)),
+                    collides: tile.objectgroup.is_some()
+                        || tile.properties.iter().any(|property| {
+                            property.name == "collides" && property.value.as_bool() == Some(true)
+                        }),
+                    animation: tile
+                        .animation
+                        .into_iter()
+                        .map(|frame| (frame.tileid, frame.duration))
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn json_image(path: &Path, image: &str, width: Option<u32>, height: Option<u32>) -> TiledImage {
+    TiledImage {
+        path: resolve_path(path, image),
+        size: width
+            .zip(height)
+            .map(|(width, height)| UVec2::new(width, height)),
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonTile {
+    id: u32,
+    image: Option<String>,
+    imagewidth: Option<u32>,
+    imageheight: Option<u32>,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+    objectgroup: Option<serde_json::Value>, // Collision shapes, only their presence is used
+    #[serde(default)]
+    animation: Vec<JsonFrame>,
+}
+
+#[derive(Deserialize)]
+struct JsonFrame {
+    tileid: u32,
+    duration: u32, // Milliseconds
+}
+
+#[derive(Deserialize)]
+struct JsonProperty {
+    name: String,
+    value: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    name: Option<String>,
+    #[serde(default = "json_default_visible")]
+    visible: bool,
+    #[serde(default = "json_default_opacity")]
+    opacity: f32,
+    tintcolor: Option<String>,
+    data: Option<JsonLayerData>,
+    encoding: Option<String>,
+    compression: Option<String>,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>, // Children of group layers
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonLayerData {
+    Gids(Vec<u32>),
+    Encoded(String), // base64, see decode_layer_data
+}
+
+fn json_default_visible() -> bool {
+    true
+}
+
+fn json_default_opacity() -> f32 {
+    1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{
+        Compression,
+        write::{GzEncoder, ZlibEncoder},
+    };
+
+    use super::*;
+
+    const GIDS: [u32; 6] = [0, 1, 2, 17, 0x8000_0003, 0x6000_0011];
+
+    fn encode(gids: &[u32], compress: impl FnOnce(&[u8]) -> Vec<u8>) -> String {
+        let bytes: Vec<u8> = gids.iter().flat_map(|gid| gid.to_le_bytes()).collect();
+        base64::engine::general_purpose::STANDARD.encode(compress(&bytes))
+    }
+
+    #[test]
+    fn csv_and_base64_layer_data_decode_to_the_same_gids() {
+        let csv = "0,1,2,\n17,2147483651,\n1610612753";
+        assert_eq!(decode_layer_data(csv, Some("csv"), None).unwrap(), GIDS);
+
+        let uncompressed = encode(&GIDS, <[u8]>::to_vec);
+        assert_eq!(
+            decode_layer_data(&uncompressed, Some("base64"), None).unwrap(),
+            GIDS
+        );
+
+        let zlib = encode(&GIDS, |bytes| {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(bytes).unwrap();
+            encoder.finish().unwrap()
+        });
+        assert_eq!(
+            decode_layer_data(&format!("\n   {}\n", zlib), Some("base64"), Some("zlib")).unwrap(),
+            GIDS
+        );
+
+        let gzip = encode(&GIDS, |bytes| {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(bytes).unwrap();
+            encoder.finish().unwrap()
+        });
+        assert_eq!(
+            decode_layer_data(&gzip, Some("base64"), Some("gzip")).unwrap(),
+            GIDS
+        );
+    }
+
+    #[test]
+    fn invalid_layer_data_is_an_error() {
+        assert!(decode_layer_data("1,x,3", Some("csv"), None).is_err());
+        assert!(decode_layer_data("AAAA", Some("base64"), Some("zstd")).is_err());
+        assert!(decode_layer_data("AAA=", Some("base64"), None).is_err()); // Two bytes
+        assert!(decode_layer_data("1,2", None, None).is_err());
+    }
+
+    #[test]
+    fn gids_find_their_tileset_and_flips() {
+        let tilesets = [(1, TiledTileset::default()), (17, TiledTileset::default())];
+
+        assert_eq!(decode_gid(0, &tilesets), None);
+        assert_eq!(decode_gid(FLIPPED_HORIZONTALLY_FLAG, &tilesets), None);
+        assert_eq!(decode_gid(1, &tilesets), Some(Tile::new(0, 0)));
+        assert_eq!(decode_gid(16, &tilesets), Some(Tile::new(0, 15)));
+        assert_eq!(decode_gid(17, &tilesets), Some(Tile::new(1, 0)));
+        assert_eq!(
+            decode_gid(
+                20 | FLIPPED_HORIZONTALLY_FLAG | FLIPPED_DIAGONALLY_FLAG,
+                &tilesets
+            ),
+            Some(Tile {
+                flip_x: true,
+                flip_diagonal: true,
+                ..Tile::new(1, 3)
+            })
+        );
+        assert_eq!(
+            decode_gid(2 | FLIPPED_VERTICALLY_FLAG, &tilesets),
+            Some(Tile {
+                flip_y: true,
+                ..Tile::new(0, 1)
+            })
+        );
+    }
+
+    #[test]
+    fn colors_are_parsed_with_and_without_alpha() {
+        assert_eq!(parse_color("#ffffff").unwrap(), Vec4::ONE);
+        assert_eq!(
+            parse_color("#80ff0000").unwrap(),
+            Vec4::new(1.0, 0.0, 0.0, 128.0 / 255.0)
+        );
+        // sRGB mid grey is about a fifth in linear space
+        assert!((parse_color("#808080").unwrap().x - 0.2158).abs() < 0.001);
+        assert!(parse_color("#fff").is_err());
+        assert!(parse_color("#gggggg").is_err());
+    }
+
+    #[test]
+    fn collection_without_images_is_an_empty_tileset() {
+        let tileset = TiledTileset {
+            tile_size: UVec2::splat(16),
+            tiles: vec![TiledTile {
+                id: 3,
+                collides: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut asset_service = AssetService::new();
+
+        let handle = load_tileset(&tileset, UVec2::splat(16), &mut asset_service)
+            .unwrap()
+            .unwrap();
+        let tileset = &asset_service.tilesets[&handle];
+        assert!(tileset.tiles.is_empty());
+        assert!(asset_service.atlases[&tileset.atlas].regions.is_empty());
+    }
+
+    #[test]
+    fn tilesets_of_another_tile_size_than_the_map_are_skipped() {
+        let path =
+            std::env::temp_dir().join(format!("tiled_importer_tile_{}.png", std::process::id()));
+        image::RgbaImage::new(16, 32).save(&path).unwrap();
+        let mut asset_service = AssetService::new();
+
+        let grid = TiledTileset {
+            tile_size: UVec2::new(16, 32),
+            columns: 1,
+            tile_count: 1,
+            image: Some(TiledImage {
+                path: path.clone(),
+                size: None,
+            }),
+            ..Default::default()
+        };
+        assert!(
+            load_tileset(&grid, UVec2::new(16, 32), &mut asset_service)
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            load_tileset(&grid, UVec2::splat(16), &mut asset_service)
+                .unwrap()
+                .is_none()
+        );
+
+        let collection = TiledTileset {
+            tile_size: UVec2::new(16, 32),
+            tiles: vec![TiledTile {
+                id: 0,
+                image: Some(TiledImage {
+                    path: path.clone(),
+                    size: None,
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(
+            load_tileset(&collection, UVec2::new(16, 32), &mut asset_service)
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            load_tileset(&collection, UVec2::splat(16), &mut asset_service)
+                .unwrap()
+                .is_none()
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
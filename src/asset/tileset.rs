@@ -0,0 +1,62 @@
+use crate::asset::AtlasHandle;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileAnimationFrame {
+    pub tile: u32,     // Tile of the same tileset shown during this frame
+    pub duration: f32, // Seconds
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TileData {
+    pub collides: bool,
+    pub animation: Vec<TileAnimationFrame>, // Empty unless the tile is animated
+}
+
+// Tiles of a tilemap, every region of the atlas is one tile
+#[derive(Debug, Clone)]
+pub struct Tileset {
+    pub name: Option<String>,
+    pub atlas: AtlasHandle,
+    pub tiles: Vec<TileData>, // Indexed like the atlas regions
+}
+
+impl Tileset {
+    pub fn new(atlas: AtlasHandle, tile_count: usize) -> Self {
+        Self {
+            name: None,
+            atlas,
+            tiles: vec![TileData::default(); tile_count],
+        }
+    }
+
+    pub fn has_animations(&self) -> bool {
+        self.tiles.iter().any(|tile| !tile.animation.is_empty())
+    }
+
+    // The tile shown in place of an animated tile after the given number of seconds, animations always loop
+    pub fn animated_tile(&self, tile: u32, time: f32) -> u32 {
+        let Some(animation) = self
+            .tiles
+            .get(tile as usize)
+            .map(|tile_data| &tile_data.animation)
+            .filter(|animation| !animation.is_empty())
+        else {
+            return tile;
+        };
+
+        let duration: f32 = animation.iter().map(|frame| frame.duration).sum();
+        if duration <= 0.0 {
+            return animation[0].tile;
+        }
+
+        let mut time = time.rem_euclid(duration);
+        for frame in animation {
+            if time < frame.duration {
+                return frame.tile;
+            }
+            time -= frame.duration;
+        }
+
+        animation[animation.len() - 1].tile
+    }
+}
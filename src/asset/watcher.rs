@@ -0,0 +1,84 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, channel},
+};
+
+use anyhow::{Context, anyhow};
+use log::{debug, warn};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+// Reports which of the watched files changed on disk since it was last asked
+pub struct FileWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    directories: HashSet<PathBuf>, // Editors often save by replacing the file, so the directory is watched instead
+    files: HashSet<PathBuf>,       // Canonical paths of the watched files
+}
+
+impl fmt::Debug for FileWatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileWatcher")
+            .field("files", &self.files)
+            .finish()
+    }
+}
+
+impl FileWatcher {
+    pub fn new() -> anyhow::Result<Self> {
+        let (sender, events) = channel();
+        let watcher =
+            notify::recommended_watcher(sender).context("Failed to create file watcher")?;
+
+        Ok(Self {
+            watcher,
+            events,
+            directories: HashSet::new(),
+            files: HashSet::new(),
+        })
+    }
+
+    pub fn watch(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path
+            .as_ref()
+            .canonicalize()
+            .with_context(|| format!("Failed to watch {:?}", path.as_ref()))?;
+        let directory = path
+            .parent()
+            .ok_or_else(|| anyhow!("Failed to watch {:?}, it has no parent directory", path))?
+            .to_path_buf();
+
+        if !self.directories.contains(&directory) {
+            self.watcher
+                .watch(&directory, RecursiveMode::NonRecursive)
+                .with_context(|| format!("Failed to watch {:?}", directory))?;
+            debug!("Watching directory: {:?}", directory);
+            self.directories.insert(directory);
+        }
+        self.files.insert(path);
+
+        Ok(())
+    }
+
+    // Canonical paths of the watched files that were created or modified, each reported once
+    pub fn changed_files(&self) -> HashSet<PathBuf> {
+        let mut changed = HashSet::new();
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+                    changed.extend(
+                        event
+                            .paths
+                            .into_iter()
+                            .filter(|path| self.files.contains(path)),
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => warn!("File watcher error: {}", e),
+            }
+        }
+
+        changed
+    }
+}
@@ -0,0 +1,8 @@
+// Camera bound to group 0 by every pass that draws the scene
+struct CameraUniform {
+    view_projection_matrix: mat4x4<f32>,
+    camera_position: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
@@ -0,0 +1,173 @@
+use std::f32::consts::TAU;
+
+use glam::{Mat4, Vec3};
+
+use crate::asset::bounds::Aabb;
+
+// Segments a circle of a debug sphere is drawn with
+const CIRCLE_SEGMENTS: u32 = 32;
+
+// How a debug shape is drawn, a plain color draws it for one frame hidden behind geometry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugStyle {
+    pub color: Vec3,      // Linear RGB
+    pub duration: f32,    // Seconds the shape stays, 0 draws it for the current frame only
+    pub depth_test: bool, // Turned off the shape is drawn over everything
+}
+
+impl From<Vec3> for DebugStyle {
+    fn from(color: Vec3) -> Self {
+        Self {
+            color,
+            duration: 0.0,
+            depth_test: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebugLine {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub color: Vec3,
+    pub depth_test: bool,
+    expires_at: f32, // Removed once the time of the debug draw reaches this, after it was drawn
+}
+
+// Lines queued by any system that has the scene and drawn by the renderer at the end of the frame,
+// e.g. scene.debug_draw.arrow(position, position + velocity, Vec3::Y)
+#[derive(Debug, Default, Clone)]
+pub struct DebugDraw {
+    lines: Vec<DebugLine>,
+    time: f32, // Seconds passed, durations count down against it
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, start: Vec3, end: Vec3, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        self.lines.push(DebugLine {
+            start,
+            end,
+            color: style.color,
+            depth_test: style.depth_test,
+            expires_at: self.time + style.duration,
+        });
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        let corner = |x: bool, y: bool, z: bool| {
+            Vec3::new(
+                if x { aabb.max.x } else { aabb.min.x },
+                if y { aabb.max.y } else { aabb.min.y },
+                if z { aabb.max.z } else { aabb.min.z },
+            )
+        };
+
+        // Every edge runs along one axis between two corners that agree on the other two
+        for (a, b) in [(false, false), (true, false), (false, true), (true, true)] {
+            self.line(corner(false, a, b), corner(true, a, b), style);
+            self.line(corner(a, false, b), corner(a, true, b), style);
+            self.line(corner(a, b, false), corner(a, b, true), style);
+        }
+    }
+
+    // Drawn as a circle around each axis
+    pub fn sphere(&mut self, center: Vec3, radius: f32, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        for (axis_u, axis_v) in [(Vec3::X, Vec3::Y), (Vec3::Y, Vec3::Z), (Vec3::Z, Vec3::X)] {
+            let point = |segment: u32| {
+                let angle = segment as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+                center + (axis_u * angle.cos() + axis_v * angle.sin()) * radius
+            };
+            for segment in 0..CIRCLE_SEGMENTS {
+                self.line(point(segment), point(segment + 1), style);
+            }
+        }
+    }
+
+    // Line with a head at the end, the head grows with the arrow up to a limit
+    pub fn arrow(&mut self, start: Vec3, end: Vec3, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        self.line(start, end, style);
+
+        let Some(direction) = (end - start).try_normalize() else {
+            return;
+        };
+        let head_length = ((end - start).length() * 0.2).min(0.5);
+        let (side, up) = direction.any_orthonormal_pair();
+        let base = end - direction * head_length;
+        for offset in [side, -side, up, -up] {
+            self.line(end, base + offset * head_length * 0.5, style);
+        }
+    }
+
+    // The X, Y and Z axes of the transform in red, green and blue, the color of the style is not used
+    pub fn axis(&mut self, transform: Mat4, length: f32, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        let origin = transform.transform_point3(Vec3::ZERO);
+        for (axis, color) in [(Vec3::X, Vec3::X), (Vec3::Y, Vec3::Y), (Vec3::Z, Vec3::Z)] {
+            let end = transform.transform_point3(axis * length);
+            self.arrow(origin, end, DebugStyle { color, ..style });
+        }
+    }
+
+    // Square grid on the XZ plane around the center with cell_count cells along each side
+    pub fn grid(
+        &mut self,
+        center: Vec3,
+        cell_size: f32,
+        cell_count: u32,
+        style: impl Into<DebugStyle>,
+    ) {
+        let style = style.into();
+        let half_size = cell_size * cell_count as f32 * 0.5;
+        for line in 0..=cell_count {
+            let offset = line as f32 * cell_size - half_size;
+            self.line(
+                center + Vec3::new(offset, 0.0, -half_size),
+                center + Vec3::new(offset, 0.0, half_size),
+                style,
+            );
+            self.line(
+                center + Vec3::new(-half_size, 0.0, offset),
+                center + Vec3::new(half_size, 0.0, offset),
+                style,
+            );
+        }
+    }
+
+    // Outline of what a camera with this view projection matrix sees, in wgpu's 0 to 1 depth range
+    pub fn frustum(&mut self, view_projection_matrix: Mat4, style: impl Into<DebugStyle>) {
+        let style = style.into();
+        let inverse = view_projection_matrix.inverse();
+        let corners = |depth: f32| {
+            [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                .map(|(x, y)| inverse.project_point3(Vec3::new(x, y, depth)))
+        };
+        let near = corners(0.0);
+        let far = corners(1.0);
+
+        for index in 0..4 {
+            let next = (index + 1) % 4;
+            self.line(near[index], near[next], style);
+            self.line(far[index], far[next], style);
+            self.line(near[index], far[index], style);
+        }
+    }
+
+    pub fn lines(&self) -> &[DebugLine] {
+        &self.lines
+    }
+
+    // Moves time along, called once per frame before systems queue their shapes
+    pub fn advance(&mut self, delta_time: f32) {
+        self.time += delta_time;
+    }
+
+    // Removes the shapes whose duration is over, called after the frame was rendered
+    pub fn end_frame(&mut self) {
+        let time = self.time;
+        self.lines.retain(|line| line.expires_at > time);
+    }
+}
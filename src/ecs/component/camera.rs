@@ -1,6 +1,6 @@
-use glam::{Mat4, Vec3, Vec4};
+use glam::{Mat4, UVec2, Vec3, Vec4};
 
-use crate::ecs::component::transform::TransformComponent;
+use crate::{asset::TextureHandle, ecs::component::transform::TransformComponent};
 
 const OPENGL_TO_WGPU_MATRIX: Mat4 = Mat4::from_cols(
     Vec4::new(1.0, 0.0, 0.0, 0.0),
@@ -9,41 +9,273 @@ const OPENGL_TO_WGPU_MATRIX: Mat4 = Mat4::from_cols(
     Vec4::new(0.0, 0.0, 0.5, 1.0),
 );
 
+// How an orthographic camera decides how much of the world fits on screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScalingMode {
+    // Size is the visible height in world units, the width follows the aspect ratio
+    FixedVertical,
+    // Size is the visible width in world units, the height follows the aspect ratio
+    FixedHorizontal,
+    // A world unit covers pixels_per_unit screen pixels scaled by the largest whole number
+    // that still shows at least size world units vertically, so pixel art stays crisp
+    PixelPerfect { pixels_per_unit: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective {
+        field_of_view: f32, // Vertical field of view in degrees
+    },
+    Orthographic {
+        size: f32,
+        scaling_mode: ScalingMode,
+    },
+}
+
+// Curve that maps HDR colors into the range the screen can show
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tonemapping {
+    None, // Clamps colors, for scenes that already hold display colors such as pixel art
+    Reinhard,
+    #[default]
+    Aces,
+    AgX, // Desaturates very bright colors towards white instead of shifting their hue
+}
+
+// Bright parts of the frame bleed light into their surroundings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSettings {
+    pub intensity: f32, // How much of the blurred light is added back onto the frame
+    pub threshold: f32, // Brightness a color needs before it blooms, 0 lets everything bloom
+    pub soft_knee: f32, // Range below the threshold that fades into blooming instead of cutting off
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            intensity: 0.05,
+            threshold: 1.0,
+            soft_knee: 0.5,
+        }
+    }
+}
+
+// Remaps tone mapped colors through a lookup table, e.g. for a warmer or desaturated look
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorGradingSettings {
+    // Horizontal strip of N slices of N by N pixels, red grows to the right within a slice,
+    // green grows downwards and blue grows from one slice to the next
+    pub lut: TextureHandle,
+    pub strength: f32, // 0 leaves the colors unchanged, 1 applies the full lookup table
+}
+
+// Darkens the edges of the frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VignetteSettings {
+    pub intensity: f32,  // How much of the vignette color covers the corners
+    pub radius: f32,     // Distance from the center, 1 being a corner, where the vignette starts
+    pub smoothness: f32, // Distance over which the vignette fades in
+    pub color: Vec3,     // Linear RGB
+}
+
+impl Default for VignetteSettings {
+    fn default() -> Self {
+        Self {
+            intensity: 0.4,
+            radius: 0.5,
+            smoothness: 0.5,
+            color: Vec3::ZERO,
+        }
+    }
+}
+
+// Effects applied to what the camera rendered before it is shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PostProcessSettings {
+    pub exposure: f32, // Multiplies the HDR colors before tone mapping
+    pub tonemapping: Tonemapping,
+    pub bloom: Option<BloomSettings>,
+    pub color_grading: Option<ColorGradingSettings>,
+    pub vignette: Option<VignetteSettings>,
+    pub fxaa: bool, // Smooths jagged edges after everything else
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self {
+            exposure: 1.0,
+            tonemapping: Tonemapping::Aces,
+            bloom: Some(BloomSettings::default()),
+            color_grading: None,
+            vignette: None,
+            fxaa: true,
+        }
+    }
+}
+
+impl PostProcessSettings {
+    // Shows the rendered colors as they are, blurring or tone mapping would spoil pixel art
+    pub fn passthrough() -> Self {
+        Self {
+            exposure: 1.0,
+            tonemapping: Tonemapping::None,
+            bloom: None,
+            color_grading: None,
+            vignette: None,
+            fxaa: false,
+        }
+    }
+}
+
+// Image the sky is drawn from and the scene is lit with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EnvironmentMap {
+    Equirectangular(TextureHandle), // Panorama twice as wide as it is high, usually an HDR image
+    Cubemap([TextureHandle; 6]),    // Faces in +X, -X, +Y, -Y, +Z, -Z order
+}
+
+// Surroundings of the camera, seen behind everything and reflected by every surface
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvironmentSettings {
+    pub map: EnvironmentMap,
+    pub intensity: f32, // Multiplies the sky and the light it casts onto the scene
+    pub skybox: bool,   // Draws the map behind everything, turned off the scene is still lit by it
+}
+
+impl EnvironmentSettings {
+    pub fn new(map: EnvironmentMap) -> Self {
+        Self {
+            map,
+            intensity: 1.0,
+            skybox: true,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct CameraComponent {
     pub look_at: Vec3,
     pub up_orientation: Vec3,
     pub aspect_ratio: f32,
-    pub field_of_view: f32,
+    pub viewport_size: UVec2, // Size in pixels of what the camera renders to, only pixel perfect scaling needs it
+    pub projection: Projection,
     pub z_near_field: f32, // Closest distance to the camera that things are rendered
     pub z_far_field: f32,  // Farthest distance to the camera that things are rendered
+    pub post_process: PostProcessSettings,
+    pub environment: Option<EnvironmentSettings>, // None lights the scene with a dim constant ambient light
 }
 
 impl CameraComponent {
+    // Orthographic camera for 2D games that looks down the -Z axis at whatever its position is over
+    pub fn new_2d(viewport_size: UVec2, size: f32, scaling_mode: ScalingMode) -> Self {
+        Self {
+            look_at: Vec3::ZERO,
+            up_orientation: Vec3::Y,
+            aspect_ratio: viewport_size.x.max(1) as f32 / viewport_size.y.max(1) as f32,
+            viewport_size,
+            projection: Projection::Orthographic { size, scaling_mode },
+            z_near_field: 0.0,
+            z_far_field: 1000.0,
+            post_process: PostProcessSettings::passthrough(),
+            environment: None,
+        }
+    }
+
+    // Follows the size of what the camera renders to, e.g. when the window is resized
+    pub fn set_viewport_size(&mut self, viewport_size: UVec2) {
+        self.viewport_size = viewport_size;
+        self.aspect_ratio = viewport_size.x.max(1) as f32 / viewport_size.y.max(1) as f32;
+    }
+
     pub fn calculate_view_projection_matrix(
         &self,
         transform_component: &TransformComponent,
     ) -> Mat4 {
+        let mut position = transform_component.position;
+
+        // Pixel perfect cameras only move in whole screen pixels so sprites don't shimmer
+        if let Some(pixel_size) = self.pixel_perfect_pixel_size() {
+            position.x = (position.x / pixel_size).round() * pixel_size;
+            position.y = (position.y / pixel_size).round() * pixel_size;
+        }
+
         // Moves the world to be at the position the camera is looking at
         let view_matrix = Mat4::look_at_rh(
-            transform_component.position,
-            self.look_at
-                + Vec3::new(
-                    transform_component.position.x,
-                    transform_component.position.y,
-                    0.0,
-                ),
+            position,
+            self.look_at + Vec3::new(position.x, position.y, 0.0),
             self.up_orientation,
         );
 
-        // Warps the scene to provide depth
-        let projection_matrix = Mat4::perspective_rh(
-            self.field_of_view.to_radians(),
-            self.aspect_ratio,
-            self.z_near_field,
-            self.z_far_field,
-        );
+        OPENGL_TO_WGPU_MATRIX * self.calculate_projection_matrix() * view_matrix
+    }
+
+    // OpenGL style depth from -1 to 1 which is then converted to the 0 to 1 range wgpu uses
+    fn calculate_projection_matrix(&self) -> Mat4 {
+        match self.projection {
+            // Warps the scene to provide depth
+            Projection::Perspective { field_of_view } => Mat4::perspective_rh_gl(
+                field_of_view.to_radians(),
+                self.aspect_ratio,
+                self.z_near_field,
+                self.z_far_field,
+            ),
+            // Keeps sizes the same regardless of distance
+            Projection::Orthographic { size, scaling_mode } => {
+                let (left, right, bottom, top) = match scaling_mode {
+                    ScalingMode::FixedVertical => {
+                        let half_height = size * 0.5;
+                        let half_width = half_height * self.aspect_ratio;
+                        (-half_width, half_width, -half_height, half_height)
+                    }
+                    ScalingMode::FixedHorizontal => {
+                        let half_width = size * 0.5;
+                        let half_height = half_width / self.aspect_ratio;
+                        (-half_width, half_width, -half_height, half_height)
+                    }
+                    ScalingMode::PixelPerfect { .. } => {
+                        let pixel_size = self.pixel_perfect_pixel_size().unwrap_or(1.0);
+                        let viewport_size = self.viewport_size.max(UVec2::ONE);
+
+                        // Odd viewport sizes would put pixel centers on the edges between texels,
+                        // so the extra pixel goes to the right and top side instead of being split
+                        let left = -((viewport_size.x / 2) as f32) * pixel_size;
+                        let bottom = -((viewport_size.y / 2) as f32) * pixel_size;
+                        (
+                            left,
+                            left + viewport_size.x as f32 * pixel_size,
+                            bottom,
+                            bottom + viewport_size.y as f32 * pixel_size,
+                        )
+                    }
+                };
+
+                Mat4::orthographic_rh_gl(
+                    left,
+                    right,
+                    bottom,
+                    top,
+                    self.z_near_field,
+                    self.z_far_field,
+                )
+            }
+        }
+    }
+
+    // World units covered by one screen pixel, None unless the camera uses pixel perfect scaling
+    pub fn pixel_perfect_pixel_size(&self) -> Option<f32> {
+        let Projection::Orthographic {
+            size,
+            scaling_mode: ScalingMode::PixelPerfect { pixels_per_unit },
+        } = self.projection
+        else {
+            return None;
+        };
 
-        OPENGL_TO_WGPU_MATRIX * projection_matrix * view_matrix
+        // The largest whole number scale that still shows the requested world height
+        let size_in_pixels = (size * pixels_per_unit).max(1.0);
+        let scale = (self.viewport_size.y as f32 / size_in_pixels)
+            .floor()
+            .max(1.0);
+        Some(1.0 / (pixels_per_unit * scale))
     }
 }
@@ -0,0 +1,5 @@
+#[derive(Debug, Default, Clone)]
+pub struct HierarchyComponent {
+    pub parent: Option<u32>,
+    pub children: Vec<u32>,
+}
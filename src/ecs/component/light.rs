@@ -0,0 +1,93 @@
+use glam::Vec3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    // Infinitely far away light such as the sun, only the entity's rotation matters
+    Directional,
+    // Shines in every direction from the entity's position and fades out at range
+    Point {
+        range: f32,
+    },
+    // Shines a cone along the entity's forward (-Z) direction, angles are in radians from the center of the cone
+    Spot {
+        range: f32,
+        inner_cone_angle: f32, // Full intensity inside this angle
+        outer_cone_angle: f32, // No light outside this angle
+    },
+}
+
+// Only directional and spot lights cast shadows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    pub depth_bias: f32, // Subtracted from the depth compared against the shadow map, fights shadow acne
+    pub normal_bias: f32, // World units the surface is pushed out along its normal before the lookup
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            depth_bias: 0.0005,
+            normal_bias: 0.02,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LightComponent {
+    pub kind: LightKind,
+    pub color: Vec3, // Linear RGB
+    pub intensity: f32,
+    pub shadow: Option<ShadowSettings>, // None when the light does not cast shadows
+}
+
+impl LightComponent {
+    pub fn directional(color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            color,
+            intensity,
+            shadow: None,
+        }
+    }
+
+    pub fn point(color: Vec3, intensity: f32, range: f32) -> Self {
+        Self {
+            kind: LightKind::Point { range },
+            color,
+            intensity,
+            shadow: None,
+        }
+    }
+
+    pub fn spot(
+        color: Vec3,
+        intensity: f32,
+        range: f32,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot {
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+            color,
+            intensity,
+            shadow: None,
+        }
+    }
+
+    pub fn with_shadow(mut self, shadow_settings: ShadowSettings) -> Self {
+        self.shadow = Some(shadow_settings);
+        self
+    }
+
+    // Distance after which the light has no effect, directional lights reach everywhere
+    pub fn range(&self) -> Option<f32> {
+        match self.kind {
+            LightKind::Directional => None,
+            LightKind::Point { range } | LightKind::Spot { range, .. } => Some(range),
+        }
+    }
+}
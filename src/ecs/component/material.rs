@@ -0,0 +1,6 @@
+use crate::asset::MaterialHandle;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialComponent {
+    pub material: MaterialHandle,
+}
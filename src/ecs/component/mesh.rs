@@ -0,0 +1,6 @@
+use crate::asset::MeshHandle;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MeshComponent {
+    pub mesh: MeshHandle,
+}
@@ -1,5 +1,14 @@
 pub mod audio;
 pub mod camera;
+pub mod hierarchy;
 pub mod input;
+pub mod light;
+pub mod material;
+pub mod mesh;
 pub mod physics;
+pub mod skin;
+pub mod sprite;
+pub mod sprite_animation;
+pub mod text;
+pub mod tilemap;
 pub mod transform;
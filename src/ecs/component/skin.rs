@@ -0,0 +1,8 @@
+use glam::Mat4;
+
+#[derive(Debug, Default, Clone)]
+pub struct SkinComponent {
+    pub joints: Vec<u32>, // Entities acting as joints, indexed by the mesh joint attributes
+    pub inverse_bind_matrices: Vec<Mat4>, // One per joint, identity when the file omits them
+    pub skeleton_root: Option<u32>,
+}
@@ -0,0 +1,44 @@
+use glam::{UVec2, Vec2, Vec4};
+
+use crate::asset::{AtlasHandle, TextureHandle};
+
+// Which part of which texture a sprite shows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpriteImage {
+    Texture(TextureHandle), // The whole texture
+    TextureRegion {
+        texture: TextureHandle,
+        position: UVec2, // Top left corner in pixels
+        size: UVec2,     // Width and height in pixels
+    },
+    Atlas {
+        atlas: AtlasHandle,
+        region: usize, // Index into the regions of the atlas
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SpriteComponent {
+    pub image: SpriteImage,
+    pub size: Vec2, // Size in world units before the transform is applied
+    pub tint: Vec4, // Linear RGBA multiplied with the texture
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub anchor: Vec2, // Point of the sprite placed at the entity position, (0, 0) is the bottom left and (1, 1) the top right
+    pub z_order: i32, // Sprites with a higher z-order are drawn on top, ties are broken by distance to the camera
+}
+
+impl SpriteComponent {
+    // Untinted and centered on the entity
+    pub fn new(image: SpriteImage, size: Vec2) -> Self {
+        Self {
+            image,
+            size,
+            tint: Vec4::ONE,
+            flip_x: false,
+            flip_y: false,
+            anchor: Vec2::splat(0.5),
+            z_order: 0,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use crate::asset::SpriteAnimationHandle;
+
+// Plays a sprite animation clip on the sprite of the same entity
+#[derive(Debug, Clone)]
+pub struct SpriteAnimationComponent {
+    pub clip: SpriteAnimationHandle,
+    pub speed: f32, // Playback rate, 1 plays the clip at its authored speed
+    pub playing: bool,
+    pub frame: usize,        // Index of the frame being shown
+    pub frame_time: f32,     // Seconds spent on the current frame
+    pub reversing: bool,     // Ping pong clips play backwards while this is set
+    pub finished: bool,      // Set when a clip that doesn't loop reaches its last frame
+    pub started: bool,       // False until the events of the first frame have fired
+    pub events: Vec<String>, // Events of the frames entered during the last update
+}
+
+impl SpriteAnimationComponent {
+    pub fn new(clip: SpriteAnimationHandle) -> Self {
+        Self {
+            clip,
+            speed: 1.0,
+            playing: true,
+            frame: 0,
+            frame_time: 0.0,
+            reversing: false,
+            finished: false,
+            started: false,
+            events: Vec::new(),
+        }
+    }
+
+    // Switches to another clip from its first frame, playing the current clip again only restarts it once finished
+    pub fn play(&mut self, clip: SpriteAnimationHandle) {
+        if clip == self.clip && !self.finished {
+            self.playing = true;
+            return;
+        }
+
+        *self = Self {
+            speed: self.speed,
+            ..Self::new(clip)
+        };
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+}
@@ -0,0 +1,58 @@
+use glam::{Vec2, Vec4};
+
+use crate::asset::{FontHandle, font::TextAlignment};
+
+// Where a text is drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextSpace {
+    World, // Placed by the transform of the entity and drawn like a sprite
+    Screen {
+        position: Vec2, // Pixels from the top left corner of the window, drawn on top of everything
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct TextComponent {
+    pub text: String,
+    pub font: FontHandle,
+    pub font_size: f32, // In world units for world text and in pixels for screen text
+    pub color: Vec4,    // Linear RGBA
+    pub alignment: TextAlignment,
+    pub max_width: Option<f32>, // Wraps lines wider than this, measured like the font size
+    pub line_spacing: f32,      // 1 keeps the line spacing the font was designed with
+    pub anchor: Vec2, // Point of the text placed at its position, (0, 0) is the bottom left and (1, 1) the top right
+    pub z_order: i32, // Ordered with sprites of the same z-order for world text and with other screen text otherwise
+    pub space: TextSpace,
+}
+
+impl TextComponent {
+    // White text centered on the entity
+    pub fn new(text: impl Into<String>, font: FontHandle, font_size: f32) -> Self {
+        Self {
+            text: text.into(),
+            font,
+            font_size,
+            color: Vec4::ONE,
+            alignment: TextAlignment::Left,
+            max_width: None,
+            line_spacing: 1.0,
+            anchor: Vec2::splat(0.5),
+            z_order: 0,
+            space: TextSpace::World,
+        }
+    }
+
+    // White text with its top left corner at a position on screen
+    pub fn screen(
+        text: impl Into<String>,
+        font: FontHandle,
+        font_size: f32,
+        position: Vec2,
+    ) -> Self {
+        Self {
+            anchor: Vec2::new(0.0, 1.0),
+            space: TextSpace::Screen { position },
+            ..Self::new(text, font, font_size)
+        }
+    }
+}
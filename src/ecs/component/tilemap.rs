@@ -0,0 +1,126 @@
+use glam::{UVec2, Vec2, Vec4};
+
+use crate::asset::{AssetService, TilesetHandle};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub tileset: usize, // Index into the tilesets of the tilemap
+    pub index: u32,     // Tile inside the tileset
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub flip_diagonal: bool, // Swaps the x and y axes of the tile before the other flips are applied
+}
+
+impl Tile {
+    pub fn new(tileset: usize, index: u32) -> Self {
+        Self {
+            tileset,
+            index,
+            flip_x: false,
+            flip_y: false,
+            flip_diagonal: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TilemapLayer {
+    pub name: Option<String>,
+    pub tiles: Vec<Option<Tile>>, // Row by row starting at the top row, one per cell of the tilemap
+    pub visible: bool,
+    pub tint: Vec4,   // Linear RGBA multiplied with every tile of the layer
+    pub z_order: i32, // Drawn in the same order as sprites, layers go before sprites of the same z-order
+}
+
+// Grid of tiles, the bottom left corner of the grid sits at the entity position
+#[derive(Debug, Clone)]
+pub struct TilemapComponent {
+    pub tilesets: Vec<TilesetHandle>,
+    pub size: UVec2,     // Number of columns and rows
+    pub tile_size: Vec2, // Size of a cell in world units before the transform is applied
+    pub layers: Vec<TilemapLayer>,
+    pub animation_time: f32, // Seconds that animated tiles have been playing for
+    pub revision: u32, // Bumped whenever the tiles change so the renderer knows to rebuild them
+}
+
+impl TilemapComponent {
+    pub fn new(size: UVec2, tile_size: Vec2, tilesets: Vec<TilesetHandle>) -> Self {
+        Self {
+            tilesets,
+            size,
+            tile_size,
+            layers: Vec::new(),
+            animation_time: 0.0,
+            revision: 0,
+        }
+    }
+
+    // Adds an empty layer on top of the existing ones and returns its index
+    pub fn add_layer(&mut self, name: Option<String>, z_order: i32) -> usize {
+        self.layers.push(TilemapLayer {
+            name,
+            tiles: vec![None; (self.size.x * self.size.y) as usize],
+            visible: true,
+            tint: Vec4::ONE,
+            z_order,
+        });
+        self.mark_changed();
+
+        self.layers.len() - 1
+    }
+
+    pub fn tile(&self, layer: usize, cell: UVec2) -> Option<Tile> {
+        let index = self.cell_index(cell)?;
+
+        self.layers.get(layer)?.tiles.get(index).copied().flatten()
+    }
+
+    pub fn set_tile(&mut self, layer: usize, cell: UVec2, tile: Option<Tile>) {
+        if let Some(index) = self.cell_index(cell)
+            && let Some(layer) = self.layers.get_mut(layer)
+        {
+            layer.tiles[index] = tile;
+            self.mark_changed();
+        }
+    }
+
+    // Has to be called after editing the layers directly instead of through set_tile
+    pub fn mark_changed(&mut self) {
+        self.revision = self.revision.wrapping_add(1);
+    }
+
+    fn cell_index(&self, cell: UVec2) -> Option<usize> {
+        (cell.x < self.size.x && cell.y < self.size.y)
+            .then(|| (cell.y * self.size.x + cell.x) as usize)
+    }
+
+    // Bottom left corner of a cell relative to the entity
+    pub fn cell_position(&self, cell: UVec2) -> Vec2 {
+        Vec2::new(cell.x as f32, self.size.y as f32 - 1.0 - cell.y as f32) * self.tile_size
+    }
+
+    // The cell containing a position relative to the entity
+    pub fn cell_at(&self, local_position: Vec2) -> Option<UVec2> {
+        let cell = (local_position / self.tile_size).floor();
+        if cell.x < 0.0 || cell.y < 0.0 {
+            return None;
+        }
+
+        let cell = UVec2::new(cell.x as u32, cell.y as u32);
+        (cell.x < self.size.x && cell.y < self.size.y)
+            .then(|| UVec2::new(cell.x, self.size.y - 1 - cell.y))
+    }
+
+    // Whether a tile flagged as colliding sits in the cell on any layer
+    pub fn collides(&self, cell: UVec2, asset_service: &AssetService) -> bool {
+        (0..self.layers.len()).any(|layer| {
+            self.tile(layer, cell).is_some_and(|tile| {
+                self.tilesets
+                    .get(tile.tileset)
+                    .and_then(|tileset| asset_service.tilesets.get(tileset))
+                    .and_then(|tileset| tileset.tiles.get(tile.index as usize))
+                    .is_some_and(|tile_data| tile_data.collides)
+            })
+        })
+    }
+}
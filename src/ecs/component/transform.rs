@@ -1,4 +1,4 @@
-use glam::Vec3;
+use glam::{EulerRot, Mat4, Quat, Vec3};
 
 #[derive(Debug, Clone, Copy)]
 pub struct TransformComponent {
@@ -7,3 +7,34 @@ pub struct TransformComponent {
     pub rotation: Vec3, // Euler angles in radians
     pub translation: Vec3,
 }
+
+impl TransformComponent {
+    pub fn rotation_quat(&self) -> Quat {
+        Quat::from_euler(
+            EulerRot::XYZ,
+            self.rotation.x,
+            self.rotation.y,
+            self.rotation.z,
+        )
+    }
+
+    // Local space to parent space, applied as scale, then rotation, then translation
+    pub fn calculate_model_matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(
+            self.scale,
+            self.rotation_quat(),
+            self.position + self.translation,
+        )
+    }
+}
+
+impl Default for TransformComponent {
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            scale: Vec3::ONE,
+            rotation: Vec3::ZERO,
+            translation: Vec3::ZERO,
+        }
+    }
+}
@@ -1,8 +1,17 @@
 use std::collections::HashMap;
 
-use crate::ecs::component::{
-    camera::CameraComponent, input::InputComponent, physics::PhysicsComponent,
-    transform::TransformComponent,
+use glam::Mat4;
+use log::warn;
+
+use crate::{
+    debug_draw::DebugDraw,
+    ecs::component::{
+        camera::CameraComponent, hierarchy::HierarchyComponent, input::InputComponent,
+        light::LightComponent, material::MaterialComponent, mesh::MeshComponent,
+        physics::PhysicsComponent, skin::SkinComponent, sprite::SpriteComponent,
+        sprite_animation::SpriteAnimationComponent, text::TextComponent, tilemap::TilemapComponent,
+        transform::TransformComponent,
+    },
 };
 
 #[derive(Debug, Default, Clone)]
@@ -12,6 +21,16 @@ pub struct Scene {
     pub camera_components: HashMap<u32, CameraComponent>,
     pub input_components: HashMap<u32, InputComponent>,
     pub physics_components: HashMap<u32, PhysicsComponent>,
+    pub hierarchy_components: HashMap<u32, HierarchyComponent>,
+    pub mesh_components: HashMap<u32, MeshComponent>,
+    pub material_components: HashMap<u32, MaterialComponent>,
+    pub skin_components: HashMap<u32, SkinComponent>,
+    pub light_components: HashMap<u32, LightComponent>,
+    pub sprite_components: HashMap<u32, SpriteComponent>,
+    pub sprite_animation_components: HashMap<u32, SpriteAnimationComponent>,
+    pub tilemap_components: HashMap<u32, TilemapComponent>,
+    pub text_components: HashMap<u32, TextComponent>,
+    pub debug_draw: DebugDraw, // Lines any system can queue for the renderer to draw
 }
 
 impl Scene {
@@ -22,6 +41,16 @@ impl Scene {
             camera_components: HashMap::new(),
             input_components: HashMap::new(),
             physics_components: HashMap::new(),
+            hierarchy_components: HashMap::new(),
+            mesh_components: HashMap::new(),
+            material_components: HashMap::new(),
+            skin_components: HashMap::new(),
+            light_components: HashMap::new(),
+            sprite_components: HashMap::new(),
+            sprite_animation_components: HashMap::new(),
+            tilemap_components: HashMap::new(),
+            text_components: HashMap::new(),
+            debug_draw: DebugDraw::default(),
         }
     }
 
@@ -30,4 +59,121 @@ impl Scene {
 
         self.next_entity_id
     }
+
+    pub fn set_parent(&mut self, child: u32, parent: u32) {
+        // Parenting an entity to itself or one of its descendants would make the hierarchy a loop
+        let mut ancestor = Some(parent);
+        while let Some(a) = ancestor {
+            if a == child {
+                warn!(
+                    "Entity {} can't be parented to {}, which is itself or one of its descendants",
+                    child, parent
+                );
+                return;
+            }
+
+            ancestor = self
+                .hierarchy_components
+                .get(&a)
+                .and_then(|hierarchy_component| hierarchy_component.parent);
+        }
+
+        // Detach from the previous parent so an entity is never listed twice
+        if let Some(previous_parent) = self
+            .hierarchy_components
+            .get(&child)
+            .and_then(|hierarchy_component| hierarchy_component.parent)
+            && let Some(previous_parent_hierarchy) =
+                self.hierarchy_components.get_mut(&previous_parent)
+        {
+            previous_parent_hierarchy.children.retain(|&c| c != child);
+        }
+
+        self.hierarchy_components.entry(child).or_default().parent = Some(parent);
+        self.hierarchy_components
+            .entry(parent)
+            .or_default()
+            .children
+            .push(child);
+    }
+
+    // Combines the local transform of the entity with the transforms of all of its ancestors
+    pub fn calculate_world_matrix(&self, entity: u32) -> Mat4 {
+        let mut world_matrix = Mat4::IDENTITY;
+        let mut current_entity = Some(entity);
+
+        while let Some(e) = current_entity {
+            if let Some(transform_component) = self.transform_components.get(&e) {
+                world_matrix = transform_component.calculate_model_matrix() * world_matrix;
+            }
+
+            current_entity = self
+                .hierarchy_components
+                .get(&e)
+                .and_then(|hierarchy_component| hierarchy_component.parent);
+        }
+
+        world_matrix
+    }
+
+    // Transform of a camera with its position in world space, cameras under a parent entity move with it.
+    // Cameras only use their position, their orientation is already given in world space by look_at.
+    pub fn calculate_camera_transform(&self, entity: u32) -> Option<TransformComponent> {
+        let transform_component = self.transform_components.get(&entity)?;
+        let parent_world_matrix = self
+            .hierarchy_components
+            .get(&entity)
+            .and_then(|hierarchy_component| hierarchy_component.parent)
+            .map_or(Mat4::IDENTITY, |parent| self.calculate_world_matrix(parent));
+
+        Some(TransformComponent {
+            position: parent_world_matrix.transform_point3(transform_component.position),
+            ..*transform_component
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    #[test]
+    fn cameras_under_a_parent_are_placed_in_world_space() {
+        let mut scene = Scene::new();
+        let rig = scene.create_entity();
+        scene.transform_components.insert(
+            rig,
+            TransformComponent {
+                position: Vec3::new(10.0, 0.0, 0.0),
+                rotation: Vec3::new(0.0, 90.0_f32.to_radians(), 0.0),
+                ..Default::default()
+            },
+        );
+        let camera = scene.create_entity();
+        let camera_transform = TransformComponent {
+            position: Vec3::new(0.0, 2.0, 5.0),
+            ..Default::default()
+        };
+        scene.transform_components.insert(camera, camera_transform);
+
+        // Without a parent the local position is already the world position
+        let unparented = scene.calculate_camera_transform(camera).unwrap();
+        assert_eq!(unparented.position, camera_transform.position);
+
+        // The rig turns the camera's offset from +Z to +X before moving it
+        scene.set_parent(camera, rig);
+        let parented = scene.calculate_camera_transform(camera).unwrap();
+        assert!(
+            parented
+                .position
+                .abs_diff_eq(Vec3::new(15.0, 2.0, 0.0), 1e-5),
+            "{:?}",
+            parented.position
+        );
+        assert_eq!(parented.rotation, camera_transform.rotation);
+
+        assert!(scene.calculate_camera_transform(rig + 100).is_none());
+    }
 }
@@ -0,0 +1,186 @@
+// Bakes an environment map into the cubemaps image based lighting samples,
+// every draw fills one face of one mip level
+#include "fullscreen.wgsl"
+
+const PI: f32 = 3.14159265359;
+
+// Spacing in radians of the directions the irradiance is summed over
+const IRRADIANCE_SAMPLE_DELTA: f32 = 0.05;
+const PREFILTER_SAMPLE_COUNT: u32 = 128u;
+const BRDF_SAMPLE_COUNT: u32 = 256u;
+
+struct BakeUniform {
+    face: u32,
+    roughness: f32,
+    source_lod: f32, // Mip level of the source that matches the size of the target
+    source_size: f32, // Size of a face of the source cubemap in texels
+};
+
+@group(0) @binding(0)
+var<uniform> bake: BakeUniform;
+@group(0) @binding(1)
+var bake_sampler: sampler;
+
+// Equirectangular panoramas and cubemap faces are baked from a texture, the lighting maps from the baked cubemap
+@group(1) @binding(0)
+var source_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var source_cube: texture_cube<f32>;
+
+// Direction a texel of a cubemap face is looked up with, faces go +X, -X, +Y, -Y, +Z, -Z
+fn face_direction(face: u32, tex_coords: vec2<f32>) -> vec3<f32> {
+    let s = tex_coords.x * 2.0 - 1.0;
+    let t = tex_coords.y * 2.0 - 1.0;
+    switch face {
+        case 0u: { return normalize(vec3<f32>(1.0, -t, -s)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -t, s)); }
+        case 2u: { return normalize(vec3<f32>(s, 1.0, t)); }
+        case 3u: { return normalize(vec3<f32>(s, -1.0, -t)); }
+        case 4u: { return normalize(vec3<f32>(s, -t, 1.0)); }
+        default: { return normalize(vec3<f32>(-s, -t, -1.0)); }
+    }
+}
+
+@fragment
+fn fs_equirectangular(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = face_direction(bake.face, in.tex_coords);
+    let tex_coords = vec2<f32>(
+        atan2(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+    return vec4<f32>(textureSampleLevel(source_texture, bake_sampler, tex_coords, bake.source_lod).rgb, 1.0);
+}
+
+@fragment
+fn fs_face(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSampleLevel(source_texture, bake_sampler, in.tex_coords, bake.source_lod).rgb, 1.0);
+}
+
+// Cosine weighted average of the light arriving from the hemisphere around the normal,
+// the diffuse light of a surface is this multiplied by its albedo
+@fragment
+fn fs_irradiance(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = face_direction(bake.face, in.tex_coords);
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if abs(normal.y) > 0.999 {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let right = normalize(cross(up, normal));
+    up = cross(normal, right);
+
+    // Sampling a mip level whose texels are as far apart as the samples averages everything in between
+    let lod = max(log2(IRRADIANCE_SAMPLE_DELTA * bake.source_size / (0.5 * PI)), 0.0);
+
+    var irradiance = vec3<f32>(0.0);
+    var sample_count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += IRRADIANCE_SAMPLE_DELTA) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += IRRADIANCE_SAMPLE_DELTA) {
+            let tangent_direction = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let direction = tangent_direction.x * right + tangent_direction.y * up + tangent_direction.z * normal;
+            // Light from grazing angles counts less, as does the smaller area near the pole
+            irradiance += textureSampleLevel(source_cube, bake_sampler, direction, lod).rgb * cos(theta) * sin(theta);
+            sample_count += 1.0;
+        }
+    }
+    return vec4<f32>(PI * irradiance / sample_count, 1.0);
+}
+
+// Low discrepancy sequence that spreads the samples evenly
+fn hammersley(index: u32, count: u32) -> vec2<f32> {
+    var bits = index;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2<f32>(f32(index) / f32(count), f32(bits) * 2.3283064365386963e-10);
+}
+
+// Half vector around the normal distributed like the microfacets of a GGX surface
+fn importance_sample_ggx(xi: vec2<f32>, normal: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let alpha = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+
+    var up = vec3<f32>(1.0, 0.0, 0.0);
+    if abs(normal.z) < 0.999 {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return normalize(tangent * cos(phi) * sin_theta + bitangent * sin(phi) * sin_theta + normal * cos_theta);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let alpha = roughness * roughness;
+    let alpha_squared = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha_squared - 1.0) + 1.0;
+    return alpha_squared / (PI * denominator * denominator);
+}
+
+// Light reflected towards the viewer by a surface of the given roughness, assuming the viewer looks
+// along the normal. Every mip level of the prefiltered map holds the next step in roughness.
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = face_direction(bake.face, in.tex_coords);
+    if bake.roughness == 0.0 {
+        return vec4<f32>(textureSampleLevel(source_cube, bake_sampler, normal, bake.source_lod).rgb, 1.0);
+    }
+
+    // Samples in directions few others go to read from a blurrier mip level to avoid bright speckles
+    let texel_solid_angle = 4.0 * PI / (6.0 * bake.source_size * bake.source_size);
+
+    var color = vec3<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < PREFILTER_SAMPLE_COUNT; i++) {
+        let half_vector = importance_sample_ggx(hammersley(i, PREFILTER_SAMPLE_COUNT), normal, bake.roughness);
+        let light_direction = normalize(2.0 * dot(normal, half_vector) * half_vector - normal);
+        let n_dot_l = dot(normal, light_direction);
+        if n_dot_l > 0.0 {
+            let n_dot_h = max(dot(normal, half_vector), 0.0);
+            let pdf = distribution_ggx(n_dot_h, bake.roughness) * 0.25 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(PREFILTER_SAMPLE_COUNT) * pdf + 0.0001);
+            let lod = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+
+            color += textureSampleLevel(source_cube, bake_sampler, light_direction, lod).rgb * n_dot_l;
+            total_weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(color / max(total_weight, 0.0001), 1.0);
+}
+
+// Smith geometry term with the k image based lighting uses
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness * 0.5;
+    let view_term = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let light_term = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return view_term * light_term;
+}
+
+// Scale and bias applied to the reflectance at normal incidence, looked up by the angle
+// between the normal and the view direction along x and the roughness along y
+@fragment
+fn fs_brdf_lookup(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n_dot_v = max(in.tex_coords.x, 0.0001);
+    let roughness = in.tex_coords.y;
+    let view_direction = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let normal = vec3<f32>(0.0, 0.0, 1.0);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < BRDF_SAMPLE_COUNT; i++) {
+        let half_vector = importance_sample_ggx(hammersley(i, BRDF_SAMPLE_COUNT), normal, roughness);
+        let light_direction = normalize(2.0 * dot(view_direction, half_vector) * half_vector - view_direction);
+        let n_dot_l = max(light_direction.z, 0.0);
+        if n_dot_l > 0.0 {
+            let n_dot_h = max(half_vector.z, 0.0);
+            let v_dot_h = max(dot(view_direction, half_vector), 0.0);
+            let visibility = geometry_smith(n_dot_v, n_dot_l, roughness) * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+    return vec4<f32>(scale, bias, 0.0, 1.0) / vec4<f32>(f32(BRDF_SAMPLE_COUNT), f32(BRDF_SAMPLE_COUNT), 1.0, 1.0);
+}
@@ -3,6 +3,7 @@ use winit::{event_loop::ActiveEventLoop, keyboard::KeyCode};
 
 use crate::ecs::entity::scene::Scene;
 
+#[derive(Default)]
 pub struct InputService {}
 
 impl InputService {
@@ -0,0 +1,8 @@
+pub mod animation;
+pub mod application;
+pub mod asset;
+pub mod debug_draw;
+pub mod ecs;
+pub mod input;
+pub mod physics;
+pub mod rendering;
@@ -2,13 +2,7 @@ use dotenv::dotenv;
 use log::info;
 use winit::event_loop::{ControlFlow, EventLoop};
 
-use crate::application::Application;
-
-mod application;
-mod ecs;
-mod input;
-mod physics;
-mod rendering;
+use daedalus_engine::application::Application;
 
 #[pollster::main]
 async fn main() {
@@ -23,7 +17,7 @@ async fn main() {
     event_loop.set_control_flow(ControlFlow::Poll);
 
     let mut app: Application = Application::new(800, 600);
-    let _ = event_loop
+    event_loop
         .run_app(&mut app)
         .expect("Failed to run application");
 }
@@ -0,0 +1,13 @@
+// Downsamples one mip level into the next by drawing a fullscreen triangle
+#include "fullscreen.wgsl"
+
+@group(0) @binding(0)
+var source_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Linear filtering at the center of four source texels averages them
+    return textureSample(source_texture, source_sampler, in.tex_coords);
+}
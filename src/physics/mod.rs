@@ -1,12 +1,17 @@
 use glam::Vec3;
 
-use crate::ecs::entity::scene::Scene;
+use crate::{debug_draw::DebugStyle, ecs::entity::scene::Scene};
 
-pub struct PhysicsService {}
+#[derive(Default)]
+pub struct PhysicsService {
+    pub draw_velocities: bool, // Queues an arrow along the velocity of every moving entity on the debug draw
+}
 
 impl PhysicsService {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            draw_velocities: false,
+        }
     }
 
     pub fn handle_physics(&self, scene: &mut Scene, delta_time: f32) {
@@ -47,6 +52,19 @@ impl PhysicsService {
             if let Some(transform_component) = scene.transform_components.get_mut(current_entity) {
                 transform_component.position.x += physics_component.velocity.x * delta_time;
                 transform_component.position.y += physics_component.velocity.y * delta_time;
+
+                if self.draw_velocities && physics_component.velocity != Vec3::ZERO {
+                    let position = transform_component.position;
+                    scene.debug_draw.arrow(
+                        position,
+                        position + physics_component.velocity,
+                        DebugStyle {
+                            color: Vec3::new(1.0, 1.0, 0.0),
+                            duration: 0.0,
+                            depth_test: false,
+                        },
+                    );
+                }
             }
         }
     }
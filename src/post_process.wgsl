@@ -0,0 +1,286 @@
+// Post processing passes drawn with a single triangle covering the screen.
+// Bloom blurs the bright parts of the HDR frame through a chain of half sized textures,
+// the composite pass tone maps the frame into display colors and FXAA smooths the result.
+struct PostProcessUniform {
+    vignette_color: vec4<f32>, // w is unused
+    exposure: f32,
+    tonemapping: u32, // 0 none, 1 Reinhard, 2 ACES, 3 AgX
+    bloom_intensity: f32,
+    bloom_threshold: f32,
+    bloom_soft_knee: f32,
+    lut_strength: f32,
+    lut_size: f32,
+    vignette_intensity: f32,
+    vignette_radius: f32,
+    vignette_smoothness: f32,
+};
+
+@group(0) @binding(0)
+var source_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler; // Linear filtering clamped to the edges
+@group(0) @binding(2)
+var<uniform> settings: PostProcessUniform;
+
+// Only used by the composite pass
+@group(1) @binding(0)
+var bloom_texture: texture_2d<f32>;
+@group(1) @binding(1)
+var lut_texture: texture_3d<f32>;
+
+// Distance in texels of the upsampling taps, larger values spread the bloom further
+const BLOOM_FILTER_RADIUS: f32 = 1.0;
+
+// FXAA tuning, the values of the reference implementation
+const FXAA_REDUCE_MIN: f32 = 1.0 / 128.0;
+const FXAA_REDUCE_MUL: f32 = 1.0 / 8.0;
+const FXAA_SPAN_MAX: f32 = 8.0;
+
+#include "fullscreen.wgsl"
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+fn sample_source(tex_coords: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(source_texture, source_sampler, tex_coords, 0.0).rgb;
+}
+
+// Weighs a group of samples down by their brightness so single very bright pixels don't flicker
+fn karis_average(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>, d: vec3<f32>) -> vec3<f32> {
+    let weight_a = 1.0 / (1.0 + luminance(a));
+    let weight_b = 1.0 / (1.0 + luminance(b));
+    let weight_c = 1.0 / (1.0 + luminance(c));
+    let weight_d = 1.0 / (1.0 + luminance(d));
+    return (a * weight_a + b * weight_b + c * weight_c + d * weight_d) / (weight_a + weight_b + weight_c + weight_d);
+}
+
+// 13 taps arranged as overlapping 2x2 boxes, which keeps the blur stable as the camera moves
+fn downsample(tex_coords: vec2<f32>, use_karis_average: bool) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source_texture));
+
+    let a = sample_source(tex_coords + texel * vec2<f32>(-2.0, -2.0));
+    let b = sample_source(tex_coords + texel * vec2<f32>(0.0, -2.0));
+    let c = sample_source(tex_coords + texel * vec2<f32>(2.0, -2.0));
+    let d = sample_source(tex_coords + texel * vec2<f32>(-2.0, 0.0));
+    let e = sample_source(tex_coords);
+    let f = sample_source(tex_coords + texel * vec2<f32>(2.0, 0.0));
+    let g = sample_source(tex_coords + texel * vec2<f32>(-2.0, 2.0));
+    let h = sample_source(tex_coords + texel * vec2<f32>(0.0, 2.0));
+    let i = sample_source(tex_coords + texel * vec2<f32>(2.0, 2.0));
+    let j = sample_source(tex_coords + texel * vec2<f32>(-1.0, -1.0));
+    let k = sample_source(tex_coords + texel * vec2<f32>(1.0, -1.0));
+    let l = sample_source(tex_coords + texel * vec2<f32>(-1.0, 1.0));
+    let m = sample_source(tex_coords + texel * vec2<f32>(1.0, 1.0));
+
+    if use_karis_average {
+        let center = karis_average(j, k, l, m);
+        let top_left = karis_average(a, b, d, e);
+        let top_right = karis_average(b, c, e, f);
+        let bottom_left = karis_average(d, e, g, h);
+        let bottom_right = karis_average(e, f, h, i);
+        return center * 0.5 + (top_left + top_right + bottom_left + bottom_right) * 0.125;
+    }
+
+    return e * 0.125
+        + (a + c + g + i) * 0.03125
+        + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+}
+
+// Keeps the colors above the threshold, fading in over the soft knee below it
+fn bloom_prefilter(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = settings.bloom_soft_knee;
+    var soft = clamp(brightness - settings.bloom_threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 0.00001);
+    let contribution = max(soft, brightness - settings.bloom_threshold) / max(brightness, 0.00001);
+    return color * contribution;
+}
+
+// First step of the bloom chain, reads the full resolution HDR frame
+@fragment
+fn fs_bloom_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(bloom_prefilter(downsample(in.tex_coords, true)), 1.0);
+}
+
+@fragment
+fn fs_bloom_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.tex_coords, false), 1.0);
+}
+
+// 3x3 tent filter over the next smaller level, added onto the larger level by the blend state
+@fragment
+fn fs_bloom_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let offset = BLOOM_FILTER_RADIUS / vec2<f32>(textureDimensions(source_texture));
+
+    var color = sample_source(in.tex_coords) * 4.0;
+    color += (sample_source(in.tex_coords + vec2<f32>(0.0, -offset.y))
+        + sample_source(in.tex_coords + vec2<f32>(-offset.x, 0.0))
+        + sample_source(in.tex_coords + vec2<f32>(offset.x, 0.0))
+        + sample_source(in.tex_coords + vec2<f32>(0.0, offset.y))) * 2.0;
+    color += sample_source(in.tex_coords + vec2<f32>(-offset.x, -offset.y))
+        + sample_source(in.tex_coords + vec2<f32>(offset.x, -offset.y))
+        + sample_source(in.tex_coords + vec2<f32>(-offset.x, offset.y))
+        + sample_source(in.tex_coords + vec2<f32>(offset.x, offset.y));
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+fn tonemap_reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color);
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms.
+// The matrices are written row by row and applied as v * M.
+fn tonemap_aces(color: vec3<f32>) -> vec3<f32> {
+    let input_matrix = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.35458, 0.04823),
+        vec3<f32>(0.07600, 0.90834, 0.01566),
+        vec3<f32>(0.02840, 0.13383, 0.83777),
+    );
+    let output_matrix = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.53108, -0.07367),
+        vec3<f32>(-0.10208, 1.10813, -0.00605),
+        vec3<f32>(-0.00327, -0.07276, 1.07602),
+    );
+
+    let v = color * input_matrix;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp((a / b) * output_matrix, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Polynomial fit of the AgX base contrast curve
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn tonemap_agx(color: vec3<f32>) -> vec3<f32> {
+    let inset_matrix = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset_matrix = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    // Compress the log encoded color into 0 to 1 and apply the contrast curve
+    var v = inset_matrix * color;
+    v = clamp(log2(max(v, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    v = agx_contrast((v - min_ev) / (max_ev - min_ev));
+
+    // The curve outputs display encoded values
+    v = outset_matrix * v;
+    return pow(clamp(v, vec3<f32>(0.0), vec3<f32>(1.0)), vec3<f32>(2.2));
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
+
+// Lookup tables are authored on display encoded colors
+fn color_grade(color: vec3<f32>) -> vec3<f32> {
+    let encoded = linear_to_srgb(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)));
+
+    // Sample between the centers of the first and last texels so the ends map onto themselves
+    let scale = (settings.lut_size - 1.0) / settings.lut_size;
+    let offset = 0.5 / settings.lut_size;
+    let graded = textureSampleLevel(lut_texture, source_sampler, encoded * scale + offset, 0.0).rgb;
+
+    return srgb_to_linear(mix(encoded, graded, settings.lut_strength));
+}
+
+// Turns the HDR frame and its bloom into display colors, written to an sRGB target
+@fragment
+fn fs_composite(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color = sample_source(in.tex_coords);
+    color += textureSampleLevel(bloom_texture, source_sampler, in.tex_coords, 0.0).rgb * settings.bloom_intensity;
+    color = max(color * settings.exposure, vec3<f32>(0.0));
+
+    switch settings.tonemapping {
+        case 1u: {
+            color = tonemap_reinhard(color);
+        }
+        case 2u: {
+            color = tonemap_aces(color);
+        }
+        case 3u: {
+            color = tonemap_agx(color);
+        }
+        default: {
+            color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+
+    if settings.lut_strength > 0.0 {
+        color = color_grade(color);
+    }
+
+    // 0 at the center and 1 in the corners
+    let distance_to_center = length(in.tex_coords - 0.5) * sqrt(2.0);
+    let vignette = settings.vignette_intensity
+        * smoothstep(settings.vignette_radius, settings.vignette_radius + max(settings.vignette_smoothness, 0.0001), distance_to_center);
+    color = mix(color, settings.vignette_color.rgb, vignette);
+
+    return vec4<f32>(color, 1.0);
+}
+
+// Luminance of the display encoded color, which is what the eye judges edges by
+fn fxaa_luma(color: vec3<f32>) -> f32 {
+    return sqrt(luminance(color));
+}
+
+// Blurs along edges found by comparing the luminance of the diagonal neighbours
+@fragment
+fn fs_fxaa(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source_texture));
+
+    let color_middle = sample_source(in.tex_coords);
+    let luma_north_west = fxaa_luma(sample_source(in.tex_coords + texel * vec2<f32>(-1.0, -1.0)));
+    let luma_north_east = fxaa_luma(sample_source(in.tex_coords + texel * vec2<f32>(1.0, -1.0)));
+    let luma_south_west = fxaa_luma(sample_source(in.tex_coords + texel * vec2<f32>(-1.0, 1.0)));
+    let luma_south_east = fxaa_luma(sample_source(in.tex_coords + texel * vec2<f32>(1.0, 1.0)));
+    let luma_middle = fxaa_luma(color_middle);
+
+    let luma_min = min(luma_middle, min(min(luma_north_west, luma_north_east), min(luma_south_west, luma_south_east)));
+    let luma_max = max(luma_middle, max(max(luma_north_west, luma_north_east), max(luma_south_west, luma_south_east)));
+
+    // The edge runs perpendicular to the direction the luminance changes in
+    var direction = vec2<f32>(
+        -((luma_north_west + luma_north_east) - (luma_south_west + luma_south_east)),
+        (luma_north_west + luma_south_west) - (luma_north_east + luma_south_east),
+    );
+    let direction_reduce = max(
+        (luma_north_west + luma_north_east + luma_south_west + luma_south_east) * 0.25 * FXAA_REDUCE_MUL,
+        FXAA_REDUCE_MIN,
+    );
+    let inverse_direction_min = 1.0 / (min(abs(direction.x), abs(direction.y)) + direction_reduce);
+    direction = clamp(direction * inverse_direction_min, vec2<f32>(-FXAA_SPAN_MAX), vec2<f32>(FXAA_SPAN_MAX)) * texel;
+
+    let color_near = 0.5 * (sample_source(in.tex_coords + direction * (1.0 / 3.0 - 0.5))
+        + sample_source(in.tex_coords + direction * (2.0 / 3.0 - 0.5)));
+    let color_far = color_near * 0.5 + 0.25 * (sample_source(in.tex_coords - direction * 0.5)
+        + sample_source(in.tex_coords + direction * 0.5));
+
+    // Sampling too far may cross into another edge, fall back to the closer samples then
+    let luma_far = fxaa_luma(color_far);
+    if luma_far < luma_min || luma_far > luma_max {
+        return vec4<f32>(color_near, 1.0);
+    }
+    return vec4<f32>(color_far, 1.0);
+}
@@ -0,0 +1,171 @@
+use wgpu::{
+    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    ColorTargetState, ColorWrites, CommandEncoder, Device, FragmentState, MultisampleState,
+    PipelineCache, PipelineCompilationOptions, PipelineLayout, PipelineLayoutDescriptor,
+    PrimitiveState, RenderPassColorAttachment, RenderPipeline, RenderPipelineDescriptor, Sampler,
+    SamplerBindingType, ShaderModule, ShaderStages, TextureFormat, TextureSampleType, TextureView,
+    TextureViewDimension, VertexState,
+};
+
+use crate::rendering::shader::capture_validation_errors;
+
+// Copies a texture onto a render target, converting between formats and sizes on the way
+pub struct BlitPipeline {
+    pipeline_layout: PipelineLayout,
+    pipeline_cache: Option<PipelineCache>,
+    pipeline: RenderPipeline,
+    target_format: TextureFormat,
+    bind_group_layout: BindGroupLayout,
+    sampler: Sampler,
+}
+
+impl BlitPipeline {
+    pub fn new(
+        device: &Device,
+        shader: ShaderModule,
+        target_format: TextureFormat,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("Blit bind group layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Blit Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            target_format,
+            pipeline_cache,
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Blit Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            pipeline_layout,
+            pipeline_cache: pipeline_cache.cloned(),
+            pipeline,
+            target_format,
+            bind_group_layout,
+            sampler,
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        pipeline_layout: &PipelineLayout,
+        shader: &ShaderModule,
+        target_format: TextureFormat,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Blit Render Pipeline"),
+            layout: Some(pipeline_layout),
+            // The triangle is generated from the vertex index, no vertex buffer needed
+            vertex: VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(ColorTargetState {
+                    format: target_format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
+            cache: pipeline_cache,
+        })
+    }
+
+    // Swaps in a recompiled shader, the current pipeline is kept when the new one fails to build
+    pub fn reload_shader(&mut self, device: &Device, shader: ShaderModule) -> anyhow::Result<()> {
+        self.pipeline = capture_validation_errors(device, || {
+            Self::create_pipeline(
+                device,
+                &self.pipeline_layout,
+                &shader,
+                self.target_format,
+                self.pipeline_cache.as_ref(),
+            )
+        })?;
+
+        Ok(())
+    }
+
+    pub fn blit(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        source: &TextureView,
+        target: &TextureView,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some("Blit bind group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit Render Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,10 @@
+// The capacity a buffer holding current entries should be recreated with to fit the required
+// number of entries, or current when it already fits. Grows by doubling so resizing stays rare.
+pub fn grow_capacity(current: u64, required: u64) -> u64 {
+    let mut capacity = current.max(1);
+    while capacity < required {
+        capacity *= 2;
+    }
+
+    capacity
+}
@@ -1,18 +1,23 @@
 use bytemuck::{Pod, Zeroable};
 use glam::Mat4;
 
-use crate::ecs::component::{camera::CameraComponent, transform::TransformComponent};
+use crate::{
+    ecs::component::{camera::CameraComponent, transform::TransformComponent},
+    rendering::depth::DepthSettings,
+};
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Pod, Zeroable)]
 pub struct CameraUniform {
     pub view_projection_matrix: [[f32; 4]; 4], // 4x4 matrix for the view-projection transformation
+    pub camera_position: [f32; 4], // World space position, w is unused and pads the field to 16 bytes
 }
 
 impl CameraUniform {
     pub fn new() -> Self {
         Self {
             view_projection_matrix: Mat4::IDENTITY.to_cols_array_2d(),
+            camera_position: [0.0; 4],
         }
     }
 
@@ -20,9 +25,11 @@ impl CameraUniform {
         &mut self,
         camera_component: &CameraComponent,
         transform_component: &TransformComponent,
+        depth_settings: &DepthSettings,
     ) {
-        self.view_projection_matrix = camera_component
-            .calculate_view_projection_matrix(transform_component)
-            .to_cols_array_2d();
+        self.view_projection_matrix = (depth_settings.projection_correction()
+            * camera_component.calculate_view_projection_matrix(transform_component))
+        .to_cols_array_2d();
+        self.camera_position = transform_component.position.extend(1.0).to_array();
     }
 }
@@ -0,0 +1,275 @@
+use bytemuck::{Pod, Zeroable, cast_slice};
+use log::debug;
+use wgpu::{
+    BindGroupLayout, Buffer, BufferAddress, BufferDescriptor, BufferUsages, ColorTargetState,
+    ColorWrites, CompareFunction, Device, FragmentState, MultisampleState, PipelineCache,
+    PipelineCompilationOptions, PipelineLayout, PipelineLayoutDescriptor, PrimitiveState,
+    PrimitiveTopology, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor, ShaderModule,
+    TextureFormat, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState,
+};
+
+use crate::{
+    debug_draw::DebugDraw,
+    rendering::{buffer::grow_capacity, depth::DepthSettings, shader::capture_validation_errors},
+};
+
+// Number of lines the vertex buffer can hold before it has to grow
+const INITIAL_LINE_CAPACITY: u64 = 1024;
+
+const ATTRIBUTES: &[VertexAttribute] = &[
+    VertexAttribute {
+        offset: 0,
+        shader_location: 0,
+        format: VertexFormat::Float32x3, // World space position
+    },
+    VertexAttribute {
+        offset: 12,
+        shader_location: 1,
+        format: VertexFormat::Float32x3, // Linear RGB
+    },
+];
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Pod, Zeroable)]
+struct DebugLineVertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl DebugLineVertex {
+    fn describe_vertex_buffer_layout() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<DebugLineVertex>() as BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: ATTRIBUTES,
+        }
+    }
+}
+
+// Draws the lines of the debug draw, streamed into a vertex buffer every frame
+pub struct DebugLineRenderer {
+    shader: ShaderModule,
+    pipeline_layout: PipelineLayout,
+    pipeline_cache: Option<PipelineCache>,
+    depth_tested_pipeline: RenderPipeline,
+    overlay_pipeline: RenderPipeline,
+    vertex_buffer: Buffer,
+    capacity: u64,              // Number of lines the vertex buffer can hold
+    depth_tested_vertices: u32, // Depth tested lines go first in the vertex buffer, followed by the overlay lines
+    overlay_vertices: u32,
+}
+
+impl DebugLineRenderer {
+    pub fn new(
+        device: &Device,
+        shader: ShaderModule,
+        camera_bind_group_layout: &BindGroupLayout,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Debug Line Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let (depth_tested_pipeline, overlay_pipeline) = Self::create_pipelines(
+            device,
+            &pipeline_layout,
+            &shader,
+            color_format,
+            depth_settings,
+            sample_count,
+            pipeline_cache,
+        );
+
+        Self {
+            shader,
+            pipeline_layout,
+            pipeline_cache: pipeline_cache.cloned(),
+            depth_tested_pipeline,
+            overlay_pipeline,
+            vertex_buffer: Self::create_vertex_buffer(device, INITIAL_LINE_CAPACITY),
+            capacity: INITIAL_LINE_CAPACITY,
+            depth_tested_vertices: 0,
+            overlay_vertices: 0,
+        }
+    }
+
+    // Lines never write depth, depth tested ones are hidden behind geometry while overlay ones are drawn over it
+    fn create_pipelines(
+        device: &Device,
+        pipeline_layout: &PipelineLayout,
+        shader: &ShaderModule,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> (RenderPipeline, RenderPipeline) {
+        let create_pipeline = |label, depth_compare| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(pipeline_layout),
+                vertex: VertexState {
+                    module: shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    buffers: &[DebugLineVertex::describe_vertex_buffer_layout()],
+                },
+                fragment: Some(FragmentState {
+                    module: shader,
+                    entry_point: Some("fs_main"),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    targets: &[Some(ColorTargetState {
+                        format: color_format,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState {
+                    topology: PrimitiveTopology::LineList, // Every two vertices form a line
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    depth_write_enabled: false,
+                    depth_compare,
+                    ..depth_settings.depth_stencil_state()
+                }),
+                multisample: MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: pipeline_cache,
+            })
+        };
+
+        (
+            create_pipeline(
+                "Depth Tested Debug Line Render Pipeline",
+                depth_settings.depth_stencil_state().depth_compare,
+            ),
+            create_pipeline(
+                "Overlay Debug Line Render Pipeline",
+                CompareFunction::Always,
+            ),
+        )
+    }
+
+    // Depth state and the sample count are baked into the pipelines
+    pub fn recreate_pipelines(
+        &mut self,
+        device: &Device,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+    ) {
+        (self.depth_tested_pipeline, self.overlay_pipeline) = Self::create_pipelines(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            color_format,
+            depth_settings,
+            sample_count,
+            self.pipeline_cache.as_ref(),
+        );
+    }
+
+    // Swaps in a recompiled shader, the current pipelines are kept when the new ones fail to build
+    pub fn reload_shader(
+        &mut self,
+        device: &Device,
+        shader: ShaderModule,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+    ) -> anyhow::Result<()> {
+        (self.depth_tested_pipeline, self.overlay_pipeline) =
+            capture_validation_errors(device, || {
+                Self::create_pipelines(
+                    device,
+                    &self.pipeline_layout,
+                    &shader,
+                    color_format,
+                    depth_settings,
+                    sample_count,
+                    self.pipeline_cache.as_ref(),
+                )
+            })?;
+        self.shader = shader;
+
+        Ok(())
+    }
+
+    fn create_vertex_buffer(device: &Device, capacity: u64) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("Debug Line Vertex Buffer"),
+            size: capacity * 2 * std::mem::size_of::<DebugLineVertex>() as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn prepare(&mut self, device: &Device, queue: &Queue, debug_draw: &DebugDraw) {
+        let lines = debug_draw.lines();
+        self.depth_tested_vertices = 0;
+        self.overlay_vertices = 0;
+        if lines.is_empty() {
+            return;
+        }
+
+        let new_capacity = grow_capacity(self.capacity, lines.len() as u64);
+        if new_capacity > self.capacity {
+            debug!("Growing debug line buffer to {} lines", new_capacity);
+            self.vertex_buffer = Self::create_vertex_buffer(device, new_capacity);
+            self.capacity = new_capacity;
+        }
+
+        let mut vertices = Vec::with_capacity(lines.len() * 2);
+        for depth_test in [true, false] {
+            for line in lines.iter().filter(|line| line.depth_test == depth_test) {
+                let color = line.color.to_array();
+                vertices.push(DebugLineVertex {
+                    position: line.start.to_array(),
+                    color,
+                });
+                vertices.push(DebugLineVertex {
+                    position: line.end.to_array(),
+                    color,
+                });
+            }
+            if depth_test {
+                self.depth_tested_vertices = vertices.len() as u32;
+            }
+        }
+        self.overlay_vertices = vertices.len() as u32 - self.depth_tested_vertices;
+
+        queue.write_buffer(&self.vertex_buffer, 0, cast_slice(&vertices));
+    }
+
+    // Expects the camera bind group to be set, returns the number of draws submitted
+    pub fn render(&self, render_pass: &mut RenderPass) -> u32 {
+        let mut draw_calls = 0;
+        let ranges = [
+            (&self.depth_tested_pipeline, 0..self.depth_tested_vertices),
+            (
+                &self.overlay_pipeline,
+                self.depth_tested_vertices..self.depth_tested_vertices + self.overlay_vertices,
+            ),
+        ];
+        for (pipeline, vertices) in ranges {
+            if vertices.is_empty() {
+                continue;
+            }
+
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.draw(vertices, 0..1);
+            draw_calls += 1;
+        }
+
+        draw_calls
+    }
+}
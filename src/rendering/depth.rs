@@ -0,0 +1,70 @@
+use glam::{Mat4, Vec4};
+use wgpu::{CompareFunction, DepthBiasState, DepthStencilState, StencilState, TextureFormat};
+
+pub const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+
+// Flips depth so the near plane is at 1 and the far plane at 0.
+// Floats are most precise close to 0, which reverse-Z spends on distant geometry
+// where the perspective divide otherwise leaves very little precision.
+const REVERSE_Z_MATRIX: Mat4 = Mat4::from_cols(
+    Vec4::new(1.0, 0.0, 0.0, 0.0),
+    Vec4::new(0.0, 1.0, 0.0, 0.0),
+    Vec4::new(0.0, 0.0, -1.0, 0.0),
+    Vec4::new(0.0, 0.0, 1.0, 1.0),
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DepthSettings {
+    pub compare_function: CompareFunction, // Written as if depth increases away from the camera
+    pub reverse_z: bool,
+}
+
+impl Default for DepthSettings {
+    fn default() -> Self {
+        Self {
+            compare_function: CompareFunction::Less,
+            reverse_z: false,
+        }
+    }
+}
+
+impl DepthSettings {
+    // Reverse-Z inverts the depth ordering, so the comparison has to be inverted with it
+    pub fn effective_compare_function(&self) -> CompareFunction {
+        if !self.reverse_z {
+            return self.compare_function;
+        }
+
+        match self.compare_function {
+            CompareFunction::Less => CompareFunction::Greater,
+            CompareFunction::LessEqual => CompareFunction::GreaterEqual,
+            CompareFunction::Greater => CompareFunction::Less,
+            CompareFunction::GreaterEqual => CompareFunction::LessEqual,
+            other => other,
+        }
+    }
+
+    // The depth value furthest away from the camera
+    pub fn clear_depth(&self) -> f32 {
+        if self.reverse_z { 0.0 } else { 1.0 }
+    }
+
+    // Applied after the camera's projection matrix
+    pub fn projection_correction(&self) -> Mat4 {
+        if self.reverse_z {
+            REVERSE_Z_MATRIX
+        } else {
+            Mat4::IDENTITY
+        }
+    }
+
+    pub fn depth_stencil_state(&self) -> DepthStencilState {
+        DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: self.effective_compare_function(),
+            stencil: StencilState::default(), // Not using stencil
+            bias: DepthBiasState::default(),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use log::{debug, error};
+use wgpu::{Device, DeviceLostReason};
+
+// Set once the device can't be rendered with anymore, the rendering service then recreates it
+#[derive(Debug, Clone, Default)]
+pub struct DeviceLost {
+    is_lost: Arc<AtomicBool>,
+}
+
+impl DeviceLost {
+    // Watches the device for loss and for running out of memory. Calls on a lost device fail validation,
+    // those errors are expected until the device is recreated, any other uncaptured error still panics.
+    pub fn watch(device: &Device) -> Self {
+        let device_lost = Self::default();
+
+        let is_lost = device_lost.is_lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            // Dropping the device reports it as destroyed, which is not a loss
+            if reason != DeviceLostReason::Destroyed {
+                error!("GPU device lost: {}", message);
+                is_lost.store(true, Ordering::Relaxed);
+            }
+        });
+
+        let is_lost = device_lost.is_lost.clone();
+        device.on_uncaptured_error(Box::new(move |e| match e {
+            wgpu::Error::OutOfMemory { .. } => {
+                error!("GPU out of memory: {}", e);
+                is_lost.store(true, Ordering::Relaxed);
+            }
+            _ if is_lost.load(Ordering::Relaxed) => {
+                debug!("Ignoring error on a lost device: {}", e)
+            }
+            _ => panic!("Uncaptured GPU error: {}", e),
+        }));
+
+        device_lost
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.is_lost.load(Ordering::Relaxed)
+    }
+
+    // Surface errors that mean the device ran out of memory are handled like a loss
+    pub fn set_lost(&self) {
+        self.is_lost.store(true, Ordering::Relaxed);
+    }
+}
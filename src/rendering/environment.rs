@@ -0,0 +1,795 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use bytemuck::{Pod, Zeroable, cast_slice};
+use glam::{Mat4, Vec3};
+use half::f16;
+use log::{debug, error};
+use wgpu::{
+    AddressMode, BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+    BindingType, Buffer, BufferBindingType, BufferUsages, ColorTargetState, ColorWrites,
+    CommandEncoder, Device, Extent3d, FilterMode, FragmentState, MultisampleState, PipelineCache,
+    PipelineCompilationOptions, PipelineLayout, PipelineLayoutDescriptor, PrimitiveState, Queue,
+    RenderPassColorAttachment, RenderPipeline, RenderPipelineDescriptor, Sampler,
+    SamplerBindingType, SamplerDescriptor, ShaderModule, ShaderStages, Texture, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView,
+    TextureViewDescriptor, TextureViewDimension, VertexState,
+    util::{BufferInitDescriptor, DeviceExt, TextureDataOrder},
+};
+
+use crate::{
+    asset::{AssetService, TextureHandle},
+    ecs::component::camera::{EnvironmentMap, EnvironmentSettings},
+    rendering::{
+        depth::DepthSettings, mipmap::MipmapGenerator, shader::capture_validation_errors,
+        texture::GpuTexture,
+    },
+};
+
+// Half floats keep the range of HDR skies, a sun is far brighter than white
+const ENVIRONMENT_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+const BRDF_LOOKUP_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+
+// Size of the faces the environment map is converted into, derived from the size of the source
+const MIN_ENVIRONMENT_SIZE: u32 = 64;
+const MAX_ENVIRONMENT_SIZE: u32 = 2048;
+const IRRADIANCE_SIZE: u32 = 32; // Diffuse light barely changes between directions
+const PREFILTERED_SIZE: u32 = 128;
+const PREFILTERED_MIP_COUNT: u32 = 5; // Roughness goes from 0 at the first level to 1 at the last one
+const BRDF_LOOKUP_SIZE: u32 = 256;
+
+// Light from every direction when the camera has no environment map
+const DEFAULT_AMBIENT_COLOR: Vec3 = Vec3::splat(0.03);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct EnvironmentUniform {
+    inverse_view_projection_matrix: [[f32; 4]; 4], // Turns pixels of the skybox back into view directions
+    intensity: f32,
+    max_reflection_lod: f32, // Mip level of the prefiltered map that the roughest surfaces reflect
+    near_depth: f32,
+    _padding: f32,
+}
+
+// Settings of a single bake draw
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct BakeUniform {
+    face: u32,
+    roughness: f32,
+    source_lod: f32,  // Mip level of the source that matches the size of the target
+    source_size: f32, // Size of a face of the source cubemap in texels
+}
+
+// Maps the main pass reads for one environment, bound to group 3 of the material pipelines
+// and to group 0 of the skybox pipeline
+struct GpuEnvironment {
+    bind_group: BindGroup,
+}
+
+struct BakePipelines {
+    equirectangular: RenderPipeline,
+    face: RenderPipeline,
+    irradiance: RenderPipeline,
+    prefilter: RenderPipeline,
+    brdf_lookup: RenderPipeline,
+}
+
+// Bakes the environment map of the camera into the cubemaps image based lighting and the skybox sample
+pub struct EnvironmentMaps {
+    shader: ShaderModule,
+    bake_bind_group_layout: BindGroupLayout, // Settings of the draw and the sampler
+    source_texture_bind_group_layout: BindGroupLayout,
+    source_cube_bind_group_layout: BindGroupLayout,
+    texture_bake_pipeline_layout: PipelineLayout, // Bakes from an equirectangular image or cubemap face
+    cube_bake_pipeline_layout: PipelineLayout,    // Bakes from the converted cubemap
+    brdf_lookup_pipeline_layout: PipelineLayout,  // Generated from the texture coordinates alone
+    bind_group_layout: BindGroupLayout,
+    pipeline_cache: Option<PipelineCache>,
+    bake_pipelines: BakePipelines,
+    sampler: Sampler,
+    uniform_buffer: Buffer,
+    brdf_lookup_view: TextureView, // The same for every environment
+    default_environment: GpuEnvironment,
+    environments: HashMap<EnvironmentMap, Option<GpuEnvironment>>, // None when the map couldn't be baked
+    settings: Option<EnvironmentSettings>, // Environment of the current frame
+}
+
+impl EnvironmentMaps {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        shader: ShaderModule,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Self {
+        let texture_entry = |binding, view_dimension| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let uniform_entry = |binding, visibility| BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let sampler_entry = BindGroupLayoutEntry {
+            binding: 1,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Sampler(SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        let bake_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[uniform_entry(0, ShaderStages::FRAGMENT), sampler_entry],
+            label: Some("Environment bake bind group layout"),
+        });
+        let source_texture_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &[texture_entry(0, TextureViewDimension::D2)],
+                label: Some("Environment source texture bind group layout"),
+            });
+        let source_cube_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &[texture_entry(1, TextureViewDimension::Cube)],
+                label: Some("Environment source cube bind group layout"),
+            });
+        let texture_bake_pipeline_layout =
+            device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("Environment Texture Bake Pipeline Layout"),
+                bind_group_layouts: &[&bake_bind_group_layout, &source_texture_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let cube_bake_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Environment Cube Bake Pipeline Layout"),
+            bind_group_layouts: &[&bake_bind_group_layout, &source_cube_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let brdf_lookup_pipeline_layout =
+            device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                label: Some("BRDF Lookup Pipeline Layout"),
+                bind_group_layouts: &[],
+                push_constant_ranges: &[],
+            });
+
+        // The sky, irradiance and prefiltered cubemaps followed by the lookup table
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                uniform_entry(0, ShaderStages::VERTEX | ShaderStages::FRAGMENT),
+                sampler_entry,
+                texture_entry(2, TextureViewDimension::Cube),
+                texture_entry(3, TextureViewDimension::Cube),
+                texture_entry(4, TextureViewDimension::Cube),
+                texture_entry(5, TextureViewDimension::D2),
+            ],
+            label: Some("Environment bind group layout"),
+        });
+        let bake_pipelines = Self::create_bake_pipelines(
+            device,
+            &texture_bake_pipeline_layout,
+            &cube_bake_pipeline_layout,
+            &brdf_lookup_pipeline_layout,
+            &shader,
+            pipeline_cache,
+        );
+        // Faces are clamped at their edges, sampling between mip levels blurs rough reflections smoothly
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Environment Uniform Buffer"),
+            contents: cast_slice(&[EnvironmentUniform {
+                inverse_view_projection_matrix: Mat4::IDENTITY.to_cols_array_2d(),
+                intensity: 1.0,
+                max_reflection_lod: 0.0,
+                near_depth: 0.0,
+                _padding: 0.0,
+            }]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let brdf_lookup_view = Self::create_brdf_lookup(device, queue, &bake_pipelines.brdf_lookup);
+
+        // A single color cubemap stands in for every map, lighting the scene evenly
+        let pixel = DEFAULT_AMBIENT_COLOR
+            .extend(1.0)
+            .to_array()
+            .map(f16::from_f32);
+        let default_view = device
+            .create_texture_with_data(
+                queue,
+                &Self::cube_descriptor("Default Environment Cubemap", 1, 1),
+                TextureDataOrder::LayerMajor,
+                cast_slice(&[pixel; 6]),
+            )
+            .create_view(&Self::cube_view_descriptor());
+        let default_environment = GpuEnvironment {
+            bind_group: Self::create_bind_group(
+                device,
+                &bind_group_layout,
+                &uniform_buffer,
+                &sampler,
+                [&default_view, &default_view, &default_view],
+                &brdf_lookup_view,
+            ),
+        };
+
+        Self {
+            shader,
+            bake_bind_group_layout,
+            source_texture_bind_group_layout,
+            source_cube_bind_group_layout,
+            texture_bake_pipeline_layout,
+            cube_bake_pipeline_layout,
+            brdf_lookup_pipeline_layout,
+            bind_group_layout,
+            pipeline_cache: pipeline_cache.cloned(),
+            bake_pipelines,
+            sampler,
+            uniform_buffer,
+            brdf_lookup_view,
+            default_environment,
+            environments: HashMap::new(),
+            settings: None,
+        }
+    }
+
+    fn create_bake_pipelines(
+        device: &Device,
+        texture_bake_pipeline_layout: &PipelineLayout,
+        cube_bake_pipeline_layout: &PipelineLayout,
+        brdf_lookup_pipeline_layout: &PipelineLayout,
+        shader: &ShaderModule,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> BakePipelines {
+        let create_pipeline = |label, layout, entry_point, format| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: VertexState {
+                    module: shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    buffers: &[], // The fullscreen triangle is generated from the vertex index
+                },
+                fragment: Some(FragmentState {
+                    module: shader,
+                    entry_point: Some(entry_point),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    targets: &[Some(ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                multiview: None,
+                cache: pipeline_cache,
+            })
+        };
+
+        BakePipelines {
+            equirectangular: create_pipeline(
+                "Equirectangular Environment Render Pipeline",
+                texture_bake_pipeline_layout,
+                "fs_equirectangular",
+                ENVIRONMENT_FORMAT,
+            ),
+            face: create_pipeline(
+                "Cubemap Face Environment Render Pipeline",
+                texture_bake_pipeline_layout,
+                "fs_face",
+                ENVIRONMENT_FORMAT,
+            ),
+            irradiance: create_pipeline(
+                "Irradiance Render Pipeline",
+                cube_bake_pipeline_layout,
+                "fs_irradiance",
+                ENVIRONMENT_FORMAT,
+            ),
+            prefilter: create_pipeline(
+                "Prefiltered Environment Render Pipeline",
+                cube_bake_pipeline_layout,
+                "fs_prefilter",
+                ENVIRONMENT_FORMAT,
+            ),
+            brdf_lookup: create_pipeline(
+                "BRDF Lookup Render Pipeline",
+                brdf_lookup_pipeline_layout,
+                "fs_brdf_lookup",
+                BRDF_LOOKUP_FORMAT,
+            ),
+        }
+    }
+
+    // Swaps in a recompiled shader, every environment is baked again with it the next time it is used
+    pub fn reload_shader(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        shader: ShaderModule,
+    ) -> anyhow::Result<()> {
+        let (bake_pipelines, brdf_lookup_view) = capture_validation_errors(device, || {
+            let bake_pipelines = Self::create_bake_pipelines(
+                device,
+                &self.texture_bake_pipeline_layout,
+                &self.cube_bake_pipeline_layout,
+                &self.brdf_lookup_pipeline_layout,
+                &shader,
+                self.pipeline_cache.as_ref(),
+            );
+            let brdf_lookup_view =
+                Self::create_brdf_lookup(device, queue, &bake_pipelines.brdf_lookup);
+            (bake_pipelines, brdf_lookup_view)
+        })?;
+        self.bake_pipelines = bake_pipelines;
+        self.brdf_lookup_view = brdf_lookup_view;
+        self.shader = shader;
+        self.environments.clear();
+
+        Ok(())
+    }
+
+    // Group 3 of the material pipelines and group 0 of the skybox pipeline
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    // Bakes the environment map the first time it is used, which takes a moment for large maps
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        settings: Option<EnvironmentSettings>,
+        asset_service: &AssetService,
+        mipmap_generator: &mut MipmapGenerator,
+    ) {
+        self.settings = settings;
+
+        if let Some(settings) = settings
+            && !self.environments.contains_key(&settings.map)
+        {
+            let environment = self
+                .bake(device, queue, settings.map, asset_service, mipmap_generator)
+                .inspect_err(|e| {
+                    error!(
+                        "Failed to bake environment map {:?}, using the default ambient light: {:?}",
+                        settings.map, e
+                    )
+                })
+                .ok();
+            self.environments.insert(settings.map, environment);
+        }
+    }
+
+    // The skybox turns its pixels back into view directions with the camera of the current frame
+    pub fn update_uniform(
+        &self,
+        queue: &Queue,
+        view_projection_matrix: Mat4,
+        depth_settings: &DepthSettings,
+    ) {
+        let max_reflection_lod = if self.environment().is_some() {
+            (PREFILTERED_MIP_COUNT - 1) as f32
+        } else {
+            0.0
+        };
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            cast_slice(&[EnvironmentUniform {
+                inverse_view_projection_matrix: view_projection_matrix.inverse().to_cols_array_2d(),
+                intensity: self.settings.map_or(1.0, |settings| settings.intensity),
+                max_reflection_lod,
+                near_depth: 1.0 - depth_settings.clear_depth(),
+                _padding: 0.0,
+            }]),
+        );
+    }
+
+    // Baked maps of the environment of the current frame, None when there is none or it failed to bake
+    fn environment(&self) -> Option<&GpuEnvironment> {
+        self.settings
+            .and_then(|settings| self.environments.get(&settings.map))
+            .and_then(Option::as_ref)
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self
+            .environment()
+            .unwrap_or(&self.default_environment)
+            .bind_group
+    }
+
+    // Only set when the environment of the current frame is baked and drawn as the sky
+    pub fn skybox_bind_group(&self) -> Option<&BindGroup> {
+        self.environment()
+            .filter(|_| self.settings.is_some_and(|settings| settings.skybox))
+            .map(|environment| &environment.bind_group)
+    }
+
+    fn bake(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        map: EnvironmentMap,
+        asset_service: &AssetService,
+        mipmap_generator: &mut MipmapGenerator,
+    ) -> anyhow::Result<GpuEnvironment> {
+        let handles: &[TextureHandle] = match &map {
+            EnvironmentMap::Equirectangular(handle) => std::slice::from_ref(handle),
+            EnvironmentMap::Cubemap(faces) => faces,
+        };
+        let textures = handles
+            .iter()
+            .map(|handle| {
+                asset_service
+                    .textures
+                    .get(handle)
+                    .ok_or_else(|| anyhow!("Texture {:?} does not exist", handle))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // Texels of the source across the 90 degrees a face covers
+        let source_face_size = match map {
+            EnvironmentMap::Equirectangular(_) => textures[0].width / 4,
+            EnvironmentMap::Cubemap(_) => textures
+                .iter()
+                .map(|texture| texture.width)
+                .max()
+                .unwrap_or(1),
+        };
+        let environment_size = source_face_size
+            .next_power_of_two()
+            .clamp(MIN_ENVIRONMENT_SIZE, MAX_ENVIRONMENT_SIZE);
+        let mip_level_count = environment_size.ilog2() + 1;
+        debug!(
+            "Baking environment map {:?} into {}x{} faces",
+            map, environment_size, environment_size
+        );
+
+        // The sources only live for the bake, the cubemaps keep everything that is needed
+        let sources = textures
+            .iter()
+            .map(|texture| GpuTexture::new(device, queue, texture, mipmap_generator))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let source_bind_groups: Vec<BindGroup> = sources
+            .iter()
+            .map(|source| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.source_texture_bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source.view),
+                    }],
+                    label: Some("Environment source bind group"),
+                })
+            })
+            .collect();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Bake Command Encoder"),
+        });
+
+        // Every mip level is converted from the source directly, sampling the source level of the same size
+        let environment = device.create_texture(&Self::cube_descriptor(
+            "Environment Cubemap",
+            environment_size,
+            mip_level_count,
+        ));
+        for level in 0..mip_level_count {
+            let level_size = environment_size >> level;
+            let source_lod = (source_face_size as f32 / level_size as f32)
+                .log2()
+                .max(0.0);
+            for face in 0..6 {
+                let (pipeline, source_bind_group) = match map {
+                    EnvironmentMap::Equirectangular(_) => {
+                        (&self.bake_pipelines.equirectangular, &source_bind_groups[0])
+                    }
+                    EnvironmentMap::Cubemap(_) => (
+                        &self.bake_pipelines.face,
+                        &source_bind_groups[face as usize],
+                    ),
+                };
+                self.draw_face(
+                    device,
+                    &mut encoder,
+                    pipeline,
+                    source_bind_group,
+                    &Self::face_view(&environment, level, face),
+                    BakeUniform {
+                        face,
+                        roughness: 0.0,
+                        source_lod,
+                        source_size: source_face_size as f32,
+                    },
+                );
+            }
+        }
+
+        let environment_view = environment.create_view(&Self::cube_view_descriptor());
+        let environment_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.source_cube_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&environment_view),
+            }],
+            label: Some("Environment cube bind group"),
+        });
+
+        let irradiance = device.create_texture(&Self::cube_descriptor(
+            "Irradiance Cubemap",
+            IRRADIANCE_SIZE,
+            1,
+        ));
+        for face in 0..6 {
+            self.draw_face(
+                device,
+                &mut encoder,
+                &self.bake_pipelines.irradiance,
+                &environment_bind_group,
+                &Self::face_view(&irradiance, 0, face),
+                BakeUniform {
+                    face,
+                    roughness: 0.0,
+                    source_lod: 0.0,
+                    source_size: environment_size as f32,
+                },
+            );
+        }
+
+        let prefiltered = device.create_texture(&Self::cube_descriptor(
+            "Prefiltered Environment Cubemap",
+            PREFILTERED_SIZE,
+            PREFILTERED_MIP_COUNT,
+        ));
+        for level in 0..PREFILTERED_MIP_COUNT {
+            for face in 0..6 {
+                self.draw_face(
+                    device,
+                    &mut encoder,
+                    &self.bake_pipelines.prefilter,
+                    &environment_bind_group,
+                    &Self::face_view(&prefiltered, level, face),
+                    BakeUniform {
+                        face,
+                        roughness: level as f32 / (PREFILTERED_MIP_COUNT - 1) as f32,
+                        source_lod: (environment_size as f32 / PREFILTERED_SIZE as f32).log2(),
+                        source_size: environment_size as f32,
+                    },
+                );
+            }
+        }
+
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Ok(GpuEnvironment {
+            bind_group: Self::create_bind_group(
+                device,
+                &self.bind_group_layout,
+                &self.uniform_buffer,
+                &self.sampler,
+                [
+                    &environment_view,
+                    &irradiance.create_view(&Self::cube_view_descriptor()),
+                    &prefiltered.create_view(&Self::cube_view_descriptor()),
+                ],
+                &self.brdf_lookup_view,
+            ),
+        })
+    }
+
+    // Fills one face of one mip level of a cubemap with a fullscreen triangle
+    fn draw_face(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        pipeline: &RenderPipeline,
+        source_bind_group: &BindGroup,
+        target_view: &TextureView,
+        bake_uniform: BakeUniform,
+    ) {
+        let bake_bind_group = Self::create_bake_bind_group(
+            device,
+            &self.bake_bind_group_layout,
+            &self.sampler,
+            bake_uniform,
+        );
+        Self::draw_fullscreen(
+            encoder,
+            pipeline,
+            &[&bake_bind_group, source_bind_group],
+            target_view,
+        );
+    }
+
+    fn draw_fullscreen(
+        encoder: &mut CommandEncoder,
+        pipeline: &RenderPipeline,
+        bind_groups: &[&BindGroup],
+        target_view: &TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Environment Bake Render Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        for (index, bind_group) in bind_groups.iter().enumerate() {
+            render_pass.set_bind_group(index as u32, *bind_group, &[]);
+        }
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn create_bake_bind_group(
+        device: &Device,
+        bake_bind_group_layout: &BindGroupLayout,
+        sampler: &Sampler,
+        bake_uniform: BakeUniform,
+    ) -> BindGroup {
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Environment Bake Uniform Buffer"),
+            contents: cast_slice(&[bake_uniform]),
+            usage: BufferUsages::UNIFORM,
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bake_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("Environment bake bind group"),
+        })
+    }
+
+    // Split sum lookup table, it only depends on the BRDF so every environment shares it
+    fn create_brdf_lookup(
+        device: &Device,
+        queue: &Queue,
+        pipeline: &RenderPipeline,
+    ) -> TextureView {
+        let view = device
+            .create_texture(&TextureDescriptor {
+                label: Some("BRDF Lookup Table"),
+                size: Extent3d {
+                    width: BRDF_LOOKUP_SIZE,
+                    height: BRDF_LOOKUP_SIZE,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: BRDF_LOOKUP_FORMAT,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            })
+            .create_view(&TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("BRDF Lookup Command Encoder"),
+        });
+        Self::draw_fullscreen(&mut encoder, pipeline, &[], &view);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        view
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        sampler: &Sampler,
+        cubemap_views: [&TextureView; 3], // Sky, irradiance and prefiltered environment
+        brdf_lookup_view: &TextureView,
+    ) -> BindGroup {
+        let [environment_view, irradiance_view, prefiltered_view] = cubemap_views;
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(environment_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(irradiance_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(prefiltered_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(brdf_lookup_view),
+                },
+            ],
+            label: Some("Environment bind group"),
+        })
+    }
+
+    fn cube_descriptor(
+        label: &'static str,
+        size: u32,
+        mip_level_count: u32,
+    ) -> TextureDescriptor<'static> {
+        TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: ENVIRONMENT_FORMAT,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::RENDER_ATTACHMENT
+                | TextureUsages::COPY_DST,
+            view_formats: &[],
+        }
+    }
+
+    // Render target for one face of one mip level
+    fn face_view(texture: &Texture, level: u32, face: u32) -> TextureView {
+        texture.create_view(&TextureViewDescriptor {
+            label: Some("Environment Face View"),
+            dimension: Some(TextureViewDimension::D2),
+            base_mip_level: level,
+            mip_level_count: Some(1),
+            base_array_layer: face,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })
+    }
+
+    fn cube_view_descriptor() -> TextureViewDescriptor<'static> {
+        TextureViewDescriptor {
+            label: Some("Environment Cube View"),
+            dimension: Some(TextureViewDimension::Cube),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use glam::{Mat4, Vec4};
+
+use crate::asset::bounds::{Aabb, BoundingSphere};
+
+// The six planes enclosing everything a camera can see, normals point into the frustum
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [Vec4; 6], // xyz is the plane normal and w the distance from the origin
+}
+
+impl Frustum {
+    // Extracts the planes from a view projection matrix with wgpu's 0 to 1 depth range.
+    // Reverse-Z only swaps the near and far planes, so either kind of matrix works.
+    pub fn from_view_projection(view_projection_matrix: Mat4) -> Self {
+        let row0 = view_projection_matrix.row(0);
+        let row1 = view_projection_matrix.row(1);
+        let row2 = view_projection_matrix.row(2);
+        let row3 = view_projection_matrix.row(3);
+
+        let planes = [
+            row3 + row0, // Left
+            row3 - row0, // Right
+            row3 + row1, // Bottom
+            row3 - row1, // Top
+            row2,        // Near
+            row3 - row2, // Far
+        ]
+        .map(|plane| plane / plane.truncate().length());
+
+        Self { planes }
+    }
+
+    // Conservative, spheres that only touch the frustum count as inside
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let center = aabb.center();
+        let half_extents = aabb.half_extents();
+
+        // The box is outside when even its corner furthest along the plane normal is behind the plane
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            normal.dot(center) + normal.abs().dot(half_extents) + plane.w >= 0.0
+        })
+    }
+
+    // Tests the cheap bounding sphere first and only falls back to the tighter box when it is unclear
+    pub fn intersects_bounds(&self, local_bounds: &Aabb, model_matrix: Mat4) -> bool {
+        let sphere = local_bounds.bounding_sphere().transform(model_matrix);
+        let mut sphere_inside_every_plane = true;
+        for plane in self.planes.iter() {
+            let distance = plane.truncate().dot(sphere.center) + plane.w;
+            if distance < -sphere.radius {
+                return false;
+            }
+            if distance < sphere.radius {
+                sphere_inside_every_plane = false;
+            }
+        }
+
+        sphere_inside_every_plane || self.intersects_aabb(&local_bounds.transform(model_matrix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    // Camera at z = 5 looking down -Z with a 90 degree field of view, at a distance d from the camera
+    // everything within d of the view axis is visible
+    fn frustum() -> Frustum {
+        let projection = Mat4::perspective_rh(90.0_f32.to_radians(), 1.0, 0.1, 100.0);
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 0.0, 5.0), Vec3::ZERO, Vec3::Y);
+        Frustum::from_view_projection(projection * view)
+    }
+
+    fn cube(center: Vec3, half_extent: f32) -> Aabb {
+        Aabb {
+            min: center - half_extent,
+            max: center + half_extent,
+        }
+    }
+
+    #[test]
+    fn plane_normals_point_into_the_frustum() {
+        let frustum = frustum();
+        let distance = |point: Vec3| -> Vec<f32> {
+            frustum
+                .planes
+                .iter()
+                .map(|plane| plane.truncate().dot(point) + plane.w)
+                .collect()
+        };
+
+        assert!(distance(Vec3::ZERO).iter().all(|distance| *distance > 0.0));
+        // Right plane, where x equals the distance from the camera
+        assert!(distance(Vec3::new(5.5, 0.0, 0.0))[1] < 0.0);
+        // Behind the camera and past the far plane
+        assert!(distance(Vec3::new(0.0, 0.0, 6.0))[4] < 0.0);
+        assert!(distance(Vec3::new(0.0, 0.0, -100.0))[5] < 0.0);
+    }
+
+    #[test]
+    fn boxes_inside_outside_and_straddling_a_plane() {
+        let frustum = frustum();
+
+        let inside = cube(Vec3::ZERO, 1.0);
+        assert!(frustum.intersects_aabb(&inside));
+        assert!(frustum.intersects_bounds(&inside, Mat4::IDENTITY));
+
+        let outside = cube(Vec3::new(20.0, 0.0, 0.0), 1.0);
+        assert!(!frustum.intersects_aabb(&outside));
+        assert!(!frustum.intersects_bounds(&outside, Mat4::IDENTITY));
+        assert!(!frustum.intersects_bounds(&inside, Mat4::from_translation(Vec3::Z * 10.0)));
+
+        let straddling = cube(Vec3::new(5.0, 0.0, 0.0), 1.0);
+        assert!(frustum.intersects_aabb(&straddling));
+        assert!(frustum.intersects_bounds(&straddling, Mat4::IDENTITY));
+    }
+
+    #[test]
+    fn boxes_whose_sphere_reaches_into_the_frustum_are_tested_as_boxes() {
+        let frustum = frustum();
+
+        // The sphere around the cube crosses the right plane but the cube itself stays outside
+        let cube = cube(Vec3::new(6.1, 0.0, 0.0), 0.5);
+        assert!(frustum.intersects_sphere(&cube.bounding_sphere()));
+        assert!(!frustum.intersects_aabb(&cube));
+        assert!(!frustum.intersects_bounds(&cube, Mat4::IDENTITY));
+    }
+}
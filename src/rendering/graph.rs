@@ -0,0 +1,669 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::anyhow;
+use glam::UVec2;
+use log::debug;
+use wgpu::{
+    BindGroup, BindGroupLayout, CommandEncoder, Device, Extent3d, Queue, Texture,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView,
+    TextureViewDescriptor,
+};
+
+// Names the engine publishes its own resources under, custom passes look them up with PassBuilder::resource
+pub const SWAPCHAIN: &str = "swapchain"; // The window surface, written last
+pub const SCENE_COLOR: &str = "scene_color"; // HDR color of everything the main pass drew, post processed at the end of the frame
+pub const SCENE_COLOR_MULTISAMPLED: &str = "scene_color_multisampled"; // Samples the main pass draws with MSAA before they are resolved into SCENE_COLOR
+pub const SCENE_DEPTH: &str = "scene_depth"; // Depth written by the main pass, multisampled and not sampleable with MSAA
+pub const SHADOW_MAPS: &str = "shadow_maps"; // Array of shadow map layers
+pub const POST_PROCESSED: &str = "post_processed"; // Scene color after tone mapping and the other post processing
+
+// One version of a resource, every write creates a new version so readers know which pass they wait for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResourceHandle(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextureSize {
+    Surface,           // Same size as the window surface
+    SurfaceScale(f32), // A fraction of the window surface, e.g. 0.5 for half resolution
+    Fixed(UVec2),
+}
+
+// Texture allocated by the graph for the frames that use it, its contents don't outlive the frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransientTextureDescriptor {
+    pub size: TextureSize,
+    pub format: TextureFormat,
+    pub usage: TextureUsages,
+    pub sample_count: u32,
+}
+
+impl TransientTextureDescriptor {
+    // Surface sized texture that can be rendered to and sampled
+    pub fn new(format: TextureFormat) -> Self {
+        Self {
+            size: TextureSize::Surface,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            sample_count: 1,
+        }
+    }
+}
+
+// A pass that can be inserted into the frame without changing the engine, e.g. outlines or fog
+pub trait RenderGraphPass {
+    fn name(&self) -> &str;
+
+    // Declares what the pass reads and writes, called every frame before the passes are ordered.
+    // Handles received here stay valid until execute is called for the same frame.
+    fn setup(&mut self, builder: &mut PassBuilder);
+
+    fn execute(&mut self, context: &mut PassContext);
+
+    // Called after the device was lost and created again, GPU objects the pass kept from
+    // earlier frames belong to the old device and have to be created again
+    fn device_recreated(&mut self) {}
+}
+
+// What a pass can use while recording its commands
+pub struct PassContext<'a> {
+    pub device: &'a Device,
+    pub queue: &'a Queue,
+    pub encoder: &'a mut CommandEncoder,
+    pub camera_bind_group_layout: &'a BindGroupLayout,
+    pub camera_bind_group: &'a BindGroup, // View projection of the main camera
+    pub resources: &'a GraphResources,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct TextureKey {
+    size: UVec2,
+    format: TextureFormat,
+    usage: TextureUsages,
+    sample_count: u32,
+}
+
+struct PooledTexture {
+    key: TextureKey,
+    texture: Texture,
+    view: TextureView,
+    in_use: bool,     // Handed to a resource whose lifetime hasn't ended yet
+    used_frame: bool, // Handed out at some point this frame
+}
+
+// Textures handed out to transient resources, kept between frames so they don't have to be recreated.
+// Textures a frame didn't use are released, which also drops textures of the previous surface size.
+#[derive(Default)]
+pub struct TransientTexturePool {
+    textures: Vec<PooledTexture>,
+}
+
+impl TransientTexturePool {
+    fn acquire(&mut self, device: &Device, key: TextureKey) -> (Texture, TextureView) {
+        if let Some(pooled) = self
+            .textures
+            .iter_mut()
+            .find(|pooled| !pooled.in_use && pooled.key == key)
+        {
+            pooled.in_use = true;
+            pooled.used_frame = true;
+            return (pooled.texture.clone(), pooled.view.clone());
+        }
+
+        debug!("Creating transient texture {:?}", key);
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Transient Texture"),
+            size: Extent3d {
+                width: key.size.x,
+                height: key.size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: key.sample_count,
+            dimension: TextureDimension::D2,
+            format: key.format,
+            usage: key.usage,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        self.textures.push(PooledTexture {
+            key,
+            texture: texture.clone(),
+            view: view.clone(),
+            in_use: true,
+            used_frame: true,
+        });
+
+        (texture, view)
+    }
+
+    fn release(&mut self, texture: &Texture) {
+        if let Some(pooled) = self
+            .textures
+            .iter_mut()
+            .find(|pooled| pooled.texture == *texture)
+        {
+            pooled.in_use = false;
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.textures.retain(|pooled| pooled.used_frame);
+        for pooled in self.textures.iter_mut() {
+            pooled.in_use = false;
+            pooled.used_frame = false;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.textures.clear();
+    }
+}
+
+enum ResourceSource {
+    Imported,
+    Transient(TransientTextureDescriptor),
+}
+
+struct GraphResource {
+    name: String,
+    source: ResourceSource,
+    size: UVec2,
+    format: TextureFormat,
+    texture: Option<(Texture, TextureView)>, // Imported textures have it from the start, transient ones once allocated
+}
+
+struct ResourceVersion {
+    resource: usize,
+    producer: Option<usize>, // Pass that wrote this version, none for imported resources
+    readers: Vec<usize>,
+}
+
+struct PassNode<T> {
+    name: String,
+    pass: T,
+    reads: Vec<ResourceHandle>,
+    writes: Vec<ResourceHandle>,
+    has_side_effects: bool, // Kept even if nothing reads what it writes
+}
+
+// Textures of the frame a pass can look up by handle
+pub struct GraphResources {
+    resources: Vec<GraphResource>,
+    versions: Vec<ResourceVersion>,
+}
+
+impl GraphResources {
+    fn resource(&self, handle: ResourceHandle) -> &GraphResource {
+        &self.resources[self.versions[handle.0 as usize].resource]
+    }
+
+    pub fn texture(&self, handle: ResourceHandle) -> &Texture {
+        &self
+            .resource(handle)
+            .texture
+            .as_ref()
+            .expect("Render graph texture used before it was allocated")
+            .0
+    }
+
+    pub fn view(&self, handle: ResourceHandle) -> &TextureView {
+        &self
+            .resource(handle)
+            .texture
+            .as_ref()
+            .expect("Render graph texture used before it was allocated")
+            .1
+    }
+
+    pub fn size(&self, handle: ResourceHandle) -> UVec2 {
+        self.resource(handle).size
+    }
+
+    pub fn format(&self, handle: ResourceHandle) -> TextureFormat {
+        self.resource(handle).format
+    }
+
+    pub fn name(&self, handle: ResourceHandle) -> &str {
+        &self.resource(handle).name
+    }
+}
+
+// Declares the resources of one pass while the graph is being built
+pub struct PassBuilder<'a> {
+    resources: &'a mut GraphResources,
+    published: &'a mut HashMap<String, ResourceHandle>,
+    surface_size: UVec2,
+    pass: usize,
+    reads: Vec<ResourceHandle>,
+    writes: Vec<ResourceHandle>,
+    has_side_effects: bool,
+}
+
+impl PassBuilder<'_> {
+    // Creates a texture written by this pass and publishes it under its name
+    pub fn create_texture(
+        &mut self,
+        name: impl Into<String>,
+        descriptor: TransientTextureDescriptor,
+    ) -> ResourceHandle {
+        let name = name.into();
+        let size = match descriptor.size {
+            TextureSize::Surface => self.surface_size,
+            TextureSize::SurfaceScale(scale) => {
+                (self.surface_size.as_vec2() * scale).round().as_uvec2()
+            }
+            TextureSize::Fixed(size) => size,
+        }
+        .max(UVec2::ONE);
+
+        self.resources.resources.push(GraphResource {
+            name: name.clone(),
+            source: ResourceSource::Transient(descriptor),
+            size,
+            format: descriptor.format,
+            texture: None,
+        });
+        let handle = ResourceHandle(self.resources.versions.len() as u32);
+        self.resources.versions.push(ResourceVersion {
+            resource: self.resources.resources.len() - 1,
+            producer: Some(self.pass),
+            readers: Vec::new(),
+        });
+        self.writes.push(handle);
+        self.published.insert(name, handle);
+
+        handle
+    }
+
+    // The pass runs after the pass that wrote this version
+    pub fn read(&mut self, handle: ResourceHandle) -> ResourceHandle {
+        self.resources.versions[handle.0 as usize]
+            .readers
+            .push(self.pass);
+        self.reads.push(handle);
+
+        handle
+    }
+
+    // Writes to the same texture after everything that reads the given version, e.g. drawing on top of it.
+    // Returns the new version later passes have to read to see the result.
+    pub fn write(&mut self, handle: ResourceHandle) -> ResourceHandle {
+        self.read(handle);
+
+        let resource = self.resources.versions[handle.0 as usize].resource;
+        let new_handle = ResourceHandle(self.resources.versions.len() as u32);
+        self.resources.versions.push(ResourceVersion {
+            resource,
+            producer: Some(self.pass),
+            readers: Vec::new(),
+        });
+        self.writes.push(new_handle);
+
+        // Passes that asked for the resource by name get this version from now on
+        let name = &self.resources.resources[resource].name;
+        if self.published.get(name) == Some(&handle) {
+            self.published.insert(name.clone(), new_handle);
+        }
+
+        new_handle
+    }
+
+    // Latest published version of a resource, e.g. SCENE_COLOR
+    pub fn resource(&self, name: &str) -> Option<ResourceHandle> {
+        self.published.get(name).copied()
+    }
+
+    // Makes a version the one later passes get for a name, e.g. to replace SCENE_COLOR with a post processed copy
+    pub fn publish(&mut self, name: impl Into<String>, handle: ResourceHandle) {
+        self.published.insert(name.into(), handle);
+    }
+
+    pub fn size(&self, handle: ResourceHandle) -> UVec2 {
+        self.resources.size(handle)
+    }
+
+    pub fn format(&self, handle: ResourceHandle) -> TextureFormat {
+        self.resources.format(handle)
+    }
+
+    // Keeps the pass even if nothing it writes is used, e.g. for passes that read data back to the CPU
+    pub fn has_side_effects(&mut self) {
+        self.has_side_effects = true;
+    }
+}
+
+// Passes of one frame and the textures they pass between each other.
+// Built every frame, then compiled into an order that only keeps passes contributing to the frame.
+pub struct RenderGraph<T> {
+    surface_size: UVec2,
+    resources: GraphResources,
+    published: HashMap<String, ResourceHandle>,
+    passes: Vec<PassNode<T>>,
+}
+
+impl<T> RenderGraph<T> {
+    pub fn new(surface_size: UVec2) -> Self {
+        Self {
+            surface_size: surface_size.max(UVec2::ONE),
+            resources: GraphResources {
+                resources: Vec::new(),
+                versions: Vec::new(),
+            },
+            published: HashMap::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    // Makes a texture owned outside the graph available to passes, writing to it keeps a pass alive
+    pub fn import_texture(
+        &mut self,
+        name: impl Into<String>,
+        texture: &Texture,
+        view: TextureView,
+    ) -> ResourceHandle {
+        let name = name.into();
+        self.resources.resources.push(GraphResource {
+            name: name.clone(),
+            source: ResourceSource::Imported,
+            size: UVec2::new(texture.width(), texture.height()),
+            format: texture.format(),
+            texture: Some((texture.clone(), view)),
+        });
+        let handle = ResourceHandle(self.resources.versions.len() as u32);
+        self.resources.versions.push(ResourceVersion {
+            resource: self.resources.resources.len() - 1,
+            producer: None,
+            readers: Vec::new(),
+        });
+        self.published.insert(name, handle);
+
+        handle
+    }
+
+    // Adds a pass and lets it declare its resources, returns whatever the setup returns
+    pub fn add_pass<R>(
+        &mut self,
+        name: impl Into<String>,
+        pass: T,
+        setup: impl FnOnce(&mut PassBuilder) -> R,
+    ) -> R {
+        let mut builder = PassBuilder {
+            resources: &mut self.resources,
+            published: &mut self.published,
+            surface_size: self.surface_size,
+            pass: self.passes.len(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+            has_side_effects: false,
+        };
+        let result = setup(&mut builder);
+
+        let (reads, writes, has_side_effects) =
+            (builder.reads, builder.writes, builder.has_side_effects);
+        self.passes.push(PassNode {
+            name: name.into(),
+            pass,
+            reads,
+            writes,
+            has_side_effects,
+        });
+
+        result
+    }
+
+    pub fn resource(&self, name: &str) -> Option<ResourceHandle> {
+        self.published.get(name).copied()
+    }
+
+    pub fn pass(&self, index: usize) -> &T {
+        &self.passes[index].pass
+    }
+
+    pub fn pass_name(&self, index: usize) -> &str {
+        &self.passes[index].name
+    }
+
+    pub fn resources(&self) -> &GraphResources {
+        &self.resources
+    }
+
+    // Passes that write imported textures or have side effects are kept along with every pass they depend on.
+    // The rest are left out. Returns the passes in an order where every pass runs after the passes it reads
+    // from, keeping the order they were added in where there is a choice.
+    pub fn compile(&self) -> anyhow::Result<Vec<usize>> {
+        let mut dependencies: Vec<HashSet<usize>> = vec![HashSet::new(); self.passes.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for read in pass.reads.iter() {
+                if let Some(producer) = self.resources.versions[read.0 as usize].producer
+                    && producer != index
+                {
+                    dependencies[index].insert(producer);
+                }
+            }
+
+            // A write has to wait for every pass still reading the version it overwrites
+            for write in pass.writes.iter() {
+                let version = &self.resources.versions[write.0 as usize];
+                for previous in self.resources.versions[..write.0 as usize]
+                    .iter()
+                    .filter(|previous| previous.resource == version.resource)
+                {
+                    dependencies[index]
+                        .extend(previous.readers.iter().filter(|reader| **reader != index));
+                }
+            }
+        }
+
+        let mut is_live = vec![false; self.passes.len()];
+        let mut pending: Vec<usize> = self
+            .passes
+            .iter()
+            .enumerate()
+            .filter(|(_, pass)| {
+                pass.has_side_effects
+                    || pass.writes.iter().any(|write| {
+                        matches!(
+                            self.resources.resource(*write).source,
+                            ResourceSource::Imported
+                        )
+                    })
+            })
+            .map(|(index, _)| index)
+            .collect();
+        while let Some(index) = pending.pop() {
+            if !std::mem::replace(&mut is_live[index], true) {
+                pending.extend(dependencies[index].iter().copied());
+            }
+        }
+
+        let mut order = Vec::with_capacity(self.passes.len());
+        let mut is_scheduled = vec![false; self.passes.len()];
+        while let Some(index) = (0..self.passes.len()).find(|index| {
+            is_live[*index]
+                && !is_scheduled[*index]
+                && dependencies[*index]
+                    .iter()
+                    .all(|dependency| is_scheduled[*dependency] || !is_live[*dependency])
+        }) {
+            is_scheduled[index] = true;
+            order.push(index);
+        }
+
+        let live_count = is_live.iter().filter(|is_live| **is_live).count();
+        if order.len() < live_count {
+            let stuck: Vec<&str> = (0..self.passes.len())
+                .filter(|index| is_live[*index] && !is_scheduled[*index])
+                .map(|index| self.passes[index].name.as_str())
+                .collect();
+            return Err(anyhow!("Render passes depend on each other: {:?}", stuck));
+        }
+
+        Ok(order)
+    }
+
+    // Hands a pooled texture to every transient resource the ordered passes use.
+    // Resources whose lifetimes don't overlap can share a texture.
+    pub fn allocate(&mut self, device: &Device, pool: &mut TransientTexturePool, order: &[usize]) {
+        let mut last_use: HashMap<usize, usize> = HashMap::new();
+        for (position, index) in order.iter().enumerate() {
+            let pass = &self.passes[*index];
+            for handle in pass.reads.iter().chain(pass.writes.iter()) {
+                last_use.insert(
+                    self.resources.versions[handle.0 as usize].resource,
+                    position,
+                );
+            }
+        }
+
+        for (position, index) in order.iter().enumerate() {
+            let pass = &self.passes[*index];
+            for handle in pass.reads.iter().chain(pass.writes.iter()) {
+                let resource = &mut self.resources.resources
+                    [self.resources.versions[handle.0 as usize].resource];
+                if let ResourceSource::Transient(descriptor) = resource.source
+                    && resource.texture.is_none()
+                {
+                    resource.texture = Some(pool.acquire(
+                        device,
+                        TextureKey {
+                            size: resource.size,
+                            format: descriptor.format,
+                            usage: descriptor.usage,
+                            sample_count: descriptor.sample_count,
+                        },
+                    ));
+                }
+            }
+
+            for (resource, _) in last_use.iter().filter(|(_, last)| **last == position) {
+                let resource = &self.resources.resources[*resource];
+                if let ResourceSource::Transient(_) = resource.source
+                    && let Some((texture, _)) = &resource.texture
+                {
+                    pool.release(texture);
+                }
+            }
+        }
+
+        pool.end_frame();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor() -> TransientTextureDescriptor {
+        TransientTextureDescriptor::new(TextureFormat::Rgba16Float)
+    }
+
+    #[test]
+    fn passes_nothing_uses_are_culled() {
+        let mut graph = RenderGraph::new(UVec2::splat(64));
+        graph.add_pass("unused", (), |builder| {
+            builder.create_texture("unused", descriptor());
+        });
+        let color = graph.add_pass("color", (), |builder| {
+            builder.create_texture("color", descriptor())
+        });
+        graph.add_pass("readback", (), |builder| {
+            builder.read(color);
+            builder.has_side_effects();
+        });
+        graph.add_pass("unread", (), |builder| {
+            builder.read(color);
+        });
+
+        assert_eq!(graph.compile().unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn writes_wait_for_readers_of_the_version_they_overwrite() {
+        let mut graph = RenderGraph::new(UVec2::splat(64));
+        let first = graph.add_pass("create", (), |builder| {
+            builder.create_texture("color", descriptor())
+        });
+        let second = graph.add_pass("overwrite", (), |builder| builder.write(first));
+        graph.add_pass("read first", (), |builder| {
+            builder.read(first);
+            builder.has_side_effects();
+        });
+        graph.add_pass("read second", (), |builder| {
+            assert_eq!(builder.resource("color"), Some(second));
+            builder.read(second);
+            builder.has_side_effects();
+        });
+
+        // The overwrite keeps the first version alive until its reader ran, its own reader follows it
+        assert_eq!(graph.compile().unwrap(), [0, 2, 1, 3]);
+    }
+
+    #[test]
+    fn independent_passes_keep_the_order_they_were_added_in() {
+        let mut graph = RenderGraph::new(UVec2::splat(64));
+        let color = graph.add_pass("color", (), |builder| {
+            builder.create_texture("color", descriptor())
+        });
+        graph.add_pass("first", (), |builder| builder.has_side_effects());
+        graph.add_pass("second", (), |builder| {
+            builder.read(color);
+            builder.has_side_effects();
+        });
+        graph.add_pass("third", (), |builder| builder.has_side_effects());
+
+        assert_eq!(graph.compile().unwrap(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn passes_depending_on_each_other_are_an_error() {
+        let mut graph = RenderGraph::new(UVec2::splat(64));
+        let (a, b) = graph.add_pass("create", (), |builder| {
+            (
+                builder.create_texture("a", descriptor()),
+                builder.create_texture("b", descriptor()),
+            )
+        });
+        // Each pass reads the version the other one overwrites
+        graph.add_pass("write b", (), |builder| {
+            builder.read(a);
+            builder.write(b);
+            builder.has_side_effects();
+        });
+        graph.add_pass("write a", (), |builder| {
+            builder.read(b);
+            builder.write(a);
+            builder.has_side_effects();
+        });
+
+        let error = graph.compile().unwrap_err();
+        assert!(error.to_string().contains("write a"), "{}", error);
+        assert!(error.to_string().contains("write b"), "{}", error);
+    }
+
+    #[test]
+    fn custom_passes_can_replace_scene_color() {
+        let mut graph = RenderGraph::new(UVec2::splat(64));
+        let scene_color = graph.add_pass("main", (), |builder| {
+            builder.create_texture(SCENE_COLOR, descriptor())
+        });
+        let outlined = graph.add_pass("outline", (), |builder| {
+            let scene_color = builder.resource(SCENE_COLOR).unwrap();
+            builder.read(scene_color);
+            let outlined = builder.create_texture("outlined", descriptor());
+            builder.publish(SCENE_COLOR, outlined);
+            outlined
+        });
+        let post_processed_input = graph.add_pass("post process", (), |builder| {
+            let scene_color = builder.resource(SCENE_COLOR).unwrap();
+            builder.read(scene_color);
+            builder.has_side_effects();
+            scene_color
+        });
+
+        assert_ne!(scene_color, outlined);
+        assert_eq!(post_processed_input, outlined);
+        assert_eq!(graph.resource(SCENE_COLOR), Some(outlined));
+        assert_eq!(graph.resources().name(outlined), "outlined");
+        assert_eq!(graph.compile().unwrap(), [0, 1, 2]);
+    }
+}
@@ -0,0 +1,285 @@
+use std::cmp::Ordering;
+
+use bytemuck::{Pod, Zeroable, cast_slice};
+use glam::Vec3;
+use log::debug;
+use wgpu::{
+    BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    Buffer, BufferBindingType, BufferDescriptor, BufferUsages, Device, Queue, SamplerBindingType,
+    ShaderStages, TextureSampleType, TextureViewDimension,
+};
+
+use crate::{
+    ecs::{
+        component::light::{LightComponent, LightKind},
+        entity::scene::Scene,
+    },
+    rendering::{buffer::grow_capacity, camera::CameraUniform, shadow::ShadowMaps},
+};
+
+// A light that made it past the light limit, in world space
+#[derive(Debug, Clone, Copy)]
+pub struct VisibleLight {
+    pub light_component: LightComponent,
+    pub position: Vec3,
+    pub direction: Vec3, // Direction the light travels in
+}
+
+// Most lights the shader loops over per fragment, the least important lights past this are dropped
+pub const MAX_LIGHTS: usize = 128;
+
+// Number of lights the light buffer can hold before it has to grow
+const INITIAL_LIGHT_CAPACITY: u64 = 16;
+
+// Matches the light type constants in the shader
+const LIGHT_TYPE_DIRECTIONAL: u32 = 0;
+const LIGHT_TYPE_POINT: u32 = 1;
+const LIGHT_TYPE_SPOT: u32 = 2;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct LightUniform {
+    pub position: [f32; 3],
+    pub range: f32,          // 0 for directional lights
+    pub direction: [f32; 3], // Direction the light travels in
+    pub light_type: u32,
+    pub color: [f32; 3], // Color multiplied by intensity
+    pub spot_scale: f32, // Turns the cosine of the angle to the spot direction into a 0 to 1 cone falloff
+    pub spot_offset: f32,
+    pub shadow_layer: i32, // First shadow map layer of the light, -1 when it casts no shadows
+    pub depth_bias: f32,
+    pub normal_bias: f32,
+}
+
+impl LightUniform {
+    pub fn new(visible_light: &VisibleLight, shadow_layer: Option<u32>) -> Self {
+        let light_component = &visible_light.light_component;
+        let (light_type, range, spot_scale, spot_offset) = match light_component.kind {
+            LightKind::Directional => (LIGHT_TYPE_DIRECTIONAL, 0.0, 0.0, 0.0),
+            LightKind::Point { range } => (LIGHT_TYPE_POINT, range, 0.0, 0.0),
+            LightKind::Spot {
+                range,
+                inner_cone_angle,
+                outer_cone_angle,
+            } => {
+                let cos_outer = outer_cone_angle.cos();
+                let cos_inner = inner_cone_angle.min(outer_cone_angle).cos();
+                let spot_scale = 1.0 / (cos_inner - cos_outer).max(0.001);
+                (LIGHT_TYPE_SPOT, range, spot_scale, -cos_outer * spot_scale)
+            }
+        };
+
+        let shadow_settings = light_component.shadow.unwrap_or_default();
+
+        Self {
+            position: visible_light.position.to_array(),
+            range,
+            direction: visible_light.direction.to_array(),
+            light_type,
+            color: (light_component.color * light_component.intensity).to_array(),
+            spot_scale,
+            spot_offset,
+            shadow_layer: shadow_layer.map_or(-1, |layer| layer as i32),
+            depth_bias: shadow_settings.depth_bias,
+            normal_bias: shadow_settings.normal_bias,
+        }
+    }
+}
+
+// Precedes the light array in the storage buffer
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct LightBufferHeader {
+    light_count: u32,
+    _padding: [u32; 3], // The light array starts at a 16 byte boundary
+}
+
+// Gathers the lights of the scene, keeping directional lights first and then the
+// point and spot lights that contribute the most near the camera, up to MAX_LIGHTS
+pub fn collect_lights(scene: &Scene, camera_uniform: &CameraUniform) -> Vec<VisibleLight> {
+    let camera_position = Vec3::from_slice(&camera_uniform.camera_position);
+    let mut directional_lights = Vec::new();
+    let mut local_lights = Vec::new();
+
+    for (entity, light_component) in scene.light_components.iter() {
+        let strength = light_component.color.max_element() * light_component.intensity;
+        if strength <= 0.0 {
+            continue;
+        }
+
+        let world_matrix = scene.calculate_world_matrix(*entity);
+        let position = world_matrix.w_axis.truncate();
+        let visible_light = VisibleLight {
+            light_component: *light_component,
+            position,
+            direction: world_matrix
+                .transform_vector3(Vec3::NEG_Z)
+                .normalize_or(Vec3::NEG_Z),
+        };
+
+        match light_component.range() {
+            None => directional_lights.push(visible_light),
+            Some(range) if range > 0.0 => {
+                // Lights are ranked by how bright they are at the edge of their range closest to the camera
+                let distance = (position.distance(camera_position) - range).max(0.0);
+                let importance = strength / (1.0 + distance * distance);
+                local_lights.push((importance, visible_light));
+            }
+            Some(_) => {}
+        }
+    }
+
+    let available_slots = MAX_LIGHTS.saturating_sub(directional_lights.len());
+    if local_lights.len() > available_slots {
+        debug!(
+            "Dropping {} of {} lights over the limit of {}",
+            local_lights.len() - available_slots,
+            local_lights.len() + directional_lights.len(),
+            MAX_LIGHTS
+        );
+        local_lights.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        local_lights.truncate(available_slots);
+    }
+
+    directional_lights.truncate(MAX_LIGHTS);
+    directional_lights.extend(local_lights.into_iter().map(|(_, light)| light));
+    directional_lights
+}
+
+// Storage buffer holding every light the shader accumulates, bound together with the shadow maps
+pub struct GpuLights {
+    buffer: Buffer,
+    pub bind_group: BindGroup,
+    capacity: u64,
+}
+
+impl GpuLights {
+    pub fn create_bind_group_layout(device: &Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Depth,
+                        view_dimension: TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+            label: Some("Light bind group layout"),
+        })
+    }
+
+    pub fn new(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        shadow_maps: &ShadowMaps,
+    ) -> Self {
+        Self::with_capacity(
+            device,
+            bind_group_layout,
+            shadow_maps,
+            INITIAL_LIGHT_CAPACITY,
+        )
+    }
+
+    fn with_capacity(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        shadow_maps: &ShadowMaps,
+        capacity: u64,
+    ) -> Self {
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Light Storage Buffer"),
+            size: std::mem::size_of::<LightBufferHeader>() as u64
+                + capacity * std::mem::size_of::<LightUniform>() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: shadow_maps.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&shadow_maps.array_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&shadow_maps.sampler),
+                },
+            ],
+            label: Some("Light bind group"),
+        });
+
+        Self {
+            buffer,
+            bind_group,
+            capacity,
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        bind_group_layout: &BindGroupLayout,
+        shadow_maps: &ShadowMaps,
+        lights: &[LightUniform],
+    ) {
+        let new_capacity = grow_capacity(self.capacity, lights.len() as u64);
+        if new_capacity > self.capacity {
+            debug!("Growing light buffer to {} entries", new_capacity);
+            *self = Self::with_capacity(device, bind_group_layout, shadow_maps, new_capacity);
+        }
+
+        let header = LightBufferHeader {
+            light_count: lights.len() as u32,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&header));
+        if !lights.is_empty() {
+            queue.write_buffer(
+                &self.buffer,
+                std::mem::size_of::<LightBufferHeader>() as u64,
+                cast_slice(lights),
+            );
+        }
+    }
+}
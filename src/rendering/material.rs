@@ -0,0 +1,194 @@
+use bytemuck::{Pod, Zeroable, cast_slice};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    Buffer, BufferBindingType, BufferUsages, Device, Queue, SamplerBindingType, ShaderStages,
+    TextureFormat, TextureSampleType, TextureViewDimension,
+    util::{BufferInitDescriptor, DeviceExt},
+};
+
+use crate::{
+    asset::{TextureHandle, material::Material},
+    rendering::{depth::DepthSettings, texture::GpuTexture},
+};
+
+// Number of texture slots a material has, each one is bound as a texture and sampler pair
+pub const MATERIAL_TEXTURE_COUNT: usize = 5;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct MaterialUniform {
+    pub base_color_factor: [f32; 4],
+    pub emissive_factor: [f32; 4], // w is unused, it pads the field to 16 bytes like the shader expects
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32, // 0 when there is no normal texture so the geometric normal is used
+    pub occlusion_strength: f32,
+}
+
+impl MaterialUniform {
+    pub fn new(material: &Material) -> Self {
+        Self {
+            base_color_factor: material.base_color_factor.to_array(),
+            emissive_factor: material.emissive_factor.extend(0.0).to_array(),
+            metallic_factor: material.metallic_factor,
+            roughness_factor: material.roughness_factor,
+            normal_scale: if material.normal_texture.is_some() {
+                material.normal_scale
+            } else {
+                0.0
+            },
+            occlusion_strength: material.occlusion_strength,
+        }
+    }
+}
+
+// The textures of a material in the order they are bound in
+pub fn material_textures(material: &Material) -> [Option<TextureHandle>; MATERIAL_TEXTURE_COUNT] {
+    [
+        material.base_color_texture,
+        material.metallic_roughness_texture,
+        material.normal_texture,
+        material.occlusion_texture,
+        material.emissive_texture,
+    ]
+}
+
+// Material properties that change the code of the primary shader rather than its inputs,
+// every combination is compiled into its own permutation of the shader and pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MaterialFeatures {
+    pub normal_map: bool,
+    pub double_sided: bool, // Also turns off back face culling
+}
+
+impl MaterialFeatures {
+    pub fn new(material: &Material) -> Self {
+        Self {
+            normal_map: material.normal_texture.is_some(),
+            double_sided: material.double_sided,
+        }
+    }
+
+    // Names the primary shader checks with #ifdef
+    pub fn defines(self) -> Vec<&'static str> {
+        let mut defines = Vec::new();
+        if self.normal_map {
+            defines.push("NORMAL_MAP");
+        }
+        if self.double_sided {
+            defines.push("DOUBLE_SIDED");
+        }
+
+        defines
+    }
+}
+
+// Everything a material pipeline is created from, materials with the same key share a pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MaterialPipelineKey {
+    pub features: MaterialFeatures,
+    pub color_format: TextureFormat,
+    pub depth_settings: DepthSettings,
+    pub sample_count: u32,
+}
+
+// GPU side copy of a material asset
+pub struct GpuMaterial {
+    pub uniform_buffer: Buffer,
+    pub bind_group: BindGroup,
+    pub textures: [Option<TextureHandle>; MATERIAL_TEXTURE_COUNT], // The textures the bind group was built with
+    pub features: MaterialFeatures,
+}
+
+impl GpuMaterial {
+    // Binding 0 holds the material uniform, followed by a texture and sampler binding per slot
+    pub fn create_bind_group_layout(device: &Device) -> BindGroupLayout {
+        let mut entries = vec![BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+
+        for slot in 0..MATERIAL_TEXTURE_COUNT as u32 {
+            entries.push(BindGroupLayoutEntry {
+                binding: 1 + slot * 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            });
+            entries.push(BindGroupLayoutEntry {
+                binding: 2 + slot * 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &entries,
+            label: Some("Material bind group layout"),
+        })
+    }
+
+    pub fn new(
+        device: &Device,
+        material: &Material,
+        gpu_textures: [&GpuTexture; MATERIAL_TEXTURE_COUNT],
+        bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        let label = material.name.as_deref().unwrap_or("Unnamed Material");
+
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&format!("{} Uniform Buffer", label)),
+            contents: cast_slice(&[MaterialUniform::new(material)]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: uniform_buffer.as_entire_binding(),
+        }];
+        for (slot, gpu_texture) in gpu_textures.iter().enumerate() {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 1 + slot as u32 * 2,
+                resource: wgpu::BindingResource::TextureView(&gpu_texture.view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: 2 + slot as u32 * 2,
+                resource: wgpu::BindingResource::Sampler(&gpu_texture.sampler),
+            });
+        }
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &entries,
+            label: Some(&format!("{} bind group", label)),
+        });
+
+        Self {
+            uniform_buffer,
+            bind_group,
+            textures: material_textures(material),
+            features: MaterialFeatures::new(material),
+        }
+    }
+
+    // Factors can be changed every frame without rebuilding the bind group
+    pub fn update(&mut self, queue: &Queue, material: &Material) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            cast_slice(&[MaterialUniform::new(material)]),
+        );
+        self.features = MaterialFeatures::new(material);
+    }
+}
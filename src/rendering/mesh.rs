@@ -0,0 +1,40 @@
+use bytemuck::cast_slice;
+use wgpu::{
+    Buffer, BufferUsages, Device,
+    util::{BufferInitDescriptor, DeviceExt},
+};
+
+use crate::asset::{bounds::Aabb, mesh::Mesh};
+
+// GPU side copy of a mesh asset
+pub struct GpuMesh {
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+    pub index_count: u32,
+    pub bounds: Option<Aabb>, // Local space bounds used for culling, None when the mesh has no vertices
+}
+
+impl GpuMesh {
+    pub fn new(device: &Device, mesh: &Mesh) -> Self {
+        let label = mesh.name.as_deref().unwrap_or("Unnamed Mesh");
+
+        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&format!("{} Vertex Buffer", label)),
+            contents: cast_slice(&mesh.vertices),
+            usage: BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(&format!("{} Index Buffer", label)),
+            contents: cast_slice(&mesh.indices),
+            usage: BufferUsages::INDEX,
+        });
+
+        Self {
+            vertex_buffer,
+            index_buffer,
+            index_count: mesh.indices.len() as u32,
+            bounds: mesh.calculate_bounds(),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use log::debug;
+use wgpu::{
+    BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    ColorTargetState, ColorWrites, Device, FilterMode, FragmentState, MultisampleState,
+    PipelineCache, PipelineCompilationOptions, PipelineLayout, PipelineLayoutDescriptor,
+    PrimitiveState, Queue, RenderPassColorAttachment, RenderPipeline, RenderPipelineDescriptor,
+    Sampler, SamplerBindingType, SamplerDescriptor, ShaderModule, ShaderStages, Texture,
+    TextureFormat, TextureSampleType, TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+use crate::rendering::shader::capture_validation_errors;
+
+// Fills in the smaller mip levels of a texture by repeatedly halving the previous level on the GPU
+pub struct MipmapGenerator {
+    shader: ShaderModule,
+    sampler: Sampler,
+    bind_group_layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    pipeline_cache: Option<PipelineCache>,
+    pipelines: HashMap<TextureFormat, RenderPipeline>, // Render targets must match the texture format
+}
+
+impl MipmapGenerator {
+    pub fn new(
+        device: &Device,
+        shader: ShaderModule,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Self {
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Mipmap Sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("Mipmap bind group layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Mipmap Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            shader,
+            sampler,
+            bind_group_layout,
+            pipeline_layout,
+            pipeline_cache: pipeline_cache.cloned(),
+            pipelines: HashMap::new(),
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        pipeline_layout: &PipelineLayout,
+        shader: &ShaderModule,
+        format: TextureFormat,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> RenderPipeline {
+        debug!("Creating mipmap pipeline for {:?}", format);
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Mipmap Render Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[], // The fullscreen triangle is generated from the vertex index
+            },
+            fragment: Some(FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
+            cache: pipeline_cache,
+        })
+    }
+
+    fn pipeline_for(&mut self, device: &Device, format: TextureFormat) -> &RenderPipeline {
+        self.pipelines.entry(format).or_insert_with(|| {
+            Self::create_pipeline(
+                device,
+                &self.pipeline_layout,
+                &self.shader,
+                format,
+                self.pipeline_cache.as_ref(),
+            )
+        })
+    }
+
+    // Swaps in a recompiled shader, the current pipelines are kept when the new ones fail to build
+    pub fn reload_shader(&mut self, device: &Device, shader: ShaderModule) -> anyhow::Result<()> {
+        self.pipelines = capture_validation_errors(device, || {
+            self.pipelines
+                .keys()
+                .map(|format| {
+                    let pipeline = Self::create_pipeline(
+                        device,
+                        &self.pipeline_layout,
+                        &shader,
+                        *format,
+                        self.pipeline_cache.as_ref(),
+                    );
+                    (*format, pipeline)
+                })
+                .collect()
+        })?;
+        self.shader = shader;
+
+        Ok(())
+    }
+
+    // The texture needs RENDER_ATTACHMENT and TEXTURE_BINDING usage and level 0 already uploaded
+    pub fn generate(&mut self, device: &Device, queue: &Queue, texture: &Texture) {
+        let mip_level_count = texture.mip_level_count();
+        if mip_level_count < 2 {
+            return;
+        }
+
+        let format = texture.format();
+        let pipeline = self.pipeline_for(device, format).clone();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Command Encoder"),
+        });
+
+        let mip_view = |level: u32| {
+            texture.create_view(&TextureViewDescriptor {
+                label: Some("Mip View"),
+                base_mip_level: level,
+                mip_level_count: Some(1),
+                ..Default::default()
+            })
+        };
+
+        for target_level in 1..mip_level_count {
+            let source_view = mip_view(target_level - 1);
+            let target_view = mip_view(target_level);
+
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&source_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+                label: Some("Mipmap bind group"),
+            });
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Mipmap Render Pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            render_pass.set_pipeline(&pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
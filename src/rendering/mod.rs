@@ -1,111 +1,280 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
+use anyhow::{Context, bail};
 use bytemuck::cast_slice;
-use log::debug;
+use glam::{Mat4, UVec2};
+use log::{debug, error, info, warn};
 use wgpu::{
-    BindGroup, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendState, Buffer,
-    BufferBindingType, BufferUsages, ColorTargetState, ColorWrites, CommandEncoder, Device,
-    DeviceDescriptor, Face, Features, FragmentState, FrontFace, Instance, InstanceDescriptor,
-    Limits, MultisampleState, PipelineCompilationOptions, PipelineLayoutDescriptor, PrimitiveState,
-    Queue, RenderPassColorAttachment, RenderPipeline, RenderPipelineDescriptor, ShaderStages,
-    Surface, SurfaceConfiguration, SurfaceError, SurfaceTexture, TextureView, Trace, VertexState,
+    Adapter, Backends, BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+    BindingType, BlendState, Buffer, BufferBindingType, BufferDescriptor, BufferSlice,
+    BufferUsages, ColorTargetState, ColorWrites, CommandEncoder, Device, DeviceDescriptor,
+    DownlevelFlags, Face, Features, FragmentState, FrontFace, IndexFormat, Instance,
+    InstanceDescriptor, Limits, MultisampleState, PipelineCache, PipelineCompilationOptions,
+    PipelineLayout, PipelineLayoutDescriptor, PresentMode, PrimitiveState, Queue,
+    RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModule, ShaderStages, Surface, SurfaceConfiguration,
+    SurfaceError, SurfaceTexture, TextureFormat, TextureUsages, TextureView, Trace, VertexState,
     util::{BufferInitDescriptor, DeviceExt},
 };
 use winit::window::Window;
 
 use crate::{
+    asset::{
+        AssetService, MaterialHandle, MeshHandle, TextureHandle, material::Material,
+        texture::Texture,
+    },
     ecs::{
-        component::{camera::CameraComponent, transform::TransformComponent},
+        component::{
+            camera::{CameraComponent, PostProcessSettings},
+            transform::TransformComponent,
+        },
         entity::scene::Scene,
     },
-    rendering::{camera::CameraUniform, vertex::Vertex},
+    rendering::{
+        blit::BlitPipeline,
+        buffer::grow_capacity,
+        camera::CameraUniform,
+        debug_line::DebugLineRenderer,
+        depth::{DEPTH_FORMAT, DepthSettings},
+        device_lost::DeviceLost,
+        environment::EnvironmentMaps,
+        frustum::Frustum,
+        graph::{
+            POST_PROCESSED, PassContext, RenderGraph, RenderGraphPass, ResourceHandle, SCENE_COLOR,
+            SCENE_COLOR_MULTISAMPLED, SCENE_DEPTH, SHADOW_MAPS, SWAPCHAIN, TextureSize,
+            TransientTextureDescriptor, TransientTexturePool,
+        },
+        light::{GpuLights, LightUniform, collect_lights},
+        material::{
+            GpuMaterial, MATERIAL_TEXTURE_COUNT, MaterialFeatures, MaterialPipelineKey,
+            material_textures,
+        },
+        mesh::GpuMesh,
+        mipmap::MipmapGenerator,
+        model::ModelInstance,
+        pipeline_cache::PersistentPipelineCache,
+        post_process::{HDR_FORMAT, LDR_FORMAT, PostProcessRenderer},
+        settings::RendererSettings,
+        shader::{BuiltinShader, ShaderLibrary, capture_validation_errors},
+        shadow::ShadowMaps,
+        skybox::SkyboxRenderer,
+        sprite::{SpriteRenderer, collect_sprites},
+        stats::RenderStats,
+        text::{TextRenderer, collect_texts},
+        texture::GpuTexture,
+        tilemap::TilemapRenderer,
+        vertex::Vertex,
+    },
 };
 
+mod blit;
+mod buffer;
 mod camera;
-mod vertex;
-
-// Hardcoded vertices for a triangle
-// arramged om counter-clockwise order from top to bottom left to bottom right
-// since our render pipeline is configured to use counter-clockwise winding order
-const VERTICES: &[Vertex] = &[
-    Vertex {
-        position: [0.0, 0.5, 0.0],
-        color: [1.0, 0.0, 0.0],
-    },
-    Vertex {
-        position: [-0.5, -0.5, 0.0],
-        color: [0.0, 1.0, 0.0],
-    },
-    Vertex {
-        position: [0.5, -0.5, 0.0],
-        color: [0.0, 0.0, 1.0],
-    },
-];
+mod debug_line;
+pub mod depth;
+mod device_lost;
+mod environment;
+pub mod frustum;
+pub mod graph;
+mod light;
+mod material;
+mod mesh;
+mod mipmap;
+mod model;
+mod pipeline_cache;
+mod post_process;
+mod preprocessor;
+pub mod settings;
+mod shader;
+mod shadow;
+mod skybox;
+mod sprite;
+pub mod stats;
+mod text;
+mod texture;
+mod tilemap;
+pub mod vertex;
+
+// Number of instances the instance buffer can hold before it has to grow
+const INITIAL_INSTANCE_CAPACITY: u64 = 64;
+
+// Samples per pixel the main pass starts with, every adapter supports 4
+const DEFAULT_MSAA_SAMPLE_COUNT: u32 = 4;
+
+// Storage buffers the built-in shaders read outside of compute shaders
+const REQUIRED_DOWNLEVEL_FLAGS: DownlevelFlags =
+    DownlevelFlags::VERTEX_STORAGE.union(DownlevelFlags::FRAGMENT_STORAGE);
 
 pub struct RenderingService {
     window: Arc<Window>,
+    settings: RendererSettings, // Kept to create the device again after it is lost
     surface: Surface<'static>,
     surface_configuration: SurfaceConfiguration,
+    supported_present_modes: Vec<PresentMode>,
     device: Device,
     queue: Queue,
-    render_pipeline: RenderPipeline,
-    vertex_buffer: Buffer,
+    device_lost: DeviceLost,
+    shader_library: ShaderLibrary,
+    pipeline_cache: PersistentPipelineCache,
+    render_pipeline_layout: PipelineLayout,
+    material_shaders: HashMap<MaterialFeatures, ShaderModule>, // Compiled the first time a permutation is drawn
+    material_pipelines: HashMap<MaterialPipelineKey, RenderPipeline>, // Kept when the settings change so changing them back is free
+    depth_settings: DepthSettings,
+    msaa_sample_count: u32,
+    supported_msaa_sample_counts: Vec<u32>, // Sample counts both the scene color and depth formats allow
     is_surface_configured: bool,
     camera_uniform: CameraUniform,
     camera_buffer: Buffer,
+    camera_bind_group_layout: BindGroupLayout,
     camera_bind_group: BindGroup,
+    instance_buffer: Buffer,
+    instance_capacity: u64,
+    gpu_meshes: HashMap<MeshHandle, GpuMesh>,
+    mipmap_generator: MipmapGenerator,
+    default_texture: GpuTexture,
+    default_normal_texture: GpuTexture,
+    gpu_textures: HashMap<TextureHandle, GpuTexture>,
+    material_bind_group_layout: BindGroupLayout,
+    default_material: GpuMaterial,
+    gpu_materials: HashMap<MaterialHandle, GpuMaterial>,
+    light_bind_group_layout: BindGroupLayout,
+    gpu_lights: GpuLights,
+    shadow_maps: ShadowMaps,
+    environment_maps: EnvironmentMaps,
+    skybox_renderer: SkyboxRenderer,
+    sprite_renderer: SpriteRenderer,
+    tilemap_renderer: TilemapRenderer,
+    text_renderer: TextRenderer,
+    debug_line_renderer: DebugLineRenderer,
+    post_process_renderer: PostProcessRenderer,
+    blit_pipeline: BlitPipeline,
+    transient_textures: TransientTexturePool,
+    render_passes: Vec<Box<dyn RenderGraphPass>>, // Custom passes added to the render graph every frame
+    frustum_culling: bool,
+    stats: RenderStats,
+}
+
+// Passes of the render graph, custom passes are indices into the render passes of the service
+enum GraphPass {
+    Shadows,
+    Main,
+    Custom(usize),
+    Bloom,
+    Composite,
+    Fxaa,
+    Present,
+}
+
+// Everything needed to draw every entity that shares a mesh and material in one instanced draw
+struct MeshDraw {
+    mesh: MeshHandle,
+    material: Option<MaterialHandle>,
+    first_instance: u32, // Index of the first instance inside the instance buffer
+    instance_count: u32,
+    visible_count: u32, // Instances inside the camera frustum come first, the rest only cast shadows
+}
+
+// Instances of one mesh and material collected while preparing draws
+#[derive(Default)]
+struct InstanceBatch {
+    visible: Vec<ModelInstance>,
+    culled: Vec<ModelInstance>, // Outside the camera frustum but may still cast shadows
+}
+
+// The window surface and the device of the first adapter that could create one
+struct Gpu {
+    surface: Surface<'static>,
+    adapter: Adapter,
+    device: Device,
+    queue: Queue,
+}
+
+impl MeshDraw {
+    // The part of the instance buffer holding this draw's instances
+    fn instance_slice<'a>(&self, instance_buffer: &'a Buffer) -> BufferSlice<'a> {
+        let instance_size = std::mem::size_of::<ModelInstance>() as u64;
+        let start = self.first_instance as u64 * instance_size;
+        instance_buffer.slice(start..start + self.instance_count as u64 * instance_size)
+    }
 }
 
 impl RenderingService {
     pub async fn new(
         window: Arc<Window>,
+        asset_service: &mut AssetService,
+        settings: &RendererSettings,
         main_camera_component: &CameraComponent,
         main_transform_component: &TransformComponent,
     ) -> anyhow::Result<Self> {
+        // The surface is attached to a window and is used for rendering,
+        // the adapter is the interface to the GPU and provides access to its capabilities,
+        // the device is the logical handle to the GPU, and the queue is used to submit commands to the GPU.
+        let gpu = Self::request_gpu(window.clone(), settings).await?;
+
+        // Configure shaders, they are shader assets so edits can be picked up while running
+        let shader_library =
+            ShaderLibrary::load(asset_service, settings.shader_directory.as_deref());
+
+        // The camera uniform starts out looking through the main camera
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_projection_matrix(
+            main_camera_component,
+            main_transform_component,
+            &DepthSettings::default(),
+        );
+
+        debug!("Camera uniform: {:?}", camera_uniform);
+
+        Self::create(
+            window,
+            gpu,
+            asset_service,
+            settings.clone(),
+            shader_library,
+            camera_uniform,
+        )
+        .await
+    }
+
+    // Creates every GPU resource on the device, on startup and after the device is lost
+    async fn create(
+        window: Arc<Window>,
+        gpu: Gpu,
+        asset_service: &AssetService,
+        settings: RendererSettings,
+        shader_library: ShaderLibrary,
+        camera_uniform: CameraUniform,
+    ) -> anyhow::Result<Self> {
+        let Gpu {
+            surface,
+            adapter,
+            device,
+            queue,
+        } = gpu;
         let window_size = window.inner_size();
+        let adapter_info = adapter.get_info();
+        info!(
+            "Using GPU adapter: {} ({:?}, {:?})",
+            adapter_info.name, adapter_info.backend, adapter_info.device_type
+        );
 
-        // The instance manages WebGPU resources and provides access to the GPU.
-        let instance_descriptor: InstanceDescriptor = InstanceDescriptor {
-            #[cfg(target_os = "windows")]
-            backends: wgpu::Backends::DX12,
-            #[cfg(target_os = "macos")]
-            backends: wgpu::Backends::METAL,
-            #[cfg(target_os = "linux")]
-            backends: wgpu::Backends::VULKAN,
-            ..Default::default()
-        };
-        let instance: Instance = Instance::new(&instance_descriptor);
-
-        // The surface is attached to a window and is used for rendering.
-        let surface: Surface<'static> = instance
-            .create_surface(window.clone())
-            .expect("Failed to create surface");
-
-        // The adapter is the interface to the GPU and provides access to its capabilities.
-        let request_adapter_options = wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            compatible_surface: Some(&surface),
-            force_fallback_adapter: false,
-        };
-        let adapter = instance
-            .request_adapter(&request_adapter_options)
-            .await
-            .expect("Failed to request adapter");
-
-        debug!("Using GPU adapter: {}", adapter.get_info().name);
-
-        // The device is the logicl handle to the GPU, and the queue is used to submit commands to the GPU.
-        let device_descriptor = DeviceDescriptor {
-            label: None,
-            required_features: Features::empty(),
-            required_limits: Limits::default(),
-            memory_hints: Default::default(),
-            trace: Trace::Off,
-        };
-        let (device, queue) = adapter
-            .request_device(&device_descriptor)
-            .await
-            .expect("Failed to request device and queue");
+        let device_lost = DeviceLost::watch(&device);
+        let pipeline_cache = PersistentPipelineCache::new(
+            &device,
+            &adapter_info,
+            settings.pipeline_cache_directory.as_deref(),
+        );
+
+        let supported_msaa_sample_counts =
+            Self::find_supported_msaa_sample_counts(&adapter, device.features());
+        let msaa_sample_count = Self::closest_msaa_sample_count(
+            &supported_msaa_sample_counts,
+            DEFAULT_MSAA_SAMPLE_COUNT,
+        );
+        debug!(
+            "Supported MSAA sample counts: {:?}",
+            supported_msaa_sample_counts
+        );
 
         // The surface capabilities define how the surface can be used for rendering.
         let surface_capabilities = surface.get_capabilities(&adapter);
@@ -115,32 +284,24 @@ impl RenderingService {
             .find(|f| f.is_srgb())
             .copied()
             .unwrap_or(surface_capabilities.formats[0]);
+        let supported_present_modes = surface_capabilities.present_modes.clone();
         let surface_configuration = SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT, // Textures will be writen to the screen
             format: surface_format,
             width: window_size.width,
             height: window_size.height,
-            present_mode: wgpu::PresentMode::Fifo,
+            present_mode: Self::supported_present_mode(
+                &supported_present_modes,
+                settings.present_mode,
+            ),
             alpha_mode: surface_capabilities.alpha_modes[0],
             view_formats: vec![],
-            desired_maximum_frame_latency: 2,
-        };
-
-        // Configure shaders
-        let shader_module_descriptor = wgpu::ShaderModuleDescriptor {
-            label: Some("Primary Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("../shader.wgsl").into()),
+            desired_maximum_frame_latency: settings.frame_latency, // Frames the CPU may queue ahead of the GPU
         };
-        let shader = device.create_shader_module(shader_module_descriptor);
 
         // Setup the uniform buffer for the camera
         // uniform buffers are used across every invocation of the shaders
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform
-            .update_view_projection_matrix(&main_camera_component, &main_transform_component);
-
-        debug!("Camera uniform: {:?}", camera_uniform);
-
+        let depth_settings = DepthSettings::default();
         let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Camera Uniform Buffer"),
             contents: cast_slice(&[camera_uniform]),
@@ -150,7 +311,7 @@ impl RenderingService {
         let camera_bind_group_layout_descriptor = BindGroupLayoutDescriptor {
             entries: &[BindGroupLayoutEntry {
                 binding: 0,
-                visibility: ShaderStages::VERTEX,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT, // The fragment shader needs the camera position for specular lighting
                 ty: BindingType::Buffer {
                     ty: BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -172,90 +333,972 @@ impl RenderingService {
         };
         let camera_bind_group = device.create_bind_group(&camera_bind_group_descriptor);
 
+        // Setup the vertex buffer for the per-instance model matrices
+        // entities sharing a mesh and material are laid out next to each other so they can be drawn at once
+        let instance_buffer = Self::create_instance_buffer(&device, INITIAL_INSTANCE_CAPACITY);
+
+        // Setup texture sampling, empty material slots sample a single white pixel
+        // which leaves the material factors unchanged
+        let mut mipmap_generator = MipmapGenerator::new(
+            &device,
+            shader_library.compile_or_embedded(&device, asset_service, BuiltinShader::Mipmap, &[]),
+            pipeline_cache.cache(),
+        );
+        let default_texture = GpuTexture::new(
+            &device,
+            &queue,
+            &Texture {
+                name: Some("Default White Texture".to_string()),
+                width: 1,
+                height: 1,
+                pixels: vec![255, 255, 255, 255],
+                ..Default::default()
+            },
+            &mut mipmap_generator,
+        )
+        .context("Failed to create the default texture")?;
+        // A normal pointing straight out of the surface
+        let default_normal_texture = GpuTexture::new(
+            &device,
+            &queue,
+            &Texture {
+                name: Some("Default Normal Texture".to_string()),
+                width: 1,
+                height: 1,
+                format: TextureFormat::Rgba8Unorm,
+                pixels: vec![128, 128, 255, 255],
+                ..Default::default()
+            },
+            &mut mipmap_generator,
+        )
+        .context("Failed to create the default normal texture")?;
+
+        // Setup materials, meshes without a material are drawn as a rough white dielectric
+        let material_bind_group_layout = GpuMaterial::create_bind_group_layout(&device);
+        let default_material = GpuMaterial::new(
+            &device,
+            &Material {
+                name: Some("Default Material".to_string()),
+                metallic_factor: 0.0,
+                ..Default::default()
+            },
+            Self::default_material_textures(&default_texture, &default_normal_texture),
+            &material_bind_group_layout,
+        );
+
+        // Setup the storage buffer for the lights, it is refilled from the scene every frame.
+        // Shadow maps share the bind group since the shader only looks them up per light.
+        let shadow_maps = ShadowMaps::new(
+            &device,
+            shader_library.compile_or_embedded(&device, asset_service, BuiltinShader::Shadow, &[]),
+            pipeline_cache.cache(),
+        );
+        let light_bind_group_layout = GpuLights::create_bind_group_layout(&device);
+        let gpu_lights = GpuLights::new(&device, &light_bind_group_layout, &shadow_maps);
+
+        // Setup image based lighting, the environment map of the main camera is baked into cubemaps
+        // the first time it is used and the scene is lit with a dim constant light until then
+        let environment_maps = EnvironmentMaps::new(
+            &device,
+            &queue,
+            shader_library.compile_or_embedded(
+                &device,
+                asset_service,
+                BuiltinShader::Environment,
+                &[],
+            ),
+            pipeline_cache.cache(),
+        );
+        let skybox_renderer = SkyboxRenderer::new(
+            &device,
+            shader_library.compile_or_embedded(&device, asset_service, BuiltinShader::Skybox, &[]),
+            environment_maps.bind_group_layout(),
+            HDR_FORMAT,
+            &depth_settings,
+            msaa_sample_count,
+            pipeline_cache.cache(),
+        );
+
         // Configure the rendering pipeline
         let render_pipeline_layout_descriptor = PipelineLayoutDescriptor {
             label: Some("Primary Render Pipeline Layout"),
-            bind_group_layouts: &[&camera_bind_group_layout],
+            bind_group_layouts: &[
+                &camera_bind_group_layout,
+                &material_bind_group_layout,
+                &light_bind_group_layout,
+                environment_maps.bind_group_layout(),
+            ],
             push_constant_ranges: &[],
         };
         let render_pipeline_layout =
             device.create_pipeline_layout(&render_pipeline_layout_descriptor);
+
+        // Sprites, tilemaps and text are drawn after the meshes in the same pass with their own pipelines
+        let sprite_renderer = SpriteRenderer::new(
+            &device,
+            shader_library.compile_or_embedded(&device, asset_service, BuiltinShader::Sprite, &[]),
+            &camera_bind_group_layout,
+            HDR_FORMAT,
+            &depth_settings,
+            msaa_sample_count,
+            pipeline_cache.cache(),
+        );
+        let tilemap_renderer = TilemapRenderer::new(
+            &device,
+            shader_library.compile_or_embedded(&device, asset_service, BuiltinShader::Tilemap, &[]),
+            &camera_bind_group_layout,
+            HDR_FORMAT,
+            &depth_settings,
+            msaa_sample_count,
+            pipeline_cache.cache(),
+        );
+        let text_renderer = TextRenderer::new(
+            &device,
+            shader_library.compile_or_embedded(&device, asset_service, BuiltinShader::Text, &[]),
+            &camera_bind_group_layout,
+            HDR_FORMAT,
+            &depth_settings,
+            msaa_sample_count,
+            pipeline_cache.cache(),
+        );
+        let debug_line_renderer = DebugLineRenderer::new(
+            &device,
+            shader_library.compile_or_embedded(
+                &device,
+                asset_service,
+                BuiltinShader::DebugLine,
+                &[],
+            ),
+            &camera_bind_group_layout,
+            HDR_FORMAT,
+            &depth_settings,
+            msaa_sample_count,
+            pipeline_cache.cache(),
+        );
+
+        // The main pass draws into an HDR texture, custom passes work on it before it is post processed
+        // into display colors and copied to the surface at the end of the frame
+        let post_process_renderer = PostProcessRenderer::new(
+            &device,
+            &queue,
+            shader_library.compile_or_embedded(
+                &device,
+                asset_service,
+                BuiltinShader::PostProcess,
+                &[],
+            ),
+            pipeline_cache.cache(),
+        );
+        let blit_pipeline = BlitPipeline::new(
+            &device,
+            shader_library.compile_or_embedded(&device, asset_service, BuiltinShader::Blit, &[]),
+            surface_configuration.format,
+            pipeline_cache.cache(),
+        );
+
+        Ok(RenderingService {
+            window,
+            settings,
+            surface,
+            surface_configuration,
+            supported_present_modes,
+            device,
+            queue,
+            device_lost,
+            shader_library,
+            pipeline_cache,
+            render_pipeline_layout,
+            material_shaders: HashMap::new(),
+            material_pipelines: HashMap::new(),
+            depth_settings,
+            msaa_sample_count,
+            supported_msaa_sample_counts,
+            is_surface_configured: false,
+            camera_uniform,
+            camera_buffer,
+            camera_bind_group_layout,
+            camera_bind_group,
+            instance_buffer,
+            instance_capacity: INITIAL_INSTANCE_CAPACITY,
+            gpu_meshes: HashMap::new(),
+            mipmap_generator,
+            default_texture,
+            default_normal_texture,
+            gpu_textures: HashMap::new(),
+            material_bind_group_layout,
+            default_material,
+            gpu_materials: HashMap::new(),
+            light_bind_group_layout,
+            gpu_lights,
+            shadow_maps,
+            environment_maps,
+            skybox_renderer,
+            sprite_renderer,
+            tilemap_renderer,
+            text_renderer,
+            debug_line_renderer,
+            post_process_renderer,
+            blit_pipeline,
+            transient_textures: TransientTexturePool::default(),
+            render_passes: Vec::new(),
+            frustum_culling: true,
+            stats: RenderStats::default(),
+        })
+    }
+
+    // Tries the configured backends first, then GL and finally a software adapter so a machine without
+    // the native backend still renders. Every attempt needs its own instance, which creates the surface,
+    // and an adapter that can't create a device moves on to the next attempt.
+    async fn request_gpu(window: Arc<Window>, settings: &RendererSettings) -> anyhow::Result<Gpu> {
+        let attempts = [
+            (settings.backends, false),
+            (Backends::GL, false),
+            (settings.backends | Backends::GL, true),
+        ];
+        for (index, (backends, force_fallback_adapter)) in attempts.into_iter().enumerate() {
+            if attempts[..index].contains(&(backends, force_fallback_adapter)) {
+                continue;
+            }
+
+            // The instance manages WebGPU resources and provides access to the GPU.
+            let instance = Instance::new(&InstanceDescriptor {
+                backends,
+                ..Default::default()
+            });
+            let surface = match instance.create_surface(window.clone()) {
+                Ok(surface) => surface,
+                Err(e) => {
+                    warn!("Failed to create a surface with {:?}: {}", backends, e);
+                    continue;
+                }
+            };
+            let request_adapter_options = wgpu::RequestAdapterOptions {
+                power_preference: settings.power_preference,
+                compatible_surface: Some(&surface),
+                force_fallback_adapter, // Software adapters are slow, they are the last resort
+            };
+            let adapter = match instance.request_adapter(&request_adapter_options).await {
+                Ok(adapter) => adapter,
+                Err(e) => {
+                    if force_fallback_adapter {
+                        warn!("No software adapter with {:?}: {}", backends, e);
+                    } else {
+                        warn!("No adapter with {:?}: {}", backends, e);
+                    }
+                    continue;
+                }
+            };
+
+            // The light buffer is read in fragment shaders and the tile texture coordinates in vertex shaders,
+            // GLES 3.0 class adapters create the device but fail validation once the pipelines are created
+            let missing_downlevel_flags =
+                REQUIRED_DOWNLEVEL_FLAGS - adapter.get_downlevel_capabilities().flags;
+            if !missing_downlevel_flags.is_empty() {
+                warn!(
+                    "Skipping {}, it lacks {:?}",
+                    adapter.get_info().name,
+                    missing_downlevel_flags
+                );
+                continue;
+            }
+
+            // The configured backends get everything their adapter offers,
+            // GL and software adapters often fall short of the default limits
+            let required_limits = if index == 0 {
+                adapter.limits()
+            } else {
+                Limits::downlevel_defaults().using_resolution(adapter.limits())
+            };
+            let device_descriptor = DeviceDescriptor {
+                label: None,
+                // Block compressed textures are used when the adapter supports them,
+                // adapter specific format features allow sample counts other than 1 and 4,
+                // the pipeline cache lets the driver skip compiling pipelines from an earlier run
+                required_features: adapter.features()
+                    & (Features::TEXTURE_COMPRESSION_BC
+                        | Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                        | Features::PIPELINE_CACHE),
+                required_limits,
+                memory_hints: Default::default(),
+                trace: Trace::Off,
+            };
+            match adapter.request_device(&device_descriptor).await {
+                Ok((device, queue)) => {
+                    return Ok(Gpu {
+                        surface,
+                        adapter,
+                        device,
+                        queue,
+                    });
+                }
+                Err(e) => warn!(
+                    "Failed to request a device from {}: {}",
+                    adapter.get_info().name,
+                    e
+                ),
+            }
+        }
+
+        bail!("No GPU or software adapter can render to the window")
+    }
+
+    // Fifo is the only present mode every surface supports
+    fn supported_present_mode(
+        supported_present_modes: &[PresentMode],
+        present_mode: PresentMode,
+    ) -> PresentMode {
+        if supported_present_modes.contains(&present_mode)
+            || matches!(
+                present_mode,
+                PresentMode::AutoVsync | PresentMode::AutoNoVsync
+            )
+        {
+            return present_mode;
+        }
+
+        warn!(
+            "The {:?} present mode is not supported, using Fifo instead",
+            present_mode
+        );
+        PresentMode::Fifo
+    }
+
+    // Textures bound to the slots of a material that has none of its own
+    fn default_material_textures<'a>(
+        default_texture: &'a GpuTexture,
+        default_normal_texture: &'a GpuTexture,
+    ) -> [&'a GpuTexture; MATERIAL_TEXTURE_COUNT] {
+        [
+            default_texture,
+            default_texture,
+            default_normal_texture,
+            default_texture,
+            default_texture,
+        ]
+    }
+
+    fn create_render_pipeline(
+        device: &Device,
+        render_pipeline_layout: &PipelineLayout,
+        shader: &ShaderModule,
+        key: &MaterialPipelineKey,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> RenderPipeline {
         let color_target_state = Some(ColorTargetState {
-            format: surface_configuration.format, // use the surface format since the fragments will be output there
-            blend: Some(BlendState::REPLACE),     // Replaces color instead of blending
-            write_mask: ColorWrites::ALL,         // Write to all color channels
+            format: key.color_format, // use the surface format since the fragments will be output there
+            blend: Some(BlendState::REPLACE), // Replaces color instead of blending
+            write_mask: ColorWrites::ALL, // Write to all color channels
         });
         let targets = &[color_target_state];
         let render_pipeline_descriptor = RenderPipelineDescriptor {
-            label: Some("Primary Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
+            label: Some(if key.features.double_sided {
+                "Double Sided Render Pipeline"
+            } else {
+                "Primary Render Pipeline"
+            }),
+            layout: Some(render_pipeline_layout),
             vertex: VertexState {
-                module: &shader,
+                module: shader,
                 entry_point: Some("vs_main"),
                 compilation_options: PipelineCompilationOptions::default(),
-                buffers: &[Vertex::describe_vertex_buffer_layout()],
+                buffers: &[
+                    Vertex::describe_vertex_buffer_layout(),
+                    ModelInstance::describe_instance_buffer_layout(),
+                ],
             },
             fragment: Some(FragmentState {
-                module: &shader,
+                module: shader,
                 entry_point: Some("fs_main"),
                 compilation_options: PipelineCompilationOptions::default(),
-                targets: targets,
+                targets,
             }),
             // Configure how the vertices are interpreted
             primitive: PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleList, // Every three vertices form a triangle
                 strip_index_format: None,
                 front_face: FrontFace::Ccw, // Triangle is facing forward (counter-clockwise)
-                cull_mode: Some(Face::Back), // Cull (remove) back-facing triangles
+                // Cull (remove) back-facing triangles unless the material is visible from both sides
+                cull_mode: if key.features.double_sided {
+                    None
+                } else {
+                    Some(Face::Back)
+                },
                 polygon_mode: wgpu::PolygonMode::Fill,
                 unclipped_depth: false,
                 conservative: false,
             },
-            depth_stencil: None, // Not using depth yet
+            depth_stencil: Some(key.depth_settings.depth_stencil_state()),
             multisample: MultisampleState {
-                count: 1,                         // Use 1 sample per pixel
-                mask: !0,                         // Use all samples
-                alpha_to_coverage_enabled: false, // disable anti-aliasing
+                count: key.sample_count, // Samples per pixel, more than 1 smooths triangle edges
+                mask: !0,                // Use all samples
+                alpha_to_coverage_enabled: false, // Transparency is blended rather than turned into coverage
             },
             multiview: None,
-            cache: None, // Don't cache shader compilation results
+            cache: pipeline_cache, // Lets the driver reuse pipelines compiled in an earlier run
         };
-        let render_pipeline = device.create_render_pipeline(&render_pipeline_descriptor);
+        device.create_render_pipeline(&render_pipeline_descriptor)
+    }
+
+    // Changing the depth settings requires rebuilding the 2D pipelines since depth state is baked into them,
+    // material pipelines are looked up by a key that includes the depth settings instead
+    pub fn set_depth_settings(&mut self, depth_settings: DepthSettings) {
+        if depth_settings == self.depth_settings {
+            return;
+        }
+
+        debug!("Applying depth settings: {:?}", depth_settings);
+        self.depth_settings = depth_settings;
+        self.recreate_pipelines();
+    }
+
+    // Sample counts the adapter can't render with fall back to the closest lower one
+    pub fn set_msaa_sample_count(&mut self, sample_count: u32) {
+        let supported_sample_count =
+            Self::closest_msaa_sample_count(&self.supported_msaa_sample_counts, sample_count);
+        if supported_sample_count != sample_count {
+            warn!(
+                "MSAA with {} samples is not supported, using {} samples instead",
+                sample_count, supported_sample_count
+            );
+        }
+
+        if supported_sample_count == self.msaa_sample_count {
+            return;
+        }
+
+        debug!("Applying MSAA sample count: {}", supported_sample_count);
+        self.msaa_sample_count = supported_sample_count;
+        self.recreate_pipelines();
+    }
+
+    pub fn msaa_sample_count(&self) -> u32 {
+        self.msaa_sample_count
+    }
+
+    // Sample counts set_msaa_sample_count accepts as they are, always including 1
+    pub fn supported_msaa_sample_counts(&self) -> &[u32] {
+        &self.supported_msaa_sample_counts
+    }
 
-        // Create a vertex buffer for the hardcoded triangle vertices
-        let vertex_buffer_init_descriptor = BufferInitDescriptor {
-            label: Some("Hardcoded Triangle Vertex Buffer"),
-            contents: cast_slice(VERTICES), // bytemuck is used to cast complex struct types to bytes
-            usage: BufferUsages::VERTEX,    // This buffer is used for vertex data
+    fn find_supported_msaa_sample_counts(adapter: &Adapter, device_features: Features) -> Vec<u32> {
+        // Without adapter specific format features only the counts WebGPU guarantees can be used
+        let sample_counts = |format: TextureFormat| {
+            if device_features.contains(Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+                adapter.get_texture_format_features(format)
+            } else {
+                format.guaranteed_format_features(device_features)
+            }
+            .flags
+            .supported_sample_counts()
         };
-        let vertex_buffer = device.create_buffer_init(&vertex_buffer_init_descriptor);
 
-        Ok(RenderingService {
-            window: window.clone(),
-            surface: surface,
-            surface_configuration: surface_configuration,
-            device: device,
-            queue: queue,
-            render_pipeline: render_pipeline,
-            vertex_buffer: vertex_buffer,
-            is_surface_configured: false,
-            camera_uniform: camera_uniform,
-            camera_buffer: camera_buffer,
-            camera_bind_group: camera_bind_group,
+        let depth_sample_counts = sample_counts(DEPTH_FORMAT);
+        sample_counts(HDR_FORMAT)
+            .into_iter()
+            .filter(|sample_count| depth_sample_counts.contains(sample_count))
+            .collect()
+    }
+
+    fn closest_msaa_sample_count(supported_sample_counts: &[u32], sample_count: u32) -> u32 {
+        supported_sample_counts
+            .iter()
+            .copied()
+            .filter(|supported| *supported <= sample_count)
+            .max()
+            .unwrap_or(1)
+    }
+
+    // Every pipeline of the main pass bakes in the depth settings and the sample count,
+    // material pipelines for the new settings are created the first time they are drawn
+    fn recreate_pipelines(&mut self) {
+        self.sprite_renderer.recreate_pipelines(
+            &self.device,
+            HDR_FORMAT,
+            &self.depth_settings,
+            self.msaa_sample_count,
+        );
+        self.tilemap_renderer.recreate_pipelines(
+            &self.device,
+            HDR_FORMAT,
+            &self.depth_settings,
+            self.msaa_sample_count,
+        );
+        self.text_renderer.recreate_pipelines(
+            &self.device,
+            HDR_FORMAT,
+            &self.depth_settings,
+            self.msaa_sample_count,
+        );
+        self.debug_line_renderer.recreate_pipelines(
+            &self.device,
+            HDR_FORMAT,
+            &self.depth_settings,
+            self.msaa_sample_count,
+        );
+        self.skybox_renderer.recreate_pipelines(
+            &self.device,
+            HDR_FORMAT,
+            &self.depth_settings,
+            self.msaa_sample_count,
+        );
+    }
+
+    // Rebuilds the pipelines of every built in shader whose asset changed, pipelines that fail to
+    // build with the new source keep the previous one so a typo doesn't take the renderer down
+    fn reload_changed_shaders(&mut self, asset_service: &AssetService) {
+        for builtin in self.shader_library.take_changed(asset_service) {
+            match self.reload_shader(builtin, asset_service) {
+                Ok(()) => info!("Reloaded the pipelines of the {:?} shader", builtin),
+                Err(e) => error!(
+                    "Failed to reload the {:?} shader, keeping the previous pipelines: {:?}",
+                    builtin, e
+                ),
+            }
+        }
+    }
+
+    fn reload_shader(
+        &mut self,
+        builtin: BuiltinShader,
+        asset_service: &AssetService,
+    ) -> anyhow::Result<()> {
+        let device = &self.device;
+        let compile = || {
+            self.shader_library
+                .compile(device, asset_service, builtin, &[])
+        };
+
+        match builtin {
+            BuiltinShader::Primary => self.reload_material_pipelines(asset_service),
+            BuiltinShader::Shadow => self.shadow_maps.reload_shader(device, compile()?),
+            BuiltinShader::Sprite => self.sprite_renderer.reload_shader(
+                device,
+                compile()?,
+                HDR_FORMAT,
+                &self.depth_settings,
+                self.msaa_sample_count,
+            ),
+            BuiltinShader::Tilemap => self.tilemap_renderer.reload_shader(
+                device,
+                compile()?,
+                HDR_FORMAT,
+                &self.depth_settings,
+                self.msaa_sample_count,
+            ),
+            BuiltinShader::Text => self.text_renderer.reload_shader(
+                device,
+                compile()?,
+                HDR_FORMAT,
+                &self.depth_settings,
+                self.msaa_sample_count,
+            ),
+            BuiltinShader::DebugLine => self.debug_line_renderer.reload_shader(
+                device,
+                compile()?,
+                HDR_FORMAT,
+                &self.depth_settings,
+                self.msaa_sample_count,
+            ),
+            BuiltinShader::Environment => {
+                self.environment_maps
+                    .reload_shader(device, &self.queue, compile()?)
+            }
+            BuiltinShader::Skybox => self.skybox_renderer.reload_shader(
+                device,
+                compile()?,
+                HDR_FORMAT,
+                &self.depth_settings,
+                self.msaa_sample_count,
+            ),
+            BuiltinShader::PostProcess => {
+                self.post_process_renderer.reload_shader(device, compile()?)
+            }
+            BuiltinShader::Blit => self.blit_pipeline.reload_shader(device, compile()?),
+            BuiltinShader::Mipmap => self.mipmap_generator.reload_shader(device, compile()?),
+        }
+    }
+
+    // Pipeline state the main pass currently draws materials with
+    fn material_pipeline_key(&self, features: MaterialFeatures) -> MaterialPipelineKey {
+        MaterialPipelineKey {
+            features,
+            color_format: HDR_FORMAT,
+            depth_settings: self.depth_settings,
+            sample_count: self.msaa_sample_count,
+        }
+    }
+
+    // Creates the pipeline for the key, compiling the permutation of the primary shader
+    // with the features first when no pipeline used them yet
+    fn create_material_pipeline(
+        &mut self,
+        key: MaterialPipelineKey,
+        asset_service: &AssetService,
+    ) -> anyhow::Result<RenderPipeline> {
+        if !self.material_shaders.contains_key(&key.features) {
+            let shader = self.shader_library.compile(
+                &self.device,
+                asset_service,
+                BuiltinShader::Primary,
+                &key.features.defines(),
+            )?;
+            self.material_shaders.insert(key.features, shader);
+        }
+
+        capture_validation_errors(&self.device, || {
+            Self::create_render_pipeline(
+                &self.device,
+                &self.render_pipeline_layout,
+                &self.material_shaders[&key.features],
+                &key,
+                self.pipeline_cache.cache(),
+            )
+        })
+    }
+
+    // Creates the pipelines of keys that are drawn for the first time,
+    // the embedded primary shader stands in when the loaded one is broken
+    fn prepare_material_pipelines(&mut self, draws: &[MeshDraw], asset_service: &AssetService) {
+        for draw in draws.iter().filter(|draw| draw.visible_count > 0) {
+            let key = self.material_pipeline_key(self.material(draw.material).features);
+            if self.material_pipelines.contains_key(&key) {
+                continue;
+            }
+
+            debug!("Creating the material pipeline for {:?}", key);
+            let pipeline = self
+                .create_material_pipeline(key, asset_service)
+                .unwrap_or_else(|e| {
+                    error!(
+                        "Failed to create the {:?} material pipeline, using the embedded shader: {:?}",
+                        key.features, e
+                    );
+                    let shader = self.shader_library.compile_embedded(
+                        &self.device,
+                        BuiltinShader::Primary,
+                        &key.features.defines(),
+                    );
+                    let pipeline = Self::create_render_pipeline(
+                        &self.device,
+                        &self.render_pipeline_layout,
+                        &shader,
+                        &key,
+                        self.pipeline_cache.cache(),
+                    );
+                    self.material_shaders.insert(key.features, shader);
+                    pipeline
+                });
+            self.material_pipelines.insert(key, pipeline);
+        }
+    }
+
+    // Recompiles every permutation and pipeline created so far, the previous ones are all kept if any of them fails
+    fn reload_material_pipelines(&mut self, asset_service: &AssetService) -> anyhow::Result<()> {
+        let material_shaders: HashMap<MaterialFeatures, ShaderModule> = self
+            .material_shaders
+            .keys()
+            .map(|features| {
+                let shader = self.shader_library.compile(
+                    &self.device,
+                    asset_service,
+                    BuiltinShader::Primary,
+                    &features.defines(),
+                )?;
+                Ok((*features, shader))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        self.material_pipelines = self
+            .material_pipelines
+            .keys()
+            .map(|key| {
+                let pipeline = capture_validation_errors(&self.device, || {
+                    Self::create_render_pipeline(
+                        &self.device,
+                        &self.render_pipeline_layout,
+                        &material_shaders[&key.features],
+                        key,
+                        self.pipeline_cache.cache(),
+                    )
+                })?;
+                Ok((*key, pipeline))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        self.material_shaders = material_shaders;
+
+        Ok(())
+    }
+
+    // Saves the driver data of the pipelines created so far, does nothing without a pipeline cache directory
+    pub fn save_pipeline_cache(&self) -> anyhow::Result<()> {
+        self.pipeline_cache.save()
+    }
+
+    // Draws without a material, or with one that failed to upload, use the default material
+    fn material(&self, material_handle: Option<MaterialHandle>) -> &GpuMaterial {
+        material_handle
+            .and_then(|material_handle| self.gpu_materials.get(&material_handle))
+            .unwrap_or(&self.default_material)
+    }
+
+    // Culling only skips objects the camera can't see, it does not change what ends up on screen
+    pub fn set_frustum_culling(&mut self, enabled: bool) {
+        self.frustum_culling = enabled;
+    }
+
+    // Adds a pass to the render graph of every following frame, it runs wherever the resources it declares allow
+    pub fn add_render_pass(&mut self, pass: Box<dyn RenderGraphPass>) {
+        debug!("Adding render pass: {}", pass.name());
+        self.render_passes.push(pass);
+    }
+
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    fn create_instance_buffer(device: &Device, capacity: u64) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("Model Instance Buffer"),
+            size: capacity * std::mem::size_of::<ModelInstance>() as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         })
     }
 
-    pub fn update_camera_uniform(&mut self, scene: &Scene) {
-        let main_camera_component = scene.camera_components.get(&1).unwrap();
-        let main_transform_component = scene.transform_components.get(&1).unwrap();
+    fn upload_texture(&mut self, texture_handle: TextureHandle, asset_service: &AssetService) {
+        if self.gpu_textures.contains_key(&texture_handle) {
+            return;
+        }
+
+        let gpu_texture = match asset_service.textures.get(&texture_handle) {
+            Some(texture) => {
+                debug!("Uploading texture {:?} to the GPU", texture_handle);
+                GpuTexture::new(
+                    &self.device,
+                    &self.queue,
+                    texture,
+                    &mut self.mipmap_generator,
+                )
+            }
+            None => Err(anyhow::anyhow!(
+                "Texture {:?} does not exist",
+                texture_handle
+            )),
+        };
+
+        // Failed textures fall back to the default texture so the error is only reported once
+        let gpu_texture = gpu_texture.unwrap_or_else(|e| {
+            error!("Failed to upload texture {:?}: {:?}", texture_handle, e);
+            self.default_texture.clone()
+        });
+        self.gpu_textures.insert(texture_handle, gpu_texture);
+    }
+
+    // Uploads a material and its textures, or refreshes it if it is already on the GPU
+    fn prepare_material(
+        &mut self,
+        material_handle: MaterialHandle,
+        asset_service: &AssetService,
+    ) -> bool {
+        let Some(material) = asset_service.materials.get(&material_handle) else {
+            return false;
+        };
+
+        let textures = material_textures(material);
+        if let Some(gpu_material) = self.gpu_materials.get_mut(&material_handle)
+            && gpu_material.textures == textures
+        {
+            gpu_material.update(&self.queue, material);
+            return true;
+        }
+
+        // New materials and materials whose textures were swapped need a new bind group
+        for texture_handle in textures.iter().flatten() {
+            self.upload_texture(*texture_handle, asset_service);
+        }
+
+        let mut gpu_textures =
+            Self::default_material_textures(&self.default_texture, &self.default_normal_texture);
+        for (gpu_texture, texture_handle) in gpu_textures.iter_mut().zip(textures.iter()) {
+            if let Some(texture_handle) = texture_handle {
+                *gpu_texture = &self.gpu_textures[texture_handle];
+            }
+        }
+
+        debug!("Uploading material {:?} to the GPU", material_handle);
+        let gpu_material = GpuMaterial::new(
+            &self.device,
+            material,
+            gpu_textures,
+            &self.material_bind_group_layout,
+        );
+        self.gpu_materials.insert(material_handle, gpu_material);
+
+        true
+    }
+
+    // Uploads the model matrix of every mesh entity and groups the entities into instanced draws.
+    // Entities outside the camera frustum are kept for the shadow passes but not drawn by the main pass.
+    fn prepare_draws(&mut self, scene: &Scene, asset_service: &AssetService) -> Vec<MeshDraw> {
+        let mut batches: HashMap<(MeshHandle, Option<MaterialHandle>), InstanceBatch> =
+            HashMap::new();
+        let mut prepared_materials: HashMap<MaterialHandle, bool> = HashMap::new();
+        let frustum = Frustum::from_view_projection(Mat4::from_cols_array_2d(
+            &self.camera_uniform.view_projection_matrix,
+        ));
+        self.stats = RenderStats::default();
+
+        for (entity, mesh_component) in scene.mesh_components.iter() {
+            if !self.gpu_meshes.contains_key(&mesh_component.mesh) {
+                let Some(mesh) = asset_service.meshes.get(&mesh_component.mesh) else {
+                    warn!(
+                        "Entity {} refers to missing mesh {:?}",
+                        entity, mesh_component.mesh
+                    );
+                    continue;
+                };
+
+                debug!("Uploading mesh {:?} to the GPU", mesh_component.mesh);
+                self.gpu_meshes
+                    .insert(mesh_component.mesh, GpuMesh::new(&self.device, mesh));
+            }
+
+            // Each material is only refreshed once per frame no matter how many meshes use it
+            let mut material = scene
+                .material_components
+                .get(entity)
+                .map(|material_component| material_component.material);
+            if let Some(material_handle) = material {
+                let is_prepared = match prepared_materials.get(&material_handle) {
+                    Some(is_prepared) => *is_prepared,
+                    None => {
+                        let is_prepared = self.prepare_material(material_handle, asset_service);
+                        prepared_materials.insert(material_handle, is_prepared);
+                        is_prepared
+                    }
+                };
+                if !is_prepared {
+                    warn!(
+                        "Entity {} refers to missing material {:?}",
+                        entity, material_handle
+                    );
+                    material = None;
+                }
+            }
+
+            let model_matrix = scene.calculate_world_matrix(*entity);
+            let is_visible = !self.frustum_culling
+                || self.gpu_meshes[&mesh_component.mesh]
+                    .bounds
+                    .is_none_or(|bounds| frustum.intersects_bounds(&bounds, model_matrix));
+
+            let batch = batches.entry((mesh_component.mesh, material)).or_default();
+            self.stats.objects += 1;
+            if is_visible {
+                batch.visible.push(ModelInstance::new(model_matrix));
+                self.stats.visible_objects += 1;
+            } else {
+                batch.culled.push(ModelInstance::new(model_matrix));
+                self.stats.culled_objects += 1;
+            }
+        }
+
+        // Lay the batches out one after another in the instance buffer
+        let mut draws = Vec::with_capacity(batches.len());
+        let mut instances: Vec<ModelInstance> = Vec::new();
+        for ((mesh, material), batch) in batches {
+            draws.push(MeshDraw {
+                mesh,
+                material,
+                first_instance: instances.len() as u32,
+                instance_count: (batch.visible.len() + batch.culled.len()) as u32,
+                visible_count: batch.visible.len() as u32,
+            });
+            instances.extend(batch.visible);
+            instances.extend(batch.culled);
+        }
+
+        let new_capacity = grow_capacity(self.instance_capacity, instances.len() as u64);
+        if new_capacity > self.instance_capacity {
+            debug!("Growing instance buffer to {} entries", new_capacity);
+            self.instance_buffer = Self::create_instance_buffer(&self.device, new_capacity);
+            self.instance_capacity = new_capacity;
+        }
+
+        if !instances.is_empty() {
+            self.queue
+                .write_buffer(&self.instance_buffer, 0, cast_slice(&instances));
+        }
+
+        draws
+    }
+
+    // Builds the sprite quads, visible tilemap chunks, text glyphs and debug lines for this frame,
+    // uploading any texture they use for the first time
+    fn prepare_2d(&mut self, scene: &Scene, asset_service: &AssetService) {
+        let view_projection_matrix =
+            Mat4::from_cols_array_2d(&self.camera_uniform.view_projection_matrix);
+        let frustum = Frustum::from_view_projection(view_projection_matrix);
+        let quads = collect_sprites(
+            scene,
+            asset_service,
+            view_projection_matrix,
+            &self.depth_settings,
+            self.frustum_culling.then_some(&frustum),
+        );
+
+        for quad in quads.iter() {
+            self.upload_texture(quad.texture, asset_service);
+        }
+
+        self.sprite_renderer
+            .prepare(&self.device, &self.queue, &quads, &self.gpu_textures);
+
+        for texture_handle in TilemapRenderer::tileset_textures(scene, asset_service) {
+            self.upload_texture(texture_handle, asset_service);
+        }
+
+        self.tilemap_renderer.prepare(
+            &self.device,
+            &self.queue,
+            scene,
+            asset_service,
+            &self.gpu_textures,
+            self.frustum_culling.then_some(&frustum),
+        );
+
+        let texts = collect_texts(
+            scene,
+            asset_service,
+            view_projection_matrix,
+            &self.depth_settings,
+            self.frustum_culling.then_some(&frustum),
+            UVec2::new(
+                self.surface_configuration.width,
+                self.surface_configuration.height,
+            ),
+        );
+        self.text_renderer
+            .prepare(&self.device, &self.queue, &texts, asset_service);
+
+        self.debug_line_renderer
+            .prepare(&self.device, &self.queue, &scene.debug_draw);
+    }
+
+    // The camera entity is the one the frame is rendered through
+    pub fn update_camera_uniform(&mut self, scene: &Scene, camera_entity: u32) {
+        let main_camera_component = scene.camera_components.get(&camera_entity).unwrap();
+        let main_transform_component = scene.calculate_camera_transform(camera_entity).unwrap();
 
         debug!(
             "Updating camera uniform with camera: {:?} and transform: {:?}",
             main_camera_component, main_transform_component
         );
 
-        self.camera_uniform
-            .update_view_projection_matrix(main_camera_component, main_transform_component);
+        self.camera_uniform.update_view_projection_matrix(
+            main_camera_component,
+            &main_transform_component,
+            &self.depth_settings,
+        );
 
         // In order for the shader to use the updated camera uniform,
         // we need to write the updated data to the camera buffer.
@@ -267,7 +1310,81 @@ impl RenderingService {
         );
     }
 
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.is_lost()
+    }
+
+    // Replaces a lost device with a new one and recreates every GPU resource and pipeline.
+    // Settings, shaders and custom passes carry over, meshes, textures and materials are uploaded again
+    // from their assets the next time they are drawn. The current device stays in place if this fails.
+    pub fn recreate_device(&mut self, asset_service: &AssetService) -> anyhow::Result<()> {
+        info!("Recreating the GPU device and every resource created with it");
+        let gpu = pollster::block_on(Self::request_gpu(self.window.clone(), &self.settings))?;
+        let mut service = pollster::block_on(Self::create(
+            self.window.clone(),
+            gpu,
+            asset_service,
+            self.settings.clone(),
+            self.shader_library.clone(),
+            self.camera_uniform,
+        ))?;
+
+        service.set_depth_settings(self.depth_settings);
+        service.set_msaa_sample_count(self.msaa_sample_count);
+        service.set_present_mode(self.present_mode());
+        service.frustum_culling = self.frustum_culling;
+        service.render_passes = std::mem::take(&mut self.render_passes);
+        for pass in &mut service.render_passes {
+            pass.device_recreated();
+        }
+
+        // The old surface lets go of the window when it is dropped, only then can the new one be configured
+        let previous = std::mem::replace(self, service);
+        if previous.is_surface_configured {
+            let (width, height) = (
+                previous.surface_configuration.width,
+                previous.surface_configuration.height,
+            );
+            drop(previous);
+            self.resize_surface(width, height);
+        }
+
+        Ok(())
+    }
+
+    // Switches between vsync and lower latency modes, unsupported ones fall back to Fifo
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) {
+        let present_mode =
+            Self::supported_present_mode(&self.supported_present_modes, present_mode);
+        if present_mode == self.surface_configuration.present_mode {
+            return;
+        }
+
+        debug!("Applying present mode: {:?}", present_mode);
+        self.surface_configuration.present_mode = present_mode;
+        if self.is_surface_configured {
+            self.surface
+                .configure(&self.device, &self.surface_configuration);
+        }
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        self.surface_configuration.present_mode
+    }
+
+    // The scene color and depth targets are sized from the surface, so they follow it from the next frame on
     pub fn resize_surface(&mut self, width: u32, height: u32) {
+        let is_same_size = width == self.surface_configuration.width
+            && height == self.surface_configuration.height;
+        if self.is_surface_configured && is_same_size {
+            return;
+        }
+
+        self.reconfigure_surface(width, height);
+    }
+
+    // Configures the surface even when its size is unchanged, e.g. after it was lost or outdated
+    pub fn reconfigure_surface(&mut self, width: u32, height: u32) {
         if width > 0 && height > 0 {
             self.surface_configuration.width = width;
             self.surface_configuration.height = height;
@@ -277,19 +1394,201 @@ impl RenderingService {
         }
     }
 
-    pub fn render(&mut self) -> Result<(), SurfaceError> {
-        if !self.is_surface_configured {
+    pub fn render(
+        &mut self,
+        scene: &Scene,
+        asset_service: &AssetService,
+        camera_entity: u32,
+    ) -> Result<(), SurfaceError> {
+        // Nothing can be drawn with a lost device until it is recreated
+        if !self.is_surface_configured || self.is_device_lost() {
             return Ok(());
         }
 
+        self.reload_changed_shaders(asset_service);
+
+        let draws = self.prepare_draws(scene, asset_service);
+        self.prepare_material_pipelines(&draws, asset_service);
+        self.prepare_2d(scene, asset_service);
+
+        let lights = collect_lights(scene, &self.camera_uniform);
+        let main_camera_transform = scene.calculate_camera_transform(camera_entity);
+        let main_camera = scene
+            .camera_components
+            .get(&camera_entity)
+            .zip(main_camera_transform.as_ref());
+        let shadow_layers = self.shadow_maps.prepare(&self.queue, &lights, main_camera);
+        let light_uniforms: Vec<LightUniform> = lights
+            .iter()
+            .zip(shadow_layers)
+            .map(|(light, shadow_layer)| LightUniform::new(light, shadow_layer))
+            .collect();
+        self.gpu_lights.update(
+            &self.device,
+            &self.queue,
+            &self.light_bind_group_layout,
+            &self.shadow_maps,
+            &light_uniforms,
+        );
+
+        // The sky and the light of the environment follow the main camera as well
+        let environment = scene
+            .camera_components
+            .get(&camera_entity)
+            .and_then(|camera| camera.environment);
+        self.environment_maps.prepare(
+            &self.device,
+            &self.queue,
+            environment,
+            asset_service,
+            &mut self.mipmap_generator,
+        );
+        self.environment_maps.update_uniform(
+            &self.queue,
+            Mat4::from_cols_array_2d(&self.camera_uniform.view_projection_matrix),
+            &self.depth_settings,
+        );
+
+        // Post processing follows the settings of the main camera
+        let post_process = scene
+            .camera_components
+            .get(&camera_entity)
+            .map_or_else(PostProcessSettings::default, |camera| camera.post_process);
+        self.post_process_renderer
+            .prepare(&self.device, &self.queue, post_process, asset_service);
+
         // Request a surface texture to render to from the surface.
-        let surface_texture_to_render_to: SurfaceTexture = self.surface.get_current_texture()?;
+        // Running out of memory for the next frame is recovered from like a lost device
+        let surface_texture_to_render_to: SurfaceTexture =
+            self.surface.get_current_texture().inspect_err(|e| {
+                if *e == SurfaceError::OutOfMemory {
+                    self.device_lost.set_lost();
+                }
+            })?;
 
         // Create a texture view for the surface texture.
         let texture_view: TextureView = surface_texture_to_render_to
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
+        // Describe the frame as passes reading and writing textures, the graph works out their order
+        // and hands out the textures that only live for this frame
+        let mut graph = RenderGraph::new(UVec2::new(
+            self.surface_configuration.width,
+            self.surface_configuration.height,
+        ));
+        let swapchain = graph.import_texture(
+            SWAPCHAIN,
+            &surface_texture_to_render_to.texture,
+            texture_view,
+        );
+        let shadow_maps = graph.import_texture(
+            SHADOW_MAPS,
+            &self.shadow_maps.texture,
+            self.shadow_maps.array_view.clone(),
+        );
+
+        // Render the depth of the scene as seen by each shadow casting light first,
+        // the main pass then samples these to find out what is in shadow
+        let shadow_maps = graph.add_pass("Shadows", GraphPass::Shadows, |builder| {
+            builder.write(shadow_maps)
+        });
+
+        // The depth texture stores how far away the closest fragment drawn to each pixel is,
+        // so geometry hidden behind it can be discarded regardless of submission order
+        // With MSAA every sample is drawn into a multisampled texture and averaged into the scene color
+        // at the end of the pass
+        let sample_count = self.msaa_sample_count;
+        let (scene_color, multisampled_color, scene_depth) =
+            graph.add_pass("Main", GraphPass::Main, |builder| {
+                builder.read(shadow_maps);
+                (
+                    builder
+                        .create_texture(SCENE_COLOR, TransientTextureDescriptor::new(HDR_FORMAT)),
+                    (sample_count > 1).then(|| {
+                        builder.create_texture(
+                            SCENE_COLOR_MULTISAMPLED,
+                            TransientTextureDescriptor {
+                                usage: TextureUsages::RENDER_ATTACHMENT,
+                                sample_count,
+                                ..TransientTextureDescriptor::new(HDR_FORMAT)
+                            },
+                        )
+                    }),
+                    builder.create_texture(
+                        SCENE_DEPTH,
+                        TransientTextureDescriptor {
+                            sample_count,
+                            // Some backends fail to resolve into a target drawn alongside a
+                            // multisampled depth texture that can also be bound for sampling
+                            usage: if sample_count > 1 {
+                                TextureUsages::RENDER_ATTACHMENT
+                            } else {
+                                TransientTextureDescriptor::new(DEPTH_FORMAT).usage
+                            },
+                            ..TransientTextureDescriptor::new(DEPTH_FORMAT)
+                        },
+                    ),
+                )
+            });
+
+        for (index, pass) in self.render_passes.iter_mut().enumerate() {
+            let name = pass.name().to_string();
+            graph.add_pass(name, GraphPass::Custom(index), |builder| {
+                pass.setup(builder)
+            });
+        }
+
+        // Post process whatever ended up as the scene color, custom passes may have replaced it
+        let scene_color = graph.resource(SCENE_COLOR).unwrap_or(scene_color);
+        let bloom_levels: Vec<ResourceHandle> = if post_process.bloom.is_some() {
+            graph.add_pass("Bloom", GraphPass::Bloom, |builder| {
+                builder.read(scene_color);
+                (0..PostProcessRenderer::bloom_level_count(builder.size(scene_color)))
+                    .map(|level| {
+                        builder.create_texture(
+                            format!("bloom_{}", level),
+                            TransientTextureDescriptor {
+                                size: TextureSize::SurfaceScale(0.5_f32.powi(level as i32 + 1)),
+                                ..TransientTextureDescriptor::new(HDR_FORMAT)
+                            },
+                        )
+                    })
+                    .collect()
+            })
+        } else {
+            Vec::new()
+        };
+        let tonemapped = graph.add_pass("Tonemap", GraphPass::Composite, |builder| {
+            builder.read(scene_color);
+            if let Some(bloom) = bloom_levels.first() {
+                builder.read(*bloom);
+            }
+            builder.create_texture(POST_PROCESSED, TransientTextureDescriptor::new(LDR_FORMAT))
+        });
+        let anti_aliased = post_process.fxaa.then(|| {
+            graph.add_pass("FXAA", GraphPass::Fxaa, |builder| {
+                builder.read(tonemapped);
+                builder.create_texture(POST_PROCESSED, TransientTextureDescriptor::new(LDR_FORMAT))
+            })
+        });
+
+        // Copy the finished frame to the surface
+        let present_source = anti_aliased.unwrap_or(tonemapped);
+        graph.add_pass("Present", GraphPass::Present, |builder| {
+            builder.read(present_source);
+            builder.write(swapchain);
+        });
+
+        let order = match graph.compile() {
+            Ok(order) => order,
+            Err(e) => {
+                error!("Failed to compile the render graph: {:?}", e);
+                return Ok(());
+            }
+        };
+        graph.allocate(&self.device, &mut self.transient_textures, &order);
+
         // Create a command encoder that acts as a buffer that will record rendering commands
         // which will be submitted to the GPU.
         // Think of this as a tape recorder
@@ -300,50 +1599,186 @@ impl RenderingService {
             .device
             .create_command_encoder(&command_encoder_descriptor);
 
+        let resources = graph.resources();
+        for index in order {
+            encoder.push_debug_group(graph.pass_name(index));
+            match graph.pass(index) {
+                GraphPass::Shadows => self.shadow_maps.render(
+                    &mut encoder,
+                    &draws,
+                    &self.gpu_meshes,
+                    &self.instance_buffer,
+                ),
+                GraphPass::Main => match multisampled_color {
+                    Some(multisampled_color) => self.render_main_pass(
+                        &mut encoder,
+                        &draws,
+                        resources.view(multisampled_color),
+                        Some(resources.view(scene_color)),
+                        resources.view(scene_depth),
+                    ),
+                    None => self.render_main_pass(
+                        &mut encoder,
+                        &draws,
+                        resources.view(scene_color),
+                        None,
+                        resources.view(scene_depth),
+                    ),
+                },
+                GraphPass::Custom(pass_index) => {
+                    let mut context = PassContext {
+                        device: &self.device,
+                        queue: &self.queue,
+                        encoder: &mut encoder,
+                        camera_bind_group_layout: &self.camera_bind_group_layout,
+                        camera_bind_group: &self.camera_bind_group,
+                        resources,
+                    };
+                    self.render_passes[*pass_index].execute(&mut context);
+                }
+                GraphPass::Bloom => {
+                    let levels: Vec<&TextureView> = bloom_levels
+                        .iter()
+                        .map(|level| resources.view(*level))
+                        .collect();
+                    self.post_process_renderer.render_bloom(
+                        &self.device,
+                        &mut encoder,
+                        resources.view(scene_color),
+                        &levels,
+                    );
+                }
+                GraphPass::Composite => self.post_process_renderer.render_composite(
+                    &self.device,
+                    &mut encoder,
+                    resources.view(scene_color),
+                    bloom_levels.first().map(|bloom| resources.view(*bloom)),
+                    resources.view(tonemapped),
+                ),
+                GraphPass::Fxaa => self.post_process_renderer.render_fxaa(
+                    &self.device,
+                    &mut encoder,
+                    resources.view(tonemapped),
+                    resources.view(present_source),
+                ),
+                GraphPass::Present => self.blit_pipeline.blit(
+                    &self.device,
+                    &mut encoder,
+                    resources.view(present_source),
+                    resources.view(swapchain),
+                ),
+            }
+            encoder.pop_debug_group();
+        }
+
+        // Submit the recorded commands to the GPU.
+        // This is like pressing play on the tape recorder to execute the recorded commands.
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        // Present the rendered frame to the surface.
+        surface_texture_to_render_to.present();
+
+        Ok(())
+    }
+
+    // Draws the sky and the meshes, followed by the sprites, tilemaps and text blended over them
+    fn render_main_pass(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        draws: &[MeshDraw],
+        color_view: &TextureView,
+        resolve_target: Option<&TextureView>, // The single sampled texture a multisampled color view is averaged into
+        depth_view: &TextureView,
+    ) {
         // Begin a render pass, which groups rendering commands together.
         // This is like starting a new recording session on the tape recorder.
-        // Scope is required to ensure the render pass is dropped before the encoder is finished.
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Clear Screen Render Pass"),
-                color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &texture_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.25,
-                            g: 0.5,
-                            b: 1.0,
-                            a: 1.0, // Clear to black with full opacity
-                        }),
-                        store: wgpu::StoreOp::Store,
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Main Render Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: color_view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.25,
+                        g: 0.5,
+                        b: 1.0,
+                        a: 1.0, // Clear to black with full opacity
+                    }),
+                    // Only the averaged colors are needed once the samples are resolved
+                    store: if resolve_target.is_some() {
+                        wgpu::StoreOp::Discard
+                    } else {
+                        wgpu::StoreOp::Store
                     },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
+                },
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.depth_settings.clear_depth()),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        // The sky goes behind everything, it is drawn first and never writes depth
+        if let Some(environment_bind_group) = self.environment_maps.skybox_bind_group() {
+            self.stats.draw_calls += self
+                .skybox_renderer
+                .render(&mut render_pass, environment_bind_group);
+        }
+
+        // Set the bind groups shared by every draw, the camera, the lights and the environment
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.gpu_lights.bind_group, &[]);
+        render_pass.set_bind_group(3, self.environment_maps.bind_group(), &[]);
 
-            // Set the pipeline for the render pass.
-            render_pass.set_pipeline(&self.render_pipeline);
+        for draw in draws.iter().filter(|draw| draw.visible_count > 0) {
+            let gpu_mesh = &self.gpu_meshes[&draw.mesh];
+            let gpu_material = self.material(draw.material);
 
-            // Set the bind group for uniform buffers
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            // Set the pipeline of the shader permutation the material needs,
+            // double sided materials also skip back face culling
+            let key = self.material_pipeline_key(gpu_material.features);
+            render_pass.set_pipeline(&self.material_pipelines[&key]);
 
-            // Set the vertex buffer to use for rendering.
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            // Set the material the fragment shader shades with
+            render_pass.set_bind_group(1, &gpu_material.bind_group, &[]);
 
-            // Draw a triangle using the render pipeline.
-            render_pass.draw(0..VERTICES.len() as u32, 0..1);
+            // Set the vertex and index buffers to use for rendering,
+            // the second vertex buffer holds the model matrix of every instance
+            render_pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, draw.instance_slice(&self.instance_buffer));
+            render_pass.set_index_buffer(gpu_mesh.index_buffer.slice(..), IndexFormat::Uint32);
+
+            // Draw every visible instance of the mesh using the render pipeline.
+            render_pass.draw_indexed(0..gpu_mesh.index_count, 0, 0..draw.visible_count);
+            self.stats.draw_calls += 1;
         }
 
-        // Submit the recorded commands to the GPU.
-        // This is like pressing play on the tape recorder to execute the recorded commands.
-        self.queue.submit(std::iter::once(encoder.finish()));
+        // Sprites, tilemap layers and world text blend over the meshes, so they are drawn last.
+        // They are interleaved by z-order with tilemap layers going first and text last within one z-order.
+        let mut z_orders: Vec<i32> = self
+            .tilemap_renderer
+            .z_orders()
+            .chain(self.sprite_renderer.z_orders())
+            .chain(self.text_renderer.z_orders())
+            .collect();
+        z_orders.sort();
+        z_orders.dedup();
+        for z_order in z_orders {
+            self.stats.draw_calls += self.tilemap_renderer.render(&mut render_pass, z_order);
+            self.stats.draw_calls += self.sprite_renderer.render(&mut render_pass, z_order);
+            self.stats.draw_calls += self.text_renderer.render(&mut render_pass, z_order);
+        }
 
-        // Present the rendered frame to the surface.
-        surface_texture_to_render_to.present();
+        // Debug lines after the scene so the ones without depth testing are drawn over all of it
+        self.stats.draw_calls += self.debug_line_renderer.render(&mut render_pass);
 
-        Ok(())
+        // Screen text overlays everything else in the frame
+        self.stats.draw_calls += self.text_renderer.render_screen(&mut render_pass);
     }
 }
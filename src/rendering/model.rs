@@ -0,0 +1,74 @@
+use bytemuck::{Pod, Zeroable};
+use glam::Mat4;
+use wgpu::{BufferAddress, VertexAttribute, VertexBufferLayout, VertexFormat};
+
+// A matrix can't be a single vertex attribute, so each one is passed as four columns
+const ATTRIBUTES: &[VertexAttribute] = &[
+    // Model matrix columns
+    VertexAttribute {
+        offset: 0,
+        shader_location: 4, // Locations 0 to 3 are used by the vertex
+        format: VertexFormat::Float32x4,
+    },
+    VertexAttribute {
+        offset: 16,
+        shader_location: 5,
+        format: VertexFormat::Float32x4,
+    },
+    VertexAttribute {
+        offset: 32,
+        shader_location: 6,
+        format: VertexFormat::Float32x4,
+    },
+    VertexAttribute {
+        offset: 48,
+        shader_location: 7,
+        format: VertexFormat::Float32x4,
+    },
+    // Normal matrix columns
+    VertexAttribute {
+        offset: 64,
+        shader_location: 8,
+        format: VertexFormat::Float32x4,
+    },
+    VertexAttribute {
+        offset: 80,
+        shader_location: 9,
+        format: VertexFormat::Float32x4,
+    },
+    VertexAttribute {
+        offset: 96,
+        shader_location: 10,
+        format: VertexFormat::Float32x4,
+    },
+    VertexAttribute {
+        offset: 112,
+        shader_location: 11,
+        format: VertexFormat::Float32x4,
+    },
+];
+
+// Per-instance data, every entity drawn with the same mesh and material is one instance of a single draw
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct ModelInstance {
+    pub model_matrix: [[f32; 4]; 4], // 4x4 matrix moving the mesh from local space to world space
+    pub normal_matrix: [[f32; 4]; 4], // Inverse transpose of the model matrix, keeps normals perpendicular under non-uniform scaling
+}
+
+impl ModelInstance {
+    pub fn new(model_matrix: Mat4) -> Self {
+        Self {
+            model_matrix: model_matrix.to_cols_array_2d(),
+            normal_matrix: model_matrix.inverse().transpose().to_cols_array_2d(),
+        }
+    }
+
+    pub fn describe_instance_buffer_layout() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<ModelInstance>() as BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance, // Advance once per instance instead of once per vertex
+            attributes: ATTRIBUTES,
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use log::{debug, info, warn};
+use wgpu::{AdapterInfo, Device, Features, PipelineCache, PipelineCacheDescriptor};
+
+// Driver data of compiled pipelines, saved to disk so the next run doesn't have to compile them again.
+// Only some backends support pipeline caches, pipelines are created without one everywhere else.
+pub struct PersistentPipelineCache {
+    cache: Option<PipelineCache>,
+    path: Option<PathBuf>, // File the data is loaded from and saved to, None keeps the cache in memory
+}
+
+impl PersistentPipelineCache {
+    pub fn new(device: &Device, adapter_info: &AdapterInfo, directory: Option<&Path>) -> Self {
+        if !device.features().contains(Features::PIPELINE_CACHE) {
+            debug!(
+                "The {:?} backend has no pipeline cache",
+                adapter_info.backend
+            );
+            return Self {
+                cache: None,
+                path: None,
+            };
+        }
+
+        // The key names the adapter and driver the data belongs to, data from another one is useless
+        let path = directory
+            .zip(wgpu::util::pipeline_cache_key(adapter_info))
+            .map(|(directory, key)| directory.join(key));
+        let data = path.as_ref().and_then(|path| match std::fs::read(path) {
+            Ok(data) => {
+                debug!("Loaded pipeline cache: {:?} ({} bytes)", path, data.len());
+                Some(data)
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("Failed to read pipeline cache {:?}: {}", path, e);
+                None
+            }
+        });
+
+        // SAFETY: the data was saved by get_data for the same cache key, and with fallback set
+        // wgpu starts from an empty cache when the data doesn't pass its validation
+        let cache = unsafe {
+            device.create_pipeline_cache(&PipelineCacheDescriptor {
+                label: Some("Pipeline Cache"),
+                data: data.as_deref(),
+                fallback: true,
+            })
+        };
+
+        Self {
+            cache: Some(cache),
+            path,
+        }
+    }
+
+    // Passed to every pipeline descriptor
+    pub fn cache(&self) -> Option<&PipelineCache> {
+        self.cache.as_ref()
+    }
+
+    // Writes the current data next to the file first so an interrupted save can't leave a truncated cache
+    pub fn save(&self) -> anyhow::Result<()> {
+        let (Some(cache), Some(path)) = (&self.cache, &self.path) else {
+            return Ok(());
+        };
+        let Some(data) = cache.get_data() else {
+            return Ok(());
+        };
+
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)
+                .with_context(|| format!("Failed to create directory {:?}", directory))?;
+        }
+        let temporary_path = path.with_extension("tmp");
+        std::fs::write(&temporary_path, &data)
+            .with_context(|| format!("Failed to write pipeline cache {:?}", temporary_path))?;
+        std::fs::rename(&temporary_path, path)
+            .with_context(|| format!("Failed to write pipeline cache {:?}", path))?;
+        info!("Saved pipeline cache: {:?} ({} bytes)", path, data.len());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,648 @@
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable, cast_slice};
+use glam::UVec2;
+use log::{debug, error};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    BlendComponent, BlendFactor, BlendOperation, BlendState, Buffer, BufferBindingType,
+    BufferUsages, ColorTargetState, ColorWrites, CommandEncoder, Device, Extent3d, FragmentState,
+    LoadOp, MultisampleState, PipelineCache, PipelineCompilationOptions, PipelineLayout,
+    PipelineLayoutDescriptor, PrimitiveState, Queue, RenderPassColorAttachment, RenderPipeline,
+    RenderPipelineDescriptor, Sampler, SamplerBindingType, ShaderModule, ShaderStages,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
+    TextureView, TextureViewDescriptor, TextureViewDimension, VertexState,
+    util::{BufferInitDescriptor, DeviceExt, TextureDataOrder},
+};
+
+use crate::{
+    asset::{AssetService, TextureHandle},
+    ecs::component::camera::{PostProcessSettings, Tonemapping},
+    rendering::shader::capture_validation_errors,
+};
+
+// The scene is rendered in HDR so lights can be brighter than the screen can show,
+// tone mapping brings it back into the display range
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+pub const LDR_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb; // Output of the tone mapping
+
+// Each bloom level is half the size of the one before it
+const MAX_BLOOM_LEVELS: u32 = 6;
+const MIN_BLOOM_LEVEL_SIZE: u32 = 8; // Pixels along the shorter side of the smallest level
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct PostProcessUniform {
+    vignette_color: [f32; 4], // w is unused
+    exposure: f32,
+    tonemapping: u32,
+    bloom_intensity: f32,
+    bloom_threshold: f32,
+    bloom_soft_knee: f32,
+    lut_strength: f32,
+    lut_size: f32,
+    vignette_intensity: f32,
+    vignette_radius: f32,
+    vignette_smoothness: f32,
+    _padding: [f32; 2],
+}
+
+impl PostProcessUniform {
+    fn new(settings: &PostProcessSettings, lut_size: Option<u32>) -> Self {
+        let bloom = settings.bloom.unwrap_or_default();
+        let vignette = settings.vignette.unwrap_or_default();
+
+        Self {
+            vignette_color: vignette.color.extend(1.0).to_array(),
+            exposure: settings.exposure,
+            tonemapping: match settings.tonemapping {
+                Tonemapping::None => 0,
+                Tonemapping::Reinhard => 1,
+                Tonemapping::Aces => 2,
+                Tonemapping::AgX => 3,
+            },
+            bloom_intensity: if settings.bloom.is_some() {
+                bloom.intensity
+            } else {
+                0.0
+            },
+            bloom_threshold: bloom.threshold,
+            bloom_soft_knee: bloom.soft_knee,
+            // Grading is skipped when the lookup table couldn't be loaded
+            lut_strength: settings
+                .color_grading
+                .filter(|_| lut_size.is_some())
+                .map_or(0.0, |color_grading| color_grading.strength),
+            lut_size: lut_size.unwrap_or(1) as f32,
+            vignette_intensity: if settings.vignette.is_some() {
+                vignette.intensity
+            } else {
+                0.0
+            },
+            vignette_radius: vignette.radius,
+            vignette_smoothness: vignette.smoothness,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+// Color grading lookup table converted into a 3D texture
+struct ColorGradingLut {
+    view: TextureView,
+    size: u32, // Entries along each axis
+}
+
+impl ColorGradingLut {
+    fn new(
+        device: &Device,
+        queue: &Queue,
+        texture_handle: TextureHandle,
+        asset_service: &AssetService,
+    ) -> anyhow::Result<Self> {
+        let texture = asset_service
+            .textures
+            .get(&texture_handle)
+            .ok_or_else(|| anyhow::anyhow!("Texture {:?} does not exist", texture_handle))?;
+
+        if !matches!(
+            texture.format,
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb
+        ) {
+            return Err(anyhow::anyhow!(
+                "Lookup table must be an uncompressed RGBA8 image, found {:?}",
+                texture.format
+            ));
+        }
+
+        let size = texture.height;
+        if size < 2 || texture.width != size * size {
+            return Err(anyhow::anyhow!(
+                "Lookup table must be a strip of {} slices of {} by {} pixels, found {}x{}",
+                size,
+                size,
+                size,
+                texture.width,
+                texture.height
+            ));
+        }
+
+        // The strip holds one blue slice after another, the 3D texture holds them one behind another
+        let size = size as usize;
+        let mut pixels = vec![0; size * size * size * 4];
+        for blue in 0..size {
+            for green in 0..size {
+                let source = (green * size * size + blue * size) * 4;
+                let destination = (blue * size * size + green * size) * 4;
+                pixels[destination..destination + size * 4]
+                    .copy_from_slice(&texture.pixels[source..source + size * 4]);
+            }
+        }
+
+        debug!("Uploading color grading lookup table {:?}", texture_handle);
+        Ok(Self {
+            view: Self::create_view(device, queue, size as u32, &pixels),
+            size: size as u32,
+        })
+    }
+
+    fn create_view(device: &Device, queue: &Queue, size: u32, pixels: &[u8]) -> TextureView {
+        device
+            .create_texture_with_data(
+                queue,
+                &TextureDescriptor {
+                    label: Some("Color Grading Lookup Table"),
+                    size: Extent3d {
+                        width: size,
+                        height: size,
+                        depth_or_array_layers: size,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D3,
+                    // The entries are display encoded colors, the shader decodes them after the lookup
+                    format: TextureFormat::Rgba8Unorm,
+                    usage: TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                },
+                TextureDataOrder::LayerMajor,
+                pixels,
+            )
+            .create_view(&TextureViewDescriptor::default())
+    }
+}
+
+struct PostProcessPipelines {
+    bloom_prefilter: RenderPipeline,
+    bloom_downsample: RenderPipeline,
+    bloom_upsample: RenderPipeline,
+    composite: RenderPipeline,
+    fxaa: RenderPipeline,
+}
+
+// Bloom, tone mapping, color grading, vignette and FXAA passes of the render graph
+pub struct PostProcessRenderer {
+    source_bind_group_layout: BindGroupLayout, // Source texture, sampler and settings, used by every pass
+    composite_bind_group_layout: BindGroupLayout, // Bloom texture and lookup table
+    sampler: Sampler,
+    uniform_buffer: Buffer,
+    pipeline_layout: PipelineLayout,
+    composite_pipeline_layout: PipelineLayout,
+    pipeline_cache: Option<PipelineCache>,
+    pipelines: PostProcessPipelines,
+    black_texture_view: TextureView, // Stands in for the bloom texture when bloom is off
+    empty_lut: ColorGradingLut,      // Bound when there is no color grading, which skips the lookup
+    luts: HashMap<TextureHandle, Option<ColorGradingLut>>, // None when the texture isn't a valid lookup table
+    settings: PostProcessSettings,
+}
+
+impl PostProcessRenderer {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        shader: ShaderModule,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Self {
+        let source_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("Post process bind group layout"),
+            });
+        let composite_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("Composite bind group layout"),
+            });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Post Process Pipeline Layout"),
+            bind_group_layouts: &[&source_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Composite Pipeline Layout"),
+            bind_group_layouts: &[&source_bind_group_layout, &composite_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipelines = Self::create_pipelines(
+            device,
+            &pipeline_layout,
+            &composite_pipeline_layout,
+            &shader,
+            pipeline_cache,
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Process Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let settings = PostProcessSettings::default();
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Post Process Uniform Buffer"),
+            contents: cast_slice(&[PostProcessUniform::new(&settings, None)]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let black_texture_view = device
+            .create_texture_with_data(
+                queue,
+                &TextureDescriptor {
+                    label: Some("Black HDR Texture"),
+                    size: Extent3d {
+                        width: 1,
+                        height: 1,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: HDR_FORMAT,
+                    usage: TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                },
+                TextureDataOrder::LayerMajor,
+                &[0; 8],
+            )
+            .create_view(&TextureViewDescriptor::default());
+        let empty_lut = ColorGradingLut {
+            view: ColorGradingLut::create_view(device, queue, 1, &[255; 4]),
+            size: 1,
+        };
+
+        Self {
+            source_bind_group_layout,
+            composite_bind_group_layout,
+            sampler,
+            uniform_buffer,
+            pipeline_layout,
+            composite_pipeline_layout,
+            pipeline_cache: pipeline_cache.cloned(),
+            pipelines,
+            black_texture_view,
+            empty_lut,
+            luts: HashMap::new(),
+            settings,
+        }
+    }
+
+    fn create_pipelines(
+        device: &Device,
+        pipeline_layout: &PipelineLayout,
+        composite_pipeline_layout: &PipelineLayout,
+        shader: &ShaderModule,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> PostProcessPipelines {
+        // Every upsample adds the blurred smaller level onto the larger one
+        let additive_blend = BlendComponent {
+            src_factor: BlendFactor::One,
+            dst_factor: BlendFactor::One,
+            operation: BlendOperation::Add,
+        };
+        let bloom_prefilter = Self::create_pipeline(
+            device,
+            pipeline_layout,
+            shader,
+            "fs_bloom_prefilter",
+            HDR_FORMAT,
+            None,
+            pipeline_cache,
+        );
+        let bloom_downsample = Self::create_pipeline(
+            device,
+            pipeline_layout,
+            shader,
+            "fs_bloom_downsample",
+            HDR_FORMAT,
+            None,
+            pipeline_cache,
+        );
+        let bloom_upsample = Self::create_pipeline(
+            device,
+            pipeline_layout,
+            shader,
+            "fs_bloom_upsample",
+            HDR_FORMAT,
+            Some(BlendState {
+                color: additive_blend,
+                alpha: additive_blend,
+            }),
+            pipeline_cache,
+        );
+        let composite = Self::create_pipeline(
+            device,
+            composite_pipeline_layout,
+            shader,
+            "fs_composite",
+            LDR_FORMAT,
+            None,
+            pipeline_cache,
+        );
+        let fxaa = Self::create_pipeline(
+            device,
+            pipeline_layout,
+            shader,
+            "fs_fxaa",
+            LDR_FORMAT,
+            None,
+            pipeline_cache,
+        );
+
+        PostProcessPipelines {
+            bloom_prefilter,
+            bloom_downsample,
+            bloom_upsample,
+            composite,
+            fxaa,
+        }
+    }
+
+    // Swaps in a recompiled shader, the current pipelines are kept when the new ones fail to build
+    pub fn reload_shader(&mut self, device: &Device, shader: ShaderModule) -> anyhow::Result<()> {
+        self.pipelines = capture_validation_errors(device, || {
+            Self::create_pipelines(
+                device,
+                &self.pipeline_layout,
+                &self.composite_pipeline_layout,
+                &shader,
+                self.pipeline_cache.as_ref(),
+            )
+        })?;
+
+        Ok(())
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        pipeline_layout: &PipelineLayout,
+        shader: &ShaderModule,
+        fragment_entry_point: &str,
+        target_format: TextureFormat,
+        blend: Option<BlendState>,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(fragment_entry_point),
+            layout: Some(pipeline_layout),
+            vertex: VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: shader,
+                entry_point: Some(fragment_entry_point),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(ColorTargetState {
+                    format: target_format,
+                    blend,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
+            cache: pipeline_cache,
+        })
+    }
+
+    // Number of half sized textures the bloom is blurred through for a surface of this size
+    pub fn bloom_level_count(surface_size: UVec2) -> u32 {
+        let shorter_side = surface_size.min_element();
+        (1..MAX_BLOOM_LEVELS)
+            .take_while(|level| shorter_side >> (level + 1) >= MIN_BLOOM_LEVEL_SIZE)
+            .count() as u32
+            + 1
+    }
+
+    // Applies the settings of the camera being rendered, uploading its lookup table the first time it is used
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        settings: PostProcessSettings,
+        asset_service: &AssetService,
+    ) {
+        let mut lut_size = None;
+        if let Some(color_grading) = settings.color_grading {
+            let lut = self.luts.entry(color_grading.lut).or_insert_with(|| {
+                ColorGradingLut::new(device, queue, color_grading.lut, asset_service)
+                    .inspect_err(|e| {
+                        // Reported once, the camera is shown without grading from then on
+                        error!(
+                            "Failed to load color grading lookup table {:?}: {:?}",
+                            color_grading.lut, e
+                        )
+                    })
+                    .ok()
+            });
+            lut_size = lut.as_ref().map(|lut| lut.size);
+        }
+
+        self.settings = settings;
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            cast_slice(&[PostProcessUniform::new(&settings, lut_size)]),
+        );
+    }
+
+    fn create_source_bind_group(&self, device: &Device, source: &TextureView) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.source_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Post process bind group"),
+        })
+    }
+
+    fn draw(
+        encoder: &mut CommandEncoder,
+        pipeline: &RenderPipeline,
+        bind_groups: &[&BindGroup],
+        target: &TextureView,
+        load: LoadOp<wgpu::Color>,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post Process Render Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        for (index, bind_group) in bind_groups.iter().enumerate() {
+            render_pass.set_bind_group(index as u32, *bind_group, &[]);
+        }
+        render_pass.draw(0..3, 0..1);
+    }
+
+    // Blurs the bright parts of the source down the levels and back up again,
+    // leaving the bloom in the first level
+    pub fn render_bloom(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        source: &TextureView,
+        levels: &[&TextureView],
+    ) {
+        let mut input = source;
+        for (index, level) in levels.iter().enumerate() {
+            let pipeline = if index == 0 {
+                &self.pipelines.bloom_prefilter
+            } else {
+                &self.pipelines.bloom_downsample
+            };
+            let bind_group = self.create_source_bind_group(device, input);
+            Self::draw(
+                encoder,
+                pipeline,
+                &[&bind_group],
+                level,
+                LoadOp::Clear(wgpu::Color::BLACK),
+            );
+            input = level;
+        }
+
+        for pair in levels.windows(2).rev() {
+            let bind_group = self.create_source_bind_group(device, pair[1]);
+            Self::draw(
+                encoder,
+                &self.pipelines.bloom_upsample,
+                &[&bind_group],
+                pair[0],
+                LoadOp::Load,
+            );
+        }
+    }
+
+    // Tone maps the HDR source with its bloom and applies color grading and the vignette
+    pub fn render_composite(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        source: &TextureView,
+        bloom: Option<&TextureView>,
+        target: &TextureView,
+    ) {
+        let lut = self
+            .settings
+            .color_grading
+            .and_then(|color_grading| self.luts.get(&color_grading.lut))
+            .and_then(Option::as_ref)
+            .unwrap_or(&self.empty_lut);
+
+        let source_bind_group = self.create_source_bind_group(device, source);
+        let composite_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.composite_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        bloom.unwrap_or(&self.black_texture_view),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&lut.view),
+                },
+            ],
+            label: Some("Composite bind group"),
+        });
+        Self::draw(
+            encoder,
+            &self.pipelines.composite,
+            &[&source_bind_group, &composite_bind_group],
+            target,
+            LoadOp::Clear(wgpu::Color::BLACK),
+        );
+    }
+
+    pub fn render_fxaa(
+        &self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        source: &TextureView,
+        target: &TextureView,
+    ) {
+        let bind_group = self.create_source_bind_group(device, source);
+        Self::draw(
+            encoder,
+            &self.pipelines.fxaa,
+            &[&bind_group],
+            target,
+            LoadOp::Clear(wgpu::Color::BLACK),
+        );
+    }
+}
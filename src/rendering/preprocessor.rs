@@ -0,0 +1,256 @@
+use std::collections::HashSet;
+
+use anyhow::{anyhow, bail};
+
+// A conditional block that is still open
+struct Condition {
+    is_active: bool, // Whether the lines of the current branch are kept, ignoring the enclosing blocks
+    has_else: bool,
+}
+
+// Expands the directives of WGSL source before it is compiled, one directive per line:
+// #include "file.wgsl" pastes in another file, a file that was already pasted in is skipped
+// #define NAME adds a define, #ifdef NAME, #ifndef NAME, #else and #endif keep or drop the lines between them
+pub fn preprocess<'a>(
+    name: &str,
+    source: &'a str,
+    defines: &[&str],
+    resolve_include: &dyn Fn(&str) -> Option<&'a str>,
+) -> anyhow::Result<String> {
+    let mut preprocessor = Preprocessor {
+        defines: defines.iter().map(|define| define.to_string()).collect(),
+        included: HashSet::new(),
+        include_stack: vec![name.to_string()],
+        resolve_include,
+        output: String::new(),
+    };
+    preprocessor.expand(name, source)?;
+
+    Ok(preprocessor.output)
+}
+
+// Names of the files the source includes directly, whether or not their directive is active
+pub fn includes(source: &str) -> impl Iterator<Item = &str> {
+    source.lines().filter_map(|line| {
+        let (directive, argument) = parse_directive(line)?;
+        if directive == "include" {
+            include_name(argument)
+        } else {
+            None
+        }
+    })
+}
+
+struct Preprocessor<'a, 'r> {
+    defines: HashSet<String>,
+    included: HashSet<String>,
+    include_stack: Vec<String>, // Files being expanded, used to catch files that include themselves
+    resolve_include: &'r dyn Fn(&str) -> Option<&'a str>,
+    output: String,
+}
+
+impl<'a> Preprocessor<'a, '_> {
+    fn expand(&mut self, name: &str, source: &'a str) -> anyhow::Result<()> {
+        let mut conditions: Vec<Condition> = Vec::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let is_active = conditions.iter().all(|condition| condition.is_active);
+            let Some((directive, argument)) = parse_directive(line) else {
+                if is_active {
+                    self.output.push_str(line);
+                    self.output.push('\n');
+                }
+                continue;
+            };
+
+            let error = |message: String| anyhow!("{}:{}: {}", name, index + 1, message);
+            match directive {
+                "include" if is_active => {
+                    let include = include_name(argument).ok_or_else(|| {
+                        error(format!("Expected a quoted file name, found {:?}", argument))
+                    })?;
+                    self.include(include)
+                        .map_err(|e| error(format!("{:#}", e)))?;
+                }
+                "define" if is_active => {
+                    if argument.is_empty() {
+                        return Err(error("#define needs a name".to_string()));
+                    }
+                    self.defines.insert(argument.to_string());
+                }
+                "include" | "define" => {}
+                "ifdef" | "ifndef" => {
+                    if argument.is_empty() {
+                        return Err(error(format!("#{} needs a name", directive)));
+                    }
+                    conditions.push(Condition {
+                        is_active: self.defines.contains(argument) == (directive == "ifdef"),
+                        has_else: false,
+                    });
+                }
+                "else" => {
+                    let condition = conditions
+                        .last_mut()
+                        .filter(|condition| !condition.has_else)
+                        .ok_or_else(|| error("#else without #ifdef".to_string()))?;
+                    condition.is_active = !condition.is_active;
+                    condition.has_else = true;
+                }
+                "endif" => {
+                    conditions
+                        .pop()
+                        .ok_or_else(|| error("#endif without #ifdef".to_string()))?;
+                }
+                _ => return Err(error(format!("Unknown directive #{}", directive))),
+            }
+        }
+
+        if !conditions.is_empty() {
+            bail!("{}: #ifdef without #endif", name);
+        }
+
+        Ok(())
+    }
+
+    fn include(&mut self, include: &str) -> anyhow::Result<()> {
+        if self.include_stack.iter().any(|name| name == include) {
+            bail!(
+                "{} includes itself through {}",
+                include,
+                self.include_stack.join(" -> ")
+            );
+        }
+        if !self.included.insert(include.to_string()) {
+            return Ok(());
+        }
+
+        let source = (self.resolve_include)(include)
+            .ok_or_else(|| anyhow!("Included file {} does not exist", include))?;
+        self.include_stack.push(include.to_string());
+        self.expand(include, source)?;
+        self.include_stack.pop();
+
+        Ok(())
+    }
+}
+
+// Splits "#name argument" into the name and the argument, None for lines that aren't directives
+fn parse_directive(line: &str) -> Option<(&str, &str)> {
+    let directive = line.trim().strip_prefix('#')?;
+    let (name, argument) = directive
+        .split_once(char::is_whitespace)
+        .unwrap_or((directive, ""));
+
+    Some((name, argument.trim()))
+}
+
+fn include_name(argument: &str) -> Option<&str> {
+    argument.strip_prefix('"')?.strip_suffix('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_includes(_: &str) -> Option<&'static str> {
+        None
+    }
+
+    fn lines(output: &str) -> Vec<&str> {
+        output.lines().collect()
+    }
+
+    #[test]
+    fn nested_conditions_keep_the_active_branches() {
+        let source = "\
+a
+#ifdef OUTER
+b
+#ifndef INNER
+c
+#else
+d
+#endif
+#else
+e
+#endif
+f";
+
+        let output = preprocess("test.wgsl", source, &[], &no_includes).unwrap();
+        assert_eq!(lines(&output), ["a", "e", "f"]);
+
+        let output = preprocess("test.wgsl", source, &["OUTER"], &no_includes).unwrap();
+        assert_eq!(lines(&output), ["a", "b", "c", "f"]);
+
+        let output = preprocess("test.wgsl", source, &["OUTER", "INNER"], &no_includes).unwrap();
+        assert_eq!(lines(&output), ["a", "b", "d", "f"]);
+    }
+
+    #[test]
+    fn defines_only_count_in_active_lines() {
+        let source = "\
+#ifdef MISSING
+#define SKIPPED
+#endif
+#define ADDED
+#ifdef SKIPPED
+a
+#endif
+#ifdef ADDED
+b
+#endif";
+
+        let output = preprocess("test.wgsl", source, &[], &no_includes).unwrap();
+        assert_eq!(lines(&output), ["b"]);
+    }
+
+    #[test]
+    fn unbalanced_conditions_are_errors() {
+        for source in [
+            "#ifdef A",
+            "#endif",
+            "#else",
+            "#ifdef A\n#else\n#else\n#endif",
+        ] {
+            assert!(preprocess("test.wgsl", source, &[], &no_includes).is_err());
+        }
+        assert!(preprocess("test.wgsl", "#unknown", &[], &no_includes).is_err());
+    }
+
+    #[test]
+    fn includes_are_pasted_once() {
+        let resolve = |name: &str| match name {
+            "common.wgsl" => Some("common"),
+            "lighting.wgsl" => Some("#include \"common.wgsl\"\nlighting"),
+            _ => None,
+        };
+        let source = "#include \"common.wgsl\"\n#include \"lighting.wgsl\"\nmain";
+
+        let output = preprocess("test.wgsl", source, &[], &resolve).unwrap();
+        assert_eq!(lines(&output), ["common", "lighting", "main"]);
+    }
+
+    #[test]
+    fn include_errors() {
+        let resolve = |name: &str| match name {
+            "a.wgsl" => Some("#include \"b.wgsl\""),
+            "b.wgsl" => Some("#include \"a.wgsl\""),
+            _ => None,
+        };
+
+        assert!(preprocess("test.wgsl", "#include \"test.wgsl\"", &[], &resolve).is_err());
+        assert!(preprocess("test.wgsl", "#include \"a.wgsl\"", &[], &resolve).is_err());
+        assert!(preprocess("test.wgsl", "#include \"missing.wgsl\"", &[], &resolve).is_err());
+        assert!(preprocess("test.wgsl", "#include missing.wgsl", &[], &resolve).is_err());
+        // Inactive includes are never resolved
+        assert!(
+            preprocess(
+                "test.wgsl",
+                "#ifdef A\n#include \"missing.wgsl\"\n#endif",
+                &[],
+                &resolve
+            )
+            .is_ok()
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use log::warn;
+use wgpu::{Backends, PowerPreference, PresentMode};
+
+// Frames the CPU may queue up ahead of the GPU, fewer lowers input latency at the cost of stalls
+const DEFAULT_FRAME_LATENCY: u32 = 2;
+
+// Choices the renderer is created with, usually read from the environment or .env
+#[derive(Debug, Clone)]
+pub struct RendererSettings {
+    pub backends: Backends, // When none of them has an adapter GL is tried, followed by a software adapter
+    pub present_mode: PresentMode, // Fifo waits for vsync, Mailbox replaces waiting frames, Immediate may tear
+    pub frame_latency: u32,
+    pub power_preference: PowerPreference,
+    pub shader_directory: Option<PathBuf>, // Built in shaders are loaded from here when set and reloaded when they change
+    pub pipeline_cache_directory: Option<PathBuf>, // Compiled pipelines are saved here when set and the backend supports it
+}
+
+impl Default for RendererSettings {
+    fn default() -> Self {
+        Self {
+            backends: Self::default_backends(),
+            present_mode: PresentMode::Fifo,
+            frame_latency: DEFAULT_FRAME_LATENCY,
+            power_preference: PowerPreference::HighPerformance,
+            shader_directory: None,
+            pipeline_cache_directory: None,
+        }
+    }
+}
+
+impl RendererSettings {
+    // The native backend of the platform
+    fn default_backends() -> Backends {
+        if cfg!(target_os = "windows") {
+            Backends::DX12
+        } else if cfg!(target_os = "macos") {
+            Backends::METAL
+        } else if cfg!(target_os = "linux") {
+            Backends::VULKAN
+        } else {
+            Backends::PRIMARY
+        }
+    }
+
+    // Reads the settings from the environment, unset or invalid values keep their default, e.g.
+    // RENDER_BACKENDS=vulkan,gl RENDER_PRESENT_MODE=mailbox RENDER_FRAME_LATENCY=1 RENDER_POWER_PREFERENCE=low
+    pub fn from_env() -> Self {
+        let default = Self::default();
+
+        Self {
+            backends: parse_env("RENDER_BACKENDS", |value| {
+                Some(Backends::from_comma_list(value)).filter(|backends| !backends.is_empty())
+            })
+            .unwrap_or(default.backends),
+            present_mode: parse_env("RENDER_PRESENT_MODE", |value| match value {
+                "fifo" | "vsync" => Some(PresentMode::Fifo),
+                "fifo_relaxed" => Some(PresentMode::FifoRelaxed),
+                "mailbox" => Some(PresentMode::Mailbox),
+                "immediate" | "no_vsync" => Some(PresentMode::Immediate),
+                "auto_vsync" => Some(PresentMode::AutoVsync),
+                "auto_no_vsync" => Some(PresentMode::AutoNoVsync),
+                _ => None,
+            })
+            .unwrap_or(default.present_mode),
+            frame_latency: parse_env("RENDER_FRAME_LATENCY", |value| {
+                value.parse().ok().filter(|latency| *latency > 0)
+            })
+            .unwrap_or(default.frame_latency),
+            power_preference: parse_env("RENDER_POWER_PREFERENCE", |value| match value {
+                "high" | "high_performance" => Some(PowerPreference::HighPerformance),
+                "low" | "low_power" => Some(PowerPreference::LowPower),
+                "none" => Some(PowerPreference::None),
+                _ => None,
+            })
+            .unwrap_or(default.power_preference),
+            // e.g. SHADER_DIRECTORY=src to edit the engine's shaders while it runs
+            shader_directory: std::env::var("SHADER_DIRECTORY").ok().map(PathBuf::from),
+            // e.g. PIPELINE_CACHE_DIRECTORY=target/pipeline_cache to keep compiled pipelines between runs
+            pipeline_cache_directory: std::env::var("PIPELINE_CACHE_DIRECTORY")
+                .ok()
+                .map(PathBuf::from),
+        }
+    }
+}
+
+// Values are matched in lower case, ones the parser rejects are reported and ignored
+fn parse_env<T>(name: &str, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+    let value = std::env::var(name).ok()?;
+    let parsed = parse(&value.trim().to_lowercase());
+    if parsed.is_none() {
+        warn!("Ignoring {}={:?}, it is not a valid value", name, value);
+    }
+
+    parsed
+}
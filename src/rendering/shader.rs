@@ -0,0 +1,331 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::anyhow;
+use log::{debug, error, warn};
+use wgpu::{Device, ErrorFilter, ShaderModule, ShaderModuleDescriptor, ShaderSource};
+
+use crate::{
+    asset::{AssetService, ShaderHandle, shader::Shader},
+    rendering::preprocessor::{includes, preprocess},
+};
+
+// Shaders the built in pipelines are created from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuiltinShader {
+    Primary,
+    Shadow,
+    Sprite,
+    Tilemap,
+    Text,
+    DebugLine,
+    Environment,
+    Skybox,
+    PostProcess,
+    Blit,
+    Mipmap,
+}
+
+impl BuiltinShader {
+    const ALL: [BuiltinShader; 11] = [
+        BuiltinShader::Primary,
+        BuiltinShader::Shadow,
+        BuiltinShader::Sprite,
+        BuiltinShader::Tilemap,
+        BuiltinShader::Text,
+        BuiltinShader::DebugLine,
+        BuiltinShader::Environment,
+        BuiltinShader::Skybox,
+        BuiltinShader::PostProcess,
+        BuiltinShader::Blit,
+        BuiltinShader::Mipmap,
+    ];
+
+    // Name of the file inside the shader directory
+    fn file_name(self) -> &'static str {
+        match self {
+            BuiltinShader::Primary => "shader.wgsl",
+            BuiltinShader::Shadow => "shadow.wgsl",
+            BuiltinShader::Sprite => "sprite.wgsl",
+            BuiltinShader::Tilemap => "tilemap.wgsl",
+            BuiltinShader::Text => "text.wgsl",
+            BuiltinShader::DebugLine => "debug_line.wgsl",
+            BuiltinShader::Environment => "environment.wgsl",
+            BuiltinShader::Skybox => "skybox.wgsl",
+            BuiltinShader::PostProcess => "post_process.wgsl",
+            BuiltinShader::Blit => "blit.wgsl",
+            BuiltinShader::Mipmap => "mipmap.wgsl",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            BuiltinShader::Primary => "Primary Shader",
+            BuiltinShader::Shadow => "Shadow Shader",
+            BuiltinShader::Sprite => "Sprite Shader",
+            BuiltinShader::Tilemap => "Tilemap Shader",
+            BuiltinShader::Text => "Text Shader",
+            BuiltinShader::DebugLine => "Debug Line Shader",
+            BuiltinShader::Environment => "Environment Shader",
+            BuiltinShader::Skybox => "Skybox Shader",
+            BuiltinShader::PostProcess => "Post Process Shader",
+            BuiltinShader::Blit => "Blit Shader",
+            BuiltinShader::Mipmap => "Mipmap Shader",
+        }
+    }
+
+    // Copy compiled into the engine, used when the shader directory doesn't have a working one
+    fn embedded_source(self) -> &'static str {
+        match self {
+            BuiltinShader::Primary => include_str!("../shader.wgsl"),
+            BuiltinShader::Shadow => include_str!("../shadow.wgsl"),
+            BuiltinShader::Sprite => include_str!("../sprite.wgsl"),
+            BuiltinShader::Tilemap => include_str!("../tilemap.wgsl"),
+            BuiltinShader::Text => include_str!("../text.wgsl"),
+            BuiltinShader::DebugLine => include_str!("../debug_line.wgsl"),
+            BuiltinShader::Environment => include_str!("../environment.wgsl"),
+            BuiltinShader::Skybox => include_str!("../skybox.wgsl"),
+            BuiltinShader::PostProcess => include_str!("../post_process.wgsl"),
+            BuiltinShader::Blit => include_str!("../blit.wgsl"),
+            BuiltinShader::Mipmap => include_str!("../mipmap.wgsl"),
+        }
+    }
+}
+
+// Files the built in shaders #include, they are loaded and reloaded like the shaders themselves
+const INCLUDES: [(&str, &str); 2] = [
+    ("camera.wgsl", include_str!("../camera.wgsl")),
+    ("fullscreen.wgsl", include_str!("../fullscreen.wgsl")),
+];
+
+// Shader asset behind a built in shader or include and the revision of it the pipelines were last built from
+#[derive(Clone)]
+struct LoadedShader {
+    handle: ShaderHandle,
+    revision: u32, // Failed compilations count as well so a broken shader is only reported once
+}
+
+// Keeps track of the shader assets the built in pipelines are created from
+#[derive(Clone)]
+pub struct ShaderLibrary {
+    shaders: HashMap<BuiltinShader, LoadedShader>,
+    includes: HashMap<&'static str, LoadedShader>,
+}
+
+impl ShaderLibrary {
+    // Loads every built in shader and include from the directory when one is given,
+    // files the directory doesn't have use the copy compiled into the engine
+    pub fn load(asset_service: &mut AssetService, directory: Option<&Path>) -> Self {
+        let shaders = BuiltinShader::ALL
+            .into_iter()
+            .map(|builtin| {
+                let loaded = Self::load_shader(
+                    asset_service,
+                    directory,
+                    builtin.file_name(),
+                    builtin.embedded_source(),
+                );
+                (builtin, loaded)
+            })
+            .collect();
+        let includes = INCLUDES
+            .into_iter()
+            .map(|(file_name, embedded_source)| {
+                let loaded =
+                    Self::load_shader(asset_service, directory, file_name, embedded_source);
+                (file_name, loaded)
+            })
+            .collect();
+
+        Self { shaders, includes }
+    }
+
+    fn load_shader(
+        asset_service: &mut AssetService,
+        directory: Option<&Path>,
+        file_name: &str,
+        embedded_source: &str,
+    ) -> LoadedShader {
+        let shader = directory
+            .map(|directory| directory.join(file_name))
+            .and_then(|path| match Shader::load(&path) {
+                Ok(shader) => Some(shader),
+                Err(e) => {
+                    warn!("Using the embedded {}: {:?}", file_name, e);
+                    None
+                }
+            })
+            .unwrap_or_else(|| Shader::from_source(file_name, embedded_source));
+
+        LoadedShader {
+            revision: shader.revision,
+            handle: asset_service.add_shader(shader),
+        }
+    }
+
+    fn source<'a>(
+        &self,
+        asset_service: &'a AssetService,
+        loaded: &LoadedShader,
+    ) -> Option<&'a str> {
+        asset_service
+            .shaders
+            .get(&loaded.handle)
+            .map(|shader| shader.source.as_str())
+    }
+
+    // Preprocesses the current source of the shader with the defines and compiles it
+    pub fn compile(
+        &self,
+        device: &Device,
+        asset_service: &AssetService,
+        builtin: BuiltinShader,
+        defines: &[&str],
+    ) -> anyhow::Result<ShaderModule> {
+        let source = self
+            .source(asset_service, &self.shaders[&builtin])
+            .unwrap_or(builtin.embedded_source());
+        let resolve_include = |name: &str| {
+            self.includes
+                .get(name)
+                .and_then(|loaded| self.source(asset_service, loaded))
+        };
+        let source = preprocess(builtin.file_name(), source, defines, &resolve_include)?;
+
+        compile_shader(device, builtin.label(), &source)
+    }
+
+    // Compiles the copy of the shader and its includes that is compiled into the engine
+    pub fn compile_embedded(
+        &self,
+        device: &Device,
+        builtin: BuiltinShader,
+        defines: &[&str],
+    ) -> ShaderModule {
+        let resolve_include = |name: &str| {
+            INCLUDES
+                .iter()
+                .find(|(file_name, _)| *file_name == name)
+                .map(|(_, source)| *source)
+        };
+        let source = preprocess(
+            builtin.file_name(),
+            builtin.embedded_source(),
+            defines,
+            &resolve_include,
+        )
+        .expect("Failed to preprocess an embedded shader");
+
+        device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(builtin.label()),
+            source: ShaderSource::Wgsl(source.into()),
+        })
+    }
+
+    // Falls back to the embedded copy when the current source doesn't compile
+    pub fn compile_or_embedded(
+        &self,
+        device: &Device,
+        asset_service: &AssetService,
+        builtin: BuiltinShader,
+        defines: &[&str],
+    ) -> ShaderModule {
+        self.compile(device, asset_service, builtin, defines)
+            .unwrap_or_else(|e| {
+                error!(
+                    "Failed to compile {}, using the embedded copy: {:?}",
+                    builtin.file_name(),
+                    e
+                );
+                self.compile_embedded(device, builtin, defines)
+            })
+    }
+
+    // Built in shaders whose file, or a file they include, changed since the last call
+    pub fn take_changed(&mut self, asset_service: &AssetService) -> Vec<BuiltinShader> {
+        let take_change = |loaded: &mut LoadedShader| {
+            let revision = asset_service
+                .shaders
+                .get(&loaded.handle)
+                .map_or(loaded.revision, |shader| shader.revision);
+            let is_changed = revision != loaded.revision;
+            loaded.revision = revision;
+
+            is_changed
+        };
+
+        let changed_includes: HashSet<&str> = self
+            .includes
+            .iter_mut()
+            .filter_map(|(name, loaded)| take_change(loaded).then_some(*name))
+            .collect();
+        let changed_shaders: Vec<BuiltinShader> = self
+            .shaders
+            .iter_mut()
+            .filter_map(|(builtin, loaded)| take_change(loaded).then_some(*builtin))
+            .collect();
+
+        BuiltinShader::ALL
+            .into_iter()
+            .filter(|builtin| {
+                changed_shaders.contains(builtin)
+                    || (!changed_includes.is_empty()
+                        && self
+                            .included_files(asset_service, *builtin)
+                            .iter()
+                            .any(|name| changed_includes.contains(name.as_str())))
+            })
+            .inspect(|builtin| debug!("{} changed", builtin.file_name()))
+            .collect()
+    }
+
+    // Every file the shader includes, directly or through other includes
+    fn included_files(&self, asset_service: &AssetService, builtin: BuiltinShader) -> Vec<String> {
+        let mut included: Vec<String> = Vec::new();
+        let mut pending: Vec<&str> = self
+            .source(asset_service, &self.shaders[&builtin])
+            .map(|source| includes(source).collect())
+            .unwrap_or_default();
+        while let Some(name) = pending.pop() {
+            if included.iter().any(|included| included == name) {
+                continue;
+            }
+            included.push(name.to_string());
+
+            if let Some(source) = self
+                .includes
+                .get(name)
+                .and_then(|loaded| self.source(asset_service, loaded))
+            {
+                pending.extend(includes(source));
+            }
+        }
+
+        included
+    }
+}
+
+fn compile_shader(device: &Device, label: &str, source: &str) -> anyhow::Result<ShaderModule> {
+    capture_validation_errors(device, || {
+        device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(label),
+            source: ShaderSource::Wgsl(source.into()),
+        })
+    })
+}
+
+// Validation errors normally reach the uncaptured error handler of the device, which panics.
+// Capturing them instead lets a broken shader or pipeline be reported while the old one stays in use.
+pub fn capture_validation_errors<T>(
+    device: &Device,
+    create: impl FnOnce() -> T,
+) -> anyhow::Result<T> {
+    device.push_error_scope(ErrorFilter::Validation);
+    let value = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(anyhow!("{}", error)),
+        None => Ok(value),
+    }
+}
@@ -0,0 +1,444 @@
+use std::{collections::HashMap, num::NonZeroU64};
+
+use bytemuck::{Pod, Zeroable, cast_slice};
+use glam::{Mat4, Vec3};
+use log::debug;
+use wgpu::{
+    AddressMode, BindGroup, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer,
+    BufferBindingType, BufferDescriptor, BufferUsages, CommandEncoder, CompareFunction,
+    DepthBiasState, DepthStencilState, Device, Extent3d, FilterMode, IndexFormat, MultisampleState,
+    PipelineCache, PipelineCompilationOptions, PipelineLayout, PipelineLayoutDescriptor,
+    PrimitiveState, Queue, RenderPassDepthStencilAttachment, RenderPipeline,
+    RenderPipelineDescriptor, Sampler, SamplerDescriptor, ShaderModule, ShaderStages, StencilState,
+    Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView,
+    TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+use crate::{
+    asset::MeshHandle,
+    ecs::component::{camera::CameraComponent, light::LightKind, transform::TransformComponent},
+    rendering::{
+        MeshDraw, light::VisibleLight, mesh::GpuMesh, model::ModelInstance,
+        shader::capture_validation_errors, vertex::Vertex,
+    },
+};
+
+// The directional light's shadow is split into cascades that cover ever larger slices of the camera frustum
+pub const CASCADE_COUNT: usize = 4;
+// Most spot lights that cast shadows at once, any further spot lights are drawn unshadowed
+pub const MAX_SPOT_SHADOWS: usize = 4;
+// Cascades come first in the shadow map array, followed by one layer per spot light
+const SHADOW_LAYER_COUNT: usize = CASCADE_COUNT + MAX_SPOT_SHADOWS;
+
+const SHADOW_MAP_SIZE: u32 = 1024;
+const SHADOW_MAP_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+
+const MAX_SHADOW_DISTANCE: f32 = 100.0; // Cascades stop at this distance from the camera even if it sees further
+const CASCADE_SPLIT_LAMBDA: f32 = 0.75; // 0 spaces the cascades evenly, 1 spaces them logarithmically
+const SHADOW_CASTER_MARGIN: f32 = 50.0; // Objects this far behind a cascade towards the light still cast into it
+const SPOT_SHADOW_NEAR: f32 = 0.05;
+
+// Shadow lookup data read by the primary shader
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct ShadowUniform {
+    pub view_projection_matrices: [[[f32; 4]; 4]; SHADOW_LAYER_COUNT], // World space to shadow map space per layer
+    pub cascade_splits: [f32; CASCADE_COUNT], // View depth at which each cascade ends
+    pub camera_forward: [f32; 4],             // w is unused and pads the field to 16 bytes
+}
+
+// Renders the depth of shadow casters from every shadowed light into layers of a shadow map array
+pub struct ShadowMaps {
+    shadow_uniform: ShadowUniform,
+    pub uniform_buffer: Buffer,
+    pub texture: Texture, // One depth layer per shadow map
+    pub array_view: TextureView,
+    pub sampler: Sampler, // Compares against the stored depth and filters the results
+    layer_views: Vec<TextureView>,
+    view_buffer: Buffer, // One view projection per layer selected by dynamic offset while rendering
+    view_bind_group: BindGroup,
+    view_stride: u64,
+    pipeline_layout: PipelineLayout,
+    pipeline_cache: Option<PipelineCache>,
+    pipeline: RenderPipeline,
+    active_layers: Vec<u32>, // Layers that are rendered this frame
+}
+
+impl ShadowMaps {
+    pub fn new(
+        device: &Device,
+        shader: ShaderModule,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Shadow Map Texture"),
+            size: Extent3d {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+                depth_or_array_layers: SHADOW_LAYER_COUNT as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: SHADOW_MAP_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let array_view = texture.create_view(&TextureViewDescriptor {
+            label: Some("Shadow Map Array View"),
+            dimension: Some(TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let layer_views = (0..SHADOW_LAYER_COUNT as u32)
+            .map(|layer| {
+                texture.create_view(&TextureViewDescriptor {
+                    label: Some("Shadow Map Layer View"),
+                    dimension: Some(TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        // Linear filtering of comparison results softens the edges on top of the PCF taps
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Shadow Map Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            compare: Some(CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let shadow_uniform = ShadowUniform::zeroed();
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Shadow Uniform Buffer"),
+            size: std::mem::size_of::<ShadowUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let view_stride = (std::mem::size_of::<Mat4>() as u64)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64);
+        let view_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Shadow View Uniform Buffer"),
+            size: view_stride * SHADOW_LAYER_COUNT as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let view_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: NonZeroU64::new(std::mem::size_of::<Mat4>() as u64),
+                },
+                count: None,
+            }],
+            label: Some("Shadow view bind group layout"),
+        });
+        let view_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &view_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &view_buffer,
+                    offset: 0,
+                    size: NonZeroU64::new(std::mem::size_of::<Mat4>() as u64),
+                }),
+            }],
+            label: Some("Shadow view bind group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&view_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(device, &pipeline_layout, &shader, pipeline_cache);
+
+        Self {
+            shadow_uniform,
+            uniform_buffer,
+            texture,
+            array_view,
+            sampler,
+            layer_views,
+            view_buffer,
+            view_bind_group,
+            view_stride,
+            pipeline_layout,
+            pipeline_cache: pipeline_cache.cloned(),
+            pipeline,
+            active_layers: Vec::new(),
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        pipeline_layout: &PipelineLayout,
+        shader: &ShaderModule,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Shadow Render Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[
+                    Vertex::describe_vertex_buffer_layout(),
+                    ModelInstance::describe_instance_buffer_layout(),
+                ],
+            },
+            fragment: None, // Only depth is written
+            // Both faces are drawn so open and double sided meshes still cast shadows
+            primitive: PrimitiveState {
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: SHADOW_MAP_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::LessEqual,
+                stencil: StencilState::default(),
+                // Slope scaled bias keeps surfaces at grazing angles from shadowing themselves
+                bias: DepthBiasState {
+                    constant: 2,
+                    slope_scale: 2.0,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: MultisampleState::default(),
+            multiview: None,
+            cache: pipeline_cache,
+        })
+    }
+
+    // Swaps in a recompiled shader, the current pipeline is kept when the new one fails to build
+    pub fn reload_shader(&mut self, device: &Device, shader: ShaderModule) -> anyhow::Result<()> {
+        self.pipeline = capture_validation_errors(device, || {
+            Self::create_pipeline(
+                device,
+                &self.pipeline_layout,
+                &shader,
+                self.pipeline_cache.as_ref(),
+            )
+        })?;
+
+        Ok(())
+    }
+
+    // Hands out shadow map layers to the lights that cast shadows and fits each layer's projection.
+    // Returns the first layer of every light, in the same order as the lights.
+    pub fn prepare(
+        &mut self,
+        queue: &Queue,
+        lights: &[VisibleLight],
+        camera: Option<(&CameraComponent, &TransformComponent)>,
+    ) -> Vec<Option<u32>> {
+        let mut light_layers = Vec::with_capacity(lights.len());
+        let mut has_cascades = false;
+        let mut spot_shadow_count = 0;
+        self.active_layers.clear();
+
+        for light in lights.iter() {
+            if light.light_component.shadow.is_none() {
+                light_layers.push(None);
+                continue;
+            }
+
+            match light.light_component.kind {
+                // Only the first directional light gets cascades
+                LightKind::Directional if !has_cascades => {
+                    let Some((camera_component, camera_transform)) = camera else {
+                        light_layers.push(None);
+                        continue;
+                    };
+
+                    has_cascades = true;
+                    self.fit_cascades(camera_component, camera_transform, light.direction);
+                    self.active_layers.extend(0..CASCADE_COUNT as u32);
+                    light_layers.push(Some(0));
+                }
+                LightKind::Spot {
+                    range,
+                    outer_cone_angle,
+                    ..
+                } if spot_shadow_count < MAX_SPOT_SHADOWS => {
+                    let layer = (CASCADE_COUNT + spot_shadow_count) as u32;
+                    spot_shadow_count += 1;
+
+                    let up = if light.direction.y.abs() > 0.99 {
+                        Vec3::Z
+                    } else {
+                        Vec3::Y
+                    };
+                    let view_matrix =
+                        Mat4::look_at_rh(light.position, light.position + light.direction, up);
+                    let projection_matrix = Mat4::perspective_rh(
+                        (outer_cone_angle * 2.0).clamp(0.01, 179.0_f32.to_radians()),
+                        1.0,
+                        SPOT_SHADOW_NEAR,
+                        range.max(SPOT_SHADOW_NEAR * 2.0),
+                    );
+                    self.shadow_uniform.view_projection_matrices[layer as usize] =
+                        (projection_matrix * view_matrix).to_cols_array_2d();
+
+                    self.active_layers.push(layer);
+                    light_layers.push(Some(layer));
+                }
+                _ => {
+                    debug!(
+                        "No shadow map layer left for {:?} light",
+                        light.light_component.kind
+                    );
+                    light_layers.push(None);
+                }
+            }
+        }
+
+        queue.write_buffer(&self.uniform_buffer, 0, cast_slice(&[self.shadow_uniform]));
+        for layer in self.active_layers.iter() {
+            queue.write_buffer(
+                &self.view_buffer,
+                *layer as u64 * self.view_stride,
+                cast_slice(&self.shadow_uniform.view_projection_matrices[*layer as usize]),
+            );
+        }
+
+        light_layers
+    }
+
+    // Splits the visible part of the camera frustum into slices and fits an orthographic
+    // projection around each one, looking along the light direction
+    fn fit_cascades(
+        &mut self,
+        camera_component: &CameraComponent,
+        camera_transform: &TransformComponent,
+        light_direction: Vec3,
+    ) {
+        // Corners of the camera's near and far planes in world space
+        let inverse_view_projection = camera_component
+            .calculate_view_projection_matrix(camera_transform)
+            .inverse();
+        let ndc_corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+        let near_corners =
+            ndc_corners.map(|(x, y)| inverse_view_projection.project_point3(Vec3::new(x, y, 0.0)));
+        let far_corners =
+            ndc_corners.map(|(x, y)| inverse_view_projection.project_point3(Vec3::new(x, y, 1.0)));
+
+        let near_center = near_corners.iter().sum::<Vec3>() / 4.0;
+        let far_center = far_corners.iter().sum::<Vec3>() / 4.0;
+        let camera_forward = (far_center - near_center).normalize_or(Vec3::NEG_Z);
+
+        let camera_near = camera_component.z_near_field;
+        let camera_far = camera_component.z_far_field;
+        let shadow_far = camera_far.min(MAX_SHADOW_DISTANCE);
+
+        // Logarithmic splits give close cascades more resolution, uniform splits keep far ones useful
+        let mut split_start = camera_near;
+        for cascade in 0..CASCADE_COUNT {
+            let fraction = (cascade + 1) as f32 / CASCADE_COUNT as f32;
+            let uniform_split = camera_near + (shadow_far - camera_near) * fraction;
+            // Orthographic cameras may start at 0 which the logarithmic split can't handle
+            let logarithmic_near = camera_near.max(0.01);
+            let logarithmic_split =
+                logarithmic_near * (shadow_far / logarithmic_near).powf(fraction);
+            let split_end =
+                uniform_split + (logarithmic_split - uniform_split) * CASCADE_SPLIT_LAMBDA;
+
+            let start_t = (split_start - camera_near) / (camera_far - camera_near);
+            let end_t = (split_end - camera_near) / (camera_far - camera_near);
+            let slice_corners: Vec<Vec3> = near_corners
+                .iter()
+                .zip(far_corners.iter())
+                .flat_map(|(near, far)| [near.lerp(*far, start_t), near.lerp(*far, end_t)])
+                .collect();
+
+            // A bounding sphere keeps the projection size constant while the camera rotates
+            let center = slice_corners.iter().sum::<Vec3>() / slice_corners.len() as f32;
+            let radius = slice_corners
+                .iter()
+                .map(|corner| corner.distance(center))
+                .fold(0.0_f32, f32::max);
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            let up = if light_direction.y.abs() > 0.99 {
+                Vec3::Z
+            } else {
+                Vec3::Y
+            };
+            let view_matrix = Mat4::look_at_rh(Vec3::ZERO, light_direction, up);
+
+            // Moving the projection in whole texels stops shadow edges from shimmering as the camera moves
+            let light_space_center = view_matrix.transform_point3(center);
+            let texel_size = radius * 2.0 / SHADOW_MAP_SIZE as f32;
+            let snapped_x = (light_space_center.x / texel_size).floor() * texel_size;
+            let snapped_y = (light_space_center.y / texel_size).floor() * texel_size;
+
+            let projection_matrix = Mat4::orthographic_rh(
+                snapped_x - radius,
+                snapped_x + radius,
+                snapped_y - radius,
+                snapped_y + radius,
+                -light_space_center.z - radius - SHADOW_CASTER_MARGIN,
+                -light_space_center.z + radius,
+            );
+
+            self.shadow_uniform.view_projection_matrices[cascade] =
+                (projection_matrix * view_matrix).to_cols_array_2d();
+            self.shadow_uniform.cascade_splits[cascade] = split_end;
+            split_start = split_end;
+        }
+
+        self.shadow_uniform.camera_forward = camera_forward.extend(0.0).to_array();
+    }
+
+    // Draws every mesh into each layer handed out this frame
+    pub fn render(
+        &self,
+        encoder: &mut CommandEncoder,
+        draws: &[MeshDraw],
+        gpu_meshes: &HashMap<MeshHandle, GpuMesh>,
+        instance_buffer: &Buffer,
+    ) {
+        for layer in self.active_layers.iter() {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Render Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &self.layer_views[*layer as usize],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_bind_group(
+                0,
+                &self.view_bind_group,
+                &[(*layer as u64 * self.view_stride) as u32],
+            );
+
+            for draw in draws.iter() {
+                let gpu_mesh = &gpu_meshes[&draw.mesh];
+                render_pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, draw.instance_slice(instance_buffer));
+                render_pass.set_index_buffer(gpu_mesh.index_buffer.slice(..), IndexFormat::Uint32);
+                render_pass.draw_indexed(0..gpu_mesh.index_count, 0, 0..draw.instance_count);
+            }
+        }
+    }
+}
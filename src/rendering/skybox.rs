@@ -0,0 +1,147 @@
+use wgpu::{
+    BindGroup, BindGroupLayout, ColorTargetState, ColorWrites, CompareFunction, DepthStencilState,
+    Device, FragmentState, MultisampleState, PipelineCache, PipelineCompilationOptions,
+    PipelineLayout, PipelineLayoutDescriptor, PrimitiveState, RenderPass, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModule, TextureFormat, VertexState,
+};
+
+use crate::rendering::{depth::DepthSettings, shader::capture_validation_errors};
+
+// Draws the environment map behind everything with a fullscreen triangle
+pub struct SkyboxRenderer {
+    shader: ShaderModule,
+    pipeline_layout: PipelineLayout,
+    pipeline_cache: Option<PipelineCache>,
+    pipeline: RenderPipeline,
+}
+
+impl SkyboxRenderer {
+    pub fn new(
+        device: &Device,
+        shader: ShaderModule,
+        environment_bind_group_layout: &BindGroupLayout,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[environment_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            color_format,
+            depth_settings,
+            sample_count,
+            pipeline_cache,
+        );
+
+        Self {
+            shader,
+            pipeline_layout,
+            pipeline_cache: pipeline_cache.cloned(),
+            pipeline,
+        }
+    }
+
+    // The sky is drawn first and never writes depth, so everything else is drawn over it
+    fn create_pipeline(
+        device: &Device,
+        pipeline_layout: &PipelineLayout,
+        shader: &ShaderModule,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Skybox Render Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[], // The fullscreen triangle is generated from the vertex index
+            },
+            fragment: Some(FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(ColorTargetState {
+                    format: color_format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: Some(DepthStencilState {
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::Always,
+                ..depth_settings.depth_stencil_state()
+            }),
+            multisample: MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: pipeline_cache,
+        })
+    }
+
+    // Depth state and the sample count are baked into the pipeline
+    pub fn recreate_pipelines(
+        &mut self,
+        device: &Device,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            color_format,
+            depth_settings,
+            sample_count,
+            self.pipeline_cache.as_ref(),
+        );
+    }
+
+    // Swaps in a recompiled shader, the current pipeline is kept when the new one fails to build
+    pub fn reload_shader(
+        &mut self,
+        device: &Device,
+        shader: ShaderModule,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+    ) -> anyhow::Result<()> {
+        self.pipeline = capture_validation_errors(device, || {
+            Self::create_pipeline(
+                device,
+                &self.pipeline_layout,
+                &shader,
+                color_format,
+                depth_settings,
+                sample_count,
+                self.pipeline_cache.as_ref(),
+            )
+        })?;
+        self.shader = shader;
+
+        Ok(())
+    }
+
+    // Takes the bind group of the baked environment, returns the number of draws submitted
+    pub fn render(&self, render_pass: &mut RenderPass, environment_bind_group: &BindGroup) -> u32 {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, environment_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        1
+    }
+}
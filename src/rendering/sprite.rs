@@ -0,0 +1,483 @@
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable, cast_slice};
+use glam::{Mat4, Vec2};
+use log::{debug, warn};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    BlendState, Buffer, BufferAddress, BufferDescriptor, BufferUsages, ColorTargetState,
+    ColorWrites, Device, FragmentState, IndexFormat, MultisampleState, PipelineCache,
+    PipelineCompilationOptions, PipelineLayout, PipelineLayoutDescriptor, PrimitiveState, Queue,
+    RenderPass, RenderPipeline, RenderPipelineDescriptor, SamplerBindingType, ShaderModule,
+    ShaderStages, TextureFormat, TextureSampleType, TextureViewDimension, VertexAttribute,
+    VertexBufferLayout, VertexFormat, VertexState,
+    util::{BufferInitDescriptor, DeviceExt},
+};
+
+use crate::{
+    asset::{AssetService, TextureHandle, bounds::Aabb},
+    ecs::{
+        component::sprite::{SpriteComponent, SpriteImage},
+        entity::scene::Scene,
+    },
+    rendering::{
+        buffer::grow_capacity, depth::DepthSettings, frustum::Frustum,
+        shader::capture_validation_errors, texture::GpuTexture,
+    },
+};
+
+// Number of sprites the vertex buffer can hold before it has to grow
+const INITIAL_SPRITE_CAPACITY: u64 = 256;
+
+const ATTRIBUTES: &[VertexAttribute] = &[
+    VertexAttribute {
+        offset: 0,
+        shader_location: 0,
+        format: VertexFormat::Float32x3, // World space position
+    },
+    VertexAttribute {
+        offset: 12,
+        shader_location: 1,
+        format: VertexFormat::Float32x2, // Texture coordinates
+    },
+    VertexAttribute {
+        offset: 20,
+        shader_location: 2,
+        format: VertexFormat::Float32x4, // Tint
+    },
+];
+
+// Sprites are transformed on the CPU so every sprite sharing a texture can go into one draw
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Pod, Zeroable)]
+pub struct SpriteVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl SpriteVertex {
+    pub fn describe_vertex_buffer_layout() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<SpriteVertex>() as BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: ATTRIBUTES,
+        }
+    }
+}
+
+// A sprite ready to be drawn, corners go counter-clockwise from the bottom left
+pub struct SpriteQuad {
+    pub texture: TextureHandle,
+    z_order: i32,
+    depth: f32, // Distance from the camera, larger is further away
+    vertices: [SpriteVertex; 4],
+}
+
+// Consecutive sprites that share a texture and z-order
+struct SpriteBatch {
+    texture: TextureHandle,
+    z_order: i32,
+    first_index: u32,
+    index_count: u32,
+}
+
+// Builds a quad for every sprite in the scene, sorted back to front so transparent edges blend correctly
+pub fn collect_sprites(
+    scene: &Scene,
+    asset_service: &AssetService,
+    view_projection_matrix: Mat4,
+    depth_settings: &DepthSettings,
+    frustum: Option<&Frustum>,
+) -> Vec<SpriteQuad> {
+    let mut quads = Vec::with_capacity(scene.sprite_components.len());
+
+    for (entity, sprite_component) in scene.sprite_components.iter() {
+        let Some((texture, min_tex_coords, max_tex_coords)) =
+            resolve_image(sprite_component, asset_service)
+        else {
+            warn!(
+                "Entity {} refers to a missing sprite image {:?}",
+                entity, sprite_component.image
+            );
+            continue;
+        };
+
+        let world_matrix = scene.calculate_world_matrix(*entity);
+        let corners = [Vec2::ZERO, Vec2::X, Vec2::ONE, Vec2::Y].map(|corner| {
+            let local_position = (corner - sprite_component.anchor) * sprite_component.size;
+            world_matrix.transform_point3(local_position.extend(0.0))
+        });
+
+        if let Some(frustum) = frustum
+            && let Some(bounds) = Aabb::from_points(corners)
+            && !frustum.intersects_aabb(&bounds)
+        {
+            continue;
+        }
+
+        // Flipping swaps which side of the region each edge samples.
+        // Texture coordinates grow downwards while the quad grows upwards.
+        let (mut left, mut right) = (min_tex_coords.x, max_tex_coords.x);
+        let (mut bottom, mut top) = (max_tex_coords.y, min_tex_coords.y);
+        if sprite_component.flip_x {
+            std::mem::swap(&mut left, &mut right);
+        }
+        if sprite_component.flip_y {
+            std::mem::swap(&mut bottom, &mut top);
+        }
+        let tex_coords = [[left, bottom], [right, bottom], [right, top], [left, top]];
+
+        let center = (corners[0] + corners[2]) * 0.5;
+        let depth = view_projection_matrix.project_point3(center).z;
+
+        let color = sprite_component.tint.to_array();
+        let mut vertices = [SpriteVertex::default(); 4];
+        for (vertex, (position, tex_coords)) in vertices
+            .iter_mut()
+            .zip(corners.iter().zip(tex_coords.iter()))
+        {
+            *vertex = SpriteVertex {
+                position: position.to_array(),
+                tex_coords: *tex_coords,
+                color,
+            };
+        }
+
+        quads.push(SpriteQuad {
+            texture,
+            z_order: sprite_component.z_order,
+            depth: if depth_settings.reverse_z {
+                -depth
+            } else {
+                depth
+            },
+            vertices,
+        });
+    }
+
+    // Lower z-orders first, then the furthest sprites, then grouped by texture so equal sprites batch
+    quads.sort_by(|a, b| {
+        a.z_order
+            .cmp(&b.z_order)
+            .then(b.depth.total_cmp(&a.depth))
+            .then(a.texture.0.cmp(&b.texture.0))
+    });
+
+    quads
+}
+
+// The texture a sprite samples and the corners of the region it shows in texture coordinates
+fn resolve_image(
+    sprite_component: &SpriteComponent,
+    asset_service: &AssetService,
+) -> Option<(TextureHandle, Vec2, Vec2)> {
+    match sprite_component.image {
+        SpriteImage::Texture(texture) => Some((texture, Vec2::ZERO, Vec2::ONE)),
+        SpriteImage::TextureRegion {
+            texture,
+            position,
+            size,
+        } => {
+            let asset = asset_service.textures.get(&texture)?;
+            let texture_size = Vec2::new(asset.width as f32, asset.height as f32).max(Vec2::ONE);
+
+            Some((
+                texture,
+                position.as_vec2() / texture_size,
+                (position + size).as_vec2() / texture_size,
+            ))
+        }
+        SpriteImage::Atlas { atlas, region } => {
+            let atlas = asset_service.atlases.get(&atlas)?;
+            let (min, max) = atlas.region_tex_coords(region)?;
+
+            Some((atlas.texture, min, max))
+        }
+    }
+}
+
+// Draws sprites as alpha blended quads streamed into a vertex buffer every frame
+pub struct SpriteRenderer {
+    shader: ShaderModule,
+    pipeline_layout: PipelineLayout,
+    pipeline_cache: Option<PipelineCache>,
+    pipeline: RenderPipeline,
+    texture_bind_group_layout: BindGroupLayout,
+    texture_bind_groups: HashMap<TextureHandle, BindGroup>,
+    vertex_buffer: Buffer,
+    index_buffer: Buffer, // Two triangles per sprite, only rewritten when the capacity grows
+    capacity: u64,        // Number of sprites the buffers can hold
+    batches: Vec<SpriteBatch>,
+}
+
+impl SpriteRenderer {
+    pub fn new(
+        device: &Device,
+        shader: ShaderModule,
+        camera_bind_group_layout: &BindGroupLayout,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Self {
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("Sprite texture bind group layout"),
+            });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Sprite Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &texture_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            color_format,
+            depth_settings,
+            sample_count,
+            pipeline_cache,
+        );
+
+        let (vertex_buffer, index_buffer) = Self::create_buffers(device, INITIAL_SPRITE_CAPACITY);
+
+        Self {
+            shader,
+            pipeline_layout,
+            pipeline_cache: pipeline_cache.cloned(),
+            pipeline,
+            texture_bind_group_layout,
+            texture_bind_groups: HashMap::new(),
+            vertex_buffer,
+            index_buffer,
+            capacity: INITIAL_SPRITE_CAPACITY,
+            batches: Vec::new(),
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        pipeline_layout: &PipelineLayout,
+        shader: &ShaderModule,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Sprite Render Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[SpriteVertex::describe_vertex_buffer_layout()],
+            },
+            fragment: Some(FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(ColorTargetState {
+                    format: color_format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            // Sprites flipped with a negative scale face away from the camera but should still be seen
+            primitive: PrimitiveState {
+                cull_mode: None,
+                ..Default::default()
+            },
+            // Sprites are hidden behind opaque geometry but don't hide each other,
+            // the draw order decides which sprite ends up on top
+            depth_stencil: Some(wgpu::DepthStencilState {
+                depth_write_enabled: false,
+                ..depth_settings.depth_stencil_state()
+            }),
+            multisample: MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: pipeline_cache,
+        })
+    }
+
+    // Depth state and the sample count are baked into the pipeline
+    pub fn recreate_pipelines(
+        &mut self,
+        device: &Device,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            color_format,
+            depth_settings,
+            sample_count,
+            self.pipeline_cache.as_ref(),
+        );
+    }
+
+    // Swaps in a recompiled shader, the current pipeline is kept when the new one fails to build
+    pub fn reload_shader(
+        &mut self,
+        device: &Device,
+        shader: ShaderModule,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+    ) -> anyhow::Result<()> {
+        self.pipeline = capture_validation_errors(device, || {
+            Self::create_pipeline(
+                device,
+                &self.pipeline_layout,
+                &shader,
+                color_format,
+                depth_settings,
+                sample_count,
+                self.pipeline_cache.as_ref(),
+            )
+        })?;
+        self.shader = shader;
+
+        Ok(())
+    }
+
+    fn create_buffers(device: &Device, capacity: u64) -> (Buffer, Buffer) {
+        let vertex_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Sprite Vertex Buffer"),
+            size: capacity * 4 * std::mem::size_of::<SpriteVertex>() as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let indices: Vec<u32> = (0..capacity as u32)
+            .flat_map(|sprite| {
+                let first = sprite * 4;
+                [first, first + 1, first + 2, first, first + 2, first + 3]
+            })
+            .collect();
+        let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Sprite Index Buffer"),
+            contents: cast_slice(&indices),
+            usage: BufferUsages::INDEX,
+        });
+
+        (vertex_buffer, index_buffer)
+    }
+
+    // Sprite textures have to be uploaded before this is called
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        quads: &[SpriteQuad],
+        gpu_textures: &HashMap<TextureHandle, GpuTexture>,
+    ) {
+        self.batches.clear();
+        if quads.is_empty() {
+            return;
+        }
+
+        let new_capacity = grow_capacity(self.capacity, quads.len() as u64);
+        if new_capacity > self.capacity {
+            debug!("Growing sprite buffers to {} sprites", new_capacity);
+            (self.vertex_buffer, self.index_buffer) = Self::create_buffers(device, new_capacity);
+            self.capacity = new_capacity;
+        }
+
+        let mut vertices = Vec::with_capacity(quads.len() * 4);
+        for quad in quads {
+            if !self.texture_bind_groups.contains_key(&quad.texture) {
+                let Some(gpu_texture) = gpu_textures.get(&quad.texture) else {
+                    continue;
+                };
+
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.texture_bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&gpu_texture.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&gpu_texture.sampler),
+                        },
+                    ],
+                    label: Some("Sprite texture bind group"),
+                });
+                self.texture_bind_groups.insert(quad.texture, bind_group);
+            }
+
+            // Start a new batch whenever the texture changes, or the z-order since
+            // tilemap layers can be drawn in between z-orders
+            let first_index = vertices.len() as u32 / 4 * 6;
+            match self.batches.last_mut() {
+                Some(batch) if batch.texture == quad.texture && batch.z_order == quad.z_order => {
+                    batch.index_count += 6
+                }
+                _ => self.batches.push(SpriteBatch {
+                    texture: quad.texture,
+                    z_order: quad.z_order,
+                    first_index,
+                    index_count: 6,
+                }),
+            }
+            vertices.extend_from_slice(&quad.vertices);
+        }
+
+        queue.write_buffer(&self.vertex_buffer, 0, cast_slice(&vertices));
+    }
+
+    pub fn z_orders(&self) -> impl Iterator<Item = i32> + '_ {
+        self.batches.iter().map(|batch| batch.z_order)
+    }
+
+    // Draws the sprites of one z-order, expects the camera bind group to be set.
+    // Returns the number of draws submitted.
+    pub fn render(&self, render_pass: &mut RenderPass, z_order: i32) -> u32 {
+        let mut draw_calls = 0;
+        for batch in self.batches.iter().filter(|batch| batch.z_order == z_order) {
+            if draw_calls == 0 {
+                render_pass.set_pipeline(&self.pipeline);
+                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(self.index_buffer.slice(..), IndexFormat::Uint32);
+            }
+
+            render_pass.set_bind_group(1, &self.texture_bind_groups[&batch.texture], &[]);
+            render_pass.draw_indexed(
+                batch.first_index..batch.first_index + batch.index_count,
+                0,
+                0..1,
+            );
+            draw_calls += 1;
+        }
+
+        draw_calls
+    }
+}
@@ -0,0 +1,8 @@
+// Counters describing the last rendered frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RenderStats {
+    pub objects: u32,         // Mesh entities in the scene
+    pub visible_objects: u32, // Mesh entities inside the camera frustum
+    pub culled_objects: u32,  // Mesh entities skipped because they are outside the camera frustum
+    pub draw_calls: u32,      // Instanced draws submitted in the main pass
+}
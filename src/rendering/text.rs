@@ -0,0 +1,739 @@
+use std::collections::HashMap;
+
+use bytemuck::cast_slice;
+use glam::{Mat4, UVec2, Vec2, Vec3};
+use log::{debug, warn};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    BlendState, Buffer, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites,
+    CompareFunction, Device, Extent3d, FragmentState, IndexFormat, MultisampleState, Origin3d,
+    PipelineCache, PipelineCompilationOptions, PipelineLayout, PipelineLayoutDescriptor,
+    PrimitiveState, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor, Sampler,
+    SamplerBindingType, ShaderModule, ShaderStages, TexelCopyBufferLayout, TexelCopyTextureInfo,
+    Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType,
+    TextureUsages, TextureView, TextureViewDimension, VertexState,
+    util::{BufferInitDescriptor, DeviceExt},
+};
+
+use crate::{
+    asset::{
+        AssetService, FontHandle,
+        bounds::Aabb,
+        font::{Font, TextLayoutSettings},
+    },
+    ecs::{component::text::TextSpace, entity::scene::Scene},
+    rendering::{
+        buffer::grow_capacity, depth::DepthSettings, frustum::Frustum,
+        shader::capture_validation_errors, sprite::SpriteVertex,
+    },
+};
+
+// Number of glyphs the vertex buffer can hold before it has to grow
+const INITIAL_GLYPH_CAPACITY: u64 = 1024;
+
+// Width and height of the glyph atlas before it has to grow
+const INITIAL_GLYPH_ATLAS_SIZE: u32 = 512;
+
+// Empty pixels kept around every glyph so filtering doesn't pick up its neighbours
+const GLYPH_PADDING: u32 = 1;
+
+// Pixel size glyphs of world text are rasterized at, they are scaled to the font size of the text from there
+const WORLD_TEXT_RASTER_SIZE: f32 = 64.0;
+
+// A glyph of one font rasterized at one size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    font: FontHandle,
+    glyph: u16,
+    size: u32, // Pixel size the glyph is rasterized at, always a whole number of pixels
+}
+
+// Glyph bitmaps packed into rows of a single channel texture as they are first drawn
+struct GlyphAtlas {
+    texture: Texture,
+    view: TextureView,
+    size: u32,
+    glyphs: HashMap<GlyphKey, UVec2>, // Top left corner of every rasterized glyph
+    cursor: UVec2,                    // Where the next glyph goes on the current row
+    row_height: u32,
+}
+
+impl GlyphAtlas {
+    fn new(device: &Device, size: u32) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Glyph Atlas"),
+            size: Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R8Unorm, // Coverage of the glyph, the color comes from the text
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            size,
+            glyphs: HashMap::new(),
+            cursor: UVec2::splat(GLYPH_PADDING),
+            row_height: 0,
+        }
+    }
+
+    // Rasterizes and uploads the glyph the first time it is asked for.
+    // Returns None once the atlas is full.
+    fn glyph(&mut self, queue: &Queue, font: &Font, key: GlyphKey) -> Option<UVec2> {
+        if let Some(position) = self.glyphs.get(&key) {
+            return Some(*position);
+        }
+
+        let (metrics, coverage) = font.rasterize(key.glyph, key.size as f32);
+        let size = UVec2::new(metrics.width as u32, metrics.height as u32);
+
+        if self.cursor.x + size.x + GLYPH_PADDING > self.size {
+            self.cursor = UVec2::new(GLYPH_PADDING, self.cursor.y + self.row_height);
+            self.row_height = 0;
+        }
+        if self.cursor.x + size.x + GLYPH_PADDING > self.size
+            || self.cursor.y + size.y + GLYPH_PADDING > self.size
+        {
+            return None;
+        }
+
+        let position = self.cursor;
+        if size.x > 0 && size.y > 0 {
+            queue.write_texture(
+                TexelCopyTextureInfo {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: position.x,
+                        y: position.y,
+                        z: 0,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                &coverage,
+                TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(size.x),
+                    rows_per_image: None,
+                },
+                Extent3d {
+                    width: size.x,
+                    height: size.y,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        self.cursor.x += size.x + GLYPH_PADDING;
+        self.row_height = self.row_height.max(size.y + GLYPH_PADDING);
+        self.glyphs.insert(key, position);
+
+        Some(position)
+    }
+}
+
+// A glyph ready to be drawn, corners go counter-clockwise from the bottom left
+struct TextGlyph {
+    glyph: u16,
+    size: UVec2, // Size of the glyph bitmap in pixels
+    corners: [Vec3; 4],
+}
+
+// The glyphs of one text component, world text is in world space and screen text in clip space
+pub struct TextDraw {
+    font: FontHandle,
+    raster_size: f32,
+    color: [f32; 4],
+    is_screen_space: bool,
+    z_order: i32,
+    depth: f32, // Distance from the camera, larger is further away
+    glyphs: Vec<TextGlyph>,
+}
+
+// Consecutive texts that share a z-order and space
+struct TextBatch {
+    is_screen_space: bool,
+    z_order: i32,
+    first_index: u32,
+    index_count: u32,
+}
+
+// Lays out every text in the scene, world text is sorted back to front like sprites
+// and screen text is drawn after it in z-order
+pub fn collect_texts(
+    scene: &Scene,
+    asset_service: &AssetService,
+    view_projection_matrix: Mat4,
+    depth_settings: &DepthSettings,
+    frustum: Option<&Frustum>,
+    viewport_size: UVec2,
+) -> Vec<TextDraw> {
+    let mut draws = Vec::with_capacity(scene.text_components.len());
+
+    for (entity, text_component) in scene.text_components.iter() {
+        if text_component.text.is_empty() || text_component.font_size <= 0.0 {
+            continue;
+        }
+        let Some(font) = asset_service.fonts.get(&text_component.font) else {
+            warn!(
+                "Entity {} refers to a missing font {:?}",
+                entity, text_component.font
+            );
+            continue;
+        };
+
+        // Screen text is rasterized at the size it is shown at, rounded to whole pixels so animated
+        // sizes don't fill the atlas with slightly different copies of every glyph.
+        // World text is rasterized at a fixed size and scaled.
+        let (font_size, raster_size) = match text_component.space {
+            TextSpace::World => (text_component.font_size, WORLD_TEXT_RASTER_SIZE),
+            TextSpace::Screen { .. } => {
+                let font_size = text_component.font_size.round().max(1.0);
+                (font_size, font_size)
+            }
+        };
+        let scale = font_size / raster_size;
+        let layout = font.layout(
+            &text_component.text,
+            raster_size,
+            &TextLayoutSettings {
+                max_width: text_component.max_width.map(|max_width| max_width / scale),
+                alignment: text_component.alignment,
+                line_spacing: text_component.line_spacing,
+            },
+        );
+        if layout.glyphs.is_empty() {
+            continue;
+        }
+
+        // Offset of the top left corner of the text from the anchor, in pixels of the layout
+        let top_left = Vec2::new(
+            -text_component.anchor.x * layout.size.x,
+            (1.0 - text_component.anchor.y) * layout.size.y,
+        );
+
+        let mut draw = TextDraw {
+            font: text_component.font,
+            raster_size,
+            color: text_component.color.to_array(),
+            is_screen_space: false,
+            z_order: text_component.z_order,
+            depth: 0.0,
+            glyphs: Vec::with_capacity(layout.glyphs.len()),
+        };
+
+        match text_component.space {
+            TextSpace::World => {
+                let world_matrix = scene.calculate_world_matrix(*entity);
+                let to_world = |point: Vec2| {
+                    let local_position =
+                        Vec2::new(top_left.x + point.x, top_left.y - point.y) * scale;
+                    world_matrix.transform_point3(local_position.extend(0.0))
+                };
+
+                let bounds_corners = [Vec2::ZERO, Vec2::X, Vec2::ONE, Vec2::Y]
+                    .map(|corner| to_world(corner * layout.size));
+                if let Some(frustum) = frustum
+                    && let Some(bounds) = Aabb::from_points(bounds_corners)
+                    && !frustum.intersects_aabb(&bounds)
+                {
+                    continue;
+                }
+
+                let center = (bounds_corners[0] + bounds_corners[2]) * 0.5;
+                let depth = view_projection_matrix.project_point3(center).z;
+                draw.depth = if depth_settings.reverse_z {
+                    -depth
+                } else {
+                    depth
+                };
+
+                for glyph in layout.glyphs.iter() {
+                    draw.glyphs.push(TextGlyph {
+                        glyph: glyph.glyph,
+                        size: glyph.size.as_uvec2(),
+                        corners: glyph_corners(glyph.position, glyph.size).map(to_world),
+                    });
+                }
+            }
+            TextSpace::Screen { position } => {
+                draw.is_screen_space = true;
+
+                // Glyphs are snapped to whole pixels so the bitmaps are drawn without being resampled
+                let origin = (position + Vec2::new(top_left.x, -top_left.y)).round();
+                let viewport_size = viewport_size.max(UVec2::ONE).as_vec2();
+                let to_clip = |point: Vec2| {
+                    let pixel = origin + point;
+                    Vec3::new(
+                        pixel.x / viewport_size.x * 2.0 - 1.0,
+                        1.0 - pixel.y / viewport_size.y * 2.0,
+                        0.0,
+                    )
+                };
+
+                for glyph in layout.glyphs.iter() {
+                    draw.glyphs.push(TextGlyph {
+                        glyph: glyph.glyph,
+                        size: glyph.size.as_uvec2(),
+                        corners: glyph_corners(glyph.position.round(), glyph.size).map(to_clip),
+                    });
+                }
+            }
+        }
+
+        draws.push(draw);
+    }
+
+    // World text first so the batches it shares z-orders with sprites come before the screen overlay
+    draws.sort_by(|a, b| {
+        a.is_screen_space
+            .cmp(&b.is_screen_space)
+            .then(a.z_order.cmp(&b.z_order))
+            .then(b.depth.total_cmp(&a.depth))
+    });
+
+    draws
+}
+
+// Corners of a glyph bitmap in layout space, counter-clockwise from the bottom left
+fn glyph_corners(position: Vec2, size: Vec2) -> [Vec2; 4] {
+    [
+        Vec2::new(position.x, position.y + size.y),
+        position + size,
+        Vec2::new(position.x + size.x, position.y),
+        position,
+    ]
+}
+
+// Draws text as alpha blended glyph quads sampled from a glyph atlas
+pub struct TextRenderer {
+    shader: ShaderModule,
+    pipeline_layout: PipelineLayout,
+    pipeline_cache: Option<PipelineCache>,
+    world_pipeline: RenderPipeline,
+    screen_pipeline: RenderPipeline,
+    atlas_bind_group_layout: BindGroupLayout,
+    atlas_bind_group: BindGroup,
+    sampler: Sampler,
+    glyph_atlas: GlyphAtlas,
+    vertex_buffer: Buffer,
+    index_buffer: Buffer, // Two triangles per glyph, only rewritten when the capacity grows
+    capacity: u64,        // Number of glyphs the buffers can hold
+    batches: Vec<TextBatch>,
+}
+
+impl TextRenderer {
+    pub fn new(
+        device: &Device,
+        shader: ShaderModule,
+        camera_bind_group_layout: &BindGroupLayout,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Self {
+        let atlas_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("Glyph atlas bind group layout"),
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Glyph Atlas Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let glyph_atlas = GlyphAtlas::new(device, INITIAL_GLYPH_ATLAS_SIZE);
+        let atlas_bind_group =
+            Self::create_atlas_bind_group(device, &atlas_bind_group_layout, &glyph_atlas, &sampler);
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Text Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &atlas_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let (world_pipeline, screen_pipeline) = Self::create_pipelines(
+            device,
+            &pipeline_layout,
+            &shader,
+            color_format,
+            depth_settings,
+            sample_count,
+            pipeline_cache,
+        );
+
+        let (vertex_buffer, index_buffer) = Self::create_buffers(device, INITIAL_GLYPH_CAPACITY);
+
+        Self {
+            shader,
+            pipeline_layout,
+            pipeline_cache: pipeline_cache.cloned(),
+            world_pipeline,
+            screen_pipeline,
+            atlas_bind_group_layout,
+            atlas_bind_group,
+            sampler,
+            glyph_atlas,
+            vertex_buffer,
+            index_buffer,
+            capacity: INITIAL_GLYPH_CAPACITY,
+            batches: Vec::new(),
+        }
+    }
+
+    fn create_atlas_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        glyph_atlas: &GlyphAtlas,
+        sampler: &Sampler,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&glyph_atlas.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: Some("Glyph atlas bind group"),
+        })
+    }
+
+    // World text is hidden behind opaque geometry like sprites, screen text is drawn over everything
+    fn create_pipelines(
+        device: &Device,
+        pipeline_layout: &PipelineLayout,
+        shader: &ShaderModule,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> (RenderPipeline, RenderPipeline) {
+        let create_pipeline = |label, entry_point, depth_compare| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(pipeline_layout),
+                vertex: VertexState {
+                    module: shader,
+                    entry_point: Some(entry_point),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    buffers: &[SpriteVertex::describe_vertex_buffer_layout()],
+                },
+                fragment: Some(FragmentState {
+                    module: shader,
+                    entry_point: Some("fs_main"),
+                    compilation_options: PipelineCompilationOptions::default(),
+                    targets: &[Some(ColorTargetState {
+                        format: color_format,
+                        blend: Some(BlendState::ALPHA_BLENDING),
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                // Text seen from behind reads mirrored but should still be seen
+                primitive: PrimitiveState {
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    depth_write_enabled: false,
+                    depth_compare,
+                    ..depth_settings.depth_stencil_state()
+                }),
+                multisample: MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                multiview: None,
+                cache: pipeline_cache,
+            })
+        };
+
+        (
+            create_pipeline(
+                "World Text Render Pipeline",
+                "vs_main",
+                depth_settings.depth_stencil_state().depth_compare,
+            ),
+            create_pipeline(
+                "Screen Text Render Pipeline",
+                "vs_screen",
+                CompareFunction::Always,
+            ),
+        )
+    }
+
+    // Depth state and the sample count are baked into the pipelines
+    pub fn recreate_pipelines(
+        &mut self,
+        device: &Device,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+    ) {
+        (self.world_pipeline, self.screen_pipeline) = Self::create_pipelines(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            color_format,
+            depth_settings,
+            sample_count,
+            self.pipeline_cache.as_ref(),
+        );
+    }
+
+    // Swaps in a recompiled shader, the current pipelines are kept when the new ones fail to build
+    pub fn reload_shader(
+        &mut self,
+        device: &Device,
+        shader: ShaderModule,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+    ) -> anyhow::Result<()> {
+        (self.world_pipeline, self.screen_pipeline) = capture_validation_errors(device, || {
+            Self::create_pipelines(
+                device,
+                &self.pipeline_layout,
+                &shader,
+                color_format,
+                depth_settings,
+                sample_count,
+                self.pipeline_cache.as_ref(),
+            )
+        })?;
+        self.shader = shader;
+
+        Ok(())
+    }
+
+    fn create_buffers(device: &Device, capacity: u64) -> (Buffer, Buffer) {
+        let vertex_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Text Vertex Buffer"),
+            size: capacity * 4 * std::mem::size_of::<SpriteVertex>() as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let indices: Vec<u32> = (0..capacity as u32)
+            .flat_map(|glyph| {
+                let first = glyph * 4;
+                [first, first + 1, first + 2, first, first + 2, first + 3]
+            })
+            .collect();
+        let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Text Index Buffer"),
+            contents: cast_slice(&indices),
+            usage: BufferUsages::INDEX,
+        });
+
+        (vertex_buffer, index_buffer)
+    }
+
+    // Starts over with an empty atlas, which drops the glyphs of earlier frames
+    fn rebuild_atlas(&mut self, device: &Device, size: u32) {
+        debug!("Rebuilding glyph atlas at {}x{}", size, size);
+
+        self.glyph_atlas = GlyphAtlas::new(device, size);
+        self.atlas_bind_group = Self::create_atlas_bind_group(
+            device,
+            &self.atlas_bind_group_layout,
+            &self.glyph_atlas,
+            &self.sampler,
+        );
+    }
+
+    // Glyph vertices of every text and how many glyphs of each text they hold,
+    // glyphs that don't fit in the atlas are left out
+    fn build_vertices(
+        &mut self,
+        queue: &Queue,
+        texts: &[TextDraw],
+        asset_service: &AssetService,
+    ) -> (Vec<SpriteVertex>, Vec<u32>) {
+        let atlas_size = self.glyph_atlas.size as f32;
+        let mut vertices = Vec::new();
+        let mut glyph_counts = Vec::with_capacity(texts.len());
+
+        for text in texts {
+            let font = &asset_service.fonts[&text.font];
+            let first_vertex = vertices.len();
+            for glyph in text.glyphs.iter() {
+                let key = GlyphKey {
+                    font: text.font,
+                    glyph: glyph.glyph,
+                    size: text.raster_size as u32,
+                };
+                let Some(position) = self.glyph_atlas.glyph(queue, font, key) else {
+                    continue;
+                };
+
+                let min = position.as_vec2() / atlas_size;
+                let max = (position + glyph.size).as_vec2() / atlas_size;
+                let tex_coords = [
+                    [min.x, max.y],
+                    [max.x, max.y],
+                    [max.x, min.y],
+                    [min.x, min.y],
+                ];
+                for (corner, tex_coords) in glyph.corners.iter().zip(tex_coords) {
+                    vertices.push(SpriteVertex {
+                        position: corner.to_array(),
+                        tex_coords,
+                        color: text.color,
+                    });
+                }
+            }
+            glyph_counts.push(((vertices.len() - first_vertex) / 4) as u32);
+        }
+
+        (vertices, glyph_counts)
+    }
+
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        texts: &[TextDraw],
+        asset_service: &AssetService,
+    ) {
+        self.batches.clear();
+        if texts.is_empty() {
+            return;
+        }
+
+        // A full atlas is rebuilt with only the glyphs of this frame, first at the same size and then
+        // twice as large until they fit. If they don't fit the largest atlas the glyphs that did fit are drawn.
+        let glyph_count: usize = texts.iter().map(|text| text.glyphs.len()).sum();
+        let (mut vertices, mut glyph_counts) = self.build_vertices(queue, texts, asset_service);
+        let max_atlas_size = device.limits().max_texture_dimension_2d;
+        let mut atlas_size = self.glyph_atlas.size;
+        while vertices.len() / 4 < glyph_count {
+            self.rebuild_atlas(device, atlas_size);
+            (vertices, glyph_counts) = self.build_vertices(queue, texts, asset_service);
+            if atlas_size >= max_atlas_size {
+                break;
+            }
+            atlas_size = (atlas_size * 2).min(max_atlas_size);
+        }
+        if vertices.len() / 4 < glyph_count {
+            warn!(
+                "Glyph atlas is full at {}x{}, some glyphs are not drawn",
+                self.glyph_atlas.size, self.glyph_atlas.size
+            );
+        }
+        if vertices.is_empty() {
+            return;
+        }
+
+        let new_capacity = grow_capacity(self.capacity, vertices.len() as u64 / 4);
+        if new_capacity > self.capacity {
+            debug!("Growing text buffers to {} glyphs", new_capacity);
+            (self.vertex_buffer, self.index_buffer) = Self::create_buffers(device, new_capacity);
+            self.capacity = new_capacity;
+        }
+
+        let mut first_index = 0;
+        for (text, glyph_count) in texts.iter().zip(glyph_counts) {
+            let index_count = glyph_count * 6;
+            match self.batches.last_mut() {
+                Some(batch)
+                    if batch.is_screen_space == text.is_screen_space
+                        && batch.z_order == text.z_order =>
+                {
+                    batch.index_count += index_count
+                }
+                _ => self.batches.push(TextBatch {
+                    is_screen_space: text.is_screen_space,
+                    z_order: text.z_order,
+                    first_index,
+                    index_count,
+                }),
+            }
+            first_index += index_count;
+        }
+
+        queue.write_buffer(&self.vertex_buffer, 0, cast_slice(&vertices));
+    }
+
+    // z-orders of the world text, screen text is drawn separately
+    pub fn z_orders(&self) -> impl Iterator<Item = i32> + '_ {
+        self.batches
+            .iter()
+            .filter(|batch| !batch.is_screen_space)
+            .map(|batch| batch.z_order)
+    }
+
+    // Draws the world text of one z-order, expects the camera bind group to be set.
+    // Returns the number of draws submitted.
+    pub fn render(&self, render_pass: &mut RenderPass, z_order: i32) -> u32 {
+        self.render_batches(render_pass, &self.world_pipeline, |batch| {
+            !batch.is_screen_space && batch.z_order == z_order
+        })
+    }
+
+    // Draws the screen text on top of everything drawn before it.
+    // Returns the number of draws submitted.
+    pub fn render_screen(&self, render_pass: &mut RenderPass) -> u32 {
+        self.render_batches(render_pass, &self.screen_pipeline, |batch| {
+            batch.is_screen_space
+        })
+    }
+
+    fn render_batches(
+        &self,
+        render_pass: &mut RenderPass,
+        pipeline: &RenderPipeline,
+        filter: impl Fn(&TextBatch) -> bool,
+    ) -> u32 {
+        let mut draw_calls = 0;
+        for batch in self.batches.iter().filter(|batch| filter(batch)) {
+            if draw_calls == 0 {
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_bind_group(1, &self.atlas_bind_group, &[]);
+                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(self.index_buffer.slice(..), IndexFormat::Uint32);
+            }
+
+            render_pass.draw_indexed(
+                batch.first_index..batch.first_index + batch.index_count,
+                0,
+                0..1,
+            );
+            draw_calls += 1;
+        }
+
+        draw_calls
+    }
+}
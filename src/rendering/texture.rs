@@ -0,0 +1,146 @@
+use anyhow::anyhow;
+use log::debug;
+use wgpu::{
+    Device, Extent3d, FilterMode, Origin3d, Queue, Sampler, SamplerDescriptor,
+    TexelCopyBufferLayout, TexelCopyTextureInfo, TextureAspect, TextureDescriptor,
+    TextureDimension, TextureUsages, TextureView, TextureViewDescriptor,
+};
+
+use crate::{
+    asset::texture::{SamplerSettings, Texture},
+    rendering::mipmap::MipmapGenerator,
+};
+
+// GPU side copy of a texture asset
+#[derive(Clone)]
+pub struct GpuTexture {
+    pub view: TextureView,
+    pub sampler: Sampler,
+}
+
+impl GpuTexture {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        texture: &Texture,
+        mipmap_generator: &mut MipmapGenerator,
+    ) -> anyhow::Result<Self> {
+        let label = texture.name.as_deref().unwrap_or("Unnamed Texture");
+
+        let missing_features = texture.format.required_features() - device.features();
+        if !missing_features.is_empty() {
+            return Err(anyhow!(
+                "Texture {} uses {:?} which needs unsupported features {:?}",
+                label,
+                texture.format,
+                missing_features
+            ));
+        }
+
+        let size = Extent3d {
+            width: texture.width,
+            height: texture.height,
+            depth_or_array_layers: 1,
+        };
+
+        // Mip levels shipped with the texture are used as is, otherwise the full chain is generated.
+        // Compressed formats cannot be rendered to, so they only get the levels they ship with.
+        let generate_mips =
+            texture.generate_mips && texture.mips.is_empty() && !texture.format.is_compressed();
+        let mip_level_count = if generate_mips {
+            size.max_mips(TextureDimension::D2)
+        } else {
+            1 + texture.mips.len() as u32
+        };
+
+        let mut usage = TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST;
+        if generate_mips && mip_level_count > 1 {
+            usage |= TextureUsages::RENDER_ATTACHMENT;
+        }
+
+        let gpu_texture = device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: texture.format,
+            usage,
+            view_formats: &[],
+        });
+
+        let (block_width, block_height) = texture.format.block_dimensions();
+        let block_size = texture.format.block_copy_size(None).unwrap_or(4);
+        for (mip_level, pixels) in std::iter::once(&texture.pixels)
+            .chain(texture.mips.iter())
+            .enumerate()
+        {
+            let mip_size = size.mip_level_size(mip_level as u32, TextureDimension::D2);
+            let blocks_per_row = mip_size.width.div_ceil(block_width);
+            let rows_of_blocks = mip_size.height.div_ceil(block_height);
+
+            let expected_length = (blocks_per_row * rows_of_blocks * block_size) as usize;
+            if pixels.len() < expected_length {
+                return Err(anyhow!(
+                    "Texture {} mip level {} has {} bytes but needs {}",
+                    label,
+                    mip_level,
+                    pixels.len(),
+                    expected_length
+                ));
+            }
+
+            queue.write_texture(
+                TexelCopyTextureInfo {
+                    texture: &gpu_texture,
+                    mip_level: mip_level as u32,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                pixels,
+                TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(blocks_per_row * block_size),
+                    rows_per_image: Some(rows_of_blocks),
+                },
+                // Compressed textures are copied in whole blocks
+                mip_size.physical_size(texture.format),
+            );
+        }
+
+        if generate_mips {
+            debug!("Generating {} mip levels for {}", mip_level_count, label);
+            mipmap_generator.generate(device, queue, &gpu_texture);
+        }
+
+        let view = gpu_texture.create_view(&TextureViewDescriptor::default());
+        let sampler = Self::create_sampler(device, &texture.sampler);
+        Ok(Self { view, sampler })
+    }
+
+    fn create_sampler(device: &Device, sampler_settings: &SamplerSettings) -> Sampler {
+        // Anisotropic filtering is only valid when every filter is linear
+        let all_linear = [
+            sampler_settings.mag_filter,
+            sampler_settings.min_filter,
+            sampler_settings.mipmap_filter,
+        ]
+        .iter()
+        .all(|filter| *filter == FilterMode::Linear);
+
+        device.create_sampler(&SamplerDescriptor {
+            label: Some("Texture Sampler"),
+            address_mode_u: sampler_settings.address_mode_u,
+            address_mode_v: sampler_settings.address_mode_v,
+            mag_filter: sampler_settings.mag_filter,
+            min_filter: sampler_settings.min_filter,
+            mipmap_filter: sampler_settings.mipmap_filter,
+            anisotropy_clamp: if all_linear {
+                sampler_settings.anisotropy_clamp.max(1)
+            } else {
+                1
+            },
+            ..Default::default()
+        })
+    }
+}
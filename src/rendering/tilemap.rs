@@ -0,0 +1,604 @@
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable, cast_slice};
+use glam::{Mat4, UVec2, Vec2, Vec2Swizzles};
+use log::{debug, warn};
+use wgpu::{
+    BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType,
+    BlendState, Buffer, BufferAddress, BufferBindingType, BufferDescriptor, BufferUsages,
+    ColorTargetState, ColorWrites, Device, FragmentState, IndexFormat, MultisampleState,
+    PipelineCache, PipelineCompilationOptions, PipelineLayout, PipelineLayoutDescriptor,
+    PrimitiveState, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor,
+    SamplerBindingType, ShaderModule, ShaderStages, TextureFormat, TextureSampleType,
+    TextureViewDimension, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState,
+    util::{BufferInitDescriptor, DeviceExt},
+};
+
+use crate::{
+    asset::{AssetService, TextureHandle, TilesetHandle, bounds::Aabb, tileset::Tileset},
+    ecs::{component::tilemap::TilemapComponent, entity::scene::Scene},
+    rendering::{
+        depth::DepthSettings, frustum::Frustum, shader::capture_validation_errors,
+        texture::GpuTexture,
+    },
+};
+
+// Width and height in cells of the pieces a tilemap is split into, each piece is culled on its own
+const CHUNK_SIZE: u32 = 16;
+
+const ATTRIBUTES: &[VertexAttribute] = &[
+    VertexAttribute {
+        offset: 0,
+        shader_location: 0,
+        format: VertexFormat::Float32x2, // Position relative to the tilemap entity
+    },
+    VertexAttribute {
+        offset: 8,
+        shader_location: 1,
+        format: VertexFormat::Float32x2, // Position inside the tile with flips applied, from 0 to 1
+    },
+    VertexAttribute {
+        offset: 16,
+        shader_location: 2,
+        format: VertexFormat::Uint32, // Tile inside the tileset
+    },
+    VertexAttribute {
+        offset: 20,
+        shader_location: 3,
+        format: VertexFormat::Float32x4, // Layer tint
+    },
+];
+
+// The texture coordinates of a tile are looked up in the fragment shader,
+// so animating a tile only rewrites its entry in the lookup table instead of the chunk
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Pod, Zeroable)]
+pub struct TileVertex {
+    pub position: [f32; 2],
+    pub tile_coords: [f32; 2],
+    pub tile: u32,
+    pub color: [f32; 4],
+}
+
+impl TileVertex {
+    pub fn describe_vertex_buffer_layout() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<TileVertex>() as BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: ATTRIBUTES,
+        }
+    }
+}
+
+// Tiles of one layer, chunk and tileset
+struct TileChunk {
+    layer: usize,
+    tileset: usize, // Index into the tilesets of the tilemap
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    index_count: u32,
+    bounds: Aabb, // Relative to the tilemap entity
+}
+
+// Everything needed to draw a tilemap with one of its tilesets
+struct TilesetBinding {
+    tile_tex_coords: Vec<[f32; 4]>, // Top left and bottom right texture coordinates of every tile
+    tex_coords_buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+struct GpuTilemap {
+    revision: u32,
+    tilesets: Vec<TilesetHandle>,
+    uniform_buffer: Buffer, // Model matrix of the tilemap entity
+    bindings: Vec<Option<TilesetBinding>>, // None for tilesets that are missing or not uploaded yet
+    chunks: Vec<TileChunk>,
+}
+
+struct TileChunkDraw {
+    entity: u32,
+    chunk: usize,
+    z_order: i32,
+}
+
+// Draws tilemaps as static chunks that are only rebuilt when their tiles change
+pub struct TilemapRenderer {
+    shader: ShaderModule,
+    pipeline_layout: PipelineLayout,
+    pipeline_cache: Option<PipelineCache>,
+    pipeline: RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    gpu_tilemaps: HashMap<u32, GpuTilemap>,
+    draws: Vec<TileChunkDraw>,
+}
+
+impl TilemapRenderer {
+    pub fn new(
+        device: &Device,
+        shader: ShaderModule,
+        camera_bind_group_layout: &BindGroupLayout,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Tilemap bind group layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Tilemap Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = Self::create_pipeline(
+            device,
+            &pipeline_layout,
+            &shader,
+            color_format,
+            depth_settings,
+            sample_count,
+            pipeline_cache,
+        );
+
+        Self {
+            shader,
+            pipeline_layout,
+            pipeline_cache: pipeline_cache.cloned(),
+            pipeline,
+            bind_group_layout,
+            gpu_tilemaps: HashMap::new(),
+            draws: Vec::new(),
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        pipeline_layout: &PipelineLayout,
+        shader: &ShaderModule,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+        pipeline_cache: Option<&PipelineCache>,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Tilemap Render Pipeline"),
+            layout: Some(pipeline_layout),
+            vertex: VertexState {
+                module: shader,
+                entry_point: Some("vs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[TileVertex::describe_vertex_buffer_layout()],
+            },
+            fragment: Some(FragmentState {
+                module: shader,
+                entry_point: Some("fs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(ColorTargetState {
+                    format: color_format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState {
+                cull_mode: None,
+                ..Default::default()
+            },
+            // Layers are blended over each other like sprites
+            depth_stencil: Some(wgpu::DepthStencilState {
+                depth_write_enabled: false,
+                ..depth_settings.depth_stencil_state()
+            }),
+            multisample: MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: pipeline_cache,
+        })
+    }
+
+    // Depth state and the sample count are baked into the pipeline
+    pub fn recreate_pipelines(
+        &mut self,
+        device: &Device,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+    ) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.pipeline_layout,
+            &self.shader,
+            color_format,
+            depth_settings,
+            sample_count,
+            self.pipeline_cache.as_ref(),
+        );
+    }
+
+    // Swaps in a recompiled shader, the current pipeline is kept when the new one fails to build
+    pub fn reload_shader(
+        &mut self,
+        device: &Device,
+        shader: ShaderModule,
+        color_format: TextureFormat,
+        depth_settings: &DepthSettings,
+        sample_count: u32,
+    ) -> anyhow::Result<()> {
+        self.pipeline = capture_validation_errors(device, || {
+            Self::create_pipeline(
+                device,
+                &self.pipeline_layout,
+                &shader,
+                color_format,
+                depth_settings,
+                sample_count,
+                self.pipeline_cache.as_ref(),
+            )
+        })?;
+        self.shader = shader;
+
+        Ok(())
+    }
+
+    // Textures of every tileset in use, they have to be uploaded before prepare is called
+    pub fn tileset_textures(scene: &Scene, asset_service: &AssetService) -> Vec<TextureHandle> {
+        scene
+            .tilemap_components
+            .values()
+            .flat_map(|tilemap_component| tilemap_component.tilesets.iter())
+            .filter_map(|tileset| asset_service.tilesets.get(tileset))
+            .filter_map(|tileset| asset_service.atlases.get(&tileset.atlas))
+            .map(|atlas| atlas.texture)
+            .collect()
+    }
+
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        scene: &Scene,
+        asset_service: &AssetService,
+        gpu_textures: &HashMap<TextureHandle, GpuTexture>,
+        frustum: Option<&Frustum>,
+    ) {
+        self.draws.clear();
+        self.gpu_tilemaps
+            .retain(|entity, _| scene.tilemap_components.contains_key(entity));
+
+        for (entity, tilemap_component) in scene.tilemap_components.iter() {
+            let is_outdated = self.gpu_tilemaps.get(entity).is_none_or(|gpu_tilemap| {
+                gpu_tilemap.revision != tilemap_component.revision
+                    || gpu_tilemap.tilesets != tilemap_component.tilesets
+            });
+            if is_outdated {
+                debug!("Building tilemap chunks for entity {}", entity);
+                let gpu_tilemap = self.build_tilemap(device, tilemap_component, asset_service);
+                self.gpu_tilemaps.insert(*entity, gpu_tilemap);
+            }
+            let gpu_tilemap = self.gpu_tilemaps.get_mut(entity).unwrap();
+
+            let model_matrix = scene.calculate_world_matrix(*entity);
+            queue.write_buffer(
+                &gpu_tilemap.uniform_buffer,
+                0,
+                cast_slice(&[model_matrix.to_cols_array_2d()]),
+            );
+
+            // Tilesets whose texture was uploaded after the tilemap was built get their bind group now
+            for (slot, tileset) in tilemap_component.tilesets.iter().enumerate() {
+                if gpu_tilemap.bindings[slot].is_none() {
+                    gpu_tilemap.bindings[slot] = Self::create_binding(
+                        device,
+                        &self.bind_group_layout,
+                        &gpu_tilemap.uniform_buffer,
+                        *tileset,
+                        asset_service,
+                        gpu_textures,
+                    );
+                }
+
+                if let (Some(binding), Some(tileset)) = (
+                    gpu_tilemap.bindings[slot].as_ref(),
+                    asset_service.tilesets.get(tileset),
+                ) && tileset.has_animations()
+                {
+                    Self::animate_tiles(queue, binding, tileset, tilemap_component.animation_time);
+                }
+            }
+
+            for (index, chunk) in gpu_tilemap.chunks.iter().enumerate() {
+                let Some(layer) = tilemap_component.layers.get(chunk.layer) else {
+                    continue;
+                };
+                if !layer.visible || gpu_tilemap.bindings[chunk.tileset].is_none() {
+                    continue;
+                }
+                if let Some(frustum) = frustum
+                    && !frustum.intersects_bounds(&chunk.bounds, model_matrix)
+                {
+                    continue;
+                }
+
+                self.draws.push(TileChunkDraw {
+                    entity: *entity,
+                    chunk: index,
+                    z_order: layer.z_order,
+                });
+            }
+        }
+
+        // Keeps layers in order within a z-order
+        self.draws.sort_by_key(|draw| draw.z_order);
+    }
+
+    fn build_tilemap(
+        &self,
+        device: &Device,
+        tilemap_component: &TilemapComponent,
+        asset_service: &AssetService,
+    ) -> GpuTilemap {
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Tilemap Uniform Buffer"),
+            size: std::mem::size_of::<Mat4>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let tile_counts: Vec<usize> = tilemap_component
+            .tilesets
+            .iter()
+            .map(|tileset| {
+                asset_service
+                    .tilesets
+                    .get(tileset)
+                    .map_or(0, |tileset| tileset.tiles.len())
+            })
+            .collect();
+
+        let chunk_counts = (tilemap_component.size + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        let mut chunks = Vec::new();
+        let mut skipped_tiles = 0;
+        for (layer_index, layer) in tilemap_component.layers.iter().enumerate() {
+            for chunk_y in 0..chunk_counts.y {
+                for chunk_x in 0..chunk_counts.x {
+                    // Vertices and indices per tileset used inside the chunk
+                    let mut geometry: HashMap<usize, (Vec<TileVertex>, Vec<u32>)> = HashMap::new();
+
+                    let first_cell = UVec2::new(chunk_x, chunk_y) * CHUNK_SIZE;
+                    let last_cell = (first_cell + CHUNK_SIZE).min(tilemap_component.size);
+                    for y in first_cell.y..last_cell.y {
+                        for x in first_cell.x..last_cell.x {
+                            let cell = UVec2::new(x, y);
+                            let Some(tile) =
+                                layer.tiles[(y * tilemap_component.size.x + x) as usize]
+                            else {
+                                continue;
+                            };
+                            if tile_counts
+                                .get(tile.tileset)
+                                .is_none_or(|&tile_count| tile.index as usize >= tile_count)
+                            {
+                                skipped_tiles += 1;
+                                continue;
+                            }
+
+                            let (vertices, indices) = geometry.entry(tile.tileset).or_default();
+                            let first_vertex = vertices.len() as u32;
+                            let position = tilemap_component.cell_position(cell);
+                            for corner in [Vec2::ZERO, Vec2::X, Vec2::ONE, Vec2::Y] {
+                                // Flips are applied in reverse since they map the screen onto the tile
+                                let mut tile_coords = Vec2::new(corner.x, 1.0 - corner.y);
+                                if tile.flip_y {
+                                    tile_coords.y = 1.0 - tile_coords.y;
+                                }
+                                if tile.flip_x {
+                                    tile_coords.x = 1.0 - tile_coords.x;
+                                }
+                                if tile.flip_diagonal {
+                                    tile_coords = tile_coords.yx();
+                                }
+
+                                vertices.push(TileVertex {
+                                    position: (position + corner * tilemap_component.tile_size)
+                                        .to_array(),
+                                    tile_coords: tile_coords.to_array(),
+                                    tile: tile.index,
+                                    color: layer.tint.to_array(),
+                                });
+                            }
+                            indices.extend_from_slice(&[
+                                first_vertex,
+                                first_vertex + 1,
+                                first_vertex + 2,
+                                first_vertex,
+                                first_vertex + 2,
+                                first_vertex + 3,
+                            ]);
+                        }
+                    }
+
+                    for (tileset, (vertices, indices)) in geometry {
+                        let Some(bounds) = Aabb::from_points(
+                            vertices
+                                .iter()
+                                .map(|vertex| Vec2::from_array(vertex.position).extend(0.0)),
+                        ) else {
+                            continue;
+                        };
+
+                        chunks.push(TileChunk {
+                            layer: layer_index,
+                            tileset,
+                            vertex_buffer: device.create_buffer_init(&BufferInitDescriptor {
+                                label: Some("Tile Chunk Vertex Buffer"),
+                                contents: cast_slice(&vertices),
+                                usage: BufferUsages::VERTEX,
+                            }),
+                            index_buffer: device.create_buffer_init(&BufferInitDescriptor {
+                                label: Some("Tile Chunk Index Buffer"),
+                                contents: cast_slice(&indices),
+                                usage: BufferUsages::INDEX,
+                            }),
+                            index_count: indices.len() as u32,
+                            bounds,
+                        });
+                    }
+                }
+            }
+        }
+
+        if skipped_tiles > 0 {
+            warn!(
+                "Skipped {} tiles referring to missing tilesets or tiles",
+                skipped_tiles
+            );
+        }
+
+        GpuTilemap {
+            revision: tilemap_component.revision,
+            tilesets: tilemap_component.tilesets.clone(),
+            uniform_buffer,
+            bindings: tilemap_component.tilesets.iter().map(|_| None).collect(),
+            chunks,
+        }
+    }
+
+    fn create_binding(
+        device: &Device,
+        bind_group_layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        tileset: TilesetHandle,
+        asset_service: &AssetService,
+        gpu_textures: &HashMap<TextureHandle, GpuTexture>,
+    ) -> Option<TilesetBinding> {
+        let tileset = asset_service.tilesets.get(&tileset)?;
+        let atlas = asset_service.atlases.get(&tileset.atlas)?;
+        let gpu_texture = gpu_textures.get(&atlas.texture)?;
+
+        let tile_tex_coords: Vec<[f32; 4]> = (0..tileset.tiles.len().max(1))
+            .map(|tile| {
+                atlas
+                    .region_tex_coords(tile)
+                    .map_or([0.0; 4], |(min, max)| [min.x, min.y, max.x, max.y])
+            })
+            .collect();
+        let tex_coords_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Tile Texture Coordinates Buffer"),
+            contents: cast_slice(&tile_tex_coords),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&gpu_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&gpu_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: tex_coords_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Tilemap bind group"),
+        });
+
+        Some(TilesetBinding {
+            tile_tex_coords,
+            tex_coords_buffer,
+            bind_group,
+        })
+    }
+
+    // Points every animated tile at the texture coordinates of its current frame
+    fn animate_tiles(queue: &Queue, binding: &TilesetBinding, tileset: &Tileset, time: f32) {
+        let mut tile_tex_coords = binding.tile_tex_coords.clone();
+        for (tile, tile_data) in tileset.tiles.iter().enumerate() {
+            if tile_data.animation.is_empty() {
+                continue;
+            }
+
+            let frame = tileset.animated_tile(tile as u32, time) as usize;
+            if let Some(tex_coords) = binding.tile_tex_coords.get(frame) {
+                tile_tex_coords[tile] = *tex_coords;
+            }
+        }
+
+        queue.write_buffer(&binding.tex_coords_buffer, 0, cast_slice(&tile_tex_coords));
+    }
+
+    pub fn z_orders(&self) -> impl Iterator<Item = i32> + '_ {
+        self.draws.iter().map(|draw| draw.z_order)
+    }
+
+    // Expects the camera bind group to be set, returns the number of draws submitted
+    pub fn render(&self, render_pass: &mut RenderPass, z_order: i32) -> u32 {
+        let mut draw_calls = 0;
+        for draw in self.draws.iter().filter(|draw| draw.z_order == z_order) {
+            let gpu_tilemap = &self.gpu_tilemaps[&draw.entity];
+            let chunk = &gpu_tilemap.chunks[draw.chunk];
+            let Some(binding) = gpu_tilemap.bindings[chunk.tileset].as_ref() else {
+                continue;
+            };
+
+            if draw_calls == 0 {
+                render_pass.set_pipeline(&self.pipeline);
+            }
+            render_pass.set_bind_group(1, &binding.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, chunk.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(chunk.index_buffer.slice(..), IndexFormat::Uint32);
+            render_pass.draw_indexed(0..chunk.index_count, 0, 0..1);
+            draw_calls += 1;
+        }
+
+        draw_calls
+    }
+}
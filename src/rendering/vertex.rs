@@ -12,13 +12,25 @@ const ATTRIBUTES: &[VertexAttribute] = &[
         shader_location: 1,
         format: VertexFormat::Float32x3, // Color is also a 32 bit float vector of 3 components
     },
+    VertexAttribute {
+        offset: 24, // Skip over the position and color fields
+        shader_location: 2,
+        format: VertexFormat::Float32x3, // Normal is a 32 bit float vector of 3 components
+    },
+    VertexAttribute {
+        offset: 36, // Skip over the position, color and normal fields
+        shader_location: 3,
+        format: VertexFormat::Float32x2, // Texture coordinates are a 32 bit float vector of 2 components
+    },
 ];
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+#[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
 pub struct Vertex {
     pub position: [f32; 3],
     pub color: [f32; 3],
+    pub normal: [f32; 3],
+    pub tex_coords: [f32; 2],
 }
 
 impl Vertex {
@@ -1,32 +1,355 @@
-// Vertex shader
-struct CameraUniform {
-    view_projection_matrix: mat4x4<f32>,
-};
+// Material features such as NORMAL_MAP and DOUBLE_SIDED are defined by the renderer,
+// every combination of them is compiled into its own pipeline
 
-@group(0) @binding(0)
-var<uniform> camera: CameraUniform;
+// Vertex shader
+#include "camera.wgsl"
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) color: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tex_coords: vec2<f32>,
+};
+
+// Matrices arrive as four column vectors since vertex attributes can't be matrices
+struct InstanceInput {
+    @location(4) model_matrix_0: vec4<f32>,
+    @location(5) model_matrix_1: vec4<f32>,
+    @location(6) model_matrix_2: vec4<f32>,
+    @location(7) model_matrix_3: vec4<f32>,
+    @location(8) normal_matrix_0: vec4<f32>,
+    @location(9) normal_matrix_1: vec4<f32>,
+    @location(10) normal_matrix_2: vec4<f32>,
+    @location(11) normal_matrix_3: vec4<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) world_normal: vec3<f32>,
 };
 
 @vertex
-fn vs_main(model: VertexInput) -> VertexOutput {
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat4x4<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+        instance.normal_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
     var out: VertexOutput;
     out.color = model.color;
-    out.clip_position = camera.view_projection_matrix * vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    out.world_normal = (normal_matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    out.clip_position = camera.view_projection_matrix * world_position;
     return out;
 }
 
 
 // Fragment shader
+struct MaterialUniform {
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec4<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> material: MaterialUniform;
+@group(1) @binding(1)
+var base_color_texture: texture_2d<f32>;
+@group(1) @binding(2)
+var base_color_sampler: sampler;
+@group(1) @binding(3)
+var metallic_roughness_texture: texture_2d<f32>;
+@group(1) @binding(4)
+var metallic_roughness_sampler: sampler;
+@group(1) @binding(5)
+var normal_texture: texture_2d<f32>;
+@group(1) @binding(6)
+var normal_sampler: sampler;
+@group(1) @binding(7)
+var occlusion_texture: texture_2d<f32>;
+@group(1) @binding(8)
+var occlusion_sampler: sampler;
+@group(1) @binding(9)
+var emissive_texture: texture_2d<f32>;
+@group(1) @binding(10)
+var emissive_sampler: sampler;
+
+const LIGHT_TYPE_DIRECTIONAL: u32 = 0u;
+const LIGHT_TYPE_POINT: u32 = 1u;
+const LIGHT_TYPE_SPOT: u32 = 2u;
+
+struct Light {
+    position: vec3<f32>,
+    range: f32,
+    direction: vec3<f32>, // Direction the light travels in
+    light_type: u32,
+    color: vec3<f32>, // Already multiplied by the intensity
+    spot_scale: f32,
+    spot_offset: f32,
+    shadow_layer: i32, // First shadow map layer, -1 when the light casts no shadows
+    depth_bias: f32,
+    normal_bias: f32,
+};
+
+struct LightBuffer {
+    light_count: u32,
+    lights: array<Light>,
+};
+
+@group(2) @binding(0)
+var<storage, read> light_buffer: LightBuffer;
+
+const CASCADE_COUNT: u32 = 4u;
+const SHADOW_LAYER_COUNT: u32 = 8u;
+
+struct ShadowUniform {
+    view_projection_matrices: array<mat4x4<f32>, SHADOW_LAYER_COUNT>,
+    cascade_splits: vec4<f32>, // View depth at which each cascade ends
+    camera_forward: vec4<f32>,
+};
+
+@group(2) @binding(1)
+var<uniform> shadows: ShadowUniform;
+@group(2) @binding(2)
+var shadow_map: texture_depth_2d_array;
+@group(2) @binding(3)
+var shadow_sampler: sampler_comparison;
+
+// Light arriving from the surroundings, baked from the environment map of the camera
+struct EnvironmentUniform {
+    inverse_view_projection_matrix: mat4x4<f32>,
+    intensity: f32,
+    max_reflection_lod: f32, // Mip level of the prefiltered map that the roughest surfaces reflect
+    near_depth: f32,
+};
+
+@group(3) @binding(0)
+var<uniform> environment: EnvironmentUniform;
+@group(3) @binding(1)
+var environment_sampler: sampler;
+@group(3) @binding(3)
+var irradiance_map: texture_cube<f32>;
+@group(3) @binding(4)
+var prefiltered_map: texture_cube<f32>;
+@group(3) @binding(5)
+var brdf_lookup: texture_2d<f32>;
+
+const PI: f32 = 3.14159265359;
+
+// Builds a tangent frame from screen space derivatives so meshes do not need tangents
+fn perturb_normal(normal: vec3<f32>, world_position: vec3<f32>, tex_coords: vec2<f32>, tangent_normal: vec3<f32>) -> vec3<f32> {
+    let dp1 = dpdx(world_position);
+    let dp2 = dpdy(world_position);
+    let duv1 = dpdx(tex_coords);
+    let duv2 = dpdy(tex_coords);
+
+    let dp2perp = cross(dp2, normal);
+    let dp1perp = cross(normal, dp1);
+    let tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    let bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+
+    // Texture coordinates that do not change across the triangle leave no tangent frame to build
+    let max_length_squared = max(dot(tangent, tangent), dot(bitangent, bitangent));
+    if max_length_squared < 1e-20 {
+        return normal;
+    }
+    let inverse_max = inverseSqrt(max_length_squared);
+    let tbn = mat3x3<f32>(tangent * inverse_max, bitangent * inverse_max, normal);
+    return normalize(tbn * tangent_normal);
+}
+
+// Trowbridge-Reitz GGX normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let alpha = roughness * roughness;
+    let alpha_squared = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha_squared - 1.0) + 1.0;
+    return alpha_squared / (PI * denominator * denominator);
+}
+
+// Smith geometry term using the Schlick-GGX approximation for direct lighting
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let view_term = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let light_term = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return view_term * light_term;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Rough surfaces reflect less at grazing angles since light reaches them from every direction
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Inverse square falloff that smoothly reaches zero at the light's range
+fn range_attenuation(distance: f32, range: f32) -> f32 {
+    let range_ratio = distance / range;
+    let window = clamp(1.0 - range_ratio * range_ratio * range_ratio * range_ratio, 0.0, 1.0);
+    return window * window / max(distance * distance, 0.0001);
+}
+
+// Radiance arriving at the fragment and the direction towards the light
+fn light_radiance(light: Light, world_position: vec3<f32>, light_direction: ptr<function, vec3<f32>>) -> vec3<f32> {
+    if light.light_type == LIGHT_TYPE_DIRECTIONAL {
+        *light_direction = -light.direction;
+        return light.color;
+    }
+
+    let to_light = light.position - world_position;
+    let distance = length(to_light);
+    *light_direction = to_light / max(distance, 0.0001);
+    var attenuation = range_attenuation(distance, light.range);
+
+    if light.light_type == LIGHT_TYPE_SPOT {
+        let cos_angle = dot(light.direction, -*light_direction);
+        let cone = clamp(cos_angle * light.spot_scale + light.spot_offset, 0.0, 1.0);
+        attenuation *= cone * cone;
+    }
+
+    return light.color * attenuation;
+}
+
+// 1 when the fragment is fully lit by the light, 0 when it is fully in shadow
+fn shadow_factor(light: Light, world_position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    if light.shadow_layer < 0 {
+        return 1.0;
+    }
+
+    // Directional lights pick the cascade covering the fragment's distance from the camera
+    var layer = u32(light.shadow_layer);
+    if light.light_type == LIGHT_TYPE_DIRECTIONAL {
+        let view_depth = dot(world_position - camera.camera_position.xyz, shadows.camera_forward.xyz);
+        var cascade = 0u;
+        while cascade < CASCADE_COUNT && view_depth > shadows.cascade_splits[cascade] {
+            cascade++;
+        }
+        if cascade == CASCADE_COUNT {
+            return 1.0;
+        }
+        layer += cascade;
+    }
+
+    let biased_position = world_position + normal * light.normal_bias;
+    let shadow_position = shadows.view_projection_matrices[layer] * vec4<f32>(biased_position, 1.0);
+    let ndc = shadow_position.xyz / shadow_position.w;
+    if any(abs(ndc.xy) > vec2<f32>(1.0)) || ndc.z > 1.0 || ndc.z < 0.0 {
+        return 1.0;
+    }
+
+    // Percentage closer filtering averages a 3x3 grid of depth comparisons for soft edges
+    let uv = ndc.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    let texel_size = 1.0 / vec2<f32>(textureDimensions(shadow_map));
+    let reference_depth = ndc.z - light.depth_bias;
+    var lit = 0.0;
+    for (var x = -1; x <= 1; x++) {
+        for (var y = -1; y <= 1; y++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel_size;
+            lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, layer, reference_depth);
+        }
+    }
+    return lit / 9.0;
+}
+
+// Outgoing radiance for a single light arriving from light_direction
+fn brdf(normal: vec3<f32>, view_direction: vec3<f32>, light_direction: vec3<f32>, radiance: vec3<f32>, albedo: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32> {
+    let half_vector = normalize(view_direction + light_direction);
+    let n_dot_l = max(dot(normal, light_direction), 0.0);
+    let n_dot_v = max(dot(normal, view_direction), 0.0001);
+    let n_dot_h = max(dot(normal, half_vector), 0.0);
+
+    // Dielectrics reflect about 4% at normal incidence, metals reflect their albedo
+    let f0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let fresnel = fresnel_schlick(max(dot(half_vector, view_direction), 0.0), f0);
+
+    let specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, roughness) * fresnel
+        / (4.0 * n_dot_v * max(n_dot_l, 0.0001));
+
+    // Metals have no diffuse reflection
+    let diffuse = (vec3<f32>(1.0) - fresnel) * (1.0 - metallic) * albedo / PI;
+
+    return (diffuse + specular) * radiance * n_dot_l;
+}
+
+// Outgoing radiance for the light of the environment, using the split sum approximation:
+// prefiltered light times the scale and bias the lookup table holds for the reflectance
+fn environment_lighting(normal: vec3<f32>, view_direction: vec3<f32>, albedo: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32> {
+    let n_dot_v = max(dot(normal, view_direction), 0.0001);
+    let f0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let fresnel = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+
+    let irradiance = textureSample(irradiance_map, environment_sampler, normal).rgb;
+    let diffuse = (vec3<f32>(1.0) - fresnel) * (1.0 - metallic) * albedo * irradiance;
+
+    let reflection = reflect(-view_direction, normal);
+    let prefiltered = textureSampleLevel(prefiltered_map, environment_sampler, reflection, roughness * environment.max_reflection_lod).rgb;
+    let brdf = textureSample(brdf_lookup, environment_sampler, vec2<f32>(n_dot_v, roughness)).rg;
+    let specular = prefiltered * (fresnel * brdf.x + brdf.y);
+
+    return (diffuse + specular) * environment.intensity;
+}
+
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0); // Red color
-}
\ No newline at end of file
+fn fs_main(in: VertexOutput, @builtin(front_facing) is_front_facing: bool) -> @location(0) vec4<f32> {
+    let base_color = material.base_color_factor
+        * vec4<f32>(in.color, 1.0)
+        * textureSample(base_color_texture, base_color_sampler, in.tex_coords);
+
+    // Roughness is stored in the green channel and metalness in the blue channel
+    let metallic_roughness = textureSample(metallic_roughness_texture, metallic_roughness_sampler, in.tex_coords);
+    let metallic = clamp(material.metallic_factor * metallic_roughness.b, 0.0, 1.0);
+    let roughness = clamp(material.roughness_factor * metallic_roughness.g, 0.04, 1.0);
+
+    var geometric_normal = normalize(in.world_normal);
+#ifdef DOUBLE_SIDED
+    // Back faces of double sided materials are lit from their own side
+    if !is_front_facing {
+        geometric_normal = -geometric_normal;
+    }
+#endif
+
+#ifdef NORMAL_MAP
+    let sampled_normal = textureSample(normal_texture, normal_sampler, in.tex_coords).xyz * 2.0 - 1.0;
+    let tangent_normal = normalize(vec3<f32>(sampled_normal.xy * material.normal_scale, sampled_normal.z));
+    let normal = perturb_normal(geometric_normal, in.world_position, in.tex_coords, tangent_normal);
+#else
+    let normal = geometric_normal;
+#endif
+
+    let view_direction = normalize(camera.camera_position.xyz - in.world_position);
+
+    var color = vec3<f32>(0.0);
+    for (var i = 0u; i < light_buffer.light_count; i++) {
+        let light = light_buffer.lights[i];
+        var light_direction: vec3<f32>;
+        var radiance = light_radiance(light, in.world_position, &light_direction);
+        if any(radiance > vec3<f32>(0.0)) {
+            radiance *= shadow_factor(light, in.world_position, geometric_normal);
+            color += brdf(normal, view_direction, light_direction, radiance, base_color.rgb, metallic, roughness);
+        }
+    }
+
+    let occlusion = mix(1.0, textureSample(occlusion_texture, occlusion_sampler, in.tex_coords).r, material.occlusion_strength);
+    color += environment_lighting(normal, view_direction, base_color.rgb, metallic, roughness) * occlusion;
+
+    color += material.emissive_factor.rgb * textureSample(emissive_texture, emissive_sampler, in.tex_coords).rgb;
+
+    return vec4<f32>(color, base_color.a);
+}
@@ -0,0 +1,26 @@
+// Depth only shader that renders the scene from a light's point of view
+struct ShadowView {
+    view_projection_matrix: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> shadow_view: ShadowView;
+
+// Only the model matrix of the instance is needed, the normal matrix is left unread
+struct InstanceInput {
+    @location(4) model_matrix_0: vec4<f32>,
+    @location(5) model_matrix_1: vec4<f32>,
+    @location(6) model_matrix_2: vec4<f32>,
+    @location(7) model_matrix_3: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return shadow_view.view_projection_matrix * model_matrix * vec4<f32>(position, 1.0);
+}
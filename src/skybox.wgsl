@@ -0,0 +1,28 @@
+// Draws the environment map behind everything else in the main pass
+#include "fullscreen.wgsl"
+
+struct EnvironmentUniform {
+    inverse_view_projection_matrix: mat4x4<f32>,
+    intensity: f32,
+    max_reflection_lod: f32,
+    near_depth: f32, // Depth of the near plane, which is 1 with reversed depth
+};
+
+@group(0) @binding(0)
+var<uniform> environment: EnvironmentUniform;
+@group(0) @binding(1)
+var environment_sampler: sampler;
+@group(0) @binding(2)
+var environment_map: texture_cube<f32>;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // The direction the pixel looks in runs from its point on the near plane to one further away
+    let ndc = vec2<f32>(in.tex_coords.x * 2.0 - 1.0, 1.0 - in.tex_coords.y * 2.0);
+    let near = environment.inverse_view_projection_matrix * vec4<f32>(ndc, environment.near_depth, 1.0);
+    let far = environment.inverse_view_projection_matrix * vec4<f32>(ndc, 0.5, 1.0);
+    let direction = far.xyz / far.w - near.xyz / near.w;
+
+    let color = textureSampleLevel(environment_map, environment_sampler, direction, 0.0).rgb;
+    return vec4<f32>(color * environment.intensity, 1.0);
+}
@@ -0,0 +1,45 @@
+// Draws glyph quads whose coverage comes from a single channel glyph atlas
+#include "camera.wgsl"
+
+@group(1) @binding(0)
+var glyph_atlas: texture_2d<f32>;
+@group(1) @binding(1)
+var glyph_sampler: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+// World text was moved to world space on the CPU
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_projection_matrix * vec4<f32>(in.position, 1.0);
+    out.tex_coords = in.tex_coords;
+    out.color = in.color;
+    return out;
+}
+
+// Screen text is already in clip space
+@vertex
+fn vs_screen(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(in.position, 1.0);
+    out.tex_coords = in.tex_coords;
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(glyph_atlas, glyph_sampler, in.tex_coords).r;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
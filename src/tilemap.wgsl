@@ -0,0 +1,48 @@
+// Draws tilemap chunks, the texture coordinates of every tile come from a lookup table
+// so animated tiles can change frame without touching the chunk vertices
+#include "camera.wgsl"
+
+struct TilemapUniform {
+    model_matrix: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> tilemap: TilemapUniform;
+@group(1) @binding(1)
+var tileset_texture: texture_2d<f32>;
+@group(1) @binding(2)
+var tileset_sampler: sampler;
+// Top left corner in xy and bottom right corner in zw of every tile
+@group(1) @binding(3)
+var<storage, read> tile_tex_coords: array<vec4<f32>>;
+
+struct VertexInput {
+    @location(0) position: vec2<f32>,
+    @location(1) tile_coords: vec2<f32>,
+    @location(2) tile: u32,
+    @location(3) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tile_coords: vec2<f32>,
+    @location(1) @interpolate(flat) tile: u32,
+    @location(2) color: vec4<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_projection_matrix * tilemap.model_matrix * vec4<f32>(in.position, 0.0, 1.0);
+    out.tile_coords = in.tile_coords;
+    out.tile = in.tile;
+    out.color = in.color;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let tex_coords = tile_tex_coords[in.tile];
+    let uv = mix(tex_coords.xy, tex_coords.zw, in.tile_coords);
+    return textureSample(tileset_texture, tileset_sampler, uv) * in.color;
+}